@@ -17,6 +17,8 @@
 //!   - MCP_URL: Server URL (can override --url)
 //!   - MCP_TOKEN: Bearer token (can override --token)
 
+#![allow(clippy::doc_overindented_list_items)]
+
 use anyhow::{Context, Result};
 use clap::Parser;
 use reqwest::Client;
@@ -1,7 +1,10 @@
 #!/usr/bin/env bash
 # Start the gnawtreewriter MCP server in a background-friendly way.
 # Usage:
-#   ./scripts/mcp-serve.sh [--addr ADDR] [--token TOKEN] [--pid PIDFILE] [--log LOGFILE] [--foreground]
+#   ./scripts/mcp-serve.sh [--addr ADDR] [--token TOKEN] [--token-file FILE] [--pid PIDFILE] [--log LOGFILE] [--foreground]
+#
+# The token comes from --token, then MCP_TOKEN. Without either a random one is
+# generated and written to the token file (mode 600); it is never printed.
 #
 # Examples:
 #   # start in background on default 127.0.0.1:8080
@@ -16,7 +19,8 @@ set -euo pipefail
 
 # Defaults
 ADDR="127.0.0.1:8080"
-TOKEN="secret"
+TOKEN="${MCP_TOKEN:-}"
+TOKENFILE=".mcp-token"
 PIDFILE=".mcp-server.pid"
 LOGFILE=".mcp-server.log"
 FOREGROUND=false
@@ -24,7 +28,7 @@ TIMEOUT=30
 
 usage() {
   cat <<EOF
-Usage: $0 [--addr ADDR] [--token TOKEN] [--pid PIDFILE] [--log LOGFILE] [--foreground] [--help]
+Usage: $0 [--addr ADDR] [--token TOKEN] [--token-file FILE] [--pid PIDFILE] [--log LOGFILE] [--foreground] [--help]
 
 Starts the gnawtreewriter MCP server. By default it runs in background and writes:
   PID -> $PIDFILE
@@ -32,7 +36,8 @@ Starts the gnawtreewriter MCP server. By default it runs in background and write
 
 Options:
   --addr <host:port>    Address to bind (default ${ADDR}). Use :0 for ephemeral port.
-  --token <token>       Bearer token used for authentication (default ${TOKEN}).
+  --token <token>       Bearer token used for authentication (default \$MCP_TOKEN, else generated).
+  --token-file <file>   Where a generated token is written (default ${TOKENFILE}).
   --pid <file>          PID file path (default ${PIDFILE}).
   --log <file>          Log file path (default ${LOGFILE}).
   --foreground, --fg    Run server in foreground (no PID/log handling).
@@ -54,6 +59,10 @@ for arg in "$@"; do
     normalized_args+=(--addr "${BASH_REMATCH[1]}")
   elif [[ "$arg" =~ ^--addr(.+)$ ]]; then
     normalized_args+=(--addr "${BASH_REMATCH[1]}")
+  elif [[ "$arg" =~ ^--token-file=(.+)$ ]]; then
+    normalized_args+=(--token-file "${BASH_REMATCH[1]}")
+  elif [[ "$arg" =~ ^--token-file$ ]]; then
+    normalized_args+=("$arg")
   elif [[ "$arg" =~ ^--token=(.+)$ ]]; then
     normalized_args+=(--token "${BASH_REMATCH[1]}")
   elif [[ "$arg" =~ ^--token(.+)$ ]]; then
@@ -82,6 +91,7 @@ while [[ $# -gt 0 ]]; do
   case "$1" in
     --addr) ADDR="$2"; shift 2 ;;
     --token) TOKEN="$2"; shift 2 ;;
+    --token-file) TOKENFILE="$2"; shift 2 ;;
     --pid) PIDFILE="$2"; shift 2 ;;
     --log) LOGFILE="$2"; shift 2 ;;
     --foreground|--fg) FOREGROUND=true; shift ;;
@@ -106,14 +116,24 @@ if [[ -f "$PIDFILE" ]]; then
   fi
 fi
 
+# Never run without a token: generate one the client can read back
+if [[ -z "$TOKEN" ]]; then
+  TOKEN=$(od -An -N16 -tx1 /dev/urandom | tr -d ' \n')
+  (umask 077 && printf '%s\n' "$TOKEN" >"$TOKENFILE")
+  echo "Generated a token in $TOKENFILE"
+fi
+
+# Pass the token through the environment so it doesn't show up in `ps`
+export MCP_TOKEN="$TOKEN"
+
 # Pick executable: prefer installed binary, otherwise fallback to cargo run
 if command -v gnawtreewriter >/dev/null 2>&1; then
-  CMD=(gnawtreewriter mcp serve --addr "$ADDR" --token "$TOKEN")
+  CMD=(gnawtreewriter mcp serve --addr "$ADDR")
 else
-  CMD=(cargo run --features mcp -- mcp serve --addr "$ADDR" --token "$TOKEN")
+  CMD=(cargo run --features mcp -- mcp serve --addr "$ADDR")
 fi
 
-echo "Starting gnawtreewriter MCP server on http://$ADDR (token='$TOKEN')"
+echo "Starting gnawtreewriter MCP server on http://$ADDR"
 
 if [[ "$FOREGROUND" == "true" ]]; then
   # Run in foreground (logs to terminal)
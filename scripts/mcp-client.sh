@@ -13,13 +13,17 @@
 #
 # Environment:
 #   MCP_URL   - default URL if --url not provided (default: http://127.0.0.1:8080/)
-#   MCP_TOKEN - default token if --token not provided (default: secret)
+#   MCP_TOKEN - default token if --token not provided (default: the token
+#               mcp-serve.sh generated in .mcp-token)
 #
 set -euo pipefail
 IFS=$'\n\t'
 
 URL="${MCP_URL:-http://127.0.0.1:8080/}"
-TOKEN="${MCP_TOKEN:-secret}"
+TOKEN="${MCP_TOKEN:-}"
+if [[ -z "$TOKEN" && -f .mcp-token ]]; then
+  TOKEN=$(<.mcp-token)
+fi
 RELEASE=false
 
 usage() {
@@ -3,15 +3,19 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::core::{
-    find_project_root, EditOperation, GnawTreeWriter, OperationType, RestorationEngine, TagManager,
-    TransactionLog, UndoRedoManager, gnaw_find, inspect, blast, gnaw_refactor, gnaw_diff, gnaw_graph, visualizer::TreeVisualizer,
+    find_project_root, EditOperation, EditOptions, GnawError, GnawTreeWriter, NodeEdit, OperationType, RestorationEngine, RestorationResult, TagManager,
+    TransactionLog, TransactionStatus, UndoRedoManager, gnaw_find, inspect, blast, gnaw_refactor, gnaw_diff, gnaw_graph, visualizer::TreeVisualizer,
+    gnaw_ignore::GnawIgnore, config::{parse_size, Config}, write_source_file,
 };
+use crate::llm::{GnawSenseBroker, SenseResponse};
 #[cfg(feature = "modernbert")]
-use crate::llm::{GnawSenseBroker, SenseResponse, SemanticIndexManager};
-use crate::parser::TreeNode;
+use crate::llm::SemanticIndexManager;
+use crate::parser::{NodeKind, TreeNode};
 use anyhow::{Context, Result};
 use colored::Colorize;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use clap::{Parser, Subcommand};
 
@@ -27,14 +31,34 @@ enum McpSubcommands {
     ///
     /// Options:
     ///   --addr <ADDR>    Address to bind (default: 127.0.0.1:8080)
-    ///   --token <TOKEN>  Optional Bearer token for basic auth (can also be set via MCP_TOKEN)
+    ///   --token-file <PATH>  Read the Bearer token from a file (preferred)
+    ///   --token <TOKEN>  Optional Bearer token for basic auth (MCP_TOKEN takes precedence)
     Serve {
-        /// Address to bind (default: 127.0.0.1:8080)
-        #[arg(long, default_value = "127.0.0.1:8080")]
-        addr: String,
+        /// Address to bind (default: `mcp.addr` from .gnawtreewriter.toml, else 127.0.0.1:8080)
         #[arg(long)]
-        /// Optional bearer token for basic auth. If omitted, `MCP_TOKEN` environment variable will be used.
+        addr: Option<String>,
+        /// File containing the bearer token; keeps it out of process listings
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        #[arg(long)]
+        /// Optional bearer token for basic auth. Ignored if `--token-file` or `MCP_TOKEN` is set; if all are
+        /// absent, `mcp.token` from .gnawtreewriter.toml is used.
         token: Option<String>,
+        /// Maximum requests handled at once; extra requests get HTTP 429
+        #[arg(long)]
+        max_concurrency: Option<usize>,
+        /// Seconds an AI tool call (sense, semantic report, ...) may run
+        #[arg(long, default_value_t = 60)]
+        tool_timeout: u64,
+        /// Seconds any other tool call may run
+        #[arg(long, default_value_t = 15)]
+        fast_tool_timeout: u64,
+        /// Never attach a pulse (related nodes, tests, hints) to edit results
+        #[arg(long)]
+        no_pulse: bool,
+        /// What the pulse indexes to find callers: the edited file's directory or the whole project
+        #[arg(long, default_value = "parent", value_parser = ["parent", "project"])]
+        pulse_scope: String,
     },
     /// Start MCP server over Stdio (Standard Input/Output).
     /// Recommended for local integration with Claude Desktop, Zed, or Gemini CLI.
@@ -51,6 +75,42 @@ enum McpSubcommands {
         #[arg(long)]
         /// Optional bearer token for basic auth. If omitted, `MCP_TOKEN` environment variable will be used.
         token: Option<String>,
+        /// Output format: text or json
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+    },
+    /// List the tools a running server exposes, with their required arguments
+    ListTools {
+        /// Server URL (default: http://127.0.0.1:8080/)
+        #[arg(long, default_value = "http://127.0.0.1:8080/")]
+        url: String,
+        #[arg(long)]
+        /// Optional bearer token for basic auth. If omitted, `MCP_TOKEN` environment variable will be used.
+        token: Option<String>,
+        /// Output format: table or json
+        #[arg(long, default_value = "table", value_parser = ["table", "json"])]
+        format: String,
+    },
+    /// Call a tool on a running server and print the JSON-RPC response.
+    ///
+    /// Examples:
+    ///   gnawtreewriter mcp call analyze file_path=src/main.rs
+    ///   gnawtreewriter mcp call list_nodes file_path=app.py max_depth=2
+    ///   gnawtreewriter mcp call edit_node --json-args '{"file_path": "app.py", "node_path": "0", "content": "x = 1"}'
+    Call {
+        /// Tool name, e.g. analyze
+        tool: String,
+        /// Arguments as key=value, sent as JSON when the tool's schema gives the key a non-string type; key:=json always sends JSON
+        args: Vec<String>,
+        /// Server URL (default: http://127.0.0.1:8080/)
+        #[arg(long, default_value = "http://127.0.0.1:8080/")]
+        url: String,
+        #[arg(long)]
+        /// Optional bearer token for basic auth. If omitted, `MCP_TOKEN` environment variable will be used.
+        token: Option<String>,
+        /// Arguments as a JSON object; key=value pairs are applied on top
+        #[arg(long)]
+        json_args: Option<String>,
     },
 }
 
@@ -76,33 +136,137 @@ pub struct Cli {
     #[arg(long, global = true)]
     /// Output machine-readable JSON for errors and results
     json: bool,
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    /// Refuse files larger than this (bytes, or with a K/M/G suffix; default 5M)
+    max_file_size: Option<u64>,
+    #[arg(long, global = true, value_name = "SECS")]
+    /// Give up on parsing a file after this many seconds, 0 for no limit (default 30)
+    parse_timeout: Option<u64>,
+    #[arg(long, global = true, value_name = "NAME", value_parser = crate::core::parse_encoding)]
+    /// Read and write source files in this encoding, e.g. latin1 or shift_jis (default utf-8)
+    encoding: Option<&'static encoding_rs::Encoding>,
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto, value_name = "WHEN")]
+    /// Colorize output: auto (only on a terminal, unless NO_COLOR is set), always or never
+    color: ColorChoice,
+    #[arg(long, global = true)]
+    /// Same as --color never
+    no_color: bool,
+    #[arg(long, global = true, value_name = "PATH")]
+    /// Write the result of analyze, list, history, export, export-relations, diff-to-batch, blueprint or ai report to this file instead of stdout
+    output: Option<PathBuf>,
+    #[arg(long, global = true, value_name = "LABEL")]
+    /// Tag the transactions this command logs with LABEL, so restore-label can roll them back
+    label: Option<String>,
+    #[arg(long, global = true)]
+    /// Record edits rejected by validation in the history as failed (also GNAW_LOG_FAILED=1)
+    log_failed: bool,
+    #[arg(long, global = true, value_enum, default_value_t = DiffFormat::Inline, value_name = "FORMAT")]
+    /// How previews show changes: inline (the whole file) or unified (hunks around each change)
+    diff_format: DiffFormat,
+    #[arg(long, global = true, value_name = "N")]
+    /// Unchanged lines shown around each change in a unified diff (default 3; implies --diff-format unified)
+    context_lines: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DiffFormat {
+    Inline,
+    Unified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to emit ANSI colors, given whether stdout is a terminal
+    fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
 }
 
 #[allow(clippy::enum_variant_names, clippy::too_many_arguments)]
 #[derive(Subcommand)]
 enum Commands {
     /// Parse files and show their AST tree structure
+    ///
+    /// Use `-` as the path (or --stdin) to read source from standard input;
+    /// --lang then picks the parser.
     Analyze {
         paths: Vec<String>,
-        #[arg(short, long, default_value = "json")]
-        format: String,
+        /// json, summary or dot (a Graphviz graph of each tree; default:
+        /// `format` from .gnawtreewriter.toml, else json)
+        #[arg(short, long)]
+        format: Option<String>,
         #[arg(long)]
         recursive: bool,
+        /// Follow symlinked files and directories when recursing
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Print the JSON schema of each result's `tree` and exit
+        #[arg(long)]
+        schema: bool,
+        /// Read the source from standard input (same as the path `-`)
+        #[arg(long)]
+        stdin: bool,
+        /// Language of the stdin source, by name (rust) or extension (rs)
+        #[arg(long, value_name = "LANG")]
+        lang: Option<String>,
+        /// Stop with an error at the first file that cannot be parsed,
+        /// instead of warning and going on
+        #[arg(long)]
+        fail_fast: bool,
+        /// With --recursive, only descend this many directory levels
+        /// (files directly in the directory are level 1)
+        #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "recursive")]
+        max_depth: Option<usize>,
+        /// Show files with syntax errors as a partial tree, with the broken
+        /// code in `error` nodes, instead of failing
+        #[arg(long)]
+        tolerant: bool,
     },
     /// List all tree nodes for a file
     List {
         file_path: String,
         #[arg(short, long)]
         filter_type: Option<String>,
+        /// Only nodes of this kind, in any language: function, class, method, import, comment or property
+        #[arg(long)]
+        kind: Option<NodeKind>,
         #[arg(short, long, default_value = "100")]
         limit: usize,
         #[arg(short, long, default_value = "0")]
         offset: usize,
+        /// List a file with syntax errors from its partial tree, with the
+        /// broken code in `error` nodes, instead of failing
+        #[arg(long)]
+        tolerant: bool,
     },
     /// Show the content of a specific node
     Show {
         file_path: String,
         node_path: String,
+        /// Prefix each line with its line number in the file
+        #[arg(long)]
+        with_line_numbers: bool,
+        /// Show the node and then each of its members (e.g. every method of
+        /// a class), each with its path
+        #[arg(long)]
+        children: bool,
+    },
+    /// Diff a node in one file against a node in another (or the same) file
+    DiffNodes {
+        file_a: String,
+        path_a: String,
+        file_b: String,
+        path_b: String,
     },
     /// Replace the content of a specific node
     Edit {
@@ -121,10 +285,24 @@ enum Commands {
         unescape_newlines: bool,
         #[arg(long)]
         force: bool,
+        /// UNSAFE: skip the backup, so this edit cannot be undone
+        #[arg(long)]
+        no_backup: bool,
+        /// UNSAFE: skip syntax validation and write the result even if it does not parse
+        #[arg(long)]
+        no_validate: bool,
         #[arg(long, short = 'n')]
         narrative: Option<String>,
+        /// Show the diff and ask before writing (skipped when stdin is not a terminal)
+        #[arg(long)]
+        confirm: bool,
+        /// Refuse the edit if the node at this path would change type (e.g. a function becoming a class)
+        #[arg(long)]
+        strict_type: bool,
     },
     /// Insert new content into a parent node
+    // `parent_path` may be left out when `--tag` names the parent
+    #[command(allow_missing_positional = true)]
     Insert {
         file_path: String,
         #[arg(required_unless_present = "tag")]
@@ -140,8 +318,58 @@ enum Commands {
         preview: bool,
         #[arg(long)]
         unescape_newlines: bool,
+        /// UNSAFE: skip the backup, so this edit cannot be undone
+        #[arg(long)]
+        no_backup: bool,
+        /// UNSAFE: skip syntax validation and write the result even if it does not parse
+        #[arg(long)]
+        no_validate: bool,
         #[arg(long, short = 'n')]
         narrative: Option<String>,
+        /// Show the diff and ask before writing (skipped when stdin is not a terminal)
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Replace an inclusive range of lines, with the same validation, backup
+    /// and logging as a node edit
+    ReplaceLines {
+        file_path: String,
+        /// First line to replace (1-based)
+        start_line: usize,
+        /// Last line to replace (inclusive)
+        end_line: usize,
+        #[arg(required_unless_present = "source_file")]
+        content: Option<String>,
+        #[arg(long, conflicts_with = "content")]
+        source_file: Option<String>,
+        #[arg(short, long)]
+        preview: bool,
+        #[arg(long)]
+        unescape_newlines: bool,
+        /// UNSAFE: skip the backup, so this edit cannot be undone
+        #[arg(long)]
+        no_backup: bool,
+        /// UNSAFE: skip syntax validation and write the result even if it does not parse
+        #[arg(long)]
+        no_validate: bool,
+    },
+    /// Replace several nodes of one file in a single validated write. The
+    /// JSON file holds an array of {"node_path": ..., "content": ...}
+    /// objects; every path refers to the file as it is before the batch.
+    EditBatch {
+        file_path: String,
+        edits_file: String,
+        #[arg(short, long)]
+        preview: bool,
+        /// Allow edits the Guardian would block
+        #[arg(long)]
+        force: bool,
+        /// UNSAFE: skip the backup, so this edit cannot be undone
+        #[arg(long)]
+        no_backup: bool,
+        /// UNSAFE: skip syntax validation and write the result even if it does not parse
+        #[arg(long)]
+        no_validate: bool,
     },
     /// Undo recent edit operations
     Undo {
@@ -157,8 +385,37 @@ enum Commands {
     History {
         #[arg(short, long, default_value = "10")]
         limit: usize,
+        /// table, json (one array) or ndjson (one transaction per line)
         #[arg(short, long, default_value = "table")]
         format: String,
+        /// Show the recorded diff for a single transaction ID
+        #[arg(long, value_name = "TRANSACTION_ID")]
+        show_diff: Option<String>,
+    },
+    /// Export the full transaction history (json, json-compact or csv)
+    Export {
+        #[arg(short, long, default_value = "json")]
+        format: String,
+        /// File to write to (defaults to stdout; same as the global --output)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the call/reference graph of a file or directory as JSON
+    /// (nodes and edges), e.g. for visualization or call-graph tools
+    ExportRelations {
+        /// File or directory to index
+        path: String,
+    },
+    /// Replay the edits of an exported transaction log onto this checkout
+    #[command(alias = "import")]
+    Replay {
+        /// File written by `export` (or a raw session log)
+        log_file: PathBuf,
+        /// Original project root recorded in the log, re-rooted onto this project
+        #[arg(long)]
+        strip_prefix: Option<PathBuf>,
+        #[arg(short, long)]
+        preview: bool,
     },
     /// Execute a batch of operations
     Batch {
@@ -172,14 +429,30 @@ enum Commands {
         pattern: String,
         #[arg(short, long)]
         filter_type: Option<String>,
+        /// Only nodes of this kind, in any language: function, class, method, import, comment or property
+        #[arg(long)]
+        kind: Option<NodeKind>,
         #[arg(short, long)]
         limit: Option<usize>,
+        /// Show the functions and classes enclosing each match
+        #[arg(long)]
+        parents: bool,
     },
     /// Get a high-level skeletal view
     Skeleton {
         file_path: String,
         #[arg(short, long, default_value = "2")]
         depth: usize,
+        /// Show the signature line of functions, methods and classes instead of just their name
+        #[arg(long)]
+        signatures: bool,
+    },
+    /// List the functions, classes and methods in a file, with line numbers
+    Outline {
+        file_path: String,
+        /// How deeply nested symbols to include; 1 lists only the top level
+        #[arg(short = 'd', long, default_value = "2")]
+        max_depth: usize,
     },
     /// Comprehensive health check of the system
     Status,
@@ -191,7 +464,7 @@ enum Commands {
     DiffToBatch {
         diff_file: String,
         #[arg(short, long)]
-        output: Option<String>,
+        output: Option<PathBuf>,
         #[arg(short, long)]
         preview: bool,
     },
@@ -267,6 +540,9 @@ enum Commands {
         timestamp: String,
         #[arg(short, long)]
         preview: bool,
+        /// With --preview, show the diff each file would get
+        #[arg(long, requires = "preview")]
+        show_diffs: bool,
     },
     /// Restore specific files by timestamp
     RestoreFiles {
@@ -282,6 +558,22 @@ enum Commands {
         session_id: String,
         #[arg(short, long)]
         preview: bool,
+        /// With --preview, only list modifications made at or after this time
+        #[arg(long, requires = "preview")]
+        since: Option<String>,
+        /// With --preview, only list modifications made at or before this time
+        #[arg(long, requires = "preview")]
+        until: Option<String>,
+    },
+    /// Undo all changes made under a --label
+    RestoreLabel {
+        label: String,
+        #[arg(short, long)]
+        preview: bool,
+        /// Restore even if edits outside the label changed the same files
+        /// later, discarding them
+        #[arg(long)]
+        force: bool,
     },
         /// Move a node to a new location (atomically delete + reinsert)
         Move {
@@ -303,6 +595,43 @@ enum Commands {
         tag: Option<String>,
         #[arg(short, long)]
         preview: bool,
+        /// UNSAFE: skip the backup, so this edit cannot be undone
+        #[arg(long)]
+        no_backup: bool,
+        /// UNSAFE: skip syntax validation and write the result even if it does not parse
+        #[arg(long)]
+        no_validate: bool,
+        /// Show the diff and ask before writing (skipped when stdin is not a terminal)
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Comment out a node using the file's comment syntax
+    Comment {
+        file_path: String,
+        node_path: String,
+        #[arg(short, long)]
+        preview: bool,
+    },
+    /// Remove comment markers from a commented-out node
+    Uncomment {
+        file_path: String,
+        node_path: String,
+        #[arg(short, long)]
+        preview: bool,
+    },
+    /// Indent a node's lines by DELTA levels in the file's indent style,
+    /// or dedent them with a negative DELTA
+    ///
+    /// Examples:
+    ///   gnawtreewriter reindent app.py 1.2.0 -1
+    ///   gnawtreewriter reindent main.rs 0.3 2 --preview
+    Reindent {
+        file_path: String,
+        node_path: String,
+        #[arg(allow_negative_numbers = true)]
+        delta: i32,
+        #[arg(short, long)]
+        preview: bool,
     },
     /// Add a property to a QML component
     AddProperty {
@@ -346,6 +675,26 @@ enum Commands {
         format: String,
         #[arg(long)]
         recursive: bool,
+        /// Follow symlinked files and directories when recursing
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Stop at the first file with an issue
+        #[arg(long)]
+        fail_fast: bool,
+        /// With --recursive, only descend this many directory levels
+        /// (files directly in the directory are level 1)
+        #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "recursive")]
+        max_depth: Option<usize>,
+    },
+    /// Watch files and keep the relational and semantic indexes up to date as they change
+    Watch {
+        /// Directory to watch (defaults to the current directory)
+        path: Option<PathBuf>,
+        #[arg(long)]
+        recursive: bool,
+        /// Milliseconds to wait for more events before re-indexing a batch
+        #[arg(long, default_value = "300")]
+        debounce_ms: u64,
     },
     /// Search for code semantically
     Sense {
@@ -355,6 +704,36 @@ enum Commands {
         deep: bool,
         #[arg(long)]
         auto_index: bool,
+        /// Device to run the model on (default: `device` from .gnawtreewriter.toml, else cpu)
+        #[arg(long, value_parser = ["cpu", "cuda", "metal"])]
+        device: Option<String>,
+    },
+    /// Fill a [MASK] marker in a node with ModernBERT's fill-mask head
+    Complete {
+        file_path: String,
+        node_path: String,
+        /// Number of suggestions to show
+        #[arg(long, default_value_t = 5)]
+        top_k: usize,
+        /// Device to run the model on (default: `device` from .gnawtreewriter.toml, else cpu)
+        #[arg(long, value_parser = ["cpu", "cuda", "metal"])]
+        device: Option<String>,
+    },
+    /// Suggest refactorings for a file or one node
+    Refactor {
+        file_path: String,
+        node_path: Option<String>,
+        /// Device to run the model on (default: `device` from .gnawtreewriter.toml, else cpu)
+        #[arg(long, value_parser = ["cpu", "cuda", "metal"])]
+        device: Option<String>,
+    },
+    /// Suggest which nodes a batch edit with the given intent should touch
+    BatchSuggest {
+        file_path: String,
+        intent: String,
+        /// Device to run the model on (default: `device` from .gnawtreewriter.toml, else cpu)
+        #[arg(long, value_parser = ["cpu", "cuda", "metal"])]
+        device: Option<String>,
     },
     /// Semantically insert code
     SenseInsert {
@@ -405,7 +784,7 @@ enum Commands {
     /// Generate a project blueprint
     Blueprint {
         #[arg(short, long)]
-        output: Option<String>,
+        output: Option<PathBuf>,
     },
     /// Semantic edit
     SemanticEdit {
@@ -510,6 +889,10 @@ enum AiSubcommands {
         #[arg(long)]
         /// Force re-download even if already present
         force: bool,
+        /// Model to install: modernbert, modernbert-large or a Hugging Face
+        /// repo id (owner/name) with ModernBERT-compatible weights
+        #[arg(long, default_value = "modernbert")]
+        model: String,
     },
     /// Show AI status and installed models
     Status,
@@ -517,6 +900,13 @@ enum AiSubcommands {
     Index {
         /// Directory to index (defaults to project root)
         path: Option<PathBuf>,
+        /// Delete the semantic and relational indexes instead of indexing
+        #[arg(long, conflicts_with = "rebuild")]
+        clear: bool,
+        /// Drop the embeddings of the files under the path first, so they
+        /// are all embedded again
+        #[arg(long)]
+        rebuild: bool,
     },
     /// Generate an engineering report of recent structural changes
     Report {
@@ -572,29 +962,137 @@ impl Cli {
     pub async fn run(self) -> Result<()> {
         let _json_mode = self.json;
         let global_dry_run = self.dry_run;
+        let color = if self.no_color { ColorChoice::Never } else { self.color };
+        let to_terminal = self.output.is_none() && std::io::IsTerminal::is_terminal(&std::io::stdout());
+        colored::control::set_override(color.enabled(to_terminal));
+        let output = self.output;
+        // Every other command prints to the terminal, so a file named here
+        // would silently stay empty
+        if output.is_some()
+            && !matches!(
+                &self.command,
+                Commands::Analyze { .. }
+                    | Commands::List { .. }
+                    | Commands::History { .. }
+                    | Commands::Export { .. }
+                    | Commands::ExportRelations { .. }
+                    | Commands::DiffToBatch { .. }
+                    | Commands::Blueprint { .. }
+                    | Commands::Ai { command: AiSubcommands::Report { .. } }
+            )
+        {
+            anyhow::bail!(
+                "--output only applies to analyze, list, history, export, export-relations, diff-to-batch, blueprint and ai report"
+            );
+        }
+        // Project defaults; flags given on the command line win
+        let config = match std::env::current_dir() {
+            Ok(dir) => Config::load(&find_project_root(&dir)).unwrap_or_else(|e| {
+                eprintln!("Warning: {:#}", e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        };
+        if let Some(limit) = self.max_file_size.or(config.max_file_size) {
+            crate::core::set_max_file_size(limit);
+        }
+        if let Some(secs) = self.parse_timeout.or(config.parse_timeout) {
+            crate::parser::set_parse_timeout((secs > 0).then(|| std::time::Duration::from_secs(secs)));
+        }
+        if let Some(encoding) = self.encoding {
+            crate::core::set_encoding(encoding);
+        }
+        if let Some(atomic) = config.atomic_writes {
+            crate::core::set_atomic_writes(atomic);
+        }
+        if let Some(label) = self.label {
+            if label.trim().is_empty() {
+                anyhow::bail!("--label must not be empty");
+            }
+            crate::core::set_label(Some(label));
+        }
+        if self.log_failed {
+            crate::core::set_log_failed_edits(true);
+        }
+        if self.diff_format == DiffFormat::Unified || self.context_lines.is_some() {
+            DIFF_CONTEXT_LINES.store(self.context_lines.unwrap_or(3), Ordering::Relaxed);
+        }
+        let resolve_device = |flag: Option<String>| -> crate::llm::DeviceType {
+            flag.or_else(|| config.device.clone()).as_deref().unwrap_or("cpu").into()
+        };
         match self.command {
             Commands::Analyze {
                 paths,
-                format: _fmt,
+                format,
                 recursive,
+                follow_symlinks,
+                schema,
+                stdin,
+                lang,
+                fail_fast,
+                max_depth,
+                tolerant,
             } => {
-                Self::handle_analyze(&paths, &_fmt, recursive)?;
+                if schema {
+                    println!("{}", serde_json::to_string_pretty(&crate::parser::TreeNode::json_schema())?);
+                    return Ok(());
+                }
+                let format = format.or_else(|| config.format.clone()).unwrap_or_else(|| "json".to_string());
+                if stdin || paths.iter().any(|p| p == "-") {
+                    if paths.iter().any(|p| p != "-") {
+                        anyhow::bail!("Reading stdin (`-` or --stdin) cannot be combined with file paths");
+                    }
+                    let Some(lang) = lang else {
+                        anyhow::bail!("Reading from stdin needs --lang, e.g. `analyze - --lang python`");
+                    };
+                    let mut source = String::new();
+                    std::io::Read::read_to_string(&mut std::io::stdin(), &mut source)?;
+                    let mut out = open_output(output.as_deref())?;
+                    Self::print_analysis(&mut out, &[Self::analyze_source(&source, &lang)?], &format)?;
+                } else {
+                    let mut out = open_output(output.as_deref())?;
+                    Self::handle_analyze(
+                        &mut out,
+                        &paths,
+                        &format,
+                        recursive,
+                        follow_symlinks,
+                        max_depth,
+                        fail_fast,
+                        tolerant,
+                    )?;
+                }
             }
             Commands::List {
                 file_path,
                 filter_type,
+                kind,
                 limit,
                 offset,
+                tolerant,
             } => {
-                let writer = GnawTreeWriter::new(&file_path)?;
-                list_nodes(&file_path, writer.analyze(), filter_type.as_deref(), limit, offset);
+                let writer =
+                    if tolerant { GnawTreeWriter::new_tolerant(&file_path)? } else { GnawTreeWriter::new(&file_path)? };
+                let mut out = open_output(output.as_deref())?;
+                list_nodes(&mut out, &file_path, writer.analyze(), filter_type.as_deref(), kind, limit, offset)?;
             }
             Commands::Show {
                 file_path,
                 node_path,
+                with_line_numbers,
+                children,
             } => {
                 let writer = GnawTreeWriter::new(&file_path)?;
-                println!("{}", writer.show_node(&node_path)?);
+                if children {
+                    println!("{}", writer.show_node_members(&node_path, with_line_numbers)?);
+                } else if with_line_numbers {
+                    println!("{}", writer.show_node_with_line_numbers(&node_path)?);
+                } else {
+                    println!("{}", writer.show_node(&node_path)?);
+                }
+            }
+            Commands::DiffNodes { file_a, path_a, file_b, path_b } => {
+                Self::handle_diff_nodes(&mut std::io::stdout(), &file_a, &path_a, &file_b, &path_b)?;
             }
             Commands::Edit {
                 file_path,
@@ -605,7 +1103,11 @@ impl Cli {
                 preview,
                 unescape_newlines,
                 force,
+                no_backup,
+                no_validate,
                 narrative,
+                confirm,
+                strict_type,
             } => {
                 let preview = preview || global_dry_run;
 
@@ -646,8 +1148,14 @@ impl Cli {
                 if preview {
                     let modified = writer.preview_edit(op)?;
                     print_diff(writer.get_source(), &modified);
+                } else if !confirm_operation(&writer, &op, confirm)? {
+                    println!("Change discarded; {} was not modified.", file_path);
                 } else {
-                    writer.edit(op, force)?;
+                    let options = EditOptions {
+                        strict_type,
+                        ..unsafe_edit_options(force, no_backup, no_validate)
+                    };
+                    writer.edit_with_options(op, options)?;
                     Self::show_visual_diff(&writer, &target_path, old_node.as_ref(), narrative.as_deref());
                     show_hint();
                 }
@@ -661,7 +1169,10 @@ impl Cli {
                 source_file,
                 preview,
                 unescape_newlines,
+                no_backup,
+                no_validate,
                 narrative,
+                confirm,
             } => {
                 let preview = preview || global_dry_run;
                 let content = resolve_content(content, source_file, unescape_newlines)?;
@@ -698,17 +1209,51 @@ impl Cli {
                 if preview {
                     let modified = writer.preview_edit(op)?;
                     print_diff(writer.get_source(), &modified);
+                } else if !confirm_operation(&writer, &op, confirm)? {
+                    println!("Change discarded; {} was not modified.", file_path);
                 } else {
-                    writer.edit(op, false)?;
+                    writer.edit_with_options(op, unsafe_edit_options(false, no_backup, no_validate))?;
                     Self::show_visual_pulse(&writer, &insert_parent, narrative.as_deref());
                     show_hint();
                 }
             }
+            Commands::ReplaceLines {
+                file_path,
+                start_line,
+                end_line,
+                content,
+                source_file,
+                preview,
+                unescape_newlines,
+                no_backup,
+                no_validate,
+            } => {
+                let preview = preview || global_dry_run;
+                let content = resolve_content(content, source_file, unescape_newlines)?;
+                let mut writer = GnawTreeWriter::new(&file_path)?;
+                let op = EditOperation::ReplaceLines { start_line, end_line, content };
+                if preview {
+                    let modified = writer.preview_edit(op)?;
+                    print_diff(writer.get_source(), &modified);
+                } else {
+                    writer.edit_with_options(op, unsafe_edit_options(false, no_backup, no_validate))?;
+                    println!("✓ Replaced lines {}-{} in {}", start_line, end_line, file_path);
+                    show_hint();
+                }
+            }
+            Commands::EditBatch { file_path, edits_file, preview, force, no_backup, no_validate } => {
+                let preview = preview || global_dry_run;
+                let options = unsafe_edit_options(force, no_backup, no_validate);
+                Self::handle_edit_batch(&mut std::io::stdout(), &file_path, &edits_file, preview, options)?;
+            }
             Commands::Delete {
                 file_path,
                 node_path,
                 tag,
                 preview,
+                no_backup,
+                no_validate,
+                confirm,
             } => {
                 let preview = preview || global_dry_run;
                 // Resolve target path from --tag flag, 'tag:<name>' positional, or explicit node_path
@@ -741,11 +1286,41 @@ impl Cli {
                 if preview {
                     let modified = writer.preview_edit(op)?;
                     print_diff(writer.get_source(), &modified);
+                } else if !confirm_operation(&writer, &op, confirm)? {
+                    println!("Change discarded; {} was not modified.", file_path);
                 } else {
-                    writer.edit(op, false)?;
+                    writer.edit_with_options(op, unsafe_edit_options(false, no_backup, no_validate))?;
                     show_hint();
                 }
             }
+            Commands::Comment {
+                file_path,
+                node_path,
+                preview,
+            } => {
+                let preview = preview || global_dry_run;
+                let op = EditOperation::Comment { node_path };
+                Self::handle_simple_edit(&file_path, op, preview)?;
+            }
+            Commands::Uncomment {
+                file_path,
+                node_path,
+                preview,
+            } => {
+                let preview = preview || global_dry_run;
+                let op = EditOperation::Uncomment { node_path };
+                Self::handle_simple_edit(&file_path, op, preview)?;
+            }
+            Commands::Reindent {
+                file_path,
+                node_path,
+                delta,
+                preview,
+            } => {
+                let preview = preview || global_dry_run;
+                let op = EditOperation::Reindent { node_path, delta };
+                Self::handle_simple_edit(&file_path, op, preview)?;
+            }
             Commands::AddProperty {
                 file_path,
                 target_path,
@@ -780,24 +1355,7 @@ impl Cli {
             } => {
                 let preview = preview || global_dry_run;
                 let mut writer = GnawTreeWriter::new(&file_path)?;
-                let component_code = match content {
-                    Some(c) => format!(
-                        "{} {{
-    {}
-}}",
-                        name, c
-                    ),
-                    None => format!(
-                        "{} {{}}
-",
-                        name
-                    ),
-                };
-                let op = EditOperation::Insert {
-                    parent_path: target_path.clone(),
-                    position: 1,
-                    content: component_code,
-                };
+                let op = writer.qml_component_insert(&target_path, &name, content.as_deref())?;
                 if preview {
                     let modified = writer.preview_edit(op)?;
                     print_diff(writer.get_source(), &modified);
@@ -813,8 +1371,27 @@ impl Cli {
             Commands::Redo { steps } => {
                 Self::handle_redo(steps)?;
             }
-            Commands::History { limit, format } => {
-                Self::handle_history(limit, &format)?;
+            Commands::History {
+                limit,
+                format,
+                show_diff,
+            } => {
+                let mut out = open_output(output.as_deref())?;
+                Self::handle_history(&mut out, limit, &format, show_diff.as_deref())?;
+            }
+            Commands::Export { format, output: export_output } => {
+                Self::handle_export(&format, export_output.or(output))?;
+            }
+            Commands::ExportRelations { path } => {
+                let mut out = open_output(output.as_deref())?;
+                Self::handle_export_relations(&mut out, &path)?;
+            }
+            Commands::Replay {
+                log_file,
+                strip_prefix,
+                preview,
+            } => {
+                Self::handle_replay(&log_file, strip_prefix, preview || global_dry_run)?;
             }
             Commands::Restore {
                 file_path,
@@ -891,18 +1468,49 @@ impl Cli {
                 Self::handle_session_start(name)?;
             }
             Commands::Mcp { command } => match command {
-                McpSubcommands::Serve { addr, token } => {
+                McpSubcommands::Serve {
+                    addr,
+                    token_file,
+                    token,
+                    max_concurrency,
+                    tool_timeout,
+                    fast_tool_timeout,
+                    no_pulse,
+                    pulse_scope,
+                } => {
                     #[cfg(not(feature = "mcp"))]
                     {
                         let _ = addr;
-                        let _ = token;
+                        let _ = (token_file, token);
+                        let _ = (max_concurrency, tool_timeout, fast_tool_timeout, &config.mcp);
+                        let _ = (no_pulse, pulse_scope);
                         let _ = std::env::var("MCP_TOKEN");
                         anyhow::bail!("MCP feature is not enabled. Recompile with --features mcp");
                     }
                     #[cfg(feature = "mcp")]
                     {
-                        let token = token.or_else(|| std::env::var("MCP_TOKEN").ok());
-                        crate::mcp::mcp_server::serve(&addr, token).await?;
+                        let addr = addr.or_else(|| config.mcp.addr.clone()).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+                        let token = crate::mcp::mcp_server::resolve_token(
+                            token_file.as_deref(),
+                            std::env::var("MCP_TOKEN").ok(),
+                            token.or_else(|| config.mcp.token.clone()),
+                        )?;
+                        let options = crate::mcp::mcp_server::ServerOptions {
+                            max_concurrency,
+                            timeouts: crate::mcp::mcp_server::ToolTimeouts {
+                                ai: std::time::Duration::from_secs(tool_timeout),
+                                tool: std::time::Duration::from_secs(fast_tool_timeout),
+                            },
+                            pulse: crate::mcp::mcp_server::PulseOptions {
+                                enabled: !no_pulse,
+                                scope: if pulse_scope == "project" {
+                                    crate::mcp::mcp_server::PulseScope::Project
+                                } else {
+                                    crate::mcp::mcp_server::PulseScope::Parent
+                                },
+                            },
+                        };
+                        crate::mcp::mcp_server::serve(&addr, token, options).await?;
                     }
                 }
                 McpSubcommands::Stdio => {
@@ -915,18 +1523,43 @@ impl Cli {
                         crate::mcp::mcp_server::serve_stdio().await?;
                     }
                 }
-                McpSubcommands::Status { url, token } => {
+                McpSubcommands::Status { url, token, format } => {
                     #[cfg(not(feature = "mcp"))]
                     {
                         let _ = url;
                         let _ = token;
+                        let _ = format;
                         let _ = std::env::var("MCP_TOKEN");
                         anyhow::bail!("MCP feature is not enabled. Recompile with --features mcp");
                     }
                     #[cfg(feature = "mcp")]
                     {
                         let token = token.or_else(|| std::env::var("MCP_TOKEN").ok());
-                        crate::mcp::mcp_server::status(&url, token).await?;
+                        crate::mcp::mcp_server::status(&url, token, &format).await?;
+                    }
+                }
+                McpSubcommands::ListTools { url, token, format } => {
+                    #[cfg(not(feature = "mcp"))]
+                    {
+                        let _ = (url, token, format);
+                        anyhow::bail!("MCP feature is not enabled. Recompile with --features mcp");
+                    }
+                    #[cfg(feature = "mcp")]
+                    {
+                        let token = token.or_else(|| std::env::var("MCP_TOKEN").ok());
+                        crate::mcp::mcp_server::list_tools(&url, token, &format).await?;
+                    }
+                }
+                McpSubcommands::Call { tool, args, url, token, json_args } => {
+                    #[cfg(not(feature = "mcp"))]
+                    {
+                        let _ = (tool, args, url, token, json_args);
+                        anyhow::bail!("MCP feature is not enabled. Recompile with --features mcp");
+                    }
+                    #[cfg(feature = "mcp")]
+                    {
+                        let token = token.or_else(|| std::env::var("MCP_TOKEN").ok());
+                        crate::mcp::mcp_server::call(&url, token, &tool, &args, json_args.as_deref()).await?;
                     }
                 }
             },
@@ -940,8 +1573,18 @@ impl Cli {
                 paths,
                 format,
                 recursive,
+                follow_symlinks,
+                fail_fast,
+                max_depth,
+            } => {
+                Self::handle_lint(&paths, &format, recursive, follow_symlinks, max_depth, fail_fast)?;
+            }
+            Commands::Watch {
+                path,
+                recursive,
+                debounce_ms,
             } => {
-                Self::handle_lint(&paths, &format, recursive)?;
+                Self::handle_watch(path, recursive, debounce_ms)?;
             }
             Commands::DebugHash { content } => {
                 Self::handle_debug_hash(&content)?;
@@ -949,8 +1592,8 @@ impl Cli {
             Commands::Commands { json } => {
                 Self::handle_commands(json)?;
             }
-            Commands::RestoreProject { timestamp, preview } => {
-                Self::handle_restore_project(&timestamp, preview)?;
+            Commands::RestoreProject { timestamp, preview, show_diffs } => {
+                Self::handle_restore_project(&mut std::io::stdout(), &timestamp, preview, show_diffs)?;
             }
             Commands::RestoreFiles {
                 since,
@@ -987,26 +1630,48 @@ impl Cli {
             Commands::RestoreSession {
                 session_id,
                 preview,
+                since,
+                until,
             } => {
                 let preview = preview || global_dry_run;
-                Self::handle_restore_session(&session_id, preview)?;
+                Self::handle_restore_session(
+                    &mut std::io::stdout(),
+                    &session_id,
+                    preview,
+                    since.as_deref(),
+                    until.as_deref(),
+                )?;
+            }
+            Commands::RestoreLabel { label, preview, force } => {
+                let preview = preview || global_dry_run;
+                Self::handle_restore_label(&mut std::io::stdout(), &label, preview, force)?;
             }
             Commands::Batch { file, preview } => {
                 Self::handle_batch(&file, preview)?;
             }
             Commands::DiffToBatch {
                 diff_file,
-                output,
+                output: batch_output,
                 preview,
             } => {
                 let preview = preview || global_dry_run;
-                Self::handle_diff_to_batch(&diff_file, output.as_deref(), preview)?;
+                let batch_output = batch_output.or(output).map(|p| p.to_string_lossy().into_owned());
+                Self::handle_diff_to_batch(&diff_file, batch_output.as_deref(), preview)?;
+            }
+            Commands::Search { file_path, pattern, filter_type, kind, limit, parents } => {
+                Self::handle_search(&file_path, &pattern, filter_type.as_deref(), kind, limit, parents)?;
             }
-            Commands::Search { file_path, pattern, filter_type, limit } => {
-                Self::handle_search(&file_path, &pattern, filter_type.as_deref(), limit)?;
+            Commands::Skeleton { file_path, depth, signatures } => {
+                Self::handle_skeleton(&file_path, depth, signatures)?;
             }
-            Commands::Skeleton { file_path, depth } => {
-                Self::handle_skeleton(&file_path, depth)?;
+            Commands::Outline { file_path, max_depth } => {
+                let writer = GnawTreeWriter::new(&file_path)?;
+                let entries = crate::parser::outline::outline(writer.analyze(), max_depth);
+                if entries.is_empty() {
+                    println!("No functions, classes or methods found in {}", file_path);
+                } else {
+                    print!("{}", crate::parser::outline::render_outline(&entries));
+                }
             }
             Commands::Status => {
                 Self::handle_health_check().await?;
@@ -1014,8 +1679,17 @@ impl Cli {
             Commands::SemanticReport { file_path } => {
                 Self::handle_semantic_report(&file_path).await?;
             }
-            Commands::Sense { query, file, deep, auto_index } => {
-                Self::handle_sense(&query, file.as_ref().and_then(|p| p.to_str()), deep, auto_index).await?;
+            Commands::Sense { query, file, deep, auto_index, device } => {
+                Self::handle_sense(&query, file.as_ref().and_then(|p| p.to_str()), deep, auto_index, resolve_device(device)).await?;
+            }
+            Commands::Complete { file_path, node_path, top_k, device } => {
+                Self::handle_complete(&file_path, &node_path, top_k, resolve_device(device))?;
+            }
+            Commands::Refactor { file_path, node_path, device } => {
+                Self::handle_refactor(&file_path, node_path.as_deref(), resolve_device(device))?;
+            }
+            Commands::BatchSuggest { file_path, intent, device } => {
+                Self::handle_batch_suggest(&file_path, &intent, resolve_device(device))?;
             }
             Commands::SenseInsert { file, anchor, content, intent, preview } => {
                 Self::handle_sense_insert(file, anchor, content, intent, preview).await?;
@@ -1024,17 +1698,24 @@ impl Cli {
                 Self::handle_scaffold(&file_path, &schema)?;
             }
             Commands::Ai { command } => match command {
-                AiSubcommands::Setup { force } => {
-                    Self::handle_ai_setup(force).await?;
+                AiSubcommands::Setup { force, model } => {
+                    Self::handle_ai_setup(&model, force).await?;
                 }
                 AiSubcommands::Status => {
                     Self::handle_ai_status()?;
                 }
-                AiSubcommands::Index { path } => {
-                    Self::handle_ai_index(path).await?;
+                AiSubcommands::Index { path, clear, rebuild } => {
+                    if clear {
+                        Self::handle_clear_index()?;
+                    } else {
+                        if rebuild {
+                            Self::handle_rebuild_index(path.as_deref())?;
+                        }
+                        Self::handle_ai_index(path).await?;
+                    }
                 }
-                AiSubcommands::Report { limit, output } => {
-                    Self::handle_ai_report(limit, output).await?;
+                AiSubcommands::Report { limit, output: report_output } => {
+                    Self::handle_ai_report(limit, report_output.or(output)).await?;
                 }
             },
                 
@@ -1048,8 +1729,9 @@ Self::handle_version()?;
             Commands::Doctor { format } => {
                 Self::handle_doctor(format.as_deref())?;
             }
-            Commands::Blueprint { output } => {
-                Self::handle_blueprint(output.as_deref())?;
+            Commands::Blueprint { output: blueprint_output } => {
+                let blueprint_output = blueprint_output.or(output).map(|p| p.to_string_lossy().into_owned());
+                Self::handle_blueprint(blueprint_output.as_deref())?;
             }
             Commands::SemanticEdit {
                 file_path,
@@ -1203,7 +1885,7 @@ Self::handle_version()?;
             false
         }
         if !node_exists(writer.analyze(), node_path) {
-            anyhow::bail!("Path '{}' not found in {}", node_path, file_path);
+            return Err(GnawError::node_not_found(node_path, writer.analyze()).into());
         }
 
         mgr.add_tag(file_path, name, node_path, force)?;
@@ -1324,33 +2006,119 @@ Undo/Redo state: {} undo, {} redo available",
         Ok(())
     }
 
-    fn handle_history(limit: usize, format: &str) -> Result<()> {
-        let current_dir = std::env::current_dir()?;
-        let project_root = find_project_root(&current_dir);
-        let transaction_log = TransactionLog::load(project_root)?;
+    /// Preview or apply a single edit operation that needs no extra resolution
+    fn handle_simple_edit(file_path: &str, op: EditOperation, preview: bool) -> Result<()> {
+        let mut writer = GnawTreeWriter::new(file_path)?;
+        if preview {
+            let modified = writer.preview_edit(op)?;
+            print_diff(writer.get_source(), &modified);
+        } else {
+            writer.edit(op, false)?;
+            show_hint();
+        }
+        Ok(())
+    }
+
+    fn handle_export(format: &str, output: Option<PathBuf>) -> Result<()> {
+        use crate::core::transaction_log::ExportFormat;
+
+        let format: ExportFormat = format.parse()?;
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+        let transaction_log = TransactionLog::load(project_root)?;
+        let exported = transaction_log.export_history(format)?;
+
+        let mut out = open_output(output.as_deref())?;
+        writeln!(out, "{}", exported)?;
+        if let Some(path) = output {
+            eprintln!("✓ History exported to: {}", path.display());
+        }
+        Ok(())
+    }
+
+    fn handle_replay(log_file: &std::path::Path, strip_prefix: Option<PathBuf>, preview: bool) -> Result<()> {
+        use crate::core::replay::{load_transactions, replay, ReplayOptions};
+
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+        let transactions = load_transactions(log_file)?;
+        let options = ReplayOptions {
+            project_root,
+            strip_prefix,
+            preview,
+        };
+        let report = replay(&transactions, &options)?;
+
+        let verb = if preview { "Would apply" } else { "Applied" };
+        println!(
+            "{} {} operation(s), skipped {} non-edit transaction(s).",
+            verb, report.applied, report.skipped
+        );
+        if !report.conflicts.is_empty() {
+            println!("⚠️  {} conflict(s):", report.conflicts.len());
+            for c in &report.conflicts {
+                println!("  {} {}: {}", c.transaction_id, c.file_path.display(), c.reason);
+            }
+            anyhow::bail!("Replay finished with conflicts");
+        }
+        Ok(())
+    }
+
+    fn handle_history(out: &mut dyn Write, limit: usize, format: &str, show_diff: Option<&str>) -> Result<()> {
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+        let transaction_log = TransactionLog::load(project_root)?;
+
+        if let Some(transaction_id) = show_diff {
+            let transaction = transaction_log.get_transaction(transaction_id)?;
+            if format == "json" {
+                writeln!(out, "{}", serde_json::to_string_pretty(&transaction)?)?;
+                return Ok(());
+            }
+
+            writeln!(out, "Transaction: {}", transaction.id)?;
+            writeln!(out, 
+                "  {:?} {} ({})",
+                transaction.operation,
+                transaction.file_path.display(),
+                transaction.timestamp.format("%Y-%m-%d %H:%M:%S")
+            )?;
+            writeln!(out, "  {}", transaction.description)?;
+            match &transaction.diff {
+                Some(diff) => print_unified_diff(out, diff)?,
+                None => writeln!(out, "No diff recorded for this transaction")?,
+            }
+            return Ok(());
+        }
 
-        let history = transaction_log.get_last_n_transactions(limit)?;
+        let history = transaction_log.get_last_n_with_failed(limit)?;
 
         match format {
             "json" => {
                 let json = serde_json::to_string_pretty(&history)?;
-                println!("{}", json);
+                writeln!(out, "{}", json)?;
+            }
+            "ndjson" => {
+                for transaction in &history {
+                    writeln!(out, "{}", serde_json::to_string(transaction)?)?;
+                }
             }
             _ => {
                 if history.is_empty() {
-                    println!("No transaction history found");
+                    writeln!(out, "No transaction history found")?;
                     return Ok(());
                 }
 
-                println!(
+                writeln!(out, 
                     "{:<20} {:<10} {:<30} {:<15} Description",
                     "Timestamp", "Operation", "File", "Node Path"
-                );
-                println!("{}", "=".repeat(90));
+                )?;
+                writeln!(out, "{}", "=".repeat(90))?;
 
                 for transaction in history.iter().rev() {
                     let timestamp = transaction.timestamp.format("%m-%d %H:%M:%S").to_string();
-                    let operation = format!("{:?}", transaction.operation);
+                    let failed = transaction.status == TransactionStatus::Failed;
+                    let operation = if failed { "FAILED".to_string() } else { format!("{:?}", transaction.operation) };
                     let file_name = transaction
                         .file_path
                         .file_name()
@@ -1358,10 +2126,15 @@ Undo/Redo state: {} undo, {} redo available",
                         .unwrap_or("unknown");
                     let node_path = transaction.node_path.as_deref().unwrap_or("N/A");
 
-                    println!(
+                    writeln!(out, 
                         "{:<20} {:<10} {:<30} {:<15} {}",
                         timestamp, operation, file_name, node_path, transaction.description
-                    );
+                    )?;
+                    if failed {
+                        if let Some(error) = transaction.metadata.get("error").and_then(|e| e.lines().next()) {
+                            writeln!(out, "{:<20} {}", "", error)?;
+                        }
+                    }
                 }
             }
         }
@@ -1395,14 +2168,10 @@ Use --no-preview to actually perform the restore"
             // Perform the restore using the RestorationEngine
             let engine = RestorationEngine::new(&project_root)?;
 
-            match engine.restore_file_to_transaction(transaction_id) {
-                Ok(restored_path) => {
-                    println!("✓ Restored: {}", restored_path.display());
-                }
-                Err(e) => {
-                    println!("❌ Restore failed: {}", e);
-                }
-            }
+            let restored_path = engine
+                .restore_file_to_transaction(transaction_id)
+                .context("Restore failed")?;
+            println!("✓ Restored: {}", restored_path.display());
         }
 
         Ok(())
@@ -1466,22 +2235,41 @@ Use --no-preview to write batch file"
         Ok(())
     }
 
-    fn handle_search(file_path: &str, pattern: &str, filter_type: Option<&str>, limit: Option<usize>) -> Result<()> {
+    fn handle_search(
+        file_path: &str,
+        pattern: &str,
+        filter_type: Option<&str>,
+        kind: Option<NodeKind>,
+        limit: Option<usize>,
+        parents: bool,
+    ) -> Result<()> {
         let writer = GnawTreeWriter::new(file_path)?;
         let tree = writer.analyze();
+        let of_kind = kind.map(|k| tree.paths_of_kind(k));
         let mut matches = Vec::new();
-
-        fn find(n: &TreeNode, acc: &mut Vec<(String, String, String)>, p: &str, f: Option<&str>) {
-            if n.content.contains(p) && f.is_none_or(|filter| n.node_type == filter) {
+        let mut enclosing: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+
+        let mut ancestors: Vec<&crate::parser::TreeNode> = Vec::new();
+        tree.walk(|n, depth| {
+            ancestors.truncate(depth);
+            if n.content.contains(pattern)
+                && filter_type.is_none_or(|filter| n.node_type == filter)
+                && of_kind.as_ref().is_none_or(|paths| paths.contains(n.path.as_str()))
+            {
                 let name = n.get_name().unwrap_or_else(|| "unnamed".to_string());
-                acc.push((n.path.clone(), n.node_type.clone(), name));
-            }
-            for child in &n.children {
-                find(child, acc, p, f);
+                matches.push((n.path.clone(), n.node_type.clone(), name));
+                let chain: Vec<String> = ancestors
+                    .iter()
+                    .filter(|a| parents && a.kind().is_some())
+                    .map(|a| format!("{} {}", a.node_type, a.get_name().unwrap_or_default()).trim_end().to_string())
+                    .collect();
+                if !chain.is_empty() {
+                    enclosing.insert(n.path.clone(), chain.join(" > "));
+                }
             }
-        }
-
-        find(tree, &mut matches, pattern, filter_type);
+            ancestors.push(n);
+            true
+        });
 
         // Sort by relevance (node types containing "definition" or "item" first)
         matches.sort_by(|a, b| {
@@ -1501,6 +2289,9 @@ Use --no-preview to write batch file"
             println!("Found {} matches in {} (showing {}):", total_found, file_path, matches.len());
             for (path, node_type, name) in &matches {
                 println!("  {} [{}] '{}'", path, node_type, name);
+                if let Some(chain) = enclosing.get(path) {
+                    println!("      in {}", chain);
+                }
             }
 
             if let Some((path, _, _)) = matches.first() {
@@ -1511,31 +2302,61 @@ Use --no-preview to write batch file"
         Ok(())
     }
 
-    fn handle_skeleton(file_path: &str, max_depth: usize) -> Result<()> {
-        let writer = GnawTreeWriter::new(file_path)?;
-        let tree = writer.analyze();
+    fn handle_diff_nodes(out: &mut dyn Write, file_a: &str, path_a: &str, file_b: &str, path_b: &str) -> Result<()> {
+        let a = GnawTreeWriter::new(file_a)?.show_node(path_a)?;
+        let b = GnawTreeWriter::new(file_b)?.show_node(path_b)?;
+        if a == b {
+            writeln!(out, "{}:{} and {}:{} are identical", file_a, path_a, file_b, path_b)?;
+            return Ok(());
+        }
+        let diff = TextDiff::from_lines(&a, &b)
+            .unified_diff()
+            .context_radius(3)
+            .header(&format!("{}:{}", file_a, path_a), &format!("{}:{}", file_b, path_b))
+            .to_string();
+        print_unified_diff(out, &diff)?;
+        Ok(())
+    }
 
-        println!("Skeletal view of {} (max depth {}):", file_path, max_depth);
+    fn handle_export_relations(out: &mut dyn Write, path: &str) -> Result<()> {
+        let target = Path::new(path);
+        let project_root = find_project_root(target);
+        let graph = crate::llm::RelationalIndexer::new(&project_root).relation_graph(target)?;
+        writeln!(out, "{}", serde_json::to_string_pretty(&graph)?)?;
+        Ok(())
+    }
 
-        fn build(n: &TreeNode, d: usize, md: usize, count: &mut usize) {
-            if d > md || *count >= 500 {
-                return;
-            }
-            *count += 1;
-            let indent = "  ".repeat(d);
-            let name = n.get_name().unwrap_or_default();
-            println!("{}{} [{}] {}", indent, n.path, n.node_type, name);
-            if *count == 500 {
-                println!("... (limit of 500 nodes reached)");
-                return;
-            }
-            for child in &n.children {
-                build(child, d + 1, md, count);
-            }
+    fn handle_edit_batch(
+        out: &mut dyn Write,
+        file_path: &str,
+        edits_file: &str,
+        preview: bool,
+        options: EditOptions,
+    ) -> Result<()> {
+        let json = std::fs::read_to_string(edits_file)
+            .with_context(|| format!("Failed to read edits file: {}", edits_file))?;
+        let edits: Vec<NodeEdit> = serde_json::from_str(&json)
+            .with_context(|| format!("{} must be a JSON array of {{\"node_path\", \"content\"}} objects", edits_file))?;
+        let count = edits.len();
+
+        let mut writer = GnawTreeWriter::new(file_path)?;
+        let op = EditOperation::EditBatch { edits };
+        if preview {
+            let modified = writer.preview_edit(op)?;
+            write!(out, "{}", format_diff(writer.get_source(), &modified))?;
+        } else {
+            writer.edit_with_options(op, options)?;
+            writeln!(out, "✓ Applied {} edits to {}", count, file_path)?;
         }
+        Ok(())
+    }
 
-        let mut count = 0;
-        build(tree, 0, max_depth, &mut count);
+    fn handle_skeleton(file_path: &str, max_depth: usize, signatures: bool) -> Result<()> {
+        let writer = GnawTreeWriter::new(file_path)?;
+        let tree = writer.analyze();
+
+        println!("Skeletal view of {} (max depth {}):", file_path, max_depth);
+        print!("{}", crate::parser::outline::skeleton(tree, max_depth, signatures));
         Ok(())
     }
 
@@ -1570,122 +2391,197 @@ Use --no-preview to write batch file"
         Ok(())
     }
 
-    async fn handle_sense(query: &str, file_path: Option<&str>, deep: bool, auto_index: bool) -> Result<()> {
+    fn handle_complete(file_path: &str, node_path: &str, top_k: usize, device: crate::llm::DeviceType) -> Result<()> {
+        #[cfg(feature = "modernbert")]
+        {
+            let current_dir = std::env::current_dir()?;
+            let project_root = find_project_root(&current_dir);
+            let mgr = crate::llm::ai_manager::AiManager::new(&project_root)?.with_device(device);
+
+            let suggestions = mgr.complete_code(file_path, node_path, top_k)?;
+            if std::env::var("GNAW_JSON").is_ok() {
+                println!("{}", serde_json::to_string(&suggestions)?);
+                return Ok(());
+            }
+            println!("Completions for {} in {}:", node_path, file_path);
+            for (i, s) in suggestions.iter().enumerate() {
+                println!("  {}. {} (confidence: {:.2})", i + 1, s.token, s.confidence);
+            }
+        }
+        #[cfg(not(feature = "modernbert"))]
+        {
+            let _ = (file_path, node_path, top_k, device);
+            Self::err_modernbert_disabled()?;
+        }
+        Ok(())
+    }
+
+    fn handle_refactor(file_path: &str, node_path: Option<&str>, device: crate::llm::DeviceType) -> Result<()> {
+        #[cfg(feature = "modernbert")]
+        {
+            let current_dir = std::env::current_dir()?;
+            let project_root = find_project_root(&current_dir);
+            let mgr = crate::llm::ai_manager::AiManager::new(&project_root)?.with_device(device);
+
+            let suggestions = mgr.suggest_refactor(file_path, node_path)?;
+            if std::env::var("GNAW_JSON").is_ok() {
+                println!("{}", serde_json::to_string(&suggestions)?);
+                return Ok(());
+            }
+            if suggestions.is_empty() {
+                println!("No refactoring suggestions for {}", file_path);
+                return Ok(());
+            }
+            println!("Refactoring suggestions for {}:", file_path);
+            for s in &suggestions {
+                println!("  [{}] {} {} (confidence: {:.2})", s.path, s.kind, s.message, s.confidence);
+            }
+        }
+        #[cfg(not(feature = "modernbert"))]
+        {
+            let _ = (file_path, node_path, device);
+            Self::err_modernbert_disabled()?;
+        }
+        Ok(())
+    }
+
+    fn handle_batch_suggest(file_path: &str, intent: &str, device: crate::llm::DeviceType) -> Result<()> {
         #[cfg(feature = "modernbert")]
         {
-            let json_mode = std::env::var("GNAW_JSON").is_ok();
             let current_dir = std::env::current_dir()?;
             let project_root = find_project_root(&current_dir);
+            let mgr = crate::llm::ai_manager::AiManager::new(&project_root)?.with_device(device);
+
+            let suggestions = mgr.suggest_batch_edits(file_path, intent)?;
+            if std::env::var("GNAW_JSON").is_ok() {
+                println!("{}", serde_json::to_string(&suggestions)?);
+                return Ok(());
+            }
+            println!("Nodes in {} matching \"{}\":", file_path, intent);
+            for (i, s) in suggestions.iter().enumerate() {
+                println!("  {}. [{}] (score: {:.2}) {}", i + 1, s.node_path, s.score, s.preview.replace('\n', " "));
+            }
+        }
+        #[cfg(not(feature = "modernbert"))]
+        {
+            let _ = (file_path, intent, device);
+            Self::err_modernbert_disabled()?;
+        }
+        Ok(())
+    }
+
+    async fn handle_sense(query: &str, file_path: Option<&str>, deep: bool, auto_index: bool, device: crate::llm::DeviceType) -> Result<()> {
+        let json_mode = std::env::var("GNAW_JSON").is_ok();
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+
+        // Check if the project has been indexed (for satelite mode).
+        // Without the model, sense falls back to fuzzy name matching instead.
+        #[cfg(not(feature = "modernbert"))]
+        let _ = auto_index;
+        #[cfg(feature = "modernbert")]
+        if file_path.is_none() {
+            let index_mgr = SemanticIndexManager::new(&project_root);
+            let model_info = index_mgr.get_model_info()?;
+            let has_index = if let Some(info) = &model_info {
+                info.dimension > 0
+            } else {
+                false
+            };
 
-            // Check if the project has been indexed (for satelite mode)
-            if file_path.is_none() {
-                let index_mgr = SemanticIndexManager::new(&project_root);
-                let model_info = index_mgr.get_model_info()?;
-                let has_index = if let Some(info) = &model_info {
-                    info.dimension > 0
+            if !has_index {
+                if auto_index {
+                    // Auto-index mode: skip interactive prompt (for AI agents / CI)
+                    println!("🧠 GnawSense requires a project index. Auto-indexing (--auto-index)...");
+                    Self::handle_ai_index(None).await?;
+                    println!();
+                    println!("Now searching for: \"{}\"...", query);
                 } else {
-                    false
-                };
+                    println!("🧠 GnawSense requires a project index for project-wide search.");
+                    println!("📂 No semantic index found for: {}", project_root.display());
+                    println!();
+
+                    // Prompt user
+                    print!("{} ", "Would you like to index this project now? [y/N]".bold());
+                    use std::io::Write;
+                    std::io::stdout().flush()?;
 
-                if !has_index {
-                    if auto_index {
-                        // Auto-index mode: skip interactive prompt (for AI agents / CI)
-                        println!("🧠 GnawSense requires a project index. Auto-indexing (--auto-index)...");
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer)?;
+                    let answer = answer.trim().to_lowercase();
+
+                    if answer == "y" || answer == "yes" {
+                        println!();
                         Self::handle_ai_index(None).await?;
                         println!();
                         println!("Now searching for: \"{}\"...", query);
                     } else {
-                        println!("🧠 GnawSense requires a project index for project-wide search.");
-                        println!("📂 No semantic index found for: {}", project_root.display());
                         println!();
-
-                        // Prompt user
-                        print!("\x1b[1mWould you like to index this project now? [y/N]\x1b[0m ");
-                        use std::io::Write;
-                        std::io::stdout().flush()?;
-
-                        let mut answer = String::new();
-                        std::io::stdin().read_line(&mut answer)?;
-                        let answer = answer.trim().to_lowercase();
-
-                        if answer == "y" || answer == "yes" {
-                            println!();
-                            Self::handle_ai_index(None).await?;
-                            println!();
-                            println!("Now searching for: \"{}\"...", query);
-                        } else {
-                            println!();
-                            println!("💡 Tip: Run 'gnawtreewriter ai index' to build the semantic index, then use 'sense' again.");
-                            println!("   You can also use 'sense --file <path>' for single-file search (no index required).");
-                            return Ok(());
-                        }
+                        println!("💡 Tip: Run 'gnawtreewriter ai index' to build the semantic index, then use 'sense' again.");
+                        println!("   You can also use 'sense --file <path>' for single-file search (no index required).");
+                        return Ok(());
                     }
                 }
             }
+        }
 
-            let broker = GnawSenseBroker::new(&project_root)?;
+        let broker = GnawSenseBroker::new(&project_root)?.with_device(device);
 
-            println!("🧠 GnawSense is thinking about: {}...", query);
-            let response = broker.sense(query, file_path).await?;
+        println!("🧠 GnawSense is thinking about: {}...", query);
+        let response = broker.sense(query, file_path).await?;
 
-            // JSON output for agents
-            if json_mode {
-                println!("{}", serde_json::to_string(&response)?);
-                return Ok(());
-            }
+        // JSON output for agents
+        if json_mode {
+            println!("{}", serde_json::to_string(&response)?);
+            return Ok(());
+        }
 
-            match response {
-                SenseResponse::Satelite { matches } => {
-                    if matches.is_empty() {
-                        println!("\n🛰️ Satelite View: No relevant results found for \"{}\".", query);
-                        println!("The index may be outdated. Try running 'gnawtreewriter ai index' again.");
-                        return Ok(());
-                    }
+        match response {
+            SenseResponse::Satelite { matches } => {
+                if matches.is_empty() {
+                    println!("\n🛰️ Satelite View: No relevant results found for \"{}\".", query);
+                    println!("The index may be outdated. Try running 'gnawtreewriter ai index' again.");
+                    return Ok(());
+                }
 
-                    // Low confidence warning
-                    if matches[0].score < 0.5 {
-                        println!("\n⚠️  Low confidence: Best match has score {:.2} (< 0.5). Results may not be relevant.", matches[0].score);
-                    }
+                // Low confidence warning
+                if matches[0].score < 0.5 {
+                    println!("\n⚠️  Low confidence: Best match has score {:.2} (< 0.5). Results may not be relevant.", matches[0].score);
+                }
 
-                    println!("\n🛰️ Satelite View: I found these relevant areas in the project:");
-                    for (i, m) in matches.iter().enumerate() {
-                        println!("  {}. {} (score: {:.2})", i + 1, m.file_path, m.score);
-                    }
-                    println!("\nTip: Use `gnawtreewriter sense \"{}\" --file {}` to zoom in.", query, matches[0].file_path);
+                println!("\n🛰️ Satelite View: I found these relevant areas in the project:");
+                for (i, m) in matches.iter().enumerate() {
+                    println!("  {}. {} (score: {:.2})", i + 1, m.file_path, m.score);
+                }
+                println!("\nTip: Use `gnawtreewriter sense \"{}\" --file {}` to zoom in.", query, matches[0].file_path);
+            }
+            SenseResponse::Zoom { file_path, nodes, impact } => {
+                if nodes.is_empty() {
+                    println!("\n🔍 Zoom View: No relevant nodes found in {} for \"{}\".", file_path, query);
+                    return Ok(());
                 }
-                SenseResponse::Zoom { file_path, nodes, impact } => {
-                    if nodes.is_empty() {
-                        println!("\n🔍 Zoom View: No relevant nodes found in {} for \"{}\".", file_path, query);
-                        return Ok(());
-                    }
 
-                    // Low confidence warning
-                    if nodes[0].score < 0.5 {
-                        println!("\n⚠️  Low confidence: Best match has score {:.2} (< 0.5). Results may not be relevant.", nodes[0].score);
-                    }
+                // Low confidence warning
+                if nodes[0].score < 0.5 {
+                    println!("\n⚠️  Low confidence: Best match has score {:.2} (< 0.5). Results may not be relevant.", nodes[0].score);
+                }
 
-                    println!("\n🔍 Zoom View: Relevant nodes in {}:", file_path);
-                    for (i, n) in nodes.iter().enumerate() {
-                        println!("  {}. [{}] (score: {:.2})", i + 1, n.path, n.score);
-                        if deep || i == 0 {
-                            println!("     \"{}\"", n.preview.replace("\n", " "));
-                        }
+                println!("\n🔍 Zoom View: Relevant nodes in {}:", file_path);
+                for (i, n) in nodes.iter().enumerate() {
+                    println!("  {}. [{}] (score: {:.2})", i + 1, n.path, n.score);
+                    if deep || i == 0 {
+                        println!("     \"{}\"", n.preview.replace("\n", " "));
                     }
+                }
 
-                    if let Some(matches) = impact {
-                        println!("\n⚠️  Impact Alert: This logic appears to be used in:");
-                        for m in matches {
-                            println!("  🔗 {} (node path: {})", m.file_path, m.node_path);
-                        }
+                if let Some(matches) = impact {
+                    println!("\n⚠️  Impact Alert: This logic appears to be used in:");
+                    for m in matches {
+                        println!("  🔗 {} (node path: {})", m.file_path, m.node_path);
                     }
                 }
             }
         }
-        #[cfg(not(feature = "modernbert"))]
-        {
-            let _ = (query, file_path, deep);
-            Self::err_modernbert_disabled()?;
-        }
         Ok(())
     }
 
@@ -1785,6 +2681,10 @@ Use --no-preview to write batch file"
         Ok(())
     }
 
+    /// Rewrite the node that best matches `query`: found by the model when it
+    /// is available, otherwise by name, and then only for a confident match
+    /// (see `heuristics::edit_target`). The MCP `semantic_edit` tool does the
+    /// same.
     async fn handle_semantic_edit(
         file_path: &str,
         query: &str,
@@ -1793,43 +2693,48 @@ Use --no-preview to write batch file"
         narrative: Option<String>,
         force: bool,
     ) -> Result<()> {
+        println!("🧠 GnawSense is searching for: \"{}\" in {}...", query, file_path);
+
         #[cfg(feature = "modernbert")]
-        {
+        let target = {
             let current_dir = std::env::current_dir()?;
             let project_root = find_project_root(&current_dir);
-            let broker = GnawSenseBroker::new(&project_root)?;
-            
-            println!("🧠 GnawSense is searching for: \"{}\" in {}...", query, file_path);
-            let response = broker.sense(query, Some(file_path)).await?;
-
-            if let SenseResponse::Zoom { nodes, .. } = response {
-                if let Some(best_node) = nodes.first() {
-                    println!("📍 Found best match at node path: {} (score: {:.2})", best_node.path, best_node.score);
-                    
-                    let content = resolve_content(content, source_file, false)?;
-                    let mut writer = GnawTreeWriter::new(file_path)?;
-                    
-                    // Capture old state for visual diff
-                    let old_node = writer.analyze().find_path(&best_node.path).cloned();
-
-                    let op = EditOperation::Edit {
-                        node_path: best_node.path.clone(),
-                        content,
-                    };
-                    
-                    writer.edit(op, force)?;
-                    Self::show_visual_diff(&writer, &best_node.path, old_node.as_ref(), narrative.as_deref());
-                    println!("✓ Successfully edited node: {}", best_node.path);
-                    return Ok(());
-                }
+            match GnawSenseBroker::new(&project_root) {
+                Ok(broker) => match broker.sense_with_model(query, Some(file_path)).await {
+                    Ok(SenseResponse::Zoom { nodes, .. }) => nodes.into_iter().next().map(|best| (best, "model")),
+                    _ => None,
+                },
+                Err(_) => None,
             }
-            anyhow::bail!("Could not find a semantic match for '{}' in {}", query, file_path);
-        }
+        };
         #[cfg(not(feature = "modernbert"))]
-        {
-            let _ = (file_path, query, content, source_file, narrative, force);
-            Self::err_modernbert_disabled()?;
-        }
+        let target: Option<(crate::llm::NodeMatch, &str)> = None;
+
+        let mut writer = GnawTreeWriter::new(file_path)?;
+        let (best_node, mode) = match target {
+            Some(target) => target,
+            None => (
+                crate::llm::heuristics::edit_target(query, writer.analyze())
+                    .map_err(|msg| anyhow::anyhow!("{} in {}", msg, file_path))?,
+                "heuristic",
+            ),
+        };
+        println!("📍 Found best match at node path: {} (score: {:.2}, {})", best_node.path, best_node.score, mode);
+
+        let content = resolve_content(content, source_file, false)?;
+
+        // Capture old state for visual diff
+        let old_node = writer.analyze().find_path(&best_node.path).cloned();
+
+        let op = EditOperation::Edit {
+            node_path: best_node.path.clone(),
+            content,
+        };
+
+        writer.edit(op, force)?;
+        Self::show_visual_diff(&writer, &best_node.path, old_node.as_ref(), narrative.as_deref());
+        println!("✓ Successfully edited node: {}", best_node.path);
+        Ok(())
     }
 
     /// gnaw-find: Search AST nodes across project files
@@ -2071,20 +2976,22 @@ Use --no-preview to write batch file"
         Ok(())
     }
 
-    async fn handle_ai_setup(force: bool) -> Result<()> {
+    async fn handle_ai_setup(model: &str, force: bool) -> Result<()> {
         let current_dir = std::env::current_dir()?;
         let project_root = find_project_root(&current_dir);
         let mgr = crate::llm::ai_manager::AiManager::new(&project_root)?;
+        let model: crate::llm::ai_manager::AiModel = model.parse()?;
         
-        println!("🚀 Setting up AI models in {}...", project_root.display());
-        if let Err(e) = mgr.setup(crate::llm::ai_manager::AiModel::ModernBert, crate::llm::ai_manager::DeviceType::Cpu, force).await {
+        println!("🚀 Setting up {} in {}...", model.repo_id(), project_root.display());
+        if let Err(e) = mgr.setup(model.clone(), crate::llm::ai_manager::DeviceType::Cpu, force).await {
+            let dir = format!(".gnawtreewriter_ai/models/{}", model.cache_dir_name());
             println!("\n⚠️  {}", "Automatic setup failed.".bold().red());
             println!("Error: {}", e);
             println!("\n💡 [The Helpful Guard]: You can download the model manually using these commands:");
-            println!("   mkdir -p .gnawtreewriter_ai/models/modernbert");
-            println!("   curl -L https://huggingface.co/answerdotai/ModernBERT-base/resolve/main/config.json -o .gnawtreewriter_ai/models/modernbert/config.json");
-            println!("   curl -L https://huggingface.co/answerdotai/ModernBERT-base/resolve/main/tokenizer.json -o .gnawtreewriter_ai/models/modernbert/tokenizer.json");
-            println!("   curl -L https://huggingface.co/answerdotai/ModernBERT-base/resolve/main/model.safetensors -o .gnawtreewriter_ai/models/modernbert/model.safetensors");
+            println!("   mkdir -p {}", dir);
+            for file in crate::llm::ai_manager::MODEL_FILES {
+                println!("   curl -L https://huggingface.co/{}/resolve/main/{} -o {}/{}", model.repo_id(), file, dir, file);
+            }
             return Err(e);
         }
         println!("✨ AI models setup successfully.");
@@ -2099,13 +3006,53 @@ Use --no-preview to write batch file"
         
         println!("\n🧠 GnawTreeWriter AI Status");
         println!("===========================");
-        println!("ModernBERT: {}", if status.modern_bert_installed { "✅ Installed".green() } else { "❌ Not found (run 'ai setup')".red() });
+        println!("Model:      {}", status.model.repo_id());
+        println!("Installed:  {}", if status.modern_bert_installed { "✅ Yes".green() } else { "❌ Not found (run 'ai setup')".red() });
         println!("Cache Dir:  {}", status.cache_dir.display());
-        println!("Device:     CPU");
+        let devices: Vec<String> = status.available_devices.iter().map(|d| format!("{:?}", d)).collect();
+        println!("Devices:    {}", devices.join(", "));
         println!();
         Ok(())
     }
 
+    /// Remove the semantic and relational indexes of the current project
+    fn handle_clear_index() -> Result<()> {
+        let project_root = find_project_root(&std::env::current_dir()?);
+        let dirs = [
+            crate::llm::SemanticIndexManager::storage_path(&project_root),
+            crate::llm::RelationalIndexer::storage_path(&project_root),
+        ];
+        let mut removed = 0;
+        for dir in dirs.iter().filter(|d| d.exists()) {
+            std::fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+            println!("🗑️  Removed {}", dir.display());
+            removed += 1;
+        }
+        if removed == 0 {
+            println!("No index to clear.");
+        }
+        Ok(())
+    }
+
+    /// Drop the embeddings of the files under `path` (the whole project if
+    /// not given) so `ai index` embeds them again. Nothing is deleted in a
+    /// build that could not re-index them.
+    fn handle_rebuild_index(path: Option<&Path>) -> Result<()> {
+        if !cfg!(feature = "modernbert") {
+            return Self::err_modernbert_disabled();
+        }
+        let current_dir = std::env::current_dir()?;
+        let project_root = std::fs::canonicalize(find_project_root(&current_dir))?;
+        let target = match path {
+            Some(p) => std::fs::canonicalize(p).with_context(|| format!("Cannot index {}", p.display()))?,
+            None => project_root.clone(),
+        };
+        let manager = crate::llm::SemanticIndexManager::new(&project_root);
+        let removed = manager.remove_under(&project_root, &target)?;
+        println!("🗑️  Dropped the embeddings of {} files under {}", removed, target.display());
+        Ok(())
+    }
+
     async fn handle_ai_index(path: Option<PathBuf>) -> Result<()> {
         #[cfg(feature = "modernbert")]
         {
@@ -2173,7 +3120,7 @@ Use --no-preview to write batch file"
 
     
 
-        fn handle_restore_project(timestamp: &str, preview: bool) -> Result<()> {
+    fn handle_restore_project(out: &mut dyn Write, timestamp: &str, preview: bool, show_diffs: bool) -> Result<()> {
         let current_dir = std::env::current_dir()?;
         let project_root = find_project_root(&current_dir);
         let transaction_log = TransactionLog::load(project_root.clone())?;
@@ -2184,37 +3131,51 @@ Use --no-preview to write batch file"
         let plan = transaction_log.get_project_restoration_plan(restore_to)?;
 
         if !plan.has_changes() {
-            println!(
+            writeln!(
+                out,
                 "No changes found since {}",
                 restore_to.format("%Y-%m-%d %H:%M:%S UTC")
-            );
+            )?;
             return Ok(());
         }
 
         if preview {
-            println!("Project Restoration Plan:");
-            println!("=========================");
-            println!("{}", plan.get_summary());
-            println!(
-                "
-Files to be restored:"
-            );
+            writeln!(out, "Project Restoration Plan:")?;
+            writeln!(out, "=========================")?;
+            writeln!(out, "{}", plan.get_summary())?;
+            writeln!(out, "\nFiles to be restored:")?;
             for file_plan in &plan.affected_files {
-                println!(
+                writeln!(
+                    out,
                     "  {} ({} modifications since {})",
                     file_plan.file_path.display(),
                     file_plan.current_modifications_count,
                     restore_to.format("%Y-%m-%d %H:%M:%S")
-                );
+                )?;
             }
-            println!(
-                "
-Use --no-preview to perform the restoration"
-            );
+            if show_diffs {
+                let engine = RestorationEngine::new(&project_root)?;
+                for file_plan in &plan.affected_files {
+                    writeln!(out, "\n{}", file_plan.file_path.display().to_string().bold())?;
+                    match engine.restoration_target(&file_plan.target_transaction_id) {
+                        Ok((path, target)) => {
+                            let current = std::fs::read_to_string(&path).unwrap_or_default();
+                            if current == target {
+                                writeln!(out, "  (already matches the restoration target)")?;
+                            } else {
+                                write!(out, "{}", format_diff(&current, &target))?;
+                            }
+                        }
+                        Err(e) => writeln!(out, "  Cannot show diff: {}", e)?,
+                    }
+                }
+            }
+            writeln!(out, "\nUse --no-preview to perform the restoration")?;
         } else {
             let engine = RestorationEngine::new(&project_root)?;
             let result = engine.execute_project_restoration(&plan)?;
             result.print_summary();
+            check_restoration(&result)?;
         }
 
         Ok(())
@@ -2280,58 +3241,112 @@ Use --no-preview to perform the restoration"
             let engine = RestorationEngine::new(&project_root)?;
             let result = engine.restore_files_before_timestamp(&filtered_files, since_time)?;
             result.print_summary();
+            check_restoration(&result)?;
         }
 
         Ok(())
     }
 
-    fn handle_restore_session(session_id: &str, preview: bool) -> Result<()> {
+    fn handle_restore_session(
+        out: &mut dyn Write,
+        session_id: &str,
+        preview: bool,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<()> {
         let current_dir = std::env::current_dir()?;
         let project_root = find_project_root(&current_dir);
         let transaction_log = TransactionLog::load(&project_root)?;
 
         // ALIAS LOOKUP: Check if the session_id is actually a human-readable alias
-        let alias_file = project_root.join(".gnawtreewriter_aliases.json");
-        let actual_id = if alias_file.exists() {
-            let data = std::fs::read_to_string(alias_file)?;
-            let aliases: std::collections::HashMap<String, String> = serde_json::from_str(&data).unwrap_or_default();
-            aliases.get(session_id).cloned().unwrap_or_else(|| session_id.to_string())
-        } else {
-            session_id.to_string()
-        };
+        let actual_id = transaction_log.resolve_session_alias(session_id);
 
         if actual_id != session_id {
-            println!("🔍 Alias found: '{}' -> {}", session_id, actual_id);
+            writeln!(out, "🔍 Alias found: '{}' -> {}", session_id, actual_id)?;
         }
 
         if preview {
-             println!("Would restore session {}...", actual_id);
-             let files = transaction_log.get_session_files(&actual_id)?;
-             if files.is_empty() {
-                 println!("No files affected in this session.");
-             } else {
-                 println!("Files to be restored:");
-                 for f in files {
-                     println!(" - {}", f.display());
-                 }
-             }
-             println!("\nUse --no-preview to perform restoration.");
-             return Ok(());
+            let start = since.map(parse_user_timestamp).transpose()?.unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+            let end = until.map(parse_user_timestamp).transpose()?.unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+
+            let in_session = transaction_log
+                .get_history_range(start, end)?
+                .into_iter()
+                .filter(|t| t.session_id == actual_id)
+                .collect();
+            let files = crate::core::edits_by_file(in_session);
+
+            writeln!(out, "Would restore session {}...", actual_id)?;
+            if files.is_empty() {
+                writeln!(out, "No files affected in this session.")?;
+            } else {
+                writeln!(out, "Files to be restored:")?;
+                for (path, mods) in &files {
+                    writeln!(out, " - {} ({} modifications)", path.display(), mods.len())?;
+                    for t in mods {
+                        writeln!(
+                            out,
+                            "     {} {:?}{}",
+                            t.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                            t.operation,
+                            t.node_path.as_deref().map(|p| format!(" {}", p)).unwrap_or_default()
+                        )?;
+                    }
+                }
+            }
+            writeln!(out, "\nUse --no-preview to perform restoration.")?;
+            return Ok(());
         }
 
         let restoration_engine = RestorationEngine::new(&project_root)?;
         let result = restoration_engine.restore_session(&actual_id)?;
 
         result.print_summary();
-        Ok(())
+        check_restoration(&result)
     }
 
-    fn handle_debug_hash(content: &str) -> Result<()> {
-        use crate::core::calculate_content_hash;
+    fn handle_restore_label(out: &mut dyn Write, label: &str, preview: bool, force: bool) -> Result<()> {
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+        let restoration_engine = RestorationEngine::new(&project_root)?;
 
-        let hash = calculate_content_hash(content);
-        println!("Content: {:?}", content);
-        println!("Hash: {}", hash);
+        if preview {
+            let transaction_log = TransactionLog::load(&project_root)?;
+            let files = crate::core::edits_by_file(transaction_log.get_label_history(label)?);
+
+            writeln!(out, "Would restore label '{}'...", label)?;
+            if files.is_empty() {
+                writeln!(out, "No files changed under this label.")?;
+            } else {
+                writeln!(out, "Files to be restored:")?;
+                for (path, mods) in &files {
+                    writeln!(out, " - {} ({} modifications)", path.display(), mods.len())?;
+                }
+            }
+            let conflicts = restoration_engine.label_conflicts(label)?;
+            if !conflicts.is_empty() {
+                writeln!(out, "Would also discard {} later edits made outside the label:", conflicts.len())?;
+                for t in &conflicts {
+                    writeln!(out, " - {} ({})", t.file_path.display(), t.id)?;
+                }
+                writeln!(out, "Pass --force to restore anyway.")?;
+            }
+            writeln!(out, "\nUse --no-preview to perform restoration.")?;
+            return Ok(());
+        }
+
+        let result = restoration_engine.restore_label(label, force)?;
+
+        result.print_summary();
+        check_restoration(&result)
+    }
+
+    fn handle_debug_hash(content: &str) -> Result<()> {
+        use crate::core::calculate_content_hash;
+
+        let hash = calculate_content_hash(content);
+        println!("Content: {:?}", content);
+        println!("Hash: {}", hash);
 
         // Also test with file content if it exists
         if std::path::Path::new(content).exists() {
@@ -2349,17 +3364,26 @@ Use --no-preview to perform the restoration"
             {"name": "analyze", "tool": "gtw_analyze", "write": false, "desc": "Parse file and show AST tree structure"},
             {"name": "list", "tool": "gtw_list", "write": false, "desc": "List all tree nodes, optionally filtered by type"},
             {"name": "show", "tool": "gtw_show", "write": false, "desc": "Show content of a specific node"},
+            {"name": "diff-nodes", "tool": "gtw_diff_nodes", "write": false, "desc": "Diff a node against a node in another file"},
             {"name": "skeleton", "tool": "gtw_skeleton", "write": false, "desc": "High-level skeletal view of file structure"},
             {"name": "search", "tool": "gtw_search", "write": false, "desc": "Search nodes by text or name pattern"},
             {"name": "nodes", "tool": "gtw_nodes", "write": false, "desc": "List AST nodes with paths and types"},
             {"name": "tag", "tool": "gtw_tag", "write": false, "desc": "Manage named references to AST nodes"},
             {"name": "history", "tool": "gtw_history", "write": false, "desc": "Show transaction history"},
+            {"name": "export", "tool": "gtw_export", "write": false, "desc": "Export the full transaction history as JSON or CSV"},
+            {"name": "export-relations", "tool": "gtw_export_relations", "write": false, "desc": "Export the call graph of a file or directory as JSON"},
+            {"name": "replay", "tool": "gtw_replay", "write": true, "desc": "Re-apply the edits of an exported transaction log"},
             {"name": "status", "tool": "gtw_status", "write": false, "desc": "Health check of the system"},
             {"name": "doctor", "tool": "gtw_doctor", "write": false, "desc": "Test all parsers and validate backups"},
             {"name": "lint", "tool": "gtw_lint", "write": false, "desc": "Find issues in files"},
+            {"name": "watch", "tool": "gtw_watch", "write": false, "desc": "Re-index changed files and report changed symbols"},
             {"name": "edit", "tool": "gtw_edit", "write": true, "desc": "Replace content of a specific node"},
             {"name": "insert", "tool": "gtw_insert", "write": true, "desc": "Insert new content into a parent node"},
+            {"name": "replace-lines", "tool": "gtw_replace_lines", "write": true, "desc": "Replace an inclusive line range"},
+            {"name": "edit-batch", "tool": "gtw_edit_batch", "write": true, "desc": "Replace several nodes of one file in a single write"},
             {"name": "delete", "tool": "gtw_delete", "write": true, "desc": "Delete a node"},
+            {"name": "comment", "tool": "gtw_comment", "write": true, "desc": "Comment out a node in the file's comment syntax"},
+            {"name": "uncomment", "tool": "gtw_uncomment", "write": true, "desc": "Remove comment markers from a node"},
             {"name": "rename", "tool": "gtw_rename", "write": true, "desc": "AST-aware symbol renaming"},
             {"name": "clone", "tool": "gtw_clone", "write": true, "desc": "Clone code structures"},
             {"name": "quick-replace", "tool": "gtw_quick_replace", "write": true, "desc": "Simple text-based search and replace"},
@@ -2371,9 +3395,13 @@ Use --no-preview to perform the restoration"
             {"name": "restore", "tool": "gtw_restore", "write": true, "desc": "Restore file to a specific transaction"},
             {"name": "restore-project", "tool": "gtw_restore_project", "write": true, "desc": "Restore entire project to a point"},
             {"name": "restore-session", "tool": "gtw_restore_session", "write": true, "desc": "Undo all changes from a session"},
+            {"name": "restore-label", "tool": "gtw_restore_label", "write": true, "desc": "Undo all changes made under a --label"},
             {"name": "session-start", "tool": "gtw_session_start", "write": false, "desc": "Start a new editing session"},
             {"name": "mcp", "tool": "gtw_mcp", "write": false, "desc": "Manage MCP server"},
-            {"name": "semantic-report", "tool": "gtw_semantic_report", "write": false, "desc": "Generate semantic code quality report"}
+            {"name": "semantic-report", "tool": "gtw_semantic_report", "write": false, "desc": "Generate semantic code quality report"},
+            {"name": "complete", "tool": "gtw_complete", "write": false, "desc": "Suggest tokens for a [MASK] marker in a node"},
+            {"name": "refactor", "tool": "gtw_refactor", "write": false, "desc": "Suggest refactorings for a file or node"},
+            {"name": "batch-suggest", "tool": "gtw_batch_suggest", "write": false, "desc": "Rank nodes a batch edit with an intent should touch"}
         ]);
 
         if json {
@@ -3004,7 +4032,39 @@ Use --no-preview to perform the restoration"
         eprintln!("{}", viz.render_with_diff(writer.analyze(), focus_path, old_node));
     }
 
-    fn handle_analyze(paths: &[String], format: &str, recursive: bool) -> Result<()> {
+    /// Analyze each file, skipping (with a warning) those that fail, or
+    /// returning the first failure with `fail_fast`. With `tolerant`, files
+    /// with syntax errors give a partial tree instead of failing.
+    fn analyze_files(files: &[String], fail_fast: bool, tolerant: bool) -> Result<Vec<serde_json::Value>> {
+        let mut results = Vec::new();
+        for file_path in files {
+            let writer = if tolerant { GnawTreeWriter::new_tolerant(file_path) } else { GnawTreeWriter::new(file_path) };
+            match writer {
+                Ok(writer) => {
+                    results.push(serde_json::json!({ "file_path": file_path, "tree": writer.analyze() }));
+                }
+                Err(e) if fail_fast => {
+                    return Err(e.context(format!("Failed to analyze {} (--fail-fast)", file_path)));
+                }
+                Err(e) => {
+                    eprintln!("Warning: Failed to analyze {}: {}", file_path, e);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_analyze(
+        out: &mut dyn Write,
+        paths: &[String],
+        format: &str,
+        recursive: bool,
+        follow_symlinks: bool,
+        max_depth: Option<usize>,
+        fail_fast: bool,
+        tolerant: bool,
+    ) -> Result<()> {
         let mut all_files = Vec::new();
 
         for path in paths {
@@ -3012,7 +4072,7 @@ Use --no-preview to perform the restoration"
             if path_buf.is_dir() {
                 if recursive {
                     // Recursively find supported files
-                    all_files.extend(Self::find_supported_files(&path_buf)?);
+                    all_files.extend(Self::find_supported_files(&path_buf, follow_symlinks, max_depth)?);
                 } else {
                     return Err(anyhow::anyhow!(
                         "Directory '{}' requires --recursive flag for safety.
@@ -3024,45 +4084,49 @@ To analyze specific files: gnawtreewriter analyze {}/*.ext",
                         path
                     ));
                 }
+            } else if !path_buf.exists() {
+                anyhow::bail!("File not found: {}", path);
             } else {
                 all_files.push(path.clone());
             }
         }
 
         if all_files.is_empty() {
-            println!("No supported files found to analyze.");
+            writeln!(out, "No supported files found to analyze.")?;
             return Ok(());
         }
 
-        let mut results = Vec::new();
-        for file_path in &all_files {
-            match GnawTreeWriter::new(file_path) {
-                Ok(writer) => {
-                    let tree = writer.analyze();
-                    results.push(serde_json::to_value(tree)?);
-                }
-                Err(e) => {
-                    eprintln!("Warning: Failed to analyze {}: {}", file_path, e);
-                }
-            }
-        }
+        Self::print_analysis(out, &Self::analyze_files(&all_files, fail_fast, tolerant)?, format)
+    }
+
+    /// Parse in-memory `source` as `lang`; the result's `file_path` is `-`
+    fn analyze_source(source: &str, lang: &str) -> Result<serde_json::Value> {
+        let hint = format!("stdin.{}", crate::parser::extension_for_language(lang)?);
+        let writer = GnawTreeWriter::from_source(&hint, source.to_string())?;
+        Ok(serde_json::json!({ "file_path": "-", "tree": writer.analyze() }))
+    }
 
+    fn print_analysis(out: &mut dyn Write, results: &[serde_json::Value], format: &str) -> Result<()> {
         match format {
             "summary" => {
-                println!("Analyzed {} files", results.len());
-                for (i, result) in results.iter().enumerate() {
-                    if let Some(file_path) = all_files.get(i) {
-                        println!("File: {}", file_path);
-                        if let Some(children) = result.get("children") {
-                            if let Some(array) = children.as_array() {
-                                println!("  Nodes: {}", array.len());
-                            }
-                        }
+                writeln!(out, "Analyzed {} files", results.len())?;
+                for result in results {
+                    writeln!(out, "File: {}", result["file_path"].as_str().unwrap_or_default())?;
+                    if let Some(array) = result["tree"]["children"].as_array() {
+                        writeln!(out, "  Nodes: {}", array.len())?;
                     }
                 }
             }
+            "dot" => {
+                let trees = results
+                    .iter()
+                    .map(|r| Ok((r["file_path"].as_str().unwrap_or_default(), serde_json::from_value(r["tree"].clone())?)))
+                    .collect::<Result<Vec<(&str, TreeNode)>>>()?;
+                let trees: Vec<(&str, &TreeNode)> = trees.iter().map(|(f, t)| (*f, t)).collect();
+                write!(out, "{}", crate::parser::outline::dot(&trees))?;
+            }
             _ => {
-                println!("{}", serde_json::to_string_pretty(&results)?);
+                writeln!(out, "{}", serde_json::to_string_pretty(results)?)?;
             }
         }
         Ok(())
@@ -3119,38 +4183,79 @@ To analyze specific files: gnawtreewriter analyze {}/*.ext",
             let after_hash = crate::core::calculate_content_hash(&modified);
             let mut tlog = TransactionLog::load(&project_root)?;
             let txid = tlog.log_transaction(OperationType::Insert, PathBuf::from(file), None, Some(before_hash), Some(after_hash), format!("QuickInsert: {} insertion(s) after /{}/", insertions, after), std::collections::HashMap::new())?;
-            std::fs::write(path, &modified).map_err(|e| anyhow::anyhow!("Failed to write {}: {}", file, e))?;
+            write_source_file(path, modified.as_bytes())
+                .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", file, e))?;
             println!("✓ QuickInsert applied: {} insertion(s) (txn {})", insertions, txid);
             Ok(())
         }
 
-    fn find_supported_files(dir: &std::path::Path) -> Result<Vec<String>> {
+    /// Recursively collect files with a supported extension under `dir`.
+    ///
+    /// Symlinks are skipped unless `follow_symlinks` is set; when they are
+    /// followed, canonical directory paths are tracked so cycles terminate.
+    /// `max_depth` limits how many directory levels are read, counting `dir`
+    /// itself as level 1.
+    fn find_supported_files(
+        dir: &std::path::Path,
+        follow_symlinks: bool,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<String>> {
         let mut files = Vec::new();
+        let mut visited = std::collections::HashSet::new();
+        let ignore = GnawIgnore::load(&find_project_root(dir));
+        let levels = max_depth.unwrap_or(usize::MAX);
+        Self::collect_supported_files(dir, follow_symlinks, levels, &ignore, &mut visited, &mut files)?;
+        Ok(files)
+    }
+
+    fn collect_supported_files(
+        dir: &std::path::Path,
+        follow_symlinks: bool,
+        levels: usize,
+        ignore: &GnawIgnore,
+        visited: &mut std::collections::HashSet<PathBuf>,
+        files: &mut Vec<String>,
+    ) -> Result<()> {
         let supported_extensions = vec![
             "py", "rs", "ts", "tsx", "js", "jsx", "php", "html", "htm", "qml", "go", "toml",
             "json", "yaml", "yml", "css", "md", "markdown", "txt", "xml", "svg", "xsl", "xsd",
-            "rss", "atom",
+            "rss", "atom", "rb", "sh", "bash", "c", "h", "cpp", "hpp", "cc", "hh", "cxx", "hxx",
         ];
 
-        if dir.is_dir() {
-            for entry in std::fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-
-                if path.is_dir() {
-                    files.extend(Self::find_supported_files(&path)?);
-                } else if let Some(ext) = path.extension() {
-                    if let Some(ext_str) = ext.to_str() {
-                        if supported_extensions.contains(&ext_str) {
-                            if let Some(path_str) = path.to_str() {
-                                files.push(path_str.to_string());
-                            }
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let canonical = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        if !visited.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if entry.file_type()?.is_symlink() && !follow_symlinks {
+                continue;
+            }
+            if ignore.is_ignored(&canonical.join(entry.file_name()), path.is_dir()) {
+                continue;
+            }
+
+            if path.is_dir() {
+                if levels > 1 {
+                    Self::collect_supported_files(&path, follow_symlinks, levels - 1, ignore, visited, files)?;
+                }
+            } else if let Some(ext) = path.extension() {
+                if let Some(ext_str) = ext.to_str() {
+                    if supported_extensions.contains(&ext_str) {
+                        if let Some(path_str) = path.to_str() {
+                            files.push(path_str.to_string());
                         }
                     }
                 }
             }
         }
-        Ok(files)
+        Ok(())
     }
 
     fn handle_quick_replace(
@@ -3231,7 +4336,7 @@ Use --no-preview to actually apply the change."
             std::collections::HashMap::new(),
         )?;
 
-        std::fs::write(path, modified)
+        write_source_file(path, modified.as_bytes())
             .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", file, e))?;
 
         println!("✓ QuickReplace applied (txn {})", txid);
@@ -3273,7 +4378,7 @@ Use --no-preview to actually apply the change."
 
         // Find the source node to clone
         let source_node = Self::find_node_by_path(&source_tree, source_path)
-            .ok_or_else(|| anyhow::anyhow!("Source node not found at path: {}", source_path))?;
+            .ok_or_else(|| GnawError::node_not_found(source_path, &source_tree))?;
 
         println!("🔄 Cloning node from {} [{}]", source_file, source_path);
         println!("  Node type: {}", source_node.node_type);
@@ -3351,7 +4456,7 @@ Use without --preview to apply the clone"
             .with_context(|| format!("Failed to parse source file: {}", source_file))?;
 
         let source_node = Self::find_node_by_path(&source_tree, source_path)
-            .ok_or_else(|| anyhow::anyhow!("Source node not found at path: {}", source_path))?;
+            .ok_or_else(|| GnawError::node_not_found(source_path, &source_tree))?;
 
         println!("📦 Moving node from {} [{}]", source_file, source_path);
         println!("  Node type: {}", source_node.node_type);
@@ -3401,7 +4506,50 @@ Use without --preview to apply the clone"
         None
     }
 
-    fn handle_lint(paths: &[String], format: &str, recursive: bool) -> Result<()> {
+    fn handle_watch(path: Option<PathBuf>, recursive: bool, debounce_ms: u64) -> Result<()> {
+        use crate::llm::IndexWatcher;
+
+        let current_dir = std::env::current_dir()?;
+        let project_root = find_project_root(&current_dir);
+        let target = std::fs::canonicalize(path.unwrap_or(current_dir))?;
+
+        let mut watcher = IndexWatcher::new(&project_root);
+        let files = watcher.prime(&target, recursive);
+        println!("👀 Watching {} ({} files). Press Ctrl+C to stop.", target.display(), files);
+
+        watcher.watch(&target, recursive, std::time::Duration::from_millis(debounce_ms), |report| {
+            let file = report
+                .file_path
+                .strip_prefix(&project_root)
+                .unwrap_or(&report.file_path)
+                .display();
+            if report.deleted {
+                println!("🗑️  {} deleted ({} symbols removed)", file, report.removed.len());
+                return;
+            }
+            println!("🔄 {} re-indexed", file);
+            for name in &report.added {
+                println!("   + {}", name);
+            }
+            for name in &report.changed {
+                println!("   ~ {}", name);
+            }
+            for name in &report.removed {
+                println!("   - {}", name);
+            }
+        })
+    }
+
+    /// Parse every file and report those that fail. Returns an error (and so
+    /// a non-zero exit) when any issue was found.
+    fn handle_lint(
+        paths: &[String],
+        format: &str,
+        recursive: bool,
+        follow_symlinks: bool,
+        max_depth: Option<usize>,
+        fail_fast: bool,
+    ) -> Result<()> {
         // For now, lint is a wrapper around analyze with issue detection
         // In the future, this could include actual linting rules
 
@@ -3411,7 +4559,7 @@ Use without --preview to apply the clone"
             let path_buf = std::path::PathBuf::from(path);
             if path_buf.is_dir() {
                 if recursive {
-                    all_files.extend(Self::find_supported_files(&path_buf)?);
+                    all_files.extend(Self::find_supported_files(&path_buf, follow_symlinks, max_depth)?);
                 } else {
                     return Err(anyhow::anyhow!(
                         "Directory '{}' requires --recursive flag for safety.
@@ -3423,6 +4571,8 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                         path
                     ));
                 }
+            } else if !path_buf.exists() {
+                anyhow::bail!("File not found: {}", path);
             } else {
                 all_files.push(path.clone());
             }
@@ -3445,6 +4595,9 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                 }
                 Err(e) => {
                     issues.push(format!("{}:1:1 error {}", file_path, e));
+                    if fail_fast {
+                        break;
+                    }
                 }
             }
         }
@@ -3467,12 +4620,15 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                         issues.len(),
                         total_files
                     );
-                    for issue in issues {
+                    for issue in &issues {
                         println!("{}", issue);
                     }
                 }
             }
         }
+        if !issues.is_empty() {
+            return Err(IssuesFound(format!("lint found {} issue(s)", issues.len())).into());
+        }
         Ok(())
     }
     
@@ -3508,6 +4664,7 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                 ("svelte", "<script>let x = 0;</script>"),
                 ("dart", "void main() {}"),
                 ("cs", "using System;"),
+                ("rb", "class Main\n  def run; end\nend"),
             ];
 
             for (ext, code) in &parser_tests {
@@ -3608,7 +4765,7 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                 println!("  Reasoning:    {}", "✅ HRM2 (Hierarchical Relational Model)".green().bold());
                 println!("  Cache:        {}", status.cache_dir.display().to_string().cyan());
                 
-                let model_dir = status.cache_dir.join("modernbert");
+                let model_dir = &status.model_dir;
                 let c = model_dir.join("config.json").exists();
                 let t = model_dir.join("tokenizer.json").exists();
                 let w = model_dir.join("model.safetensors").exists();
@@ -3626,7 +4783,7 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
                 }
     
                 // Test load attempt (fast check)
-                match mgr.load_model(crate::llm::AiModel::ModernBert, crate::llm::DeviceType::Cpu) {
+                match mgr.load_model(status.model.clone(), crate::llm::DeviceType::Cpu) {
                     Ok(_) => println!("  Runtime:      {}", "✅ AI Services ready for GnawSense operations".green()),
                     Err(e) => println!("  Runtime:      {} {}", "❌ Load failed:".red(), e.to_string().red()),
                 }
@@ -3681,17 +4838,173 @@ To lint specific files: gnawtreewriter lint {}/*.ext",
         }
 }
 
+/// Exit status for a command that ran and succeeded
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit status for a command that ran but found problems: lint issues, files
+/// that could not be restored
+pub const EXIT_ISSUES: i32 = 1;
+/// Exit status for bad usage or a command that could not run (missing file, IO error...)
+pub const EXIT_ERROR: i32 = 2;
+
+/// Returned by commands that completed but found problems, so `main` exits
+/// with `EXIT_ISSUES` rather than `EXIT_ERROR`
+#[derive(Debug)]
+pub struct IssuesFound(pub String);
+
+impl std::fmt::Display for IssuesFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for IssuesFound {}
+
+/// The process exit status for an error returned by `Cli::run`
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if err.downcast_ref::<IssuesFound>().is_some() {
+        EXIT_ISSUES
+    } else {
+        EXIT_ERROR
+    }
+}
+
+/// `IssuesFound` when a restoration left some files unrestored
+fn check_restoration(result: &RestorationResult) -> Result<()> {
+    if result.success {
+        return Ok(());
+    }
+    Err(IssuesFound(format!(
+        "{} of {} file(s) could not be restored",
+        result.failed_files.len(),
+        result.total_files
+    ))
+    .into())
+}
+
+/// Context lines around each change in preview diffs, set from `--context-lines`
+/// and `--diff-format unified`. `usize::MAX` shows the whole file inline.
+static DIFF_CONTEXT_LINES: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 fn print_diff(old: &str, new: &str) {
+    print!("{}", format_diff(old, new));
+}
+
+/// Line diff of `old` and `new`, green for additions and red for deletions
+/// when color is enabled (see `--color`)
+fn format_diff(old: &str, new: &str) -> String {
+    format_diff_with_context(old, new, DIFF_CONTEXT_LINES.load(Ordering::Relaxed))
+}
+
+/// `format_diff` showing only `context_lines` unchanged lines around each
+/// change, in hunks headed `@@ -a,b +c,d @@`, unless it is `usize::MAX`
+fn format_diff_with_context(old: &str, new: &str, context_lines: usize) -> String {
     let diff = TextDiff::from_lines(old, new);
-    println!("\x1b[1m--- Preview of changes ---\x1b[0m");
-    for change in diff.iter_all_changes() {
-        match change.tag() {
-            ChangeTag::Delete => print!("\x1b[31m-{}\x1b[0m", change),
-            ChangeTag::Insert => print!("\x1b[32m+{}\x1b[0m", change),
-            ChangeTag::Equal => print!(" {}", change),
+    let mut out = format!("{}\n", "--- Preview of changes ---".bold());
+    let push_change = |out: &mut String, change: similar::Change<&str>| {
+        let line = change.to_string();
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let text = match change.tag() {
+            ChangeTag::Delete => format!("-{}", line).red().to_string(),
+            ChangeTag::Insert => format!("+{}", line).green().to_string(),
+            ChangeTag::Equal => format!(" {}", line),
         };
+        out.push_str(&text);
+        out.push('\n');
+    };
+    if context_lines == usize::MAX {
+        for change in diff.iter_all_changes() {
+            push_change(&mut out, change);
+        }
+    } else {
+        let mut unified = diff.unified_diff();
+        unified.context_radius(context_lines);
+        for hunk in unified.iter_hunks() {
+            out.push_str(&format!("{}\n", hunk.header().to_string().bold()));
+            for change in hunk.iter_changes() {
+                push_change(&mut out, change);
+            }
+        }
+    }
+    out.push_str(&format!("{}\n", "--- End of preview ---".bold()));
+    out
+}
+
+/// Answer to the next `--confirm` prompt, standing in for the terminal
+#[cfg(test)]
+static SCRIPTED_ANSWER: std::sync::Mutex<Option<&'static str>> = std::sync::Mutex::new(None);
+
+/// With `--confirm` on an interactive terminal, show the diff of `op` and
+/// ask before it is written. Returns false if the change was declined.
+fn confirm_operation(writer: &GnawTreeWriter, op: &EditOperation, confirm: bool) -> Result<bool> {
+    #[cfg(test)]
+    if let Some(answer) = SCRIPTED_ANSWER.lock().unwrap().take() {
+        return confirm_with(writer, op, confirm, &mut answer.as_bytes());
+    }
+    let interactive = confirm && std::io::IsTerminal::is_terminal(&std::io::stdin());
+    confirm_with(writer, op, interactive, &mut std::io::stdin().lock())
+}
+
+fn confirm_with(
+    writer: &GnawTreeWriter,
+    op: &EditOperation,
+    interactive: bool,
+    input: &mut impl std::io::BufRead,
+) -> Result<bool> {
+    if !interactive {
+        return Ok(true);
+    }
+    let modified = writer.preview_edit(op.clone())?;
+    print_diff(writer.get_source(), &modified);
+    print!("{} ", "Apply these changes? [y/N]".bold());
+    use std::io::Write;
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Where a command's primary result goes: the `--output` file, or stdout
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(
+            std::fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+/// Print a stored unified diff with added/removed lines colored
+fn print_unified_diff(out: &mut dyn Write, diff: &str) -> std::io::Result<()> {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") || line.starts_with("@@") {
+            writeln!(out, "{}", line.bold())?;
+        } else if line.starts_with('+') {
+            writeln!(out, "{}", line.green())?;
+        } else if line.starts_with('-') {
+            writeln!(out, "{}", line.red())?;
+        } else {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Build `EditOptions` from the CLI flags, warning loudly when a safety step
+/// is being skipped.
+fn unsafe_edit_options(force: bool, no_backup: bool, no_validate: bool) -> EditOptions {
+    if no_backup {
+        eprintln!("⚠️  --no-backup: no backup will be written; this edit cannot be undone.");
+    }
+    if no_validate {
+        eprintln!("⚠️  --no-validate: syntax validation is skipped.");
+    }
+    EditOptions {
+        force,
+        no_backup,
+        no_validate,
+        ..EditOptions::default()
     }
-    println!("\x1b[1m--- End of preview ---\x1b[0m");
 }
 
 fn show_hint() {
@@ -3721,49 +5034,55 @@ fn show_hint() {
         .as_nanos();
     let index = (nanos % hints.len() as u128) as usize;
 
-    eprintln!("\x1b[2m[GnawTip]: {}\x1b[0m", hints[index]);
+    eprintln!("{}", format!("[GnawTip]: {}", hints[index]).dimmed());
 }
 
-    fn list_nodes(file_path: &str, tree: &TreeNode, filter_type: Option<&str>, limit: usize, offset: usize) {
-        let mut all_nodes_meta = Vec::new();
-
-        fn collect(n: &TreeNode, filter: Option<&str>, acc: &mut Vec<(String, String, String)>) {
-            if filter.is_none() || filter.unwrap() == n.node_type {
-                acc.push((
+    fn list_nodes(
+        out: &mut dyn Write,
+        file_path: &str,
+        tree: &TreeNode,
+        filter_type: Option<&str>,
+        kind: Option<NodeKind>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<()> {
+        let of_kind = kind.map(|k| tree.paths_of_kind(k));
+        let all_nodes_meta: Vec<(String, String, String)> = tree
+            .iter()
+            .filter(|n| filter_type.is_none_or(|filter| n.node_type == filter))
+            .filter(|n| of_kind.as_ref().is_none_or(|paths| paths.contains(n.path.as_str())))
+            .map(|n| {
+                (
                     n.path.clone(),
                     n.node_type.clone(),
                     n.get_name().unwrap_or_else(|| "unnamed".to_string()),
-                ));
-            }
-            for child in &n.children {
-                collect(child, filter, acc);
-            }
-        }
-
-        collect(tree, filter_type, &mut all_nodes_meta);
+                )
+            })
+            .collect();
         let total_count = all_nodes_meta.len();
         
         let target_nodes: Vec<_> = all_nodes_meta.into_iter().skip(offset).take(limit).collect();
 
         if target_nodes.is_empty() {
-            println!("No nodes found matching criteria (Total: {}, Offset: {})", total_count, offset);
-            return;
+            writeln!(out, "No nodes found matching criteria (Total: {}, Offset: {})", total_count, offset)?;
+            return Ok(());
         }
 
         if offset > 0 || total_count > limit {
-            println!("--- Showing {} nodes (offset {}, total {}) ---", target_nodes.len(), offset, total_count);
+            writeln!(out, "--- Showing {} nodes (offset {}, total {}) ---", target_nodes.len(), offset, total_count)?;
         }
 
         for (path, node_type, name) in &target_nodes {
-            println!("  {} [{}] {}", path, node_type, name);
+            writeln!(out, "  {} [{}] {}", path, node_type, name)?;
         }
 
         if let Some((path, _, name)) = target_nodes.first() {
             if !path.is_empty() { // Don't suggest editing the source_file root directly usually
-                println!("\n💡 [GnawTip]: To edit a node (e.g. '{}'), use:", name);
-                println!("   gnawtreewriter edit {} {} -", file_path, path);
+                writeln!(out, "\n💡 [GnawTip]: To edit a node (e.g. '{}'), use:", name)?;
+                writeln!(out, "   gnawtreewriter edit {} {} -", file_path, path)?;
             }
         }
+        Ok(())
     }
 
 fn resolve_content(
@@ -3846,6 +5165,23 @@ mod tests {
 
     static TEST_MUTEX: Mutex<()> = Mutex::new(());
 
+    /// Forces `colored` on or off and clears the override when dropped, even
+    /// if the test panics. Hold `TEST_MUTEX` while it is alive.
+    struct ColorOverride;
+
+    impl ColorOverride {
+        fn set(enabled: bool) -> Self {
+            colored::control::set_override(enabled);
+            ColorOverride
+        }
+    }
+
+    impl Drop for ColorOverride {
+        fn drop(&mut self) {
+            colored::control::unset_override();
+        }
+    }
+
     #[test]
     fn test_handle_restore_cli() -> Result<()> {
         let _guard = TEST_MUTEX.lock().unwrap();
@@ -3906,6 +5242,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_analyze_results_carry_file_path() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        fs::create_dir(tmp.path().join(".git"))?;
+        let good = tmp.path().join("a.py");
+        let other = tmp.path().join("b.rs");
+        fs::write(&good, "x = 1\n")?;
+        fs::write(&other, "fn main() {}\n")?;
+        let missing = tmp.path().join("missing.py");
+
+        let files: Vec<String> = [&good, &missing, &other]
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        let results = Cli::analyze_files(&files, false, false)?;
+
+        // The failed file is skipped without shifting the others
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["file_path"], files[0]);
+        assert_eq!(results[0]["tree"]["node_type"], "module");
+        assert_eq!(results[1]["file_path"], files[2]);
+        assert_eq!(results[1]["tree"]["node_type"], "source_file");
+
+        let err = Cli::analyze_files(&files, true, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("missing.py"), "{:#}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_has_no_ansi_codes_without_color() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+        assert!(ColorChoice::Always.enabled(false));
+
+        let _color = ColorOverride::set(false);
+        let plain = format_diff("a\nb\n", "a\nc\n");
+        assert!(!plain.contains('\x1b'), "{:?}", plain);
+        assert!(plain.contains("-b\n") && plain.contains("+c\n"));
+
+        colored::control::set_override(true);
+        assert!(format_diff("a\n", "b\n").contains("\x1b[31m-a"));
+    }
+
+    #[test]
+    fn test_unified_diff_collapses_distant_unchanged_lines() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let _color = ColorOverride::set(false);
+        let old: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new = old.replace("line 10\n", "line ten\n");
+
+        let inline = format_diff_with_context(&old, &new, usize::MAX);
+        assert!(inline.contains(" line 1\n") && inline.contains(" line 20\n"));
+
+        let unified = format_diff_with_context(&old, &new, 2);
+        assert!(unified.contains("@@ -8,5 +8,5 @@"), "{}", unified);
+        assert!(unified.contains(" line 8\n line 9\n-line 10\n+line ten\n line 11\n line 12\n"), "{}", unified);
+        assert!(!unified.contains(" line 7\n") && !unified.contains(" line 13\n"), "{}", unified);
+        assert!(!unified.contains(" line 1\n") && !unified.contains(" line 20\n"));
+    }
+
+    #[test]
+    fn test_confirm_no_leaves_file_unchanged() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        fs::create_dir(tmp.path().join(".git"))?;
+        let file_path = tmp.path().join("confirm.py");
+        fs::write(&file_path, "x = 1\ny = 2\n")?;
+        let file = file_path.to_str().unwrap();
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(tmp.path())?;
+
+        let run = |args: &[&str], answer: &'static str| -> Result<()> {
+            *SCRIPTED_ANSWER.lock().unwrap() = Some(answer);
+            let args: Vec<String> = ["gnawtreewriter"].iter().chain(args).map(|a| a.to_string()).collect();
+            // Parsing and running the CLI need more stack than a test thread has
+            std::thread::Builder::new()
+                .stack_size(16 << 20)
+                .spawn(|| {
+                    let cli = Cli::try_parse_from(args)?;
+                    tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(cli.run())
+                })?
+                .join()
+                .unwrap()
+        };
+        let snapshot = || -> Result<Vec<(std::ffi::OsString, Vec<u8>)>> {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(tmp.path())? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    files.push((entry.file_name(), fs::read(entry.path())?));
+                }
+            }
+            files.sort();
+            Ok(files)
+        };
+
+        // Accepting writes the file and starts the transaction log
+        run(&["edit", file, "0", "x = 3", "--confirm"], "yes\n")?;
+        assert_eq!(fs::read_to_string(&file_path)?.trim_end(), "x = 3\ny = 2");
+        assert!(tmp.path().join(".gnawtreewriter_session.json").exists());
+
+        let before = snapshot()?;
+        for answer in ["n\n", ""] {
+            run(&["edit", file, "0", "x = 4", "--confirm"], answer)?;
+            run(&["insert", file, "0", "2", "z = 4", "--confirm"], answer)?;
+            run(&["delete", file, "1", "--confirm"], answer)?;
+            // Neither the file nor the transaction log were written
+            assert_eq!(snapshot()?, before);
+        }
+
+        // Not a terminal: nothing is asked and the change goes ahead
+        let writer = GnawTreeWriter::new(file)?;
+        let op = EditOperation::Edit { node_path: "0".into(), content: "x = 2".into() };
+        assert!(confirm_with(&writer, &op, false, &mut "".as_bytes())?);
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
     #[test]
     fn test_quick_replace_preview() -> Result<()> {
         let _guard = TEST_MUTEX.lock().unwrap();
@@ -3970,4 +5427,419 @@ mod tests {
         std::env::set_current_dir(orig_dir)?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_supported_files_symlink_cycle() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        let sub = root.join("sub");
+        fs::create_dir(&sub)?;
+        fs::write(sub.join("a.py"), "x = 1\n")?;
+        // sub/loop -> root creates a cycle
+        std::os::unix::fs::symlink(root, sub.join("loop"))?;
+
+        // Default: symlinks are skipped entirely
+        let files = Cli::find_supported_files(root, false, None)?;
+        assert_eq!(files.len(), 1);
+
+        // Following symlinks must still terminate and not duplicate files
+        let files = Cli::find_supported_files(root, true, None)?;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("a.py"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_supported_files_max_depth() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        fs::create_dir_all(root.join("one/two"))?;
+        fs::write(root.join("top.py"), "x = 1\n")?;
+        fs::write(root.join("one/mid.py"), "x = 1\n")?;
+        fs::write(root.join("one/two/deep.py"), "x = 1\n")?;
+
+        let mut files = Cli::find_supported_files(root, false, Some(2))?;
+        files.sort();
+        assert_eq!(files.len(), 2, "unexpected files: {:?}", files);
+        assert!(files[0].ends_with("one/mid.py") && files[1].ends_with("top.py"));
+
+        assert_eq!(Cli::find_supported_files(root, false, Some(1))?.len(), 1);
+        assert_eq!(Cli::find_supported_files(root, false, None)?.len(), 3);
+
+        // --max-depth must be at least 1 and only makes sense with --recursive
+        let parses = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            std::thread::Builder::new()
+                .stack_size(16 << 20)
+                .spawn(move || Cli::try_parse_from(args).is_ok())
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        for command in ["analyze", "lint"] {
+            assert!(parses(&["gnawtreewriter", command, ".", "--recursive", "--max-depth", "1"]));
+            assert!(!parses(&["gnawtreewriter", command, ".", "--recursive", "--max-depth", "0"]));
+            assert!(!parses(&["gnawtreewriter", command, ".", "--max-depth", "2"]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_supported_files_honors_gnawignore() -> Result<()> {
+        let tmp = tempdir()?;
+        let root = tmp.path();
+        fs::create_dir(root.join(".git"))?;
+        fs::write(root.join(".gnawignore"), "generated/\n")?;
+        for dir in ["src", "generated", "node_modules/pkg", "target/debug"] {
+            fs::create_dir_all(root.join(dir))?;
+            fs::write(root.join(dir).join("file.py"), "x = 1\n")?;
+        }
+
+        let files = Cli::find_supported_files(root, false, None)?;
+        assert_eq!(files.len(), 1, "unexpected files: {:?}", files);
+        assert!(files[0].ends_with("src/file.py"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_project_preview_shows_diffs() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file_path = project_root.join("timetravel.py");
+        fs::write(&file_path, "x = 1\ny = 2\n")?;
+        let edit_first = |content: &str| -> Result<()> {
+            let mut writer = GnawTreeWriter::new(file_path.to_str().unwrap())?;
+            let target = writer.analyze().children[0].path.clone();
+            writer.edit(EditOperation::Edit { node_path: target, content: content.to_string() }, true)
+        };
+
+        edit_first("x = 10")?;
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let restore_to = Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        edit_first("x = 99")?;
+
+        let current = fs::read_to_string(&file_path)?;
+        let mut out = Vec::new();
+        Cli::handle_restore_project(&mut out, &restore_to, true, true)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("timetravel.py (1 modifications"), "{}", out);
+        assert!(out.contains("-x = 99"), "{}", out);
+        assert!(out.contains("+x = 10"), "{}", out);
+        // Previewing does not touch the file
+        assert_eq!(fs::read_to_string(&file_path)?, current);
+
+        let mut out = Vec::new();
+        Cli::handle_restore_project(&mut out, &restore_to, true, false)?;
+        assert!(!String::from_utf8(out)?.contains("+x = 10"));
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_session_preview_lists_modifications_per_file() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let edit_first = |name: &str, content: &str| -> Result<()> {
+            let file_path = project_root.join(name);
+            if !file_path.exists() {
+                fs::write(&file_path, "x = 1\n")?;
+            }
+            let mut writer = GnawTreeWriter::new(file_path.to_str().unwrap())?;
+            let target = writer.analyze().children[0].path.clone();
+            writer.edit(EditOperation::Edit { node_path: target, content: content.to_string() }, true)
+        };
+
+        edit_first("a.py", "x = 2")?;
+        edit_first("b.py", "x = 3")?;
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let since = Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        edit_first("a.py", "x = 4")?;
+
+        let session = TransactionLog::load(project_root)?.get_current_session_id().to_string();
+        let mut out = Vec::new();
+        Cli::handle_restore_session(&mut out, &session, true, None, None)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("a.py (2 modifications)"), "{}", out);
+        assert!(out.contains("b.py (1 modifications)"), "{}", out);
+        assert_eq!(out.matches(" Edit ").count(), 3, "{}", out);
+
+        let mut out = Vec::new();
+        Cli::handle_restore_session(&mut out, &session, true, Some(&since), None)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("a.py (1 modifications)"), "{}", out);
+        assert!(!out.contains("b.py"), "{}", out);
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_label_reverts_only_labelled_edits() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let edit_first = |name: &str, content: &str| -> Result<()> {
+            let mut writer = GnawTreeWriter::new(project_root.join(name).to_str().unwrap())?;
+            let target = writer.analyze().children[0].path.clone();
+            writer.edit(EditOperation::Edit { node_path: target, content: content.to_string() }, true)
+        };
+        fs::write(project_root.join("a.py"), "x = 1\n")?;
+        fs::write(project_root.join("b.py"), "y = 1\n")?;
+
+        crate::core::set_label(Some("task-a".into()));
+        let labelled = (|| -> Result<()> {
+            edit_first("a.py", "x = 2")?;
+            edit_first("a.py", "x = 3")
+        })();
+        crate::core::set_label(None);
+        labelled?;
+        edit_first("b.py", "y = 2")?;
+
+        let history = TransactionLog::load(project_root)?.get_label_history("task-a")?;
+        assert_eq!(history.len(), 2);
+
+        let mut out = Vec::new();
+        Cli::handle_restore_label(&mut out, "task-a", true, false)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("a.py (2 modifications)"), "{}", out);
+        assert!(!out.contains("b.py"), "{}", out);
+
+        Cli::handle_restore_label(&mut Vec::new(), "task-a", false, false)?;
+        assert_eq!(fs::read_to_string(project_root.join("a.py"))?.trim_end(), "x = 1");
+        assert_eq!(fs::read_to_string(project_root.join("b.py"))?.trim_end(), "y = 2");
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_label_refuses_to_discard_later_unlabelled_edits() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file = project_root.join("a.py");
+        fs::write(&file, "x = 1\ny = 1\n")?;
+        let edit = |node_path: &str, content: &str, label: Option<&str>| -> Result<()> {
+            crate::core::set_label(label.map(str::to_string));
+            let mut writer = GnawTreeWriter::new(file.to_str().unwrap())?;
+            let result = writer.edit(EditOperation::Edit { node_path: node_path.into(), content: content.into() }, true);
+            crate::core::set_label(None);
+            result
+        };
+        edit("0", "x = 2", Some("task-a"))?;
+        edit("1", "y = 2", None)?;
+        edit("0", "x = 3", Some("task-a"))?;
+
+        let mut out = Vec::new();
+        Cli::handle_restore_label(&mut out, "task-a", true, false)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("Would also discard 1 later edits"), "{}", out);
+
+        let err = Cli::handle_restore_label(&mut Vec::new(), "task-a", false, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("without a label"), "{:#}", err);
+        assert_eq!(fs::read_to_string(&file)?.trim_end(), "x = 3\ny = 2");
+
+        Cli::handle_restore_label(&mut Vec::new(), "task-a", false, true)?;
+        assert_eq!(fs::read_to_string(&file)?.trim_end(), "x = 1\ny = 1");
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_batch_applies_two_edits_in_one_write() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file_path = project_root.join("batch.py");
+        fs::write(&file_path, "def a():\n    return 1\n\ndef b():\n    return 2\n")?;
+        let edits_path = project_root.join("edits.json");
+        // Both paths are from the original file: the first edit adds lines,
+        // which must not shift where the second one lands
+        fs::write(
+            &edits_path,
+            r#"[
+                {"node_path": "0", "content": "def a():\n    x = 10\n    return x"},
+                {"node_path": "1", "content": "def b():\n    return 20"}
+            ]"#,
+        )?;
+
+        let mut out = Vec::new();
+        Cli::handle_edit_batch(
+            &mut out,
+            file_path.to_str().unwrap(),
+            edits_path.to_str().unwrap(),
+            false,
+            EditOptions::default(),
+        )?;
+        assert!(String::from_utf8(out)?.contains("Applied 2 edits"));
+        assert_eq!(
+            fs::read_to_string(&file_path)?.trim_end(),
+            "def a():\n    x = 10\n    return x\n\ndef b():\n    return 20"
+        );
+
+        let history = TransactionLog::load(project_root)?.get_last_n_transactions(1)?;
+        assert!(history[0].description.contains("Edited 2 nodes"), "{}", history[0].description);
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_edit_is_logged_as_failed() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file_path = project_root.join("reject.py");
+        fs::write(&file_path, "x = 1\n")?;
+        let bad_edit = || -> Result<()> {
+            let mut writer = GnawTreeWriter::new(file_path.to_str().unwrap())?;
+            let target = writer.analyze().children[0].path.clone();
+            writer.edit(EditOperation::Edit { node_path: target, content: "x = = 2".to_string() }, true)
+        };
+
+        // Off by default
+        assert!(bad_edit().is_err());
+        let logged = TransactionLog::load(project_root)?.get_last_n_with_failed(10)?;
+        assert!(logged.iter().all(|t| t.status == TransactionStatus::Applied));
+
+        crate::core::set_log_failed_edits(true);
+        let result = bad_edit();
+        crate::core::set_log_failed_edits(false);
+        assert!(result.is_err());
+
+        let tlog = TransactionLog::load(project_root)?;
+        let failed = tlog.get_last_n_with_failed(10)?.pop().expect("failed transaction");
+        assert_eq!(failed.status, TransactionStatus::Failed);
+        assert_eq!(failed.operation, OperationType::Edit);
+        assert!(failed.metadata["error"].contains("Validation failed"), "{:?}", failed.metadata);
+        // Undo and restore only see applied changes
+        assert!(tlog.get_full_history()?.iter().all(|t| t.status == TransactionStatus::Applied));
+
+        let mut out = Vec::new();
+        Cli::handle_history(&mut out, 10, "table", None)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("FAILED"), "{}", out);
+        assert!(out.contains("Validation failed"), "{}", out);
+        assert_eq!(fs::read_to_string(&file_path)?, "x = 1\n");
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_show_diff_for_edit() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file_path = project_root.join("diffme.py");
+        fs::write(&file_path, "x = 1\ny = 2\n")?;
+
+        let mut writer = GnawTreeWriter::new(file_path.to_str().unwrap())?;
+        let target = writer
+            .analyze()
+            .children
+            .first()
+            .expect("first statement")
+            .path
+            .clone();
+        writer.edit(
+            EditOperation::Edit {
+                node_path: target,
+                content: "x = 42".to_string(),
+            },
+            true,
+        )?;
+
+        let tlog = TransactionLog::load(project_root)?;
+        let edit = tlog
+            .get_file_history(&file_path)?
+            .into_iter()
+            .find(|t| t.operation == OperationType::Edit)
+            .expect("edit transaction");
+        let diff = edit.diff.as_deref().expect("diff recorded");
+        assert!(diff.contains("-x = 1"), "diff: {}", diff);
+        assert!(diff.contains("+x = 42"), "diff: {}", diff);
+
+        Cli::handle_history(&mut std::io::sink(), 10, "table", Some(&edit.id))?;
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_ndjson_prints_one_transaction_per_line() -> Result<()> {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        let tmp = tempdir()?;
+        let project_root = tmp.path();
+        fs::create_dir(project_root.join(".git"))?;
+
+        let orig_dir = env::current_dir()?;
+        env::set_current_dir(project_root)?;
+
+        let file_path = project_root.join("app.py");
+        fs::write(&file_path, "x = 1\ny = 2\n")?;
+        for (node_path, content) in [("0", "x = 10"), ("1", "y = 20")] {
+            let mut writer = GnawTreeWriter::new(file_path.to_str().unwrap())?;
+            writer.edit(EditOperation::Edit { node_path: node_path.to_string(), content: content.to_string() }, true)?;
+        }
+
+        let mut out = Vec::new();
+        Cli::handle_history(&mut out, 10, "ndjson", None)?;
+        let stdout = String::from_utf8(out)?;
+        let transactions: Vec<crate::core::Transaction> = stdout
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+            .collect();
+        let edited: Vec<&str> = transactions
+            .iter()
+            .filter(|t| t.file_path.ends_with("app.py"))
+            .filter_map(|t| t.node_path.as_deref())
+            .collect();
+        assert_eq!(edited, ["0", "1"], "{}", stdout);
+
+        env::set_current_dir(orig_dir)?;
+        Ok(())
+    }
+}
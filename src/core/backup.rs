@@ -9,6 +9,7 @@
 
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
+use encoding_rs::{Encoding, UTF_8};
 use serde_json::Value;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -147,35 +148,166 @@ pub fn find_backup_by_content_hash_for_file<P: AsRef<Path>>(
     Ok(None)
 }
 
-/// Read the `source_code` field from a backup JSON and write it to `target_path`.
+/// A path in `backup_dir` for a new backup of `file_name`, named by the
+/// current time. Backups taken within the same millisecond get a counter
+/// suffix instead of overwriting each other.
+pub fn new_backup_path(backup_dir: &Path, file_name: &str) -> PathBuf {
+    let timestamp = Utc::now().format("%Y%m%d_%H%M%S_%3f");
+    let mut path = backup_dir.join(format!("{}_backup_{}.json", file_name, timestamp));
+    let mut n = 1;
+    while path.exists() {
+        path = backup_dir.join(format!("{}_backup_{}_{}.json", file_name, timestamp, n));
+        n += 1;
+    }
+    path
+}
+
+/// Write a backup JSON for `file_path` holding `source_code` into `backup_dir`,
+/// in the same shape as `GnawTreeWriter::create_backup()` (without the tree).
+pub fn write_backup<P: AsRef<Path>>(
+    backup_dir: P,
+    file_path: &Path,
+    source_code: &str,
+) -> Result<PathBuf> {
+    write_backup_as(backup_dir, file_path, source_code, UTF_8, false)
+}
+
+/// Like `write_backup`, for a file written in `encoding` that may start with
+/// a UTF-8 BOM. `source_code` is the decoded text without the BOM; restoring
+/// encodes it back and puts the BOM in front again.
+pub fn write_backup_as<P: AsRef<Path>>(
+    backup_dir: P,
+    file_path: &Path,
+    source_code: &str,
+    encoding: &'static Encoding,
+    has_bom: bool,
+) -> Result<PathBuf> {
+    let backup_dir = backup_dir.as_ref();
+    fs::create_dir_all(backup_dir).context("Failed to create backup directory")?;
+
+    let file_name = file_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown");
+    let backup_path = new_backup_path(backup_dir, file_name);
+
+    let backup_data = serde_json::json!({
+        "file_path": file_path.to_string_lossy(),
+        "timestamp": Utc::now().to_rfc3339(),
+        "tree": Value::Null,
+        "source_code": source_code,
+        "encoding": encoding.name(),
+        "bom": has_bom,
+        "mode": file_mode(file_path)
+    });
+
+    fs::write(&backup_path, serde_json::to_string_pretty(&backup_data)?)
+        .context(format!("Failed to write backup: {}", backup_path.display()))?;
+
+    Ok(backup_path)
+}
+
+/// Read the `source_code` field from a backup JSON and write it to `target_path`
+/// in the encoding the backup recorded, putting back the BOM and file mode as
+/// well.
 /// Returns the written `PathBuf` on success.
 pub fn restore_from_backup<P: AsRef<Path>, Q: AsRef<Path>>(
     backup_path: P,
     target_path: Q,
 ) -> Result<PathBuf> {
-    let backup_path = backup_path.as_ref();
     let target_path = target_path.as_ref();
+    let json = read_backup_json(backup_path.as_ref())?;
+    let source_code = backup_source(&json)?;
+    let encoding = backup_encoding(&json)?;
+
+    let (bytes, _, unmappable) = encoding.encode(source_code);
+    if unmappable {
+        return Err(anyhow!(
+            "Backup {} holds characters that cannot be written as {}",
+            backup_path.as_ref().display(),
+            encoding.name()
+        ));
+    }
+    let bytes = if json["bom"].as_bool() == Some(true) {
+        [crate::core::UTF8_BOM.as_bytes(), &bytes].concat()
+    } else {
+        bytes.into_owned()
+    };
+    crate::core::write_source_file(target_path, &bytes).context(format!(
+        "Failed to write restored file: {}",
+        target_path.display()
+    ))?;
+    if let Some(mode) = json["mode"].as_u64() {
+        set_file_mode(target_path, mode as u32)
+            .context(format!("Failed to restore the mode of {}", target_path.display()))?;
+    }
 
+    Ok(target_path.to_path_buf())
+}
+
+/// The source code saved in a backup file
+pub fn read_backup_source<P: AsRef<Path>>(backup_path: P) -> Result<String> {
+    backup_source(&read_backup_json(backup_path.as_ref())?).map(String::from)
+}
+
+/// The encoding the file a backup was taken of is written in
+pub fn read_backup_encoding<P: AsRef<Path>>(backup_path: P) -> Result<&'static Encoding> {
+    backup_encoding(&read_backup_json(backup_path.as_ref())?)
+}
+
+fn read_backup_json(backup_path: &Path) -> Result<Value> {
     let backup_content = fs::read_to_string(backup_path).context(format!(
         "Failed to read backup file: {}",
         backup_path.display()
     ))?;
 
-    let json: Value = serde_json::from_str(&backup_content).context(format!(
+    serde_json::from_str(&backup_content).context(format!(
         "Failed to parse backup JSON: {}",
         backup_path.display()
-    ))?;
+    ))
+}
 
-    let source_code = json["source_code"]
+fn backup_source(json: &Value) -> Result<&str> {
+    json["source_code"]
         .as_str()
-        .ok_or_else(|| anyhow!("Backup file missing 'source_code'"))?;
+        .ok_or_else(|| anyhow!("Backup file missing 'source_code'"))
+}
 
-    fs::write(target_path, source_code).context(format!(
-        "Failed to write restored file: {}",
-        target_path.display()
-    ))?;
+/// Backups written before the encoding was recorded are UTF-8
+fn backup_encoding(json: &Value) -> Result<&'static Encoding> {
+    match json["encoding"].as_str() {
+        Some(label) => Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| anyhow!("Backup file has unknown encoding '{}'", label)),
+        None => Ok(UTF_8),
+    }
+}
 
-    Ok(target_path.to_path_buf())
+/// Permission bits of `path` (Unix only), recorded in backups as `mode`
+pub fn file_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).ok().map(|m| m.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Set permission bits read with `file_mode`; a no-op off Unix
+pub fn set_file_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -257,4 +389,46 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_restore_writes_the_recorded_encoding() -> Result<()> {
+        let tmp = tempdir()?;
+        let backup_dir = tmp.path().join(".gnawtreewriter_backups");
+        let file_path = tmp.path().join("latin1.py");
+
+        let latin1 = crate::core::parse_encoding("latin1").unwrap();
+        let backup = write_backup_as(&backup_dir, &file_path, "name = 'café'\n", latin1, false)?;
+        assert_eq!(read_backup_encoding(&backup)?, latin1);
+
+        restore_from_backup(&backup, &file_path)?;
+        assert_eq!(fs::read(&file_path)?, b"name = 'caf\xe9'\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_puts_the_bom_back() -> Result<()> {
+        let tmp = tempdir()?;
+        let backup_dir = tmp.path().join(".gnawtreewriter_backups");
+        let file_path = tmp.path().join("bom.py");
+
+        let backup = write_backup_as(&backup_dir, &file_path, "x = 1\n", UTF_8, true)?;
+        restore_from_backup(&backup, &file_path)?;
+        assert_eq!(fs::read(&file_path)?, b"\xef\xbb\xbfx = 1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_backups_in_the_same_millisecond_do_not_overwrite() -> Result<()> {
+        let tmp = tempdir()?;
+        let backup_dir = tmp.path().join(".gnawtreewriter_backups");
+        let file_path = tmp.path().join("f.txt");
+
+        let paths: Vec<PathBuf> = (0..5)
+            .map(|i| write_backup(&backup_dir, &file_path, &format!("v{}", i)))
+            .collect::<Result<_>>()?;
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(parse_backup_file(path)?.content_hash, Some(crate::core::calculate_content_hash(&format!("v{}", i))));
+        }
+        Ok(())
+    }
 }
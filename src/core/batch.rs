@@ -247,19 +247,11 @@ impl Batch {
             }
 
             // Try to write
-            if let Err(e) = fs::write(&fd.file, &fd.after) {
+            if let Err(e) = crate::core::write_source_file(std::path::Path::new(&fd.file), fd.after.as_bytes()) {
                 // Rollback previously written files
                 for w in &written {
                     if let Some(backup) = backups.get(w) {
-                        if let Ok(backup_content) = fs::read_to_string(backup) {
-                            if let Ok(v) =
-                                serde_json::from_str::<serde_json::Value>(&backup_content)
-                            {
-                                if let Some(src) = v.get("source_code").and_then(|s| s.as_str()) {
-                                    let _ = fs::write(w, src);
-                                }
-                            }
-                        }
+                        let _ = crate::core::backup::restore_from_backup(backup, w);
                     }
                 }
                 anyhow::bail!("Failed to write {}: {}. Rolled back changes.", fd.file, e);
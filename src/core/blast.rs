@@ -1,5 +1,6 @@
 //! gnaw-blast: Change Impact Analysis
 
+use crate::core::GnawError;
 use crate::{GnawTreeWriter, TreeNode};
 use anyhow::Result;
 use serde::Serialize;
@@ -66,7 +67,7 @@ pub fn blast(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     let target_name = target.get_name().unwrap_or_else(|| "unnamed".to_string());
 
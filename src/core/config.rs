@@ -0,0 +1,174 @@
+//! Project defaults read from `.gnawtreewriter.toml` at the project root.
+//!
+//! Every setting is optional and only fills in for a flag that was not given
+//! on the command line:
+//!
+//! ```toml
+//! format = "summary"          # analyze output
+//! backup_dir = ".backups"     # relative to the project root
+//! max_file_size = "10M"
+//! parse_timeout = 60          # seconds
+//! device = "cuda"
+//! atomic_writes = false      # write edits in place
+//!
+//! [mcp]
+//! addr = "127.0.0.1:9000"
+//! token = "secret"
+//! test_patterns = ["{stem}.test.ts", "__tests__/{stem}.ts"]
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked up in the project root
+pub const CONFIG_FILE: &str = ".gnawtreewriter.toml";
+
+/// Where backups go when `backup_dir` is not configured
+pub const DEFAULT_BACKUP_DIR: &str = ".gnawtreewriter_backups";
+
+const DEVICES: &[&str] = &["cpu", "cuda", "metal"];
+
+const FORMATS: &[&str] = &["json", "summary", "dot"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Output format for `analyze`: json, summary or dot
+    pub format: Option<String>,
+    /// Backup directory, relative to the project root
+    pub backup_dir: Option<PathBuf>,
+    /// Same as `--max-file-size`: bytes, or a string with a K/M/G suffix
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_file_size: Option<u64>,
+    /// Same as `--parse-timeout`, in seconds; 0 turns the limit off
+    pub parse_timeout: Option<u64>,
+    /// Device for the AI commands: cpu, cuda or metal
+    pub device: Option<String>,
+    /// Write edits through a temporary file renamed over the original
+    /// (default true); turn off to keep hard links to edited files shared
+    pub atomic_writes: Option<bool>,
+    pub mcp: McpConfig,
+}
+
+/// Defaults for `mcp serve`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct McpConfig {
+    pub addr: Option<String>,
+    pub token: Option<String>,
+    /// Where the edit pulse looks for a file's tests; `{stem}` is the file
+    /// name without its extension
+    pub test_patterns: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Read `.gnawtreewriter.toml` from `project_root`. A missing file gives
+    /// the defaults; an invalid one is an error.
+    pub fn load(project_root: &Path) -> Result<Self> {
+        let path = project_root.join(CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("Invalid config: {}", path.display()))?;
+        for (key, value, allowed) in [("format", &config.format, FORMATS), ("device", &config.device, DEVICES)] {
+            if let Some(value) = value {
+                if !allowed.contains(&value.as_str()) {
+                    anyhow::bail!(
+                        "Invalid config: {}: {} must be one of {}, got '{}'",
+                        path.display(),
+                        key,
+                        allowed.join(", "),
+                        value
+                    );
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// The backup directory for `project_root`
+    pub fn backup_dir(&self, project_root: &Path) -> PathBuf {
+        project_root.join(self.backup_dir.as_deref().unwrap_or(Path::new(DEFAULT_BACKUP_DIR)))
+    }
+}
+
+/// The configured backup directory for `project_root`, falling back to the
+/// default if the config cannot be read
+pub fn backup_dir(project_root: &Path) -> PathBuf {
+    Config::load(project_root).unwrap_or_default().backup_dir(project_root)
+}
+
+/// Parse a byte size such as `1048576`, `512K`, `5M` or `1G`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    let n = digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size '{}': use bytes or a K/M/G suffix", value))?;
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", value))
+}
+
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(n)) => Ok(Some(n)),
+        Some(Size::Text(s)) => parse_size(&s).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_reads_values_and_rejects_bad_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Config::load(dir.path()).unwrap(), Config::default());
+        assert_eq!(backup_dir(dir.path()), dir.path().join(DEFAULT_BACKUP_DIR));
+
+        let file = dir.path().join(CONFIG_FILE);
+        fs::write(&file, "max_file_size = \"2K\"\nbackup_dir = \"bak\"\n[mcp]\naddr = \"0.0.0.0:1\"\n").unwrap();
+        let config = Config::load(dir.path()).unwrap();
+        assert_eq!(config.max_file_size, Some(2048));
+        assert_eq!(config.mcp.addr.as_deref(), Some("0.0.0.0:1"));
+        assert_eq!(backup_dir(dir.path()), dir.path().join("bak"));
+
+        fs::write(&file, "max_file_size = 100\n").unwrap();
+        assert_eq!(Config::load(dir.path()).unwrap().max_file_size, Some(100));
+
+        fs::write(&file, "device = \"gpu\"\n").unwrap();
+        assert!(Config::load(dir.path()).is_err());
+        fs::write(&file, "format = \"dot\"\n").unwrap();
+        assert_eq!(Config::load(dir.path()).unwrap().format.as_deref(), Some("dot"));
+        fs::write(&file, "format = \"yaml\"\n").unwrap();
+        let err = Config::load(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("format must be one of json, summary, dot"), "{}", err);
+        fs::write(&file, "max_filesize = 1\n").unwrap();
+        assert!(Config::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn parse_size_rejects_sizes_that_overflow() {
+        assert_eq!(parse_size("5M"), Ok(5 * 1024 * 1024));
+        assert!(parse_size("99999999999999G").unwrap_err().contains("too large"));
+        assert!(parse_size("lots").unwrap_err().contains("invalid size"));
+    }
+}
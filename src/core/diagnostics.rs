@@ -147,6 +147,8 @@ impl DiagnosticError {
             "path_resolution".to_string()
         } else if msg.contains("GUARDIAN") {
             "guardian_block".to_string()
+        } else if msg.contains("changed on disk") {
+            "conflict".to_string()
         } else if msg.contains("Failed to parse") {
             "parse_error".to_string()
         } else if msg.contains("Failed to read") || msg.contains("Failed to write") {
@@ -217,11 +219,11 @@ impl AstDiff {
         let before_types = collect_node_types(before);
         let after_types = collect_node_types(after);
 
-        let before_node_count = count_nodes(before);
-        let after_node_count = count_nodes(after);
+        let before_node_count = before.descendant_count() + 1;
+        let after_node_count = after.descendant_count() + 1;
 
-        let before_depth = max_depth(before, 0);
-        let after_depth = max_depth(after, 0);
+        let before_depth = before.max_depth();
+        let after_depth = after.max_depth();
 
         let mut changes = Vec::new();
         let mut warnings = Vec::new();
@@ -394,7 +396,7 @@ impl DoctorReport {
                     self.pass(
                         "parser",
                         &format!(".{}", extension),
-                        &format!("Parsed OK — root: '{}', {} nodes", tree.node_type, count_nodes(&tree)),
+                        &format!("Parsed OK — root: '{}', {} nodes", tree.node_type, tree.descendant_count() + 1),
                     );
                 }
                 Err(e) => {
@@ -417,7 +419,7 @@ impl DoctorReport {
 
     /// Check backup integrity
     pub fn check_backups(&mut self, project_root: &std::path::Path) {
-        let backup_dir = project_root.join(".gnawtreewriter_backups");
+        let backup_dir = crate::core::config::backup_dir(project_root);
 
         if !backup_dir.exists() {
             self.warn("backup", "backup_dir", "No backup directory found");
@@ -533,38 +535,19 @@ fn generate_suggestion(error_type: &str, language: &str, _msg: &str) -> Option<S
         "guardian_block" => Some("Review the edit — it may be removing critical logic. Use --force to override".to_string()),
         "parse_error" => Some(format!("The {} parser could not process this file. Check for encoding issues or mixed content", language)),
         "io_error" => Some("Verify file permissions and that the path exists".to_string()),
+        "conflict" => Some("Another tool changed the file; re-read it before editing, or use --force to overwrite".to_string()),
         _ => None,
     }
 }
 
 fn collect_node_types(node: &TreeNode) -> std::collections::HashMap<String, usize> {
     let mut types = std::collections::HashMap::new();
-    fn walk(n: &TreeNode, acc: &mut std::collections::HashMap<String, usize>) {
-        *acc.entry(n.node_type.clone()).or_insert(0) += 1;
-        for c in &n.children {
-            walk(c, acc);
-        }
+    for n in node.iter() {
+        *types.entry(n.node_type.clone()).or_insert(0) += 1;
     }
-    walk(node, &mut types);
     types
 }
 
-fn count_nodes(node: &TreeNode) -> usize {
-    1 + node.children.iter().map(count_nodes).sum::<usize>()
-}
-
-fn max_depth(node: &TreeNode, current: usize) -> usize {
-    if node.children.is_empty() {
-        current
-    } else {
-        node.children
-            .iter()
-            .map(|c| max_depth(c, current + 1))
-            .max()
-            .unwrap_or(current)
-    }
-}
-
 fn is_important_node_type(ntype: String) -> bool {
     let important = [
         "function_declaration",
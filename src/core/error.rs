@@ -0,0 +1,170 @@
+//! Typed failures raised by `GnawTreeWriter`.
+//!
+//! Most of the crate still passes errors around as `anyhow::Error`; these
+//! variants are what ends up inside, so callers such as the MCP server can
+//! tell a missing node from a failed validation with `GnawError::classify`.
+
+use thiserror::Error;
+
+use crate::parser::TreeNode;
+
+#[derive(Debug, Error)]
+pub enum GnawError {
+    /// Reading or writing a file (or its backups and logs) failed
+    #[error("{message}: {source}")]
+    Io {
+        message: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The file could not be parsed or decoded
+    #[error("{0}")]
+    Parse(String),
+    /// The requested node path does not exist in the tree; `suggestions`
+    /// are existing paths that look like it
+    #[error("Node not found: {path}{}", did_you_mean(.suggestions))]
+    NodeNotFound { path: String, suggestions: Vec<String> },
+    /// The edit was rejected because the result is invalid
+    #[error("{0}")]
+    ValidationFailed(String),
+    /// The operation is not available for this file type
+    #[error("{0}")]
+    Unsupported(String),
+    /// The file changed on disk after it was loaded; writing would
+    /// overwrite someone else's change
+    #[error("{0}")]
+    Conflict(String),
+    /// The file exceeds the configured `--max-file-size`
+    #[error("File too large: {path} is {size} bytes, the limit is {limit} bytes (raise it with --max-file-size)")]
+    FileTooLarge { path: String, size: u64, limit: u64 },
+}
+
+impl GnawError {
+    pub fn io(message: impl Into<String>, source: std::io::Error) -> Self {
+        GnawError::Io {
+            message: message.into(),
+            source,
+        }
+    }
+
+    /// A `NodeNotFound` for `path`, suggesting the closest paths in `tree`
+    pub fn node_not_found(path: &str, tree: &TreeNode) -> Self {
+        GnawError::NodeNotFound {
+            path: path.to_string(),
+            suggestions: tree.similar_paths(path, 3),
+        }
+    }
+
+    /// Stable, machine-readable name of the variant
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GnawError::Io { .. } => "io",
+            GnawError::Parse(_) => "parse",
+            GnawError::NodeNotFound { .. } => "node_not_found",
+            GnawError::ValidationFailed(_) => "validation_failed",
+            GnawError::Unsupported(_) => "unsupported",
+            GnawError::Conflict(_) => "conflict",
+            GnawError::FileTooLarge { .. } => "file_too_large",
+        }
+    }
+
+    /// JSON-RPC error code, from the range reserved for server errors.
+    /// -32001 (Unauthorized), -32005 (server busy) and -32007 (tool timeout)
+    /// belong to the MCP server.
+    pub fn code(&self) -> i64 {
+        match self {
+            GnawError::Io { .. } => -32008,
+            GnawError::Parse(_) => -32002,
+            GnawError::NodeNotFound { .. } => -32003,
+            GnawError::ValidationFailed(_) => -32004,
+            GnawError::Unsupported(_) => -32009,
+            GnawError::Conflict(_) => -32010,
+            GnawError::FileTooLarge { .. } => -32006,
+        }
+    }
+
+    /// Find the `GnawError` behind an `anyhow::Error`, looking through any
+    /// context that was added on the way up. Plain I/O errors count as `Io`.
+    pub fn classify(err: &anyhow::Error) -> Option<ErrorKind> {
+        if let Some(e) = err.downcast_ref::<GnawError>() {
+            return Some(ErrorKind::of(e));
+        }
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<GnawError>() {
+                return Some(ErrorKind::of(e));
+            }
+            if cause.downcast_ref::<std::io::Error>().is_some() {
+                return Some(ErrorKind {
+                    kind: "io",
+                    code: -32008,
+                });
+            }
+        }
+        None
+    }
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", suggestions.join(", "))
+    }
+}
+
+/// The code and kind of a classified error, detached from the error itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorKind {
+    pub kind: &'static str,
+    pub code: i64,
+}
+
+impl ErrorKind {
+    fn of(err: &GnawError) -> Self {
+        ErrorKind {
+            kind: err.kind(),
+            code: err.code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn classify_sees_through_context() {
+        let err: anyhow::Result<()> =
+            Err(GnawError::NodeNotFound { path: "0.1".into(), suggestions: Vec::new() }.into());
+        let err = err.context("while editing").unwrap_err();
+        assert_eq!(GnawError::classify(&err).map(|k| k.kind), Some("node_not_found"));
+
+        let io: anyhow::Error = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        let io = GnawError::classify(&io.context("while reading")).unwrap();
+        assert_eq!((io.kind, io.code), ("io", -32008));
+
+        let plain = anyhow::anyhow!("something else");
+        assert_eq!(GnawError::classify(&plain), None);
+    }
+
+    #[test]
+    fn codes_are_distinct_from_each_other_and_the_server_codes() {
+        let errors = [
+            GnawError::io("read", std::io::Error::from(std::io::ErrorKind::NotFound)),
+            GnawError::Parse(String::new()),
+            GnawError::NodeNotFound { path: String::new(), suggestions: Vec::new() },
+            GnawError::ValidationFailed(String::new()),
+            GnawError::Unsupported(String::new()),
+            GnawError::Conflict(String::new()),
+            GnawError::FileTooLarge { path: String::new(), size: 0, limit: 0 },
+        ];
+        // Unauthorized, server busy and tool timeout
+        let mut codes = vec![-32001, -32005, -32007];
+        codes.extend(errors.iter().map(GnawError::code));
+        let count = codes.len();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), count, "{:?}", codes);
+    }
+}
@@ -0,0 +1,100 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// Directories that are never worth scanning, regardless of `.gnawignore`.
+pub const DEFAULT_IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+/// Name of the ignore file read from the project root (gitignore syntax).
+pub const IGNORE_FILE: &str = ".gnawignore";
+
+/// Path filter for directory scans, built from the default skip list and an
+/// optional `.gnawignore` at the project root.
+pub struct GnawIgnore {
+    root: PathBuf,
+    canonical_root: PathBuf,
+    matcher: Option<Gitignore>,
+}
+
+impl GnawIgnore {
+    /// Load `.gnawignore` from `project_root`. A missing or unreadable file
+    /// leaves only the default skip list active.
+    pub fn load(project_root: &Path) -> Self {
+        let ignore_file = project_root.join(IGNORE_FILE);
+        let matcher = if ignore_file.is_file() {
+            let mut builder = GitignoreBuilder::new(project_root);
+            match builder.add(&ignore_file) {
+                None => builder.build().ok(),
+                Some(_) => None,
+            }
+        } else {
+            None
+        };
+
+        Self {
+            root: project_root.to_path_buf(),
+            canonical_root: std::fs::canonicalize(project_root).unwrap_or_else(|_| project_root.to_path_buf()),
+            matcher,
+        }
+    }
+
+    /// Returns true when `path` should be skipped by a scan. Only the part
+    /// of `path` below the project root is matched, so a project that itself
+    /// sits inside a `target/` or `node_modules/` directory is still scanned.
+    /// A path outside the root is judged by its file name alone.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = match path
+            .strip_prefix(&self.root)
+            .or_else(|_| path.strip_prefix(&self.canonical_root))
+        {
+            Ok(relative) => relative,
+            Err(_) => return path.file_name().is_some_and(|n| DEFAULT_IGNORED_DIRS.iter().any(|d| n == *d)),
+        };
+        if relative
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .any(|c| DEFAULT_IGNORED_DIRS.contains(&c))
+        {
+            return true;
+        }
+
+        match &self.matcher {
+            Some(matcher) if relative.components().next().is_some() => {
+                matcher.matched_path_or_any_parents(relative, is_dir).is_ignore()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_defaults_and_gnawignore() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path();
+        fs::write(root.join(IGNORE_FILE), "generated/\n*.min.js\n").unwrap();
+
+        let ignore = GnawIgnore::load(root);
+        assert!(ignore.is_ignored(&root.join("target/debug/x.rs"), false));
+        assert!(ignore.is_ignored(&root.join("node_modules"), true));
+        assert!(ignore.is_ignored(&root.join("generated/a.py"), false));
+        assert!(ignore.is_ignored(&root.join("web/app.min.js"), false));
+        assert!(!ignore.is_ignored(&root.join("src/main.rs"), false));
+    }
+
+    #[test]
+    fn test_project_inside_an_ignored_directory_is_scanned() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("target").join("project");
+        fs::create_dir_all(&root).unwrap();
+
+        let ignore = GnawIgnore::load(&root);
+        assert!(!ignore.is_ignored(&root.join("src/main.rs"), false));
+        assert!(!ignore.is_ignored(&root.join("src"), true));
+        assert!(ignore.is_ignored(&root.join("node_modules/x.js"), false));
+    }
+}
@@ -1,5 +1,6 @@
 //! gnaw-refactor: Automated code refactoring
 
+use crate::core::GnawError;
 use crate::{GnawTreeWriter, TreeNode};
 use anyhow::Result;
 use serde::Serialize;
@@ -103,7 +104,7 @@ fn rename_symbol(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     let old_name = target.get_name().unwrap_or_else(|| "unnamed".to_string());
     let mut changes = Vec::new();
@@ -202,7 +203,7 @@ fn extract_function(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     // Get the block to extract
     let block = target.children.iter()
@@ -244,7 +245,7 @@ fn move_code(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     let changes = vec![Change {
         file: file_path.to_string(),
@@ -279,7 +280,7 @@ fn change_signature(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     let old_sig = target.get_name().unwrap_or_else(|| "function".to_string());
 
@@ -313,7 +314,7 @@ fn inline_function(
     let tree = writer.analyze();
 
     let target = tree.find_path(node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found at path: {}", node_path))?;
+        .ok_or_else(|| GnawError::node_not_found(node_path, tree))?;
 
     let func_name = target.get_name().unwrap_or_else(|| "function".to_string());
 
@@ -1,25 +1,31 @@
-use crate::parser::{get_parser, TreeNode};
+use crate::parser::{get_parser, ParserEngine, TreeNode};
 use anyhow::{Context, Result};
 use chrono::Utc;
-use std::collections::HashMap;
+use encoding_rs::{Encoding, UTF_8};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
 
 pub mod alf;
 pub mod anchor;
 pub mod backup;
 pub mod batch;
 pub mod gnaw_find;
+pub mod gnaw_ignore;
 pub mod inspect;
 pub mod blast;
 pub mod gnaw_refactor;
 pub mod gnaw_diff;
 pub mod gnaw_graph;
 pub mod blueprint;
+pub mod config;
 pub mod diff_parser;
 pub mod guardian;
 pub mod diagnostics;
+pub mod error;
 pub mod healer;
+pub mod replay;
 pub mod report;
 pub mod restoration_engine;
 pub mod scaffold;
@@ -31,24 +37,36 @@ pub mod undo_redo;
 pub mod visualizer;
 
 pub use batch::{Batch, BatchEdit};
+pub use error::GnawError;
 pub use gnaw_refactor::{RefactorKind, RefactorResult, Change, RefactorSummary, refactor, format_refactor_text};
-pub use restoration_engine::{RestorationEngine, RestorationResult, RestorationStats};
+pub use restoration_engine::{edits_by_file, RestorationEngine, RestorationResult, RestorationStats};
 pub use scaffold::ScaffoldEngine;
 pub use tag_manager::TagManager;
 pub use label_manager::LabelManager;
 pub use transaction_log::{
-    calculate_content_hash, FileRestorationPlan, OperationType, ProjectRestorationPlan,
-    Transaction, TransactionLog,
+    calculate_content_hash, set_label, set_log_failed_edits, FileRestorationPlan, OperationType,
+    ProjectRestorationPlan, Transaction, TransactionLog, TransactionStatus,
 };
 pub use undo_redo::{UndoRedoManager, UndoRedoResult, UndoRedoState};
 
 pub struct GnawTreeWriter {
     file_path: String,
     source_code: String,
+    /// The file started with a UTF-8 BOM, which is kept out of `source_code`
+    /// and written back on save
+    has_bom: bool,
+    /// Encoding of the file on disk; `source_code` is always UTF-8
+    encoding: &'static Encoding,
+    /// Hash of the file's content when it was last read or written, checked
+    /// again before each write so changes made by another tool in between
+    /// are not overwritten. `None` for buffers made with `from_source`.
+    disk_hash: Option<String>,
     tree: TreeNode,
     transaction_log: TransactionLog,
 }
 
+pub(crate) const UTF8_BOM: &str = "\u{feff}";
+
 #[derive(Debug, Clone)]
 pub enum EditOperation {
     Edit {
@@ -68,25 +86,455 @@ pub enum EditOperation {
     Delete {
         node_path: String,
     },
+    /// Comment out the node's lines using the file's comment syntax
+    Comment {
+        node_path: String,
+    },
+    /// Remove the comment markers added by `Comment` from the node's own lines
+    Uncomment {
+        node_path: String,
+    },
+    /// Indent every line of the node by `delta` levels in the file's indent
+    /// style, or dedent it if `delta` is negative
+    Reindent {
+        node_path: String,
+        delta: i32,
+    },
+    /// Replace an inclusive, 1-based line range without looking up a node
+    ReplaceLines {
+        start_line: usize,
+        end_line: usize,
+        content: String,
+    },
+    /// Replace several nodes at once. Every path is resolved against the
+    /// tree as it was before the batch, and the file is validated and
+    /// written once.
+    EditBatch {
+        edits: Vec<NodeEdit>,
+    },
+}
+
+/// One replacement in an `EditOperation::EditBatch`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NodeEdit {
+    pub node_path: String,
+    pub content: String,
+}
+
+/// Knobs for `GnawTreeWriter::edit_with_options`.
+///
+/// `no_backup` and `no_validate` are UNSAFE escape hatches for trusted,
+/// high-volume pipelines: without a backup the edit cannot be undone, and
+/// without validation a broken edit is written to disk as-is.
+#[derive(Debug, Clone, Default)]
+pub struct EditOptions {
+    /// Bypass the Guardian integrity check
+    pub force: bool,
+    /// Skip writing a backup before the file is changed
+    pub no_backup: bool,
+    /// Skip validating (and healing) the result before it is written. It is
+    /// parsed once afterwards, to refresh the tree and warn if it is broken;
+    /// a broken result leaves the previous tree in place.
+    pub no_validate: bool,
+    /// Only update the in-memory source and tree: no backup, no transaction
+    /// log and nothing written to disk
+    pub in_memory: bool,
+    /// Reject an `Edit` that changes the `node_type` at the edited path,
+    /// e.g. a function replaced by a class
+    pub strict_type: bool,
+    /// Who made the edit (an MCP client, say), recorded in the transaction
+    /// metadata under `transaction_log::CLIENT_ID_KEY`
+    pub client_id: Option<String>,
+}
+
+/// The metadata logged for `operation`: what replay needs to redo it, plus
+/// the client that made it, if known
+fn edit_metadata(operation: &EditOperation, client_id: Option<&str>) -> std::collections::HashMap<String, String> {
+    let mut metadata = replay::operation_metadata(operation);
+    if let Some(client_id) = client_id {
+        metadata.insert(transaction_log::CLIENT_ID_KEY.to_string(), client_id.to_string());
+    }
+    metadata
+}
+
+/// The operation type, node path and description logged for `operation`
+fn describe_operation(operation: &EditOperation) -> Result<(OperationType, Option<String>, String)> {
+    let described = match operation {
+        EditOperation::Edit {
+            node_path,
+            content: _,
+        } => (
+            OperationType::Edit,
+            Some(node_path.clone()),
+            format!("Edited node: {}", node_path),
+        ),
+        EditOperation::Insert {
+            parent_path,
+            position,
+            content: _,
+        } => (
+            OperationType::Insert,
+            Some(parent_path.clone()),
+            format!("Inserted content at {}, position {}", parent_path, position),
+        ),
+        EditOperation::Delete { node_path } => (
+            OperationType::Delete,
+            Some(node_path.clone()),
+            format!("Deleted node: {}", node_path),
+        ),
+        EditOperation::Comment { node_path } => (
+            OperationType::Edit,
+            Some(node_path.clone()),
+            format!("Commented out node: {}", node_path),
+        ),
+        EditOperation::Uncomment { node_path } => (
+            OperationType::Edit,
+            Some(node_path.clone()),
+            format!("Uncommented node: {}", node_path),
+        ),
+        EditOperation::Reindent { node_path, delta } => (
+            OperationType::Edit,
+            Some(node_path.clone()),
+            format!("Reindented node: {} by {:+} levels", node_path, delta),
+        ),
+        EditOperation::ReplaceLines { start_line, end_line, .. } => (
+            OperationType::Edit,
+            None,
+            format!("Replaced lines {}-{}", start_line, end_line),
+        ),
+        EditOperation::EditBatch { edits } => (
+            OperationType::Edit,
+            None,
+            format!(
+                "Edited {} nodes: {}",
+                edits.len(),
+                edits.iter().map(|e| e.node_path.as_str()).collect::<Vec<_>>().join(", ")
+            ),
+        ),
+        EditOperation::Clone {
+            source_path,
+            target_path,
+            target_node,
+        } => {
+            let _ = (source_path, target_path, target_node);
+            return Err(GnawError::Unsupported(
+                "Clone operation should be handled in CLI layer".into(),
+            )
+            .into());
+        }
+    };
+    Ok(described)
+}
+
+/// `source_code` with `node`'s text swapped for `new_content`. With column
+/// information only the node's span is replaced, otherwise its whole lines.
+fn replace_node_text(source_code: &str, node: &TreeNode, new_content: &str) -> String {
+    let lines: Vec<&str> = source_code.lines().collect();
+
+    // If we have column information, use it for surgical precision
+    if node.start_col > 0 && node.end_col > 0 {
+        let mut new_lines: Vec<String> = Vec::new();
+
+        // Lines before the node's start line
+        for i in 0..node.start_line - 1 {
+            if i < lines.len() {
+                new_lines.push(lines[i].to_string());
+            }
+        }
+
+        // Handle the start line (with prefix)
+        let start_line_idx = node.start_line - 1;
+        let start_line_text = lines[start_line_idx];
+        let prefix: String = start_line_text.chars().take(node.start_col - 1).collect();
+
+        // Handle the end line (with suffix)
+        let end_line_idx = node.end_line - 1;
+        let end_line_text = lines[end_line_idx];
+        let suffix: String = end_line_text.chars().skip(node.end_col - 1).collect();
+
+        // Combine prefix, new_content, and suffix
+        let mut combined = prefix;
+        combined.push_str(new_content);
+        combined.push_str(&suffix);
+
+        // Since combined might be multi-line if new_content is, we push its lines
+        // We use a custom splitting to preserve empty lines at the end if needed
+        let mut first = true;
+        for line in combined.split('\n') {
+            if first {
+                new_lines.push(line.to_string());
+                first = false;
+            } else {
+                new_lines.push(line.to_string());
+            }
+        }
+
+        // Lines after the node's end line
+        for line in lines.iter().skip(node.end_line) {
+            new_lines.push(line.to_string());
+        }
+
+        new_lines.join("\n")
+    } else {
+        let mut new_lines: Vec<String> = Vec::new();
+
+        // Lines before the node
+        for i in 0..node.start_line - 1 {
+            if i < lines.len() {
+                new_lines.push(lines[i].to_string());
+            }
+        }
+
+        // Add the new content
+        // Note: new_content might be multi-line
+        for line in new_content.lines() {
+            new_lines.push(line.to_string());
+        }
+
+        // Lines after the node
+        for line in lines.iter().skip(node.end_line) {
+            new_lines.push(line.to_string());
+        }
+
+        new_lines.join("\n")
+    }
+}
+
+/// The 0-based line index where `insert_node` puts content under `parent`
+/// for `position`, in a file of `line_count` lines. Fails if `parent`'s line
+/// span makes no sense for the file, rather than computing a line from it.
+fn insert_line(parent: &TreeNode, position: usize, line_count: usize) -> Result<usize> {
+    if parent.start_line == 0 || parent.end_line < parent.start_line {
+        return Err(GnawError::ValidationFailed(format!(
+            "Cannot insert into {} ({}): its line span {}-{} is invalid",
+            display_path(&parent.path),
+            parent.node_type,
+            parent.start_line,
+            parent.end_line
+        ))
+        .into());
+    }
+    if parent.start_line > line_count + 1 {
+        return Err(GnawError::ValidationFailed(format!(
+            "Cannot insert into {} ({}): it starts at line {}, past the end of the {}-line file",
+            display_path(&parent.path),
+            parent.node_type,
+            parent.start_line,
+            line_count
+        ))
+        .into());
+    }
+    Ok(match position {
+        0 => {
+            // If it starts with a brace, insert after it
+            if parent.content.trim_start().starts_with('{') {
+                parent.start_line
+            } else {
+                parent.start_line - 1
+            }
+        }
+        1 => {
+            // Insert at the end of the parent node.
+            // For source_file: TreeSitter end_line can exceed lines.len()
+            // (trailing newline counted as extra line), so we clamp.
+            // For other nodes (blocks, etc.): end_line points to the closing
+            // delimiter line, so we subtract 1 to insert BEFORE it.
+            if parent.node_type == "source_file" {
+                parent.end_line.min(line_count)
+            } else {
+                parent.end_line.saturating_sub(1)
+            }
+        },
+        2 => {
+            let mut last_prop_line = parent.start_line;
+            let mut found = false;
+            // After the last property of a QML object, or the last import
+            // (or pragma) at the top of a QML file
+            for child in &parent.children {
+                if matches!(child.node_type.as_str(), "ui_property" | "ui_binding" | "ui_id" | "ui_import" | "ui_pragma")
+                    && child.end_line < parent.end_line
+                {
+                    last_prop_line = child.end_line;
+                    found = true;
+                }
+            }
+            if found {
+                last_prop_line
+            } else {
+                // Fallback to top (after brace if exists)
+                parent.start_line
+            }
+        }
+        // SUPPORT FOR ARBITRARY INDICES
+        idx => {
+            // If we want to insert at a specific index relative to children
+            if idx - 3 < parent.children.len() {
+                parent.children[idx - 3].end_line
+            } else if !parent.children.is_empty() {
+                // If index is out of bounds but we have children, append after last child
+                parent.children.last().unwrap().end_line
+            } else {
+                // Fallback to inside parent (start)
+                parent.start_line
+            }
+        }
+    })
+}
+
+/// A node path for messages; the root's path is empty
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "the root node"
+    } else {
+        path
+    }
+}
+
+/// Prefix each line of `content` with its 1-based line number, counting from
+/// `first_line`, e.g. ` 9 | fn main() {`
+pub fn number_lines(content: &str, first_line: usize) -> String {
+    let last = first_line + content.lines().count().saturating_sub(1);
+    let width = last.to_string().len();
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$} | {}", first_line + i, line, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Each of `nodes` under a `--- <path> <node_type> <name> (lines a-b)`
+/// header, with its content (line-numbered if `line_numbers`)
+pub fn show_sections(nodes: &[&TreeNode], line_numbers: bool) -> String {
+    nodes
+        .iter()
+        .map(|node| {
+            let mut header = format!("--- {} {}", node.path, node.node_type);
+            if let Some(name) = node.get_name() {
+                header.push(' ');
+                header.push_str(&name);
+            }
+            let content = if line_numbers { number_lines(&node.content, node.start_line) } else { node.content.clone() };
+            format!("{} (lines {}-{})\n{}", header, node.start_line, node.end_line, content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Comment syntax for a file type, derived from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentSyntax {
+    Line(&'static str),
+    Block(&'static str, &'static str),
+}
+
+impl CommentSyntax {
+    pub fn for_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" | "ts" | "tsx" | "js" | "jsx" | "mjs" | "cjs" | "go" | "c" | "h" | "cpp" | "cc"
+            | "cxx" | "hpp" | "hh" | "hxx" | "java" | "kt" | "kts" | "swift" | "cs" | "dart"
+            | "zig" | "qml" | "php" | "slint" => Some(CommentSyntax::Line("//")),
+            "py" | "sh" | "bash" | "toml" | "yaml" | "yml" | "rb" => Some(CommentSyntax::Line("#")),
+            "sql" => Some(CommentSyntax::Line("--")),
+            "html" | "htm" | "xml" | "svg" | "xsl" | "xsd" | "md" | "markdown" | "svelte" => {
+                Some(CommentSyntax::Block("<!--", "-->"))
+            }
+            "css" => Some(CommentSyntax::Block("/*", "*/")),
+            _ => None,
+        }
+    }
 }
 
 impl GnawTreeWriter {
     pub fn new(file_path: &str) -> Result<Self> {
-        let path = Path::new(file_path);
-        let source_code =
-            fs::read_to_string(path).context(format!("Failed to read file: {}", file_path))?;
+        Self::new_with_encoding(file_path, source_encoding())
+    }
 
-        let parser = get_parser(path)?;
-        let tree = parser.parse(&source_code)?;
+    /// Like `new`, for a file in `encoding` instead of the `--encoding`
+    /// default. The source is decoded to UTF-8 and re-encoded on save.
+    pub fn new_with_encoding(file_path: &str, encoding: &'static Encoding) -> Result<Self> {
+        Self::open(file_path, encoding, false)
+    }
+
+    /// Like `new`, but a file with syntax errors still loads: the tree is the
+    /// parser's best effort, with unparsable code in `error` nodes. Meant for
+    /// `analyze` and `list` on a broken file; edits still check their result
+    /// with the strict parser.
+    pub fn new_tolerant(file_path: &str) -> Result<Self> {
+        Self::open(file_path, source_encoding(), true)
+    }
+
+    fn open(file_path: &str, encoding: &'static Encoding, tolerant: bool) -> Result<Self> {
+        let (source_code, has_bom) = read_source_file_as(Path::new(file_path), encoding)?;
+        Self::open_source(file_path, source_code, has_bom, encoding, tolerant)
+    }
+
+    /// Like `new` (or `new_tolerant`), for a file whose bytes the caller has
+    /// already read, so they are not read a second time. The caller is
+    /// responsible for the `max_file_size()` check.
+    #[cfg(feature = "mcp")]
+    pub(crate) fn from_file_bytes(file_path: &str, bytes: Vec<u8>, tolerant: bool) -> Result<Self> {
+        let encoding = source_encoding();
+        let (source_code, has_bom) = decode_source(Path::new(file_path), bytes, encoding)?;
+        Self::open_source(file_path, source_code, has_bom, encoding, tolerant)
+    }
+
+    fn open_source(
+        file_path: &str,
+        source_code: String,
+        has_bom: bool,
+        encoding: &'static Encoding,
+        tolerant: bool,
+    ) -> Result<Self> {
+        let path = Path::new(file_path);
 
         // Initialize transaction log for the project root
         // Use find_project_root to ensure we log to the correct centralized location
         let project_root = find_project_root(path);
+        let mut writer = Self::with_source(file_path, source_code, has_bom, encoding, project_root, tolerant)?;
+        writer.disk_hash = Some(calculate_content_hash(&writer.source_code));
+        Ok(writer)
+    }
+
+    /// Parse `source` without reading a file. `file_path_hint` picks the
+    /// parser by its extension and is the path later saves go to, in the
+    /// `--encoding` default; if its directory does not exist, the project
+    /// root is found from the current directory instead.
+    pub fn from_source(file_path_hint: &str, source: String) -> Result<Self> {
+        let (source_code, has_bom) = match source.strip_prefix(UTF8_BOM) {
+            Some(rest) => (rest.to_string(), true),
+            None => (source, false),
+        };
+        let hint = Path::new(file_path_hint);
+        let project_root = match hint.parent().filter(|p| p.is_dir()) {
+            Some(dir) => find_project_root(dir),
+            None => find_project_root(&std::env::current_dir()?),
+        };
+        Self::with_source(file_path_hint, source_code, has_bom, source_encoding(), project_root, false)
+    }
+
+    fn with_source(
+        file_path: &str,
+        source_code: String,
+        has_bom: bool,
+        encoding: &'static Encoding,
+        project_root: PathBuf,
+        tolerant: bool,
+    ) -> Result<Self> {
+        let parser = get_parser(Path::new(file_path)).map_err(|e| GnawError::Unsupported(e.to_string()))?;
+        if tolerant && !parser.recovers_from_errors() {
+            eprintln!("⚠️  --tolerant has no effect on {}: its parser cannot recover from syntax errors", file_path);
+        }
+        let parsed = if tolerant { parser.parse_tolerant(&source_code) } else { parser.parse(&source_code) };
+        let tree = parsed.map_err(|e| GnawError::Parse(e.to_string()))?;
         let transaction_log = TransactionLog::load(project_root)?;
 
         Ok(Self {
             file_path: file_path.to_string(),
             source_code,
+            has_bom,
+            encoding,
+            disk_hash: None,
             tree,
             transaction_log,
         })
@@ -98,26 +546,26 @@ impl GnawTreeWriter {
             .and_then(|n| n.to_str())
             .unwrap_or("unknown");
 
-        let timestamp = Utc::now().format("%Y%m%d_%H%M%S_%3f");
-        let backup_name = format!("{}_backup_{}.json", file_name, timestamp);
-
         // Backup should also be in project root to avoid scattering
         let project_root = find_project_root(Path::new(&self.file_path));
-        let backup_dir = project_root.join(".gnawtreewriter_backups");
+        let backup_dir = config::backup_dir(&project_root);
 
         fs::create_dir_all(&backup_dir)?;
 
-        let backup_path = backup_dir.join(&backup_name);
+        let backup_path = backup::new_backup_path(&backup_dir, file_name);
 
         let backup_data = serde_json::json!({
             "file_path": self.file_path,
             "timestamp": Utc::now().to_rfc3339(),
             "tree": &self.tree,
-            "source_code": self.source_code
+            "source_code": self.source_code,
+            "encoding": self.encoding.name(),
+            "bom": self.has_bom,
+            "mode": backup::file_mode(Path::new(&self.file_path))
         });
 
         fs::write(&backup_path, serde_json::to_string_pretty(&backup_data)?)
-            .context(format!("Failed to write backup: {}", backup_path.display()))?;
+            .map_err(|e| GnawError::io(format!("Failed to write backup: {}", backup_path.display()), e))?;
 
         Ok(backup_path)
     }
@@ -126,22 +574,75 @@ impl GnawTreeWriter {
         &self.tree
     }
 
+    /// Return the deepest node whose `start_line..=end_line` contains the
+    /// 1-based `line`, e.g. to map an editor cursor to an editable node.
+    pub fn node_at_line(&self, line: usize) -> Option<&TreeNode> {
+        self.tree.find_node_at_line(line)
+    }
+
+    /// Like `show_node`, with each line prefixed by its line number in the file
+    pub fn show_node_with_line_numbers(&self, node_path: &str) -> Result<String> {
+        let node = self
+            .resolve_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        Ok(number_lines(&node.content, node.start_line))
+    }
+
     pub fn show_node(&self, node_path: &str) -> Result<String> {
         let node = self
             .resolve_path(node_path)
-            .context(format!("Node not found: {}", node_path))?;
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
         Ok(node.content.clone())
     }
 
+    /// The node at `node_path` followed by its members (see
+    /// `TreeNode::members`), e.g. a class and then its methods and fields
+    pub fn node_members(&self, node_path: &str) -> Result<Vec<&TreeNode>> {
+        let node = self
+            .resolve_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        Ok(std::iter::once(node).chain(node.members()).collect())
+    }
+
+    /// Like `show_node`, for the node and then each of its members, see
+    /// `show_sections`
+    pub fn show_node_members(&self, node_path: &str, line_numbers: bool) -> Result<String> {
+        Ok(show_sections(&self.node_members(node_path)?, line_numbers))
+    }
+
     // Test indent insert
     pub fn edit(&mut self, operation: EditOperation, force: bool) -> Result<()> {
+        self.edit_with_options(
+            operation,
+            EditOptions {
+                force,
+                ..EditOptions::default()
+            },
+        )
+    }
+
+    /// Like `edit`, but with control over the backup and validation steps.
+    /// See `EditOptions` for why skipping them is unsafe.
+    pub fn edit_with_options(&mut self, operation: EditOperation, options: EditOptions) -> Result<()> {
+        let result = self.apply_edit(&operation, &options);
+        if let Err(e) = &result {
+            let rejected = matches!(e.downcast_ref::<GnawError>(), Some(GnawError::ValidationFailed(_)));
+            if rejected && !options.in_memory && transaction_log::log_failed_edits() {
+                self.log_failed_edit(&operation, options.client_id.as_deref(), e);
+            }
+        }
+        result
+    }
+
+    fn apply_edit(&mut self, operation: &EditOperation, options: &EditOptions) -> Result<()> {
+        let force = options.force;
         // Calculate before hash
         let before_hash = calculate_content_hash(&self.source_code);
 
-        let modified_code = match &operation {
+        let modified_code = match operation {
             EditOperation::Edit { node_path, content } => {
                 let resolved = self.resolve_path(node_path)
-                    .context(format!("Could not resolve node path: {}", node_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
                 self.edit_node_at_path(&resolved.path, content)?
             }
             EditOperation::Insert {
@@ -150,14 +651,33 @@ impl GnawTreeWriter {
                 content,
             } => {
                 let resolved = self.resolve_path(parent_path)
-                    .context(format!("Could not resolve parent path: {}", parent_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(parent_path, &self.tree))?;
                 self.insert_node_at_path(&resolved.path, *position, content)?
             },
             EditOperation::Delete { node_path } => {
                 let resolved = self.resolve_path(node_path)
-                    .context(format!("Could not resolve node path: {}", node_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
                 self.delete_node_at_path(&resolved.path)?
             },
+            EditOperation::Comment { node_path } => {
+                let resolved = self.resolve_path(node_path)
+                    .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+                self.comment_node_at_path(&resolved.path)?
+            },
+            EditOperation::Uncomment { node_path } => {
+                let resolved = self.resolve_path(node_path)
+                    .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+                self.uncomment_node_at_path(&resolved.path)?
+            },
+            EditOperation::Reindent { node_path, delta } => {
+                let resolved = self.resolve_path(node_path)
+                    .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+                self.reindent_node_at_path(&resolved.path, *delta)?
+            },
+            EditOperation::ReplaceLines { start_line, end_line, content } => {
+                self.replace_lines(*start_line, *end_line, content)?
+            }
+            EditOperation::EditBatch { edits } => self.edit_nodes(edits)?,
             EditOperation::Clone {
                 source_path,
                 target_path,
@@ -165,30 +685,38 @@ impl GnawTreeWriter {
             } => {
                 // Clone is handled in CLI layer, not in core edit
                 let _ = (source_path, target_path, target_node);
-                return Err(anyhow::anyhow!(
-                    "Clone operation should be handled in CLI layer"
-                ));
+                return Err(GnawError::Unsupported(
+                    "Clone operation should be handled in CLI layer".into(),
+                )
+                .into());
             }
         };
 
         // GUARDIAN INTEGRITY CHECK: Analyze the impact of the change
-        if let EditOperation::Edit { node_path, content: _ } = &operation {
+        let edited_paths: Vec<&str> = match operation {
+            EditOperation::Edit { node_path, content: _ } => vec![node_path.as_str()],
+            EditOperation::EditBatch { edits } => edits.iter().map(|e| e.node_path.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !edited_paths.is_empty() {
             if !force {
-                let resolved = self.resolve_path(node_path).context("Guardian could not resolve node")?;
                 let guardian = crate::core::guardian::GuardianEngine::new();
-                let report = guardian.audit_edit(resolved, &modified_code);
-                
-                match report.level {
-                    crate::core::guardian::IntegrityLevel::Critical => {
-                        return Err(anyhow::anyhow!("🛑 GUARDIAN BLOCK: This edit removes critical logic or structure.\nMessages: {}\nUse --force to override.", report.messages.join(", ")));
-                    }
-                    crate::core::guardian::IntegrityLevel::Warning => {
-                        eprintln!("⚠️  GUARDIAN WARNING: Significant structural loss detected: {}", report.messages.join(", "));
-                    }
-                    crate::core::guardian::IntegrityLevel::Notice => {
-                        eprintln!("ℹ️  Guardian Note: Minor structural reduction observed.");
+                for node_path in &edited_paths {
+                    let resolved = self.resolve_path(node_path).context("Guardian could not resolve node")?;
+                    let report = guardian.audit_edit(resolved, &modified_code);
+
+                    match report.level {
+                        crate::core::guardian::IntegrityLevel::Critical => {
+                            return Err(GnawError::ValidationFailed(format!("🛑 GUARDIAN BLOCK: This edit removes critical logic or structure.\nMessages: {}\nUse --force to override.", report.messages.join(", "))).into());
+                        }
+                        crate::core::guardian::IntegrityLevel::Warning => {
+                            eprintln!("⚠️  GUARDIAN WARNING: Significant structural loss detected: {}", report.messages.join(", "));
+                        }
+                        crate::core::guardian::IntegrityLevel::Notice => {
+                            eprintln!("ℹ️  Guardian Note: Minor structural reduction observed.");
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             } else {
                 eprintln!("🛡️  Guardian bypassed via --force.");
@@ -199,93 +727,86 @@ impl GnawTreeWriter {
         let path = Path::new(&self.file_path);
         let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
         let parser = get_parser(path)?;
-        
-        let modified_code = match parser.parse(&modified_code) {
-            Ok(_) => modified_code,
-            Err(e) => {
-                // TRY TO HEAL (Duplex Loop)
-                let healer = crate::core::healer::Healer::new();
-                if let Some(action) = healer.suggest_fix(&modified_code, &e, extension) {
-                    let mut healed_code = modified_code.clone();
-                    // Basic healing: append the fix
-                    healed_code.push_str(&action.fix);
+
+        // Unvalidated output is parsed only once, by `refresh_source` after
+        // the write, which also warns if it does not parse
+        let modified_code = if options.no_validate {
+            modified_code
+        } else {
+            match parser.parse(&modified_code) {
+                Ok(_) => modified_code,
+                Err(e) => {
+                    // TRY TO HEAL (Duplex Loop)
+                    let healer = crate::core::healer::Healer::new();
+                    if let Some(action) = healer.suggest_fix(&modified_code, &e, extension) {
+                        let mut healed_code = modified_code.clone();
+                        // Basic healing: append the fix
+                        healed_code.push_str(&action.fix);
                     
-                    // Validate healed code
-                    if parser.parse(&healed_code).is_ok() {
-                        eprintln!("✨ Duplex Loop: Automatically healed syntax error: {}", action.description);
-                        healed_code
+                        // Validate healed code
+                        if parser.parse(&healed_code).is_ok() {
+                            eprintln!("✨ Duplex Loop: Automatically healed syntax error: {}", action.description);
+                            healed_code
+                        } else {
+                            return Err(GnawError::ValidationFailed(format!("Validation failed: The proposed edit would result in invalid syntax.\nError: {}\n\nChange was NOT applied.", e)).into());
+                        }
                     } else {
-                        return Err(anyhow::anyhow!("Validation failed: The proposed edit would result in invalid syntax.\nError: {}\n\nChange was NOT applied.", e));
-                    }
-                } else {
-                    let tip = match extension {
-                        "rs" => "\n\n💡 Tip: In Rust, check for missing semicolons ';' at the end of statements, or unbalanced braces '{}'.",
-                        "qml" => "\n\n💡 Tip: In QML, ensure properties have a colon ':' and that braces '{}' and brackets '[]' are balanced.",
-                        "py" => "\n\n💡 Tip: In Python, check your indentation levels and ensure colons ':' are present after def/if/for/while.",
-                        _ => "\n\n💡 Tip: Ensure you included all necessary punctuation and punctuation is balanced for this file type.",
-                    };
+                        let tip = match extension {
+                            "rs" => "\n\n💡 Tip: In Rust, check for missing semicolons ';' at the end of statements, or unbalanced braces '{}'.",
+                            "qml" => "\n\n💡 Tip: In QML, ensure properties have a colon ':' and that braces '{}' and brackets '[]' are balanced.",
+                            "py" => "\n\n💡 Tip: In Python, check your indentation levels and ensure colons ':' are present after def/if/for/while.",
+                            _ => "\n\n💡 Tip: Ensure you included all necessary punctuation and punctuation is balanced for this file type.",
+                        };
                     
-                    let mut msg = format!("Validation failed: The proposed edit would result in invalid syntax.\nError: {}", e);
-                    if e.line > 0 {
-                        msg.push_str(&format!("\nCheck near line {}.", e.line));
+                        let mut msg = format!("Validation failed: The proposed edit would result in invalid syntax.\nError: {}", e);
+                        if e.line > 0 {
+                            msg.push_str(&format!("\nCheck near line {}.", e.line));
+                        }
+                        msg.push_str(tip);
+                        msg.push_str("\nChange was NOT applied.");
+                        return Err(GnawError::ValidationFailed(msg).into());
                     }
-                    msg.push_str(tip);
-                    msg.push_str("\nChange was NOT applied.");
-                    return Err(anyhow::anyhow!(msg));
                 }
             }
         };
 
+        if options.strict_type {
+            for node_path in &edited_paths {
+                self.check_node_type_kept(node_path, &modified_code, parser.as_ref())?;
+            }
+        }
+
+        if options.in_memory {
+            return self.refresh_source(modified_code, options.no_validate);
+        }
+
         // Calculate after hash
         let after_hash = calculate_content_hash(&modified_code);
 
+        let on_disk = self.encode_for_disk(&modified_code)?;
+
+        if !force {
+            self.check_unchanged_on_disk()?;
+        }
+
         // Only create backup and write if validation passed
-        self.create_backup()?;
+        if !options.no_backup {
+            self.create_backup()?;
+        }
 
         // Log the transaction
-        let (operation_type, node_path, description) = match &operation {
-            EditOperation::Edit {
-                node_path,
-                content: _,
-            } => (
-                OperationType::Edit,
-                Some(node_path.clone()),
-                format!("Edited node: {}", node_path),
-            ),
-            EditOperation::Insert {
-                parent_path,
-                position,
-                content: _,
-            } => (
-                OperationType::Insert,
-                Some(parent_path.clone()),
-                format!("Inserted content at {}, position {}", parent_path, position),
-            ),
-            EditOperation::Delete { node_path } => (
-                OperationType::Delete,
-                Some(node_path.clone()),
-                format!("Deleted node: {}", node_path),
-            ),
-            EditOperation::Clone {
-                source_path,
-                target_path,
-                target_node,
-            } => {
-                let _ = (source_path, target_path, target_node);
-                return Err(anyhow::anyhow!(
-                    "Clone operation should be handled in CLI layer"
-                ));
-            }
-        };
+        let (operation_type, node_path, description) = describe_operation(operation)?;
 
-        let transaction_id = self.transaction_log.log_transaction(
+        let diff = transaction_log::unified_diff(&self.source_code, &modified_code, &self.file_path);
+        let transaction_id = self.transaction_log.log_transaction_with_diff(
             operation_type,
             PathBuf::from(&self.file_path),
             node_path,
             Some(before_hash),
             Some(after_hash),
             description.clone(),
-            HashMap::new(),
+            edit_metadata(operation, options.client_id.as_deref()),
+            Some(diff),
         )?;
 
         // ALF INTEGRATION: Automatically log the tool use
@@ -298,22 +819,223 @@ impl GnawTreeWriter {
             );
         }
 
-        fs::write(&self.file_path, &modified_code)
-            .context(format!("Failed to write file: {}", self.file_path))?;
+        // The mode as it is now, so a chmod since loading is not undone
+        let mode = backup::file_mode(Path::new(&self.file_path));
+        write_source_file(Path::new(&self.file_path), &on_disk)
+            .and_then(|()| mode.map_or(Ok(()), |mode| backup::set_file_mode(Path::new(&self.file_path), mode)))
+            .map_err(|e| GnawError::io(format!("Failed to write file: {}", self.file_path), e))?;
+        self.disk_hash = Some(calculate_content_hash(&modified_code));
 
         // Refresh internal state to reflect the changes on disk
-        self.source_code = modified_code;
-        let parser = get_parser(Path::new(&self.file_path))?;
-        self.tree = parser.parse(&self.source_code)?;
+        self.refresh_source(modified_code, options.no_validate)
+    }
+
+    /// `source` as the bytes to write: re-encoded in the file's encoding,
+    /// with the BOM put back if the file had one
+    fn encode_for_disk(&self, source: &str) -> Result<Vec<u8>> {
+        if self.encoding == UTF_8 {
+            let bom = if self.has_bom { UTF8_BOM } else { "" };
+            return Ok(format!("{}{}", bom, source).into_bytes());
+        }
+        let (bytes, _, unmappable) = self.encoding.encode(source);
+        if unmappable {
+            return Err(GnawError::ValidationFailed(format!(
+                "The result contains characters that cannot be written as {}.\nChange was NOT applied.",
+                self.encoding.name()
+            ))
+            .into());
+        }
+        Ok(bytes.into_owned())
+    }
+
+    /// Record an edit that was rejected, with the reason, as a `Failed`
+    /// transaction. Logging problems are ignored: the rejection is what the
+    /// caller needs to see.
+    fn log_failed_edit(&mut self, operation: &EditOperation, client_id: Option<&str>, error: &anyhow::Error) {
+        let Ok((operation_type, node_path, description)) = describe_operation(operation) else {
+            return;
+        };
+        let mut metadata = edit_metadata(operation, client_id);
+        metadata.insert("error".to_string(), error.to_string());
+        let _ = self.transaction_log.log_failed_transaction(
+            operation_type,
+            PathBuf::from(&self.file_path),
+            node_path,
+            Some(calculate_content_hash(&self.source_code)),
+            description,
+            metadata,
+        );
+    }
+
+    /// Apply `operation` to the in-memory source only and return the result.
+    /// Validation and the Guardian check run as for `edit`, but the file on
+    /// disk, backups and the transaction log are left alone, which suits
+    /// buffers created with `from_source`.
+    pub fn edit_in_memory(&mut self, operation: EditOperation, force: bool) -> Result<String> {
+        self.edit_with_options(
+            operation,
+            EditOptions {
+                force,
+                in_memory: true,
+                ..EditOptions::default()
+            },
+        )?;
+        Ok(self.source_code.clone())
+    }
+
+    /// The insert that adds a QML child component `name { content }` at the
+    /// end of `parent_path`. `content` is nested one level below the
+    /// component in the file's indent style; the insert itself lines the
+    /// block up with its siblings.
+    pub fn qml_component_insert(&self, parent_path: &str, name: &str, content: Option<&str>) -> Result<EditOperation> {
+        let extension = Path::new(&self.file_path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        if extension != "qml" {
+            return Err(GnawError::Unsupported(format!(
+                "add-component only works on QML files, not {}; use insert to add code to other languages",
+                self.file_path
+            ))
+            .into());
+        }
+
+        let content = content.map(str::trim_end).filter(|c| !c.trim().is_empty());
+        let component = match content {
+            None => format!("{} {{}}", name),
+            Some(body) => {
+                let file_style = IndentStyle::detect(self.source_code.lines());
+                let body_style = IndentStyle::detect(body.lines());
+                let lines: Vec<String> = body.lines().map(|l| body_style.convert(l, file_style)).collect();
+                let base = common_indent(lines.iter().map(String::as_str));
+                let unit = file_style.unit();
+                let body: Vec<String> = lines
+                    .iter()
+                    .map(|l| match l.strip_prefix(base) {
+                        Some(rest) if !l.trim().is_empty() => format!("{}{}", unit, rest),
+                        _ => String::new(),
+                    })
+                    .collect();
+                format!("{} {{\n{}\n}}", name, body.join("\n"))
+            }
+        };
+
+        Ok(EditOperation::Insert {
+            parent_path: parent_path.to_string(),
+            position: 1,
+            content: component,
+        })
+    }
+
+    /// Re-parse `modified_code` and make sure the node at `node_path` still
+    /// has the type it had before the edit
+    fn check_node_type_kept(&self, node_path: &str, modified_code: &str, parser: &dyn ParserEngine) -> Result<()> {
+        let before = self
+            .resolve_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        let tree = parser.parse(modified_code).map_err(|e| GnawError::Parse(e.to_string()))?;
+        let after = tree.find_path(&before.path).map(|n| n.node_type.as_str());
+        if after != Some(before.node_type.as_str()) {
+            return Err(GnawError::ValidationFailed(format!(
+                "Strict type check failed: node {} is a {} but would become {}.\nChange was NOT applied.",
+                before.path,
+                before.node_type,
+                after.map_or("nothing (the path no longer exists)".to_string(), |t| format!("a {}", t))
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Fail if the file no longer holds what was loaded (or last written):
+    /// the edit was computed from a stale copy and would clobber the change
+    fn check_unchanged_on_disk(&self) -> Result<()> {
+        let Some(expected) = &self.disk_hash else {
+            return Ok(());
+        };
+        let current = read_source_file_as(Path::new(&self.file_path), self.encoding)
+            .map(|(source, _)| calculate_content_hash(&source))
+            .ok();
+        if current.as_ref() != Some(expected) {
+            return Err(GnawError::Conflict(format!(
+                "{}: file changed on disk since it was loaded; re-read it, or pass --force to overwrite",
+                self.file_path
+            ))
+            .into());
+        }
+        Ok(())
+    }
 
+    fn refresh_source(&mut self, source_code: String, no_validate: bool) -> Result<()> {
+        self.source_code = source_code;
+        let parser = get_parser(Path::new(&self.file_path))?;
+        match parser.parse(&self.source_code) {
+            Ok(tree) => self.tree = tree,
+            // Unvalidated output may not parse; keep the previous tree
+            Err(e) if no_validate => {
+                eprintln!("⚠️  --no-validate: the result does not parse and was applied anyway: {}", e);
+            }
+            Err(e) => return Err(e.into()),
+        }
         Ok(())
     }
 
+    /// Find/replace `find` with `replace` only within the span of the node at
+    /// `node_path`. Replaces every occurrence unless `count` limits it.
+    /// The result is applied through `edit`, so it is validated, backed up and
+    /// logged like any other node edit. Returns the number of replacements.
+    pub fn replace_in_node(
+        &mut self,
+        node_path: &str,
+        find: &str,
+        replace: &str,
+        count: Option<usize>,
+    ) -> Result<usize> {
+        self.replace_in_node_with_options(node_path, find, replace, count, EditOptions::default())
+    }
+
+    /// Like `replace_in_node`, applied through `edit_with_options`
+    pub fn replace_in_node_with_options(
+        &mut self,
+        node_path: &str,
+        find: &str,
+        replace: &str,
+        count: Option<usize>,
+        options: EditOptions,
+    ) -> Result<usize> {
+        if find.is_empty() {
+            return Err(GnawError::ValidationFailed("Search text must not be empty".into()).into());
+        }
+
+        let node = self
+            .resolve_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        let resolved_path = node.path.clone();
+
+        let occurrences = node.content.matches(find).count();
+        let replacements = count.map_or(occurrences, |c| c.min(occurrences));
+        if replacements == 0 {
+            return Err(GnawError::ValidationFailed(format!(
+                "'{}' not found in node {}",
+                find, node_path
+            ))
+            .into());
+        }
+
+        let new_content = node.content.replacen(find, replace, replacements);
+        self.edit_with_options(
+            EditOperation::Edit {
+                node_path: resolved_path,
+                content: new_content,
+            },
+            options,
+        )?;
+
+        Ok(replacements)
+    }
+
     pub fn preview_edit(&self, operation: EditOperation) -> Result<String> {
         match operation {
             EditOperation::Edit { node_path, content } => {
                 let resolved = self.resolve_path(&node_path)
-                    .context(format!("Could not resolve node path: {}", node_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(&node_path, &self.tree))?;
                 self.edit_node_at_path(&resolved.path, &content)
             }
             EditOperation::Insert {
@@ -322,14 +1044,33 @@ impl GnawTreeWriter {
                 content,
             } => {
                 let resolved = self.resolve_path(&parent_path)
-                    .context(format!("Could not resolve parent path: {}", parent_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(&parent_path, &self.tree))?;
                 self.insert_node_at_path(&resolved.path, position, &content)
             },
             EditOperation::Delete { node_path } => {
                 let resolved = self.resolve_path(&node_path)
-                    .context(format!("Could not resolve node path: {}", node_path))?;
+                    .ok_or_else(|| GnawError::node_not_found(&node_path, &self.tree))?;
                 self.delete_node_at_path(&resolved.path)
             },
+            EditOperation::Comment { node_path } => {
+                let resolved = self.resolve_path(&node_path)
+                    .ok_or_else(|| GnawError::node_not_found(&node_path, &self.tree))?;
+                self.comment_node_at_path(&resolved.path)
+            },
+            EditOperation::Uncomment { node_path } => {
+                let resolved = self.resolve_path(&node_path)
+                    .ok_or_else(|| GnawError::node_not_found(&node_path, &self.tree))?;
+                self.uncomment_node_at_path(&resolved.path)
+            },
+            EditOperation::Reindent { node_path, delta } => {
+                let resolved = self.resolve_path(&node_path)
+                    .ok_or_else(|| GnawError::node_not_found(&node_path, &self.tree))?;
+                self.reindent_node_at_path(&resolved.path, delta)
+            },
+            EditOperation::ReplaceLines { start_line, end_line, content } => {
+                self.replace_lines(start_line, end_line, &content)
+            }
+            EditOperation::EditBatch { edits } => self.edit_nodes(&edits),
             EditOperation::Clone {
                 source_path,
                 target_path,
@@ -345,7 +1086,7 @@ impl GnawTreeWriter {
 
     /// Resolves a path string which can be either a numeric path (1.2.3)
     /// or a semantic query (@fn:name, @struct:name, @name).
-    fn resolve_path<'a>(&'a self, query: &str) -> Option<&'a TreeNode> {
+    pub(crate) fn resolve_path<'a>(&'a self, query: &str) -> Option<&'a TreeNode> {
         if let Some(name_query) = query.strip_prefix('@') {
             // Semantic search
             if let Some((kind, name)) = name_query.split_once(':') {
@@ -356,24 +1097,10 @@ impl GnawTreeWriter {
             }
         } else {
             // Standard numeric path
-            self.find_node_by_path(&self.tree, query)
+            self.tree.find_path(query)
         }
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn find_node_by_path<'a>(&self, tree: &'a TreeNode, path: &str) -> Option<&'a TreeNode> {
-        if tree.path == path {
-            return Some(tree);
-        }
-
-        for child in &tree.children {
-            if let Some(node) = self.find_node_by_path(child, path) {
-                return Some(node);
-            }
-        }
-
-        None
-    }
 
     #[allow(clippy::only_used_in_recursion)]
     fn find_node_by_name<'a>(&self, tree: &'a TreeNode, name: &str, kind: Option<&str>) -> Option<&'a TreeNode> {
@@ -412,78 +1139,74 @@ impl GnawTreeWriter {
 
     fn edit_node_at_path(&self, node_path: &str, new_content: &str) -> Result<String> {
         let node = self
-            .find_node_by_path(&self.tree, node_path)
-            .context(format!("Node not found at path: {}", node_path))?;
-
-        let lines: Vec<&str> = self.source_code.lines().collect();
-
-        // If we have column information, use it for surgical precision
-        if node.start_col > 0 && node.end_col > 0 {
-            let mut new_lines: Vec<String> = Vec::new();
-
-            // Lines before the node's start line
-            for i in 0..node.start_line - 1 {
-                if i < lines.len() {
-                    new_lines.push(lines[i].to_string());
-                }
-            }
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        Ok(replace_node_text(&self.source_code, node, new_content))
+    }
 
-            // Handle the start line (with prefix)
-            let start_line_idx = node.start_line - 1;
-            let start_line_text = lines[start_line_idx];
-            let prefix: String = start_line_text.chars().take(node.start_col - 1).collect();
-
-            // Handle the end line (with suffix)
-            let end_line_idx = node.end_line - 1;
-            let end_line_text = lines[end_line_idx];
-            let suffix: String = end_line_text.chars().skip(node.end_col - 1).collect();
-
-            // Combine prefix, new_content, and suffix
-            let mut combined = prefix;
-            combined.push_str(new_content);
-            combined.push_str(&suffix);
-
-            // Since combined might be multi-line if new_content is, we push its lines
-            // We use a custom splitting to preserve empty lines at the end if needed
-            let mut first = true;
-            for line in combined.split('\n') {
-                if first {
-                    new_lines.push(line.to_string());
-                    first = false;
-                } else {
-                    new_lines.push(line.to_string());
-                }
-            }
+    /// Apply every edit in a batch to the source. Paths are resolved against
+    /// the current tree up front, then the nodes are replaced from the end of
+    /// the file backwards so the positions of the ones before stay valid.
+    fn edit_nodes(&self, edits: &[NodeEdit]) -> Result<String> {
+        if edits.is_empty() {
+            return Err(GnawError::ValidationFailed("The batch has no edits".into()).into());
+        }
+        let mut targets = Vec::with_capacity(edits.len());
+        for edit in edits {
+            let node = self
+                .resolve_path(&edit.node_path)
+                .ok_or_else(|| GnawError::node_not_found(&edit.node_path, &self.tree))?;
+            targets.push((node, edit.content.as_str()));
+        }
 
-            // Lines after the node's end line
-            for line in lines.iter().skip(node.end_line) {
-                new_lines.push(line.to_string());
+        targets.sort_by_key(|(node, _)| (node.start_line, node.start_col));
+        for pair in targets.windows(2) {
+            let (first, second) = (pair[0].0, pair[1].0);
+            let whole_lines = |n: &TreeNode| n.start_col == 0 || n.end_col == 0;
+            let overlaps = if whole_lines(first) || whole_lines(second) {
+                second.start_line <= first.end_line
+            } else {
+                (second.start_line, second.start_col) < (first.end_line, first.end_col)
+            };
+            if overlaps {
+                return Err(GnawError::ValidationFailed(format!(
+                    "Edits to {} and {} overlap; each node may only be edited once per batch",
+                    first.path, second.path
+                ))
+                .into());
             }
+        }
 
-            Ok(new_lines.join("\n"))
-        } else {
-            let mut new_lines: Vec<String> = Vec::new();
+        let mut source = self.source_code.clone();
+        for (node, content) in targets.iter().rev() {
+            source = replace_node_text(&source, node, content);
+        }
+        Ok(source)
+    }
 
-            // Lines before the node
-            for i in 0..node.start_line - 1 {
-                if i < lines.len() {
-                    new_lines.push(lines[i].to_string());
-                }
-            }
+    /// Swap lines `start_line..=end_line` (1-based) for `new_content`
+    fn replace_lines(&self, start_line: usize, end_line: usize, new_content: &str) -> Result<String> {
+        let lines: Vec<&str> = self.source_code.lines().collect();
+        if start_line == 0 || start_line > end_line || end_line > lines.len() {
+            return Err(GnawError::ValidationFailed(format!(
+                "Invalid line range {}-{}: the file has {} lines",
+                start_line,
+                end_line,
+                lines.len()
+            ))
+            .into());
+        }
 
-            // Add the new content
-            // Note: new_content might be multi-line
-            for line in new_content.lines() {
-                new_lines.push(line.to_string());
-            }
+        let mut new_lines: Vec<&str> = lines[..start_line - 1].to_vec();
+        new_lines.extend(new_content.lines());
+        new_lines.extend(&lines[end_line..]);
 
-            // Lines after the node
-            for line in lines.iter().skip(node.end_line) {
-                new_lines.push(line.to_string());
-            }
-
-            Ok(new_lines.join("\n"))
+        let mut result = new_lines.join("\n");
+        if self.source_code.ends_with('\n') {
+            result.push('\n');
         }
+        Ok(result)
     }
 
     fn insert_node_at_path(
@@ -493,68 +1216,30 @@ impl GnawTreeWriter {
         content: &str,
     ) -> Result<String> {
         let parent = self
-            .find_node_by_path(&self.tree, node_path)
-            .context(format!("Parent node not found at path: {}", node_path))?;
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
 
         let lines: Vec<&str> = self.source_code.lines().collect();
         let mut new_lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
 
-        let insert_pos = match position {
-            0 => {
-                // If it starts with a brace, insert after it
-                if parent.content.trim_start().starts_with('{') {
-                    parent.start_line
-                } else {
-                    parent.start_line - 1
-                }
-            }
-            1 => {
-                // Insert at the end of the parent node.
-                // For source_file: TreeSitter end_line can exceed lines.len()
-                // (trailing newline counted as extra line), so we clamp.
-                // For other nodes (blocks, etc.): end_line points to the closing
-                // delimiter line, so we subtract 1 to insert BEFORE it.
-                if parent.node_type == "source_file" {
-                    parent.end_line.min(lines.len())
-                } else {
-                    parent.end_line.saturating_sub(1)
-                }
-            },
-            2 => {
-                let mut last_prop_line = parent.start_line;
-                let mut found = false;
-                for child in &parent.children {
-                    if (child.node_type == "ui_property" || child.node_type == "ui_binding")
-                        && child.end_line < parent.end_line
-                    {
-                        last_prop_line = child.end_line;
-                        found = true;
-                    }
-                }
-                if found {
-                    last_prop_line
-                } else {
-                    // Fallback to top (after brace if exists)
-                    parent.start_line
-                }
-            }
-            // SUPPORT FOR ARBITRARY INDICES
-            idx => {
-                // If we want to insert at a specific index relative to children
-                if idx - 3 < parent.children.len() {
-                    parent.children[idx - 3].end_line
-                } else if !parent.children.is_empty() {
-                    // If index is out of bounds but we have children, append after last child
-                    parent.children.last().unwrap().end_line
-                } else {
-                    // Fallback to inside parent (start)
-                    parent.start_line
-                }
-            }
-        };
+        let insert_pos = insert_line(parent, position, lines.len())?;
 
         // Detect indentation from parent or siblings
-        let indentation = if !lines.is_empty() {
+        let leading_ws = |line: &str| -> String { line.chars().take_while(|c| c.is_whitespace()).collect() };
+        let before_closer = insert_pos > 0
+            && insert_pos < lines.len()
+            && lines[insert_pos].trim_start().starts_with(['}', ']', ')']);
+        let indentation = if before_closer {
+            // Inserting before a closing delimiter: line up with the block's
+            // body rather than with the delimiter
+            let prev = lines[insert_pos - 1];
+            if prev.trim_end().ends_with(['{', '[', '(']) {
+                format!("{}{}", leading_ws(prev), IndentStyle::detect(lines.iter().copied()).unit())
+            } else {
+                leading_ws(prev)
+            }
+        } else if !lines.is_empty() {
             let ref_line = if insert_pos < lines.len() {
                 lines[insert_pos]
             } else {
@@ -578,9 +1263,26 @@ impl GnawTreeWriter {
             String::new()
         };
 
-        let indented_content: Vec<String> = content
+        // Re-express the content's own indentation in the file's convention so
+        // tab-indented files don't end up with mixed tabs and spaces.
+        let file_style = IndentStyle::detect(lines.iter().copied());
+        let content_style = IndentStyle::detect(content.lines());
+
+        let normalized: Vec<String> = content
             .lines()
-            .map(|line| format!("{}{}", indentation, line))
+            .map(|line| content_style.convert(line, file_style))
+            .collect();
+
+        // Strip the block's own base indentation so pre-indented content is not
+        // double-indented; relative nesting inside the block is preserved.
+        let base_indent = common_indent(normalized.iter().map(String::as_str));
+
+        let indented_content: Vec<String> = normalized
+            .iter()
+            .map(|line| match line.strip_prefix(base_indent) {
+                Some(rest) if !line.trim().is_empty() => format!("{}{}", indentation, rest),
+                _ => String::new(),
+            })
             .collect();
 
         if insert_pos >= new_lines.len() {
@@ -596,8 +1298,9 @@ impl GnawTreeWriter {
 
     fn delete_node_at_path(&self, node_path: &str) -> Result<String> {
         let node = self
-            .find_node_by_path(&self.tree, node_path)
-            .context(format!("Node not found at path: {}", node_path))?;
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
 
         let lines: Vec<&str> = self.source_code.lines().collect();
         let start_idx = node.start_line - 1;
@@ -611,14 +1314,519 @@ impl GnawTreeWriter {
 
         Ok(new_lines.join("\n"))
     }
+    fn comment_syntax(&self) -> Result<CommentSyntax> {
+        let extension = Path::new(&self.file_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        CommentSyntax::for_extension(extension).ok_or_else(|| {
+            GnawError::Unsupported(format!("Commenting is not supported for '.{}' files", extension))
+                .into()
+        })
+    }
+
+    /// Replace lines `start_idx..end_idx` (0-based, exclusive) and rebuild the
+    /// source, keeping a trailing newline if the original had one.
+    fn splice_lines(&self, start_idx: usize, end_idx: usize, replacement: Vec<String>) -> String {
+        let lines: Vec<&str> = self.source_code.lines().collect();
+        let mut new_lines: Vec<String> = lines[..start_idx].iter().map(|s| s.to_string()).collect();
+        new_lines.extend(replacement);
+        new_lines.extend(lines[end_idx..].iter().map(|s| s.to_string()));
+
+        let mut result = new_lines.join("\n");
+        if self.source_code.ends_with('\n') {
+            result.push('\n');
+        }
+        result
+    }
+
+    fn node_line_range(&self, node: &TreeNode) -> Result<(usize, usize)> {
+        let line_count = self.source_code.lines().count();
+        let start_idx = node.start_line.saturating_sub(1);
+        let end_idx = node.end_line.min(line_count);
+        if start_idx >= end_idx {
+            return Err(GnawError::ValidationFailed(format!("Node {} spans no lines", node.path)).into());
+        }
+        Ok((start_idx, end_idx))
+    }
+
+    /// The source with the lines of the node at `node_path` shifted by
+    /// `delta` indentation levels. Blank lines, and lines that start inside
+    /// a multi-line string, are left alone; dedenting fails if a line is not
+    /// indented deeply enough.
+    fn reindent_node_at_path(&self, node_path: &str, delta: i32) -> Result<String> {
+        if delta == 0 {
+            return Err(GnawError::ValidationFailed("Reindent needs a non-zero number of levels".into()).into());
+        }
+        if delta.unsigned_abs() as usize > MAX_REINDENT_LEVELS {
+            return Err(GnawError::ValidationFailed(format!(
+                "Cannot reindent by {} levels: at most {} at a time",
+                delta, MAX_REINDENT_LEVELS
+            ))
+            .into());
+        }
+        let node = self
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        let (start_idx, end_idx) = self.node_line_range(node)?;
+        let lines: Vec<&str> = self.source_code.lines().collect();
+
+        // 1-based lines that continue a string begun on an earlier line;
+        // their leading whitespace is part of the string's value
+        let mut in_string = std::collections::HashSet::new();
+        self.tree.walk(|n, _| {
+            let is_string = n.node_type.contains("string") || n.node_type.contains("heredoc");
+            if is_string {
+                in_string.extend(n.start_line + 1..=n.end_line);
+            }
+            !is_string
+        });
+        let code_lines = lines.iter().enumerate().filter(|(i, _)| !in_string.contains(&(i + 1)));
+        let unit = IndentStyle::detect(code_lines.map(|(_, l)| *l)).unit();
+        let levels = delta.unsigned_abs() as usize;
+
+        let mut reindented = Vec::with_capacity(end_idx - start_idx);
+        for (i, line) in lines[start_idx..end_idx].iter().enumerate() {
+            if line.trim().is_empty() || in_string.contains(&(start_idx + i + 1)) {
+                reindented.push(line.to_string());
+            } else if delta > 0 {
+                reindented.push(format!("{}{}", unit.repeat(levels), line));
+            } else {
+                let mut rest = *line;
+                for _ in 0..levels {
+                    rest = rest
+                        .strip_prefix(unit.as_str())
+                        .or_else(|| rest.strip_prefix('\t'))
+                        .ok_or_else(|| {
+                            GnawError::ValidationFailed(format!(
+                                "Cannot dedent {} by {} levels: line {} is not indented that far",
+                                node_path,
+                                levels,
+                                start_idx + i + 1
+                            ))
+                        })?;
+                }
+                reindented.push(rest.to_string());
+            }
+        }
+        Ok(self.splice_lines(start_idx, end_idx, reindented))
+    }
+
+    fn comment_node_at_path(&self, node_path: &str) -> Result<String> {
+        let node = self
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        let syntax = self.comment_syntax()?;
+        let (start_idx, end_idx) = self.node_line_range(node)?;
+        let lines: Vec<&str> = self.source_code.lines().collect();
+        let span = &lines[start_idx..end_idx];
+
+        let commented: Vec<String> = match syntax {
+            CommentSyntax::Line(prefix) => {
+                // Put the markers in one column, after the indent all lines share
+                let indent = common_indent(span.iter().copied());
+                span.iter()
+                    .map(|line| match line.strip_prefix(indent) {
+                        Some(rest) if !line.trim().is_empty() => {
+                            format!("{}{} {}", indent, prefix, rest)
+                        }
+                        _ => line.to_string(),
+                    })
+                    .collect()
+            }
+            CommentSyntax::Block(open, close) => {
+                let mut out: Vec<String> = span.iter().map(|l| l.to_string()).collect();
+                let first = &out[0];
+                let (ws, rest) = first.split_at(first.len() - first.trim_start().len());
+                out[0] = format!("{}{} {}", ws, open, rest);
+                let last = out.len() - 1;
+                out[last] = format!("{} {}", out[last].trim_end(), close);
+                out
+            }
+        };
+
+        Ok(self.splice_lines(start_idx, end_idx, commented))
+    }
+
+    fn uncomment_node_at_path(&self, node_path: &str) -> Result<String> {
+        let node = self
+            .tree
+            .find_path(node_path)
+            .ok_or_else(|| GnawError::node_not_found(node_path, &self.tree))?;
+        let syntax = self.comment_syntax()?;
+        let (start_idx, end_idx) = self.node_line_range(node)?;
+        let lines: Vec<&str> = self.source_code.lines().collect();
+
+        let mut end_idx = end_idx;
+        let uncommented: Vec<String> = match syntax {
+            CommentSyntax::Line(prefix) => {
+                if !lines[start_idx..end_idx].iter().any(|l| uncomment_line(l, prefix).is_some()) {
+                    return Err(GnawError::ValidationFailed(format!("Node {} is not commented out", node_path)).into());
+                }
+                // Comment turns a multi-line node into one comment per line;
+                // take in the rest of that run so the two round-trip
+                end_idx = comment_run_end(&lines, start_idx, end_idx, prefix);
+                lines[start_idx..end_idx]
+                    .iter()
+                    .map(|line| uncomment_line(line, prefix).unwrap_or_else(|| line.to_string()))
+                    .collect()
+            }
+            CommentSyntax::Block(open, close) => {
+                let mut out: Vec<String> =
+                    lines[start_idx..end_idx].iter().map(|l| l.to_string()).collect();
+                let first = out[0].clone();
+                let (ws, rest) = first.split_at(first.len() - first.trim_start().len());
+                let body = rest
+                    .strip_prefix(open)
+                    .ok_or_else(|| GnawError::ValidationFailed(format!("Node {} is not commented out", node_path)))?;
+                out[0] = format!("{}{}", ws, body.strip_prefix(' ').unwrap_or(body));
+
+                let last = out.len() - 1;
+                let trimmed = out[last].trim_end().to_string();
+                let body = trimmed
+                    .strip_suffix(close)
+                    .ok_or_else(|| GnawError::ValidationFailed(format!("Node {} is not commented out", node_path)))?;
+                out[last] = body.strip_suffix(' ').unwrap_or(body).to_string();
+                out
+            }
+        };
+
+        Ok(self.splice_lines(start_idx, end_idx, uncommented))
+    }
+
     pub fn get_source(&self) -> &str {
         &self.source_code
     }
 }
 
-/// Helper function to find the project root
-/// Searches upwards for .gnawtreewriter_session.json or .git
+/// `line` with its leading `prefix` comment marker (and one space after it)
+/// removed, or `None` if it is not a line comment.
+fn uncomment_line(line: &str, prefix: &str) -> Option<String> {
+    let (ws, rest) = line.split_at(line.len() - line.trim_start().len());
+    let body = rest.strip_prefix(prefix)?;
+    Some(format!("{}{}", ws, body.strip_prefix(' ').unwrap_or(body)))
+}
+
+/// Where the run of comment lines that starts at `start_idx` ends. The run
+/// goes on past `end_idx` while the uncommented code still has open brackets
+/// or is indented deeper than its first line, so a commented-out block is
+/// taken whole, blank lines included, but a comment after it is not.
+fn comment_run_end(lines: &[&str], start_idx: usize, end_idx: usize, prefix: &str) -> usize {
+    let indent = |code: &str| code.len() - code.trim_start().len();
+    let brackets = |code: &str| {
+        code.chars()
+            .map(|c| match c {
+                '{' | '(' | '[' => 1,
+                '}' | ')' | ']' => -1,
+                _ => 0,
+            })
+            .sum::<i32>()
+    };
+    let Some(first) = uncomment_line(lines[start_idx], prefix) else {
+        return end_idx;
+    };
+    let base = indent(&first);
+    let mut depth: i32 = lines[start_idx..end_idx]
+        .iter()
+        .filter_map(|l| uncomment_line(l, prefix))
+        .map(|code| brackets(&code))
+        .sum();
+
+    let mut end = end_idx;
+    while let Some(next) = (end..lines.len()).find(|&i| !lines[i].trim().is_empty()) {
+        let Some(code) = uncomment_line(lines[next], prefix) else {
+            break;
+        };
+        if depth <= 0 && indent(&code) <= base {
+            break;
+        }
+        depth += brackets(&code);
+        end = next + 1;
+    }
+    end
+}
+
+/// Default upper bound on the size of a file `GnawTreeWriter` will load
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+static MAX_FILE_SIZE: AtomicU64 = AtomicU64::new(DEFAULT_MAX_FILE_SIZE);
+
+/// Set the process-wide file size limit, e.g. from `--max-file-size`.
+pub fn set_max_file_size(bytes: u64) {
+    MAX_FILE_SIZE.store(bytes, Ordering::Relaxed);
+}
+
+pub fn max_file_size() -> u64 {
+    MAX_FILE_SIZE.load(Ordering::Relaxed)
+}
+
+static ATOMIC_WRITES: AtomicBool = AtomicBool::new(true);
+
+/// Write edited files in place instead of through a temporary file, e.g.
+/// from `atomic_writes = false`. In-place writes keep hard links shared.
+pub fn set_atomic_writes(enabled: bool) {
+    ATOMIC_WRITES.store(enabled, Ordering::Relaxed);
+}
+
+/// Write an edited source file: atomically (see `write_atomic`) unless
+/// turned off with `set_atomic_writes`
+pub fn write_source_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if ATOMIC_WRITES.load(Ordering::Relaxed) {
+        write_atomic(path, contents)
+    } else {
+        fs::write(path, contents)
+    }
+}
+
+/// Number of `write_atomic` calls so far, used to name their temp files
+static ATOMIC_WRITE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Write `contents` to a temporary file next to `path` and rename it over
+/// `path`, so a write that is interrupted leaves the old file whole. The
+/// file keeps its permissions, and a symlink is written through to its
+/// target rather than replaced.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let target = if path.is_symlink() { fs::canonicalize(path)? } else { path.to_path_buf() };
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    // Unique per write, so concurrent writes of one file never share a temp file
+    let n = ATOMIC_WRITE_COUNT.fetch_add(1, Ordering::Relaxed);
+    let temp = dir.join(format!(".{}.gnaw-tmp-{}-{}", name, std::process::id(), n));
+
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp)?;
+    let result = (|| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+        if let Ok(metadata) = fs::metadata(&target) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+static ENCODING: RwLock<&'static Encoding> = RwLock::new(UTF_8);
+
+/// Set the process-wide source encoding, e.g. from `--encoding`.
+pub fn set_encoding(encoding: &'static Encoding) {
+    *ENCODING.write().unwrap_or_else(|e| e.into_inner()) = encoding;
+}
+
+/// The encoding files are read and written in (UTF-8 unless set)
+pub fn source_encoding() -> &'static Encoding {
+    *ENCODING.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Look up an encoding by name, e.g. `latin1`, `shift_jis` or `utf-8`.
+/// UTF-16 (and the `replacement` encoding) are refused: they can be read but
+/// not written back, `Encoding::encode` would produce UTF-8.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("unknown encoding '{}': use a name such as utf-8, latin1 or shift_jis", label))?;
+    if encoding.output_encoding() != encoding {
+        return Err(format!(
+            "encoding '{}' is not supported: files in {} cannot be written back unchanged",
+            label,
+            encoding.name()
+        ));
+    }
+    Ok(encoding)
+}
+
+/// Read a source file in `source_encoding()`, see `read_source_file_as`.
+pub fn read_source_file(path: &Path) -> Result<(String, bool)> {
+    read_source_file_as(path, source_encoding())
+}
+
+/// Read a source file as UTF-8, stripping a leading BOM, or decode it from
+/// another `encoding`. Returns the text and whether a BOM was present.
+/// Files larger than `max_file_size()` are refused before being read.
+pub fn read_source_file_as(path: &Path, encoding: &'static Encoding) -> Result<(String, bool)> {
+    let limit = max_file_size();
+    if let Ok(meta) = fs::metadata(path) {
+        if meta.len() > limit {
+            return Err(GnawError::FileTooLarge {
+                path: path.display().to_string(),
+                size: meta.len(),
+                limit,
+            }
+            .into());
+        }
+    }
+
+    let bytes = fs::read(path)
+        .map_err(|e| GnawError::io(format!("Failed to read file: {}", path.display()), e))?;
+    decode_source(path, bytes, encoding)
+}
+
+/// Decode the bytes of the source file at `path` as `read_source_file_as` does
+fn decode_source(path: &Path, bytes: Vec<u8>, encoding: &'static Encoding) -> Result<(String, bool)> {
+    if encoding != UTF_8 {
+        let text = encoding.decode_without_bom_handling_and_without_replacement(&bytes).ok_or_else(|| {
+            GnawError::Parse(format!("File {} is not valid {}", path.display(), encoding.name()))
+        })?;
+        return Ok((text.into_owned(), false));
+    }
+
+    let text = String::from_utf8(bytes).map_err(|e| {
+        let offset = e.utf8_error().valid_up_to();
+        let line = e.as_bytes()[..offset].iter().filter(|b| **b == b'\n').count() + 1;
+        GnawError::Parse(format!(
+            "File {} is not valid UTF-8 (invalid byte at offset {}, line {}).\n\
+             Pass its encoding with --encoding, e.g. --encoding latin1, or convert the file first:\n  \
+             iconv -f latin1 -t utf-8 {} -o {}.utf8",
+            path.display(),
+            offset,
+            line,
+            path.display(),
+            path.display()
+        ))
+    })?;
+
+    match text.strip_prefix(UTF8_BOM) {
+        Some(stripped) => Ok((stripped.to_string(), true)),
+        None => Ok((text, false)),
+    }
+}
+
+/// Most indentation levels one reindent may add or remove
+pub const MAX_REINDENT_LEVELS: usize = 64;
+
+/// Indentation convention of a block of text: tabs, or spaces of a given width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndentStyle {
+    Tabs,
+    Spaces(usize),
+}
+
+impl IndentStyle {
+    /// Guess the convention from the leading whitespace of `lines`.
+    /// Falls back to four spaces when nothing is indented.
+    fn detect<'a>(lines: impl Iterator<Item = &'a str>) -> Self {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut width = 0;
+
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            if line.starts_with('\t') {
+                tabs += 1;
+            } else {
+                let n = line.chars().take_while(|c| *c == ' ').count();
+                if n > 0 {
+                    spaces += 1;
+                    width = if width == 0 { n } else { gcd(width, n) };
+                }
+            }
+        }
+
+        if tabs > spaces {
+            IndentStyle::Tabs
+        } else if width > 0 {
+            IndentStyle::Spaces(width)
+        } else {
+            IndentStyle::Spaces(4)
+        }
+    }
+
+    /// One level of indentation
+    fn unit(self) -> String {
+        match self {
+            IndentStyle::Tabs => "\t".to_string(),
+            IndentStyle::Spaces(width) => " ".repeat(width),
+        }
+    }
+
+    /// Rewrite the leading whitespace of `line` from `self` into `target`.
+    fn convert(self, line: &str, target: IndentStyle) -> String {
+        if self == target {
+            return line.to_string();
+        }
+
+        let ws_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let (ws, rest) = line.split_at(ws_len);
+
+        let mut levels = 0;
+        let mut pending_spaces = 0;
+        for c in ws.chars() {
+            if c == '\t' {
+                levels += 1;
+            } else {
+                pending_spaces += 1;
+            }
+        }
+        if let IndentStyle::Spaces(width) = self {
+            levels += pending_spaces / width;
+            pending_spaces %= width;
+        }
+
+        let prefix = match target {
+            IndentStyle::Tabs => "\t".repeat(levels),
+            IndentStyle::Spaces(width) => " ".repeat(levels * width),
+        };
+        format!("{}{}{}", prefix, " ".repeat(pending_spaces), rest)
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The leading whitespace every non-blank line of `lines` starts with.
+/// Stripping it with `strip_prefix` cannot cut a line inside a character.
+fn common_indent<'a>(lines: impl Iterator<Item = &'a str>) -> &'a str {
+    let mut common: Option<&str> = None;
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(c) => {
+                let shared: usize = c
+                    .chars()
+                    .zip(indent.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum();
+                &c[..shared]
+            }
+        });
+    }
+    common.unwrap_or("")
+}
+
+/// Files or directories that mark a project root, checked in each directory
+/// on the way up: the project config file, the session file, `.git`
+pub const PROJECT_ROOT_MARKERS: &[&str] = &[config::CONFIG_FILE, ".gnawtreewriter_session.json", ".git"];
+
+/// Environment variable that overrides root detection
+pub const PROJECT_ROOT_ENV: &str = "GNAW_PROJECT_ROOT";
+
+/// Find the project root that backups, transaction logs and the index are
+/// stored under.
+///
+/// `GNAW_PROJECT_ROOT` wins if set. Otherwise this walks up from `start_path`
+/// to the nearest directory containing one of `PROJECT_ROOT_MARKERS`, and
+/// falls back to the start directory if there is none.
 pub fn find_project_root(start_path: &Path) -> PathBuf {
+    if let Some(root) = std::env::var_os(PROJECT_ROOT_ENV).filter(|v| !v.is_empty()) {
+        let root = PathBuf::from(root);
+        return fs::canonicalize(&root).unwrap_or(root);
+    }
+
     let mut current = if start_path.is_file() {
         start_path.parent().unwrap_or(Path::new(".")).to_path_buf()
     } else {
@@ -633,8 +1841,7 @@ pub fn find_project_root(start_path: &Path) -> PathBuf {
     let start = current.clone();
 
     loop {
-        // Check for session file or git
-        if current.join(".gnawtreewriter_session.json").exists() || current.join(".git").exists() {
+        if PROJECT_ROOT_MARKERS.iter().any(|m| current.join(m).exists()) {
             return current;
         }
 
@@ -643,4 +1850,65 @@ pub fn find_project_root(start_path: &Path) -> PathBuf {
             return start;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_into_a_zero_span_parent_is_an_error_not_a_panic() {
+        let parent = TreeNode {
+            path: "0".into(),
+            node_type: "block".into(),
+            start_line: 0,
+            end_line: 0,
+            ..TreeNode::default()
+        };
+        for position in [0, 1, 2, 5] {
+            let err = insert_line(&parent, position, 3).unwrap_err();
+            assert_eq!(err.to_string(), "Cannot insert into 0 (block): its line span 0-0 is invalid");
+            assert_eq!(GnawError::classify(&err).unwrap().kind, "validation_failed");
+        }
+
+        let past_end = TreeNode { start_line: 9, end_line: 10, ..parent.clone() };
+        let err = insert_line(&past_end, 0, 3).unwrap_err();
+        assert!(err.to_string().contains("starts at line 9, past the end of the 3-line file"), "{}", err);
+
+        let block = TreeNode { start_line: 2, end_line: 3, ..parent };
+        assert_eq!(insert_line(&block, 0, 3).unwrap(), 1);
+        assert_eq!(insert_line(&block, 1, 3).unwrap(), 2);
+    }
+
+    #[test]
+    fn common_indent_never_splits_a_character() {
+        assert_eq!(common_indent(["    a", "  b", "", "      c"].into_iter()), "  ");
+        // U+3000 is whitespace three bytes long
+        assert_eq!(common_indent(["\u{3000}a", "  b"].into_iter()), "");
+        assert_eq!(common_indent(["\t\u{3000}a", "\t\u{3000} b"].into_iter()), "\t\u{3000}");
+        assert_eq!(common_indent(["", "  "].into_iter()), "");
+    }
+
+    #[test]
+    fn concurrent_atomic_writes_of_one_file_all_land() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.txt");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(&path, format!("writer {}\n", i).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert!(fs::read_to_string(&path).unwrap().starts_with("writer "));
+        // No temp files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
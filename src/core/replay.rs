@@ -0,0 +1,244 @@
+//! Replay of an exported transaction log onto another checkout.
+//!
+//! `GnawTreeWriter::edit` records the operation and its content in each
+//! transaction's metadata (see `operation_metadata`). Replay rebuilds the
+//! operations from that, checks that each file still matches the recorded
+//! `before_hash`, and re-applies them in order.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::transaction_log::{calculate_content_hash, OperationType, Transaction};
+use crate::core::{read_source_file, EditOperation, EditOptions, GnawTreeWriter};
+
+const META_OP: &str = "op";
+const META_CONTENT: &str = "content";
+const META_POSITION: &str = "position";
+const META_START_LINE: &str = "start_line";
+const META_END_LINE: &str = "end_line";
+const META_EDITS: &str = "edits";
+const META_DELTA: &str = "delta";
+
+/// Metadata that lets a logged edit be replayed later
+pub(crate) fn operation_metadata(operation: &EditOperation) -> HashMap<String, String> {
+    let mut meta = HashMap::new();
+    let op = match operation {
+        EditOperation::Edit { content, .. } => {
+            meta.insert(META_CONTENT.to_string(), content.clone());
+            "edit"
+        }
+        EditOperation::Insert { position, content, .. } => {
+            meta.insert(META_CONTENT.to_string(), content.clone());
+            meta.insert(META_POSITION.to_string(), position.to_string());
+            "insert"
+        }
+        EditOperation::Delete { .. } => "delete",
+        EditOperation::Comment { .. } => "comment",
+        EditOperation::Uncomment { .. } => "uncomment",
+        EditOperation::Reindent { delta, .. } => {
+            meta.insert(META_DELTA.to_string(), delta.to_string());
+            "reindent"
+        }
+        EditOperation::ReplaceLines { start_line, end_line, content } => {
+            meta.insert(META_CONTENT.to_string(), content.clone());
+            meta.insert(META_START_LINE.to_string(), start_line.to_string());
+            meta.insert(META_END_LINE.to_string(), end_line.to_string());
+            "replace_lines"
+        }
+        EditOperation::EditBatch { edits } => {
+            let Ok(edits) = serde_json::to_string(edits) else {
+                return meta;
+            };
+            meta.insert(META_EDITS.to_string(), edits);
+            "edit_batch"
+        }
+        EditOperation::Clone { .. } => return meta,
+    };
+    meta.insert(META_OP.to_string(), op.to_string());
+    meta
+}
+
+/// Rebuild the `EditOperation` recorded in a transaction, if it has enough
+/// information to be replayed
+pub fn operation_from_transaction(transaction: &Transaction) -> Option<EditOperation> {
+    let meta = &transaction.metadata;
+    let content = || meta.get(META_CONTENT).cloned();
+    if meta.get(META_OP).map(String::as_str) == Some("replace_lines") {
+        return Some(EditOperation::ReplaceLines {
+            start_line: meta.get(META_START_LINE)?.parse().ok()?,
+            end_line: meta.get(META_END_LINE)?.parse().ok()?,
+            content: content()?,
+        });
+    }
+    if meta.get(META_OP).map(String::as_str) == Some("edit_batch") {
+        let edits = serde_json::from_str(meta.get(META_EDITS)?).ok()?;
+        return Some(EditOperation::EditBatch { edits });
+    }
+    let node_path = transaction.node_path.clone()?;
+
+    let op = match meta.get(META_OP).map(String::as_str) {
+        Some("edit") => EditOperation::Edit { node_path, content: content()? },
+        Some("insert") => EditOperation::Insert {
+            parent_path: node_path,
+            position: meta.get(META_POSITION)?.parse().ok()?,
+            content: content()?,
+        },
+        Some("delete") => EditOperation::Delete { node_path },
+        Some("comment") => EditOperation::Comment { node_path },
+        Some("uncomment") => EditOperation::Uncomment { node_path },
+        Some("reindent") => EditOperation::Reindent { node_path, delta: meta.get(META_DELTA)?.parse().ok()? },
+        // Logs written before operations were recorded: deletes need nothing else
+        None if transaction.operation == OperationType::Delete => EditOperation::Delete { node_path },
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Read transactions from an `export` file (a JSON array) or a raw session
+/// log (one JSON transaction per line)
+pub fn load_transactions(path: &Path) -> Result<Vec<Transaction>> {
+    let data = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read transaction log: {}", path.display()))?;
+    if data.trim_start().starts_with('[') {
+        return serde_json::from_str(&data)
+            .with_context(|| format!("Invalid exported history: {}", path.display()));
+    }
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).context("Invalid transaction line"))
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayConflict {
+    pub transaction_id: String,
+    pub file_path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplayReport {
+    pub applied: usize,
+    /// Transactions that are not edits (sessions, restores, ...)
+    pub skipped: usize,
+    pub conflicts: Vec<ReplayConflict>,
+}
+
+/// How recorded file paths map onto the checkout being replayed into
+pub struct ReplayOptions {
+    /// Relative paths are resolved against this directory
+    pub project_root: PathBuf,
+    /// Prefix to strip from recorded absolute paths (the original checkout);
+    /// the remainder is resolved against `project_root`
+    pub strip_prefix: Option<PathBuf>,
+    /// Only check for conflicts, don't write anything
+    pub preview: bool,
+}
+
+impl ReplayOptions {
+    fn resolve(&self, recorded: &Path) -> PathBuf {
+        if let Some(prefix) = &self.strip_prefix {
+            if let Ok(rest) = recorded.strip_prefix(prefix) {
+                return self.project_root.join(rest);
+            }
+        }
+        if recorded.is_absolute() {
+            recorded.to_path_buf()
+        } else {
+            self.project_root.join(recorded)
+        }
+    }
+}
+
+/// Re-apply the edit operations in `transactions`, in order. A file whose
+/// content does not match a transaction's `before_hash` is reported as a
+/// conflict and none of its later transactions are applied.
+pub fn replay(transactions: &[Transaction], options: &ReplayOptions) -> Result<ReplayReport> {
+    let mut report = ReplayReport::default();
+    let mut blocked: HashSet<PathBuf> = HashSet::new();
+    // In preview mode nothing is written, so track the hash each file would have
+    let mut expected: HashMap<PathBuf, String> = HashMap::new();
+
+    for transaction in transactions {
+        if !matches!(
+            transaction.operation,
+            OperationType::Edit | OperationType::Insert | OperationType::Delete
+        ) {
+            report.skipped += 1;
+            continue;
+        }
+
+        let file_path = options.resolve(&transaction.file_path);
+        let conflict = |report: &mut ReplayReport, reason: String| {
+            report.conflicts.push(ReplayConflict {
+                transaction_id: transaction.id.clone(),
+                file_path: file_path.clone(),
+                reason,
+            });
+        };
+
+        if blocked.contains(&file_path) {
+            conflict(&mut report, "Skipped after an earlier conflict in this file".into());
+            continue;
+        }
+        let Some(operation) = operation_from_transaction(transaction) else {
+            conflict(&mut report, "Transaction does not record enough content to replay".into());
+            continue;
+        };
+
+        let current_hash = match expected.get(&file_path) {
+            Some(hash) => hash.clone(),
+            None => match read_source_file(&file_path) {
+                Ok((source, _)) => calculate_content_hash(&source),
+                Err(e) => {
+                    conflict(&mut report, format!("Cannot read file: {}", e));
+                    blocked.insert(file_path);
+                    continue;
+                }
+            },
+        };
+        if let Some(before) = &transaction.before_hash {
+            if &current_hash != before {
+                conflict(&mut report, "File has diverged from the recorded state".into());
+                blocked.insert(file_path);
+                continue;
+            }
+        }
+
+        if options.preview {
+            if let Some(after) = &transaction.after_hash {
+                expected.insert(file_path, after.clone());
+            }
+            report.applied += 1;
+            continue;
+        }
+
+        let path_str = file_path.to_string_lossy().to_string();
+        let result = GnawTreeWriter::new(&path_str).and_then(|mut writer| {
+            let options = EditOptions {
+                force: true,
+                ..EditOptions::default()
+            };
+            writer.edit_with_options(operation, options)?;
+            Ok(calculate_content_hash(writer.get_source()))
+        });
+        match result {
+            Ok(after) => {
+                report.applied += 1;
+                if transaction.after_hash.as_ref().is_some_and(|h| h != &after) {
+                    conflict(&mut report, "Applied, but the result differs from the recorded state".into());
+                    blocked.insert(file_path);
+                }
+            }
+            Err(e) => {
+                conflict(&mut report, format!("Failed to apply: {}", e));
+                blocked.insert(file_path);
+            }
+        }
+    }
+
+    Ok(report)
+}
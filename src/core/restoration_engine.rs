@@ -1,13 +1,17 @@
-use crate::core::transaction_log::{ProjectRestorationPlan, Transaction, TransactionLog};
+use crate::core::transaction_log::{
+    calculate_content_hash, OperationType, ProjectRestorationPlan, Transaction, TransactionLog, CLIENT_ID_KEY,
+    LABEL_KEY,
+};
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
 
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 /// Engine for executing file and project restoration operations
 pub struct RestorationEngine {
-    _project_root: PathBuf,
+    project_root: PathBuf,
     backup_dir: PathBuf,
     transaction_log: TransactionLog,
 }
@@ -32,7 +36,7 @@ impl RestorationEngine {
     /// Create a new restoration engine
     pub fn new<P: AsRef<Path>>(project_root: P) -> Result<Self> {
         let project_root = project_root.as_ref().to_path_buf();
-        let backup_dir = project_root.join(".gnawtreewriter_backups");
+        let backup_dir = crate::core::config::backup_dir(&project_root);
         let transaction_log = TransactionLog::load(project_root.clone())?;
 
         if !backup_dir.exists() {
@@ -40,7 +44,7 @@ impl RestorationEngine {
         }
 
         Ok(Self {
-            _project_root: project_root,
+            project_root,
             backup_dir,
             transaction_log,
         })
@@ -102,23 +106,35 @@ impl RestorationEngine {
 
     /// Restore a single file to the state after a specific transaction
     pub fn restore_file_to_transaction(&self, transaction_id: &str) -> Result<PathBuf> {
+        let (transaction, backup) = self.backup_for_transaction(transaction_id)?;
+        self.restore_from_backup(&transaction.file_path, &backup)
+    }
+
+    /// The file and the content `restore_file_to_transaction` would write to
+    /// it, without writing anything
+    pub fn restoration_target(&self, transaction_id: &str) -> Result<(PathBuf, String)> {
+        let (transaction, backup) = self.backup_for_transaction(transaction_id)?;
+        Ok((transaction.file_path, crate::core::backup::read_backup_source(backup)?))
+    }
+
+    /// The backup holding a file's state after `transaction_id`
+    fn backup_for_transaction(&self, transaction_id: &str) -> Result<(Transaction, PathBuf)> {
         // Find the transaction
         let transaction = self
             .transaction_log
             .find_transaction(transaction_id)?
             .ok_or_else(|| anyhow!("Transaction not found: {}", transaction_id))?;
 
-        // Try hash-based restoration first
-        if let Ok(result) = self.restore_by_hash(&transaction) {
-            return Ok(result);
-        }
-
-        // Fallback to timestamp-based restoration
-        self.restore_by_timestamp(&transaction)
+        // Try hash-based lookup first, then fall back to timestamps
+        let backup = match self.backup_by_hash(&transaction) {
+            Ok(backup) => backup,
+            Err(_) => self.backup_by_timestamp(&transaction)?,
+        };
+        Ok((transaction, backup))
     }
 
-    /// Attempt restoration using hash matching
-    fn restore_by_hash(&self, transaction: &Transaction) -> Result<PathBuf> {
+    /// Find the backup by hash matching
+    fn backup_by_hash(&self, transaction: &Transaction) -> Result<PathBuf> {
         let target_hash = transaction
             .after_hash
             .as_ref()
@@ -126,7 +142,7 @@ impl RestorationEngine {
 
         // Try to find backup by after_hash first
         if let Some(backup_file) = self.find_backup_by_content_hash(target_hash)? {
-            return self.restore_from_backup(&transaction.file_path, &backup_file.path);
+            return Ok(backup_file.path);
         }
 
         // Try to find the next transaction that has our after_hash as before_hash
@@ -136,7 +152,7 @@ impl RestorationEngine {
             if let Some(next_before_hash) = &next_tx.before_hash {
                 if next_before_hash == target_hash {
                     if let Some(backup_file) = self.find_backup_by_content_hash(next_before_hash)? {
-                        return self.restore_from_backup(&transaction.file_path, &backup_file.path);
+                        return Ok(backup_file.path);
                     }
                 }
             }
@@ -145,8 +161,8 @@ impl RestorationEngine {
         Err(anyhow!("Hash-based restoration failed"))
     }
 
-    /// Attempt restoration using timestamp matching
-    fn restore_by_timestamp(&self, transaction: &Transaction) -> Result<PathBuf> {
+    /// Find the backup by timestamp matching
+    fn backup_by_timestamp(&self, transaction: &Transaction) -> Result<PathBuf> {
         eprintln!("🔄 Falling back to timestamp-based restoration");
 
         let backups = self.list_backup_files()?;
@@ -178,7 +194,7 @@ impl RestorationEngine {
         match best_backup {
             Some(backup) => {
                 eprintln!("✅ Using timestamp-based backup: {}", backup.path.display());
-                self.restore_from_backup(&transaction.file_path, &backup.path)
+                Ok(backup.path.clone())
             }
             None => Err(anyhow!("No suitable backup found for transaction")),
         }
@@ -264,9 +280,83 @@ impl RestorationEngine {
 
     /// Restore all files affected in a specific session
     pub fn restore_session(&self, session_id: &str) -> Result<RestorationResult> {
-        let session_files = self.transaction_log.get_session_files(session_id)?;
+        let session_transactions = self.get_session_transactions(session_id)?;
+        self.restore_before_transactions(&format!("session: {}", session_id), &session_transactions)
+    }
 
-        if session_files.is_empty() {
+    /// Restore all files touched by transactions tagged with `label` to
+    /// their state before the first of them. Unlabelled or differently
+    /// labelled edits made to those files since would be lost too, so unless
+    /// `force` is set it is refused when `label_conflicts` finds any.
+    pub fn restore_label(&self, label: &str, force: bool) -> Result<RestorationResult> {
+        if !force {
+            refuse_conflicts(&format!("label {}", label), &self.label_conflicts(label)?, |t| {
+                match t.metadata.get(LABEL_KEY) {
+                    Some(other) => format!("under label {}", other),
+                    None => "without a label".to_string(),
+                }
+            })?;
+        }
+        let labelled = self.transaction_log.get_label_history(label)?;
+        self.restore_before_transactions(&format!("label: {}", label), &labelled)
+    }
+
+    /// Restore all files edited by `client_id` to their state before its
+    /// first edit. That would also undo what other clients changed in those
+    /// files since, so unless `force` is set it is refused when
+    /// `client_conflicts` finds any such edits.
+    pub fn restore_client(&self, client_id: &str, force: bool) -> Result<RestorationResult> {
+        if !force {
+            refuse_conflicts(&format!("client {}", client_id), &self.client_conflicts(client_id)?, |t| {
+                let other = t.metadata.get(CLIENT_ID_KEY).map_or("an unnamed client", String::as_str);
+                format!("by {}", other)
+            })?;
+        }
+        let edits = self.transaction_log.get_client_history(client_id)?;
+        self.restore_before_transactions(&format!("client: {}", client_id), &edits)
+    }
+
+    /// Edits by other clients (or by no named client) to the files
+    /// `client_id` edited, made after its first edit of each file, oldest
+    /// first
+    pub fn client_conflicts(&self, client_id: &str) -> Result<Vec<Transaction>> {
+        let own = self.transaction_log.get_client_history(client_id)?;
+        self.later_edits_by_others(&own, CLIENT_ID_KEY, client_id)
+    }
+
+    /// Edits without `label` to the files edited under it, made after the
+    /// first labelled edit of each file, oldest first
+    pub fn label_conflicts(&self, label: &str) -> Result<Vec<Transaction>> {
+        let own = self.transaction_log.get_label_history(label)?;
+        self.later_edits_by_others(&own, LABEL_KEY, label)
+    }
+
+    /// Edits to the files edited in `own`, made after the first of them in
+    /// each file, whose `key` metadata is not `value`, oldest first
+    fn later_edits_by_others(&self, own: &[Transaction], key: &str, value: &str) -> Result<Vec<Transaction>> {
+        let is_edit = |t: &Transaction| {
+            t.status.is_applied()
+                && matches!(t.operation, OperationType::Edit | OperationType::Insert | OperationType::Delete)
+        };
+        let own: Vec<Transaction> = own.iter().filter(|t| is_edit(t)).cloned().collect();
+
+        let mut conflicts = Vec::new();
+        for (file, edits) in edits_by_file(own) {
+            let since = edits[0].timestamp;
+            conflicts.extend(self.transaction_log.get_file_history(&file)?.into_iter().filter(|t| {
+                is_edit(t) && t.timestamp > since && t.metadata.get(key).map(String::as_str) != Some(value)
+            }));
+        }
+        conflicts.sort_by_key(|t| t.timestamp);
+        Ok(conflicts)
+    }
+
+    /// Restore every file edited in `transactions` to its content just
+    /// before its first edit there
+    fn restore_before_transactions(&self, what: &str, transactions: &[Transaction]) -> Result<RestorationResult> {
+        let files: Vec<PathBuf> = edits_by_file(transactions.to_vec()).into_iter().map(|(file, _)| file).collect();
+
+        if files.is_empty() {
             return Ok(RestorationResult {
                 restored_files: Vec::new(),
                 failed_files: Vec::new(),
@@ -275,19 +365,81 @@ impl RestorationEngine {
             });
         }
 
-        eprintln!("🔄 Restoring session: {}", session_id);
-        eprintln!("Files to restore: {}", session_files.len());
+        eprintln!("🔄 Restoring {}", what);
+        eprintln!("Files to restore: {}", files.len());
 
-        // For session restoration, we want to find the state of each file
-        // just before the session started
-        let session_transactions = self.get_session_transactions(session_id)?;
-        let session_start_time = session_transactions
+        // We want the state of each file just before the first transaction
+        let start_time = transactions
             .iter()
             .map(|t| t.timestamp)
             .min()
-            .ok_or_else(|| anyhow!("Session has no transactions"))?;
+            .ok_or_else(|| anyhow!("No transactions to restore"))?;
+
+        let mut restored_files = Vec::new();
+        let mut failed_files = Vec::new();
+
+        for file_path in &files {
+            // The backup taken before the file's first edit holds exactly the
+            // earlier content; older history is only a fallback.
+            let restored = self
+                .restore_file_to_session_start(file_path, transactions)
+                .or_else(|_| self.restore_file_before_timestamp(file_path, start_time));
+
+            match restored {
+                Ok(restored_path) => {
+                    eprintln!("✅ Restored: {}", restored_path.display());
+                    restored_files.push(restored_path);
+                }
+                Err(e) => {
+                    let error_msg = format!("Failed to restore: {}", e);
+                    eprintln!("❌ Failed to restore {}: {}", file_path.display(), error_msg);
+                    failed_files.push((file_path.clone(), error_msg));
+                }
+            }
+        }
 
-        self.restore_files_before_timestamp(&session_files, session_start_time)
+        Ok(RestorationResult {
+            success: failed_files.is_empty(),
+            total_files: files.len(),
+            restored_files,
+            failed_files,
+        })
+    }
+
+    /// Restore a file from the backup matching the before_hash of its first
+    /// transaction in `session_transactions`
+    fn restore_file_to_session_start(
+        &self,
+        file_path: &Path,
+        session_transactions: &[Transaction],
+    ) -> Result<PathBuf> {
+        let first = session_transactions
+            .iter()
+            .filter(|t| t.file_path == file_path)
+            .filter(|t| {
+                matches!(
+                    t.operation,
+                    crate::core::transaction_log::OperationType::Edit
+                        | crate::core::transaction_log::OperationType::Insert
+                        | crate::core::transaction_log::OperationType::Delete
+                )
+            })
+            .min_by_key(|t| t.timestamp)
+            .ok_or_else(|| anyhow!("No session transactions for {}", file_path.display()))?;
+
+        let before_hash = first
+            .before_hash
+            .as_ref()
+            .ok_or_else(|| anyhow!("Transaction has no before_hash"))?;
+
+        let backup = crate::core::backup::find_backup_by_content_hash_for_file(
+            &self.backup_dir,
+            before_hash,
+            file_path,
+        )?
+        .ok_or_else(|| anyhow!("No backup found for hash {}", before_hash))?;
+
+        self.restore_from_backup(file_path, &backup.path)
     }
 
     /// Get all transactions for a specific session
@@ -354,10 +506,39 @@ impl RestorationEngine {
     // `parse_backup_file` removed. Use the helpers in `crate::core::backup`
     // (e.g. `crate::core::backup::parse_backup_file`) directly where needed.
 
-    /// Restore file from a backup file (delegates to core::backup)
+    /// Restore file from a backup file (delegates to core::backup).
+    ///
+    /// The pre-restore content is backed up and a `Restore` transaction is
+    /// logged, so `undo` can revert the restoration.
     fn restore_from_backup(&self, target_path: &Path, backup_path: &Path) -> Result<PathBuf> {
+        // The file is read in the encoding it is restored to; one that is
+        // there but cannot be read must not lose its only copy
+        let encoding = crate::core::backup::read_backup_encoding(backup_path)?;
+        let before = if target_path.exists() {
+            let (content, has_bom) = crate::core::read_source_file_as(target_path, encoding)
+                .context(format!("Cannot back up {} before restoring it", target_path.display()))?;
+            crate::core::backup::write_backup_as(&self.backup_dir, target_path, &content, encoding, has_bom)?;
+            Some(content)
+        } else {
+            None
+        };
+
         // Note: core::backup::restore_from_backup expects (backup_path, target_path)
-        crate::core::backup::restore_from_backup(backup_path, target_path)
+        let restored = crate::core::backup::restore_from_backup(backup_path, target_path)?;
+
+        let (after, _) = crate::core::read_source_file_as(&restored, encoding)?;
+        let mut log = TransactionLog::load(&self.project_root)?;
+        log.log_transaction(
+            OperationType::Restore,
+            target_path.to_path_buf(),
+            None,
+            before.as_deref().map(calculate_content_hash),
+            Some(calculate_content_hash(&after)),
+            format!("Restored from backup: {}", backup_path.display()),
+            HashMap::new(),
+        )?;
+
+        Ok(restored)
     }
 
     /// Get restoration statistics
@@ -418,6 +599,41 @@ impl RestorationResult {
     }
 }
 
+/// The edits, inserts and deletes in `transactions` grouped by file, in
+/// the order the files were first touched. Other operations are left out.
+pub fn edits_by_file(transactions: Vec<Transaction>) -> Vec<(PathBuf, Vec<Transaction>)> {
+    let mut files: Vec<(PathBuf, Vec<Transaction>)> = Vec::new();
+    for t in transactions {
+        if !matches!(t.operation, OperationType::Edit | OperationType::Insert | OperationType::Delete) {
+            continue;
+        }
+        match files.iter_mut().find(|(path, _)| *path == t.file_path) {
+            Some((_, edits)) => edits.push(t),
+            None => files.push((t.file_path.clone(), vec![t])),
+        }
+    }
+    files
+}
+
+/// Refuse to restore `what` when that would discard `conflicts`, listing
+/// each one with `describe` saying whose edit it was
+fn refuse_conflicts(what: &str, conflicts: &[Transaction], describe: impl Fn(&Transaction) -> String) -> Result<()> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let listed: Vec<String> = conflicts
+        .iter()
+        .map(|t| format!("  {} edited {} ({})", t.file_path.display(), describe(t), t.id))
+        .collect();
+    Err(crate::core::GnawError::ValidationFailed(format!(
+        "Restoring {} would discard {} later edits made outside it:\n{}\nPass force to restore anyway.",
+        what,
+        conflicts.len(),
+        listed.join("\n")
+    ))
+    .into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -429,7 +645,7 @@ mod tests {
         let engine = RestorationEngine::new(temp_dir.path()).unwrap();
 
         assert!(engine.backup_dir.exists());
-        assert_eq!(engine._project_root, temp_dir.path());
+        assert_eq!(engine.project_root, temp_dir.path());
     }
 
     #[test]
@@ -443,4 +659,47 @@ mod tests {
 
         assert_eq!(result.success_rate(), 2.0 / 3.0);
     }
+
+    #[test]
+    fn test_restore_is_logged_and_undoable() -> Result<()> {
+        use crate::core::{EditOperation, GnawTreeWriter, UndoRedoManager};
+
+        let temp_dir = tempdir()?;
+        fs::create_dir(temp_dir.path().join(".git"))?;
+        let root = fs::canonicalize(temp_dir.path())?;
+        let file = root.join("state.py");
+        fs::write(&file, "x = 1\n")?;
+
+        let mut writer = GnawTreeWriter::new(file.to_str().unwrap())?;
+        for value in ["x = 2", "x = 3"] {
+            writer.edit(
+                EditOperation::Edit {
+                    node_path: "0".to_string(),
+                    content: value.to_string(),
+                },
+                true,
+            )?;
+        }
+
+        let log = TransactionLog::load(&root)?;
+        let first_edit = log
+            .get_file_history(&file)?
+            .into_iter()
+            .find(|t| t.operation == OperationType::Edit)
+            .expect("first edit");
+
+        let engine = RestorationEngine::new(&root)?;
+        engine.restore_file_to_transaction(&first_edit.id)?;
+        assert!(fs::read_to_string(&file)?.contains("x = 2"));
+
+        let log = TransactionLog::load(&root)?;
+        let last = log.get_file_history(&file)?.pop().expect("restore transaction");
+        assert_eq!(last.operation, OperationType::Restore);
+
+        let mut undo = UndoRedoManager::new(&root)?;
+        let results = undo.undo(1)?;
+        assert!(results[0].success, "{}", results[0].message);
+        assert!(fs::read_to_string(&file)?.contains("x = 3"));
+        Ok(())
+    }
 }
@@ -5,6 +5,8 @@ use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
 
 /// Represents a single transaction in the log
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +21,26 @@ pub struct Transaction {
     pub description: String,
     pub session_id: String,
     pub metadata: HashMap<String, String>,
+    /// Unified diff of the change, recorded for edit/insert/delete operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// `Failed` for an edit that was rejected; see `log_failed_transaction`
+    #[serde(default, skip_serializing_if = "TransactionStatus::is_applied")]
+    pub status: TransactionStatus,
+}
+
+/// Whether a transaction changed the file or records a rejected attempt
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TransactionStatus {
+    #[default]
+    Applied,
+    Failed,
+}
+
+impl TransactionStatus {
+    pub fn is_applied(&self) -> bool {
+        *self == TransactionStatus::Applied
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -34,6 +56,41 @@ pub enum OperationType {
     SessionEnd,
 }
 
+/// `Transaction::metadata` key holding the label set with `set_label`
+pub const LABEL_KEY: &str = "label";
+
+/// `Transaction::metadata` key holding `EditOptions::client_id`
+pub const CLIENT_ID_KEY: &str = "client_id";
+
+static LABEL: RwLock<Option<String>> = RwLock::new(None);
+
+/// Set the process-wide label recorded on every transaction logged from
+/// now on, e.g. from `--label`. `None` stops labelling.
+pub fn set_label(label: Option<String>) {
+    *LABEL.write().unwrap_or_else(|e| e.into_inner()) = label;
+}
+
+/// Environment variable that turns on `set_log_failed_edits`
+pub const LOG_FAILED_ENV: &str = "GNAW_LOG_FAILED";
+
+static LOG_FAILED_EDITS: AtomicBool = AtomicBool::new(false);
+
+/// Also log edits that are rejected by validation, as `Failed` transactions,
+/// e.g. from `--log-failed`
+pub fn set_log_failed_edits(enabled: bool) {
+    LOG_FAILED_EDITS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether rejected edits are logged: set with `set_log_failed_edits` or
+/// `GNAW_LOG_FAILED`
+pub fn log_failed_edits() -> bool {
+    LOG_FAILED_EDITS.load(Ordering::Relaxed) || std::env::var_os(LOG_FAILED_ENV).is_some_and(|v| !v.is_empty())
+}
+
+fn current_label() -> Option<String> {
+    LABEL.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
 /// Transaction log manager
 pub struct TransactionLog {
     log_file: PathBuf,
@@ -97,7 +154,7 @@ impl TransactionLog {
         let full_history = Self::load_full_history_from_file(&log_file)?;
         let current_session: Vec<Transaction> = full_history
             .into_iter()
-            .filter(|t| t.session_id == session_id)
+            .filter(|t| t.session_id == session_id && t.status.is_applied())
             .collect();
 
         Ok(Self {
@@ -108,6 +165,28 @@ impl TransactionLog {
         })
     }
 
+    /// The active session id for `project_root`, read without creating a log.
+    /// `None` until the first edit starts a session.
+    pub fn current_session_id<P: AsRef<Path>>(project_root: P) -> Option<String> {
+        let session_id_file = project_root.as_ref().join(".gnawtreewriter_session_id");
+        std::fs::read_to_string(session_id_file)
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    /// Resolve a human-readable session alias (see `start_new_session`) to its id.
+    /// Unknown names are returned unchanged.
+    pub fn resolve_session_alias(&self, session_id: &str) -> String {
+        let project_root = self.log_file.parent().unwrap_or(Path::new("."));
+        let alias_file = project_root.join(".gnawtreewriter_aliases.json");
+        std::fs::read_to_string(alias_file)
+            .ok()
+            .and_then(|data| serde_json::from_str::<HashMap<String, String>>(&data).ok())
+            .and_then(|aliases| aliases.get(session_id).cloned())
+            .unwrap_or_else(|| session_id.to_string())
+    }
+
     /// Ensure a session exists (for implicit session creation)
     fn ensure_session_exists(&mut self) -> Result<()> {
         if self.current_session.is_empty() {
@@ -130,6 +209,8 @@ impl TransactionLog {
                 description: "Default session auto-started".to_string(),
                 session_id: self.session_id.clone(),
                 metadata: HashMap::new(),
+                diff: None,
+                status: TransactionStatus::Applied,
             };
 
             self.current_session.push(transaction.clone());
@@ -149,6 +230,31 @@ impl TransactionLog {
         after_hash: Option<String>,
         description: String,
         metadata: HashMap<String, String>,
+    ) -> Result<String> {
+        self.log_transaction_with_diff(
+            operation,
+            file_path,
+            node_path,
+            before_hash,
+            after_hash,
+            description,
+            metadata,
+            None,
+        )
+    }
+
+    /// Log a new transaction together with the unified diff of the change
+    #[allow(clippy::too_many_arguments)]
+    pub fn log_transaction_with_diff(
+        &mut self,
+        operation: OperationType,
+        file_path: PathBuf,
+        node_path: Option<String>,
+        before_hash: Option<String>,
+        after_hash: Option<String>,
+        description: String,
+        mut metadata: HashMap<String, String>,
+        diff: Option<String>,
     ) -> Result<String> {
         // Ensure we have an active session (auto-start default session if needed)
         // Skip for session operations to avoid infinite recursion
@@ -157,6 +263,9 @@ impl TransactionLog {
             OperationType::SessionStart | OperationType::SessionEnd
         ) {
             self.ensure_session_exists()?;
+            if let Some(label) = current_label() {
+                metadata.entry(LABEL_KEY.to_string()).or_insert(label);
+            }
         }
 
         let transaction = Transaction {
@@ -170,6 +279,8 @@ impl TransactionLog {
             description,
             session_id: self.session_id.clone(),
             metadata,
+            diff,
+            status: TransactionStatus::Applied,
         };
 
         let transaction_id = transaction.id.clone();
@@ -183,6 +294,42 @@ impl TransactionLog {
         Ok(transaction_id)
     }
 
+    /// Log an attempted change that was rejected, with `metadata["error"]`
+    /// saying why. Failed transactions only show up in
+    /// `get_last_n_with_failed`; undo and restore never see them.
+    pub fn log_failed_transaction(
+        &mut self,
+        operation: OperationType,
+        file_path: PathBuf,
+        node_path: Option<String>,
+        before_hash: Option<String>,
+        description: String,
+        mut metadata: HashMap<String, String>,
+    ) -> Result<String> {
+        self.ensure_session_exists()?;
+        if let Some(label) = current_label() {
+            metadata.entry(LABEL_KEY.to_string()).or_insert(label);
+        }
+
+        let transaction = Transaction {
+            id: generate_transaction_id(),
+            timestamp: Utc::now(),
+            operation,
+            file_path,
+            node_path,
+            before_hash,
+            after_hash: None,
+            description,
+            session_id: self.session_id.clone(),
+            metadata,
+            diff: None,
+            status: TransactionStatus::Failed,
+        };
+        self.append_to_log(&transaction)?;
+
+        Ok(transaction.id)
+    }
+
     /// Get transaction by ID (wrapper around find_transaction for easier Result handling)
     pub fn get_transaction(&self, transaction_id: &str) -> Result<Transaction> {
         self.find_transaction(transaction_id)?
@@ -194,9 +341,11 @@ impl TransactionLog {
         &self.current_session
     }
 
-    /// Get full transaction history from file
+    /// Get full transaction history from file, without failed attempts
     pub fn get_full_history(&self) -> Result<Vec<Transaction>> {
-        Self::load_full_history_from_file(&self.log_file)
+        let mut history = Self::load_full_history_from_file(&self.log_file)?;
+        history.retain(|t| t.status.is_applied());
+        Ok(history)
     }
 
     /// Load full history from a log file (helper method)
@@ -295,6 +444,26 @@ impl TransactionLog {
         Ok(files.into_iter().collect())
     }
 
+    /// Get all transactions tagged with `label`, oldest first
+    pub fn get_label_history(&self, label: &str) -> Result<Vec<Transaction>> {
+        self.get_history_with(LABEL_KEY, label)
+    }
+
+    /// Get all transactions made by `client_id`, oldest first
+    pub fn get_client_history(&self, client_id: &str) -> Result<Vec<Transaction>> {
+        self.get_history_with(CLIENT_ID_KEY, client_id)
+    }
+
+    /// Transactions whose `metadata[key]` is `value`, oldest first
+    fn get_history_with(&self, key: &str, value: &str) -> Result<Vec<Transaction>> {
+        let full_history = self.get_full_history()?;
+
+        Ok(full_history
+            .into_iter()
+            .filter(|t| t.metadata.get(key).is_some_and(|v| v == value))
+            .collect())
+    }
+
     /// Find the last transaction for a file before a specific timestamp
     pub fn get_last_transaction_before(
         &self,
@@ -404,6 +573,13 @@ impl TransactionLog {
         Ok(full_history.into_iter().rev().take(n).rev().collect())
     }
 
+    /// Like `get_last_n_transactions`, but failed attempts count too
+    pub fn get_last_n_with_failed(&self, n: usize) -> Result<Vec<Transaction>> {
+        let full_history = Self::load_full_history_from_file(&self.log_file)?;
+
+        Ok(full_history.into_iter().rev().take(n).rev().collect())
+    }
+
     /// Start a new session (clears current session, keeps history)
     pub fn start_new_session(&mut self, name: Option<String>) -> Result<()> {
         if !self.current_session.is_empty() {
@@ -438,7 +614,8 @@ impl TransactionLog {
             aliases.insert(alias.clone(), self.session_id.clone());
             let data = serde_json::to_string_pretty(&aliases)?;
             let _ = std::fs::write(alias_file, data);
-            println!("✓ Session alias created: '{}' -> {}", alias, self.session_id);
+            // stderr: this also runs under the MCP stdio transport
+            eprintln!("✓ Session alias created: '{}' -> {}", alias, self.session_id);
         }
 
         self.log_transaction(
@@ -463,6 +640,7 @@ impl TransactionLog {
                 .context("Failed to serialize history to JSON"),
             ExportFormat::JsonCompact => serde_json::to_string(&history)
                 .context("Failed to serialize history to compact JSON"),
+            ExportFormat::Csv => Ok(history_to_csv(&history)),
         }
     }
 
@@ -490,6 +668,55 @@ impl TransactionLog {
 pub enum ExportFormat {
     Json,
     JsonCompact,
+    /// One row per transaction; diffs and metadata are omitted
+    Csv,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "json-compact" | "jsoncompact" => Ok(ExportFormat::JsonCompact),
+            "csv" => Ok(ExportFormat::Csv),
+            other => Err(anyhow::anyhow!(
+                "Unknown export format '{}'. Use json, json-compact or csv.",
+                other
+            )),
+        }
+    }
+}
+
+fn history_to_csv(history: &[Transaction]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    let mut out = String::from(
+        "id,timestamp,session_id,operation,file_path,node_path,before_hash,after_hash,description\n",
+    );
+    for t in history {
+        let row = [
+            t.id.clone(),
+            t.timestamp.to_rfc3339(),
+            t.session_id.clone(),
+            format!("{:?}", t.operation),
+            t.file_path.display().to_string(),
+            t.node_path.clone().unwrap_or_default(),
+            t.before_hash.clone().unwrap_or_default(),
+            t.after_hash.clone().unwrap_or_default(),
+            t.description.clone(),
+        ];
+        let row: Vec<String> = row.iter().map(|v| field(v)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
 }
 
 /// Plan for restoring multiple files to a specific point in time
@@ -551,6 +778,15 @@ pub fn calculate_content_hash(content: &str) -> String {
     format!("{:x}", hasher.finish())
 }
 
+/// Render a unified diff between two versions of a file for storage in the log
+pub fn unified_diff(before: &str, after: &str, file_label: &str) -> String {
+    similar::TextDiff::from_lines(before, after)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", file_label), &format!("b/{}", file_label))
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -611,6 +847,34 @@ mod tests {
         assert_eq!(found.unwrap().id, transaction_id);
     }
 
+    #[test]
+    fn test_export_history_round_trips() {
+        let temp_dir = tempdir().unwrap();
+        let mut log = TransactionLog::new(temp_dir.path()).unwrap();
+        log.log_transaction(
+            OperationType::Edit,
+            PathBuf::from("test.py"),
+            Some("0.1".to_string()),
+            Some("hash1".to_string()),
+            Some("hash2".to_string()),
+            "Edit, with \"quotes\"".to_string(),
+            HashMap::new(),
+        )
+        .unwrap();
+
+        let json = log.export_history(ExportFormat::Json).unwrap();
+        let parsed: Vec<Transaction> = serde_json::from_str(&json).unwrap();
+        let original = log.get_full_history().unwrap();
+        assert_eq!(parsed.len(), original.len());
+        assert_eq!(parsed[1].id, original[1].id);
+        assert_eq!(parsed[1].operation, OperationType::Edit);
+        assert_eq!(parsed[1].description, original[1].description);
+
+        let csv = log.export_history(ExportFormat::Csv).unwrap();
+        assert_eq!(csv.lines().count(), original.len() + 1);
+        assert!(csv.contains("\"Edit, with \"\"quotes\"\"\""));
+    }
+
     #[test]
     fn test_content_hash() {
         let hash1 = calculate_content_hash("def test(): pass");
@@ -1,7 +1,6 @@
 use crate::core::transaction_log::{OperationType, Transaction, TransactionLog};
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
-use serde_json::Value;
 
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -19,7 +18,7 @@ impl UndoRedoManager {
     /// Create a new undo/redo manager
     pub fn new<P: AsRef<Path>>(project_root: P) -> Result<Self> {
         let project_root = project_root.as_ref().to_path_buf();
-        let backup_dir = project_root.join(".gnawtreewriter_backups");
+        let backup_dir = crate::core::config::backup_dir(&project_root);
 
         // Ensure backup directory exists
         if !backup_dir.exists() {
@@ -822,23 +821,7 @@ impl UndoRedoManager {
 
     /// Restore file from backup
     fn restore_from_backup(&self, target_path: &Path, backup_path: &Path) -> Result<()> {
-        let backup_content = fs::read_to_string(backup_path).context(format!(
-            "Failed to read backup file: {}",
-            backup_path.display()
-        ))?;
-
-        let json: Value =
-            serde_json::from_str(&backup_content).context("Failed to parse backup JSON")?;
-
-        let source_code = json["source_code"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Backup file missing source_code"))?;
-
-        fs::write(target_path, source_code).context(format!(
-            "Failed to write restored file: {}",
-            target_path.display()
-        ))?;
-
+        crate::core::backup::restore_from_backup(backup_path, target_path)?;
         Ok(())
     }
 }
@@ -964,6 +947,8 @@ mod tests {
             description: "Insert test".to_string(),
             session_id: "session_1".to_string(),
             metadata: HashMap::new(),
+            diff: None,
+            status: Default::default(),
         };
 
         let manager = UndoRedoManager::new(tmp.path())?;
@@ -1029,6 +1014,8 @@ mod tests {
             description: "Fake insert".to_string(),
             session_id: "s1".to_string(),
             metadata: std::collections::HashMap::new(),
+            diff: None,
+            status: Default::default(),
         };
 
         let manager = UndoRedoManager::new(tmp.path())?;
@@ -1086,6 +1073,8 @@ mod tests {
             description: "Fake redo".to_string(),
             session_id: "s1".to_string(),
             metadata: std::collections::HashMap::new(),
+            diff: None,
+            status: Default::default(),
         };
 
         let manager = UndoRedoManager::new(tmp.path())?;
@@ -7,4 +7,4 @@ pub mod parser;
 pub mod mcp;
 
 pub use core::GnawTreeWriter;
-pub use parser::TreeNode;
+pub use parser::{NodeKind, TreeNode};
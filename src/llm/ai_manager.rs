@@ -4,7 +4,7 @@ use candle_core::{DType, Device, Tensor};
 #[cfg(feature = "modernbert")]
 use candle_nn::{self, VarBuilder};
 #[cfg(feature = "modernbert")]
-use candle_transformers::models::modernbert::{Config, ModernBert};
+use candle_transformers::models::modernbert::{Config, ModernBert, ModernBertForMaskedLM};
 #[cfg(feature = "modernbert")]
 use crate::core::LabelManager;
 use std::fs;
@@ -14,14 +14,67 @@ use tokenizers::Tokenizer;
 
 
 /// Supported AI models for local execution
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq, Default)]
 pub enum AiModel {
+    /// answerdotai/ModernBERT-base
+    #[default]
     ModernBert,
+    /// answerdotai/ModernBERT-large
+    ModernBertLarge,
+    /// Any Hugging Face repo with a ModernBERT-compatible config, tokenizer
+    /// and safetensors weights, e.g. `org/ModernBERT-small-q8`
+    Custom(String),
 }
 
+impl AiModel {
+    /// Hugging Face repo the model files are downloaded from
+    pub fn repo_id(&self) -> &str {
+        match self {
+            AiModel::ModernBert => "answerdotai/ModernBERT-base",
+            AiModel::ModernBertLarge => "answerdotai/ModernBERT-large",
+            AiModel::Custom(id) => id,
+        }
+    }
+
+    /// Directory name under the model cache. The base model keeps its
+    /// original `modernbert` directory so existing installs still load.
+    pub fn cache_dir_name(&self) -> String {
+        match self {
+            AiModel::ModernBert => "modernbert".to_string(),
+            AiModel::ModernBertLarge => "modernbert-large".to_string(),
+            AiModel::Custom(id) => id.replace('/', "--"),
+        }
+    }
+}
+
+impl std::str::FromStr for AiModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "modernbert" | "modernbert-base" | "answerdotai/ModernBERT-base" => Ok(AiModel::ModernBert),
+            "modernbert-large" | "answerdotai/ModernBERT-large" => Ok(AiModel::ModernBertLarge),
+            id if id.split('/').count() == 2 && !id.starts_with('/') && !id.ends_with('/') => {
+                Ok(AiModel::Custom(id.to_string()))
+            }
+            other => anyhow::bail!(
+                "Unknown model '{}'. Use modernbert, modernbert-large or a Hugging Face repo id (owner/name)",
+                other
+            ),
+        }
+    }
+}
+
+/// Files every model directory must contain
+pub const MODEL_FILES: [&str; 3] = ["config.json", "model.safetensors", "tokenizer.json"];
+
+/// Records which model `ai setup` installed last, inside the model cache
+const ACTIVE_MODEL_FILE: &str = "active_model";
+
 /// Execution device for AI models
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, Default)]
 pub enum DeviceType {
+    #[default]
     Cpu,
     Cuda,
     Metal,
@@ -37,6 +90,22 @@ impl From<&str> for DeviceType {
     }
 }
 
+/// Devices a model can run on here. CPU is always available; CUDA and
+/// Metal are listed only when compiled in and a device actually opens.
+pub fn probe_devices() -> Vec<DeviceType> {
+    #[allow(unused_mut)]
+    let mut devices = vec![DeviceType::Cpu];
+    #[cfg(all(feature = "modernbert", feature = "cuda"))]
+    if Device::new_cuda(0).is_ok() {
+        devices.push(DeviceType::Cuda);
+    }
+    #[cfg(all(feature = "modernbert", feature = "metal"))]
+    if Device::new_metal(0).is_ok() {
+        devices.push(DeviceType::Metal);
+    }
+    devices
+}
+
 #[cfg(feature = "modernbert")]
 pub struct ModernBertModel {
     pub model: ModernBert,
@@ -55,14 +124,61 @@ impl ModernBertModel {
     }
 }
 
+/// ModernBERT with its fill-mask head, used by `complete_code`
+#[cfg(feature = "modernbert")]
+pub struct MaskedLmModel {
+    pub model: ModernBertForMaskedLM,
+    pub tokenizer: Tokenizer,
+    pub device: Device,
+}
+
+/// A restructuring worth considering for one node
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RefactorSuggestion {
+    pub path: String,
+    /// extract-function, flatten-nesting, add-docs or merge-duplicate
+    pub kind: String,
+    pub message: String,
+    pub confidence: f32,
+}
+
+/// A node that an intent like "add logging" probably applies to
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BatchEditSuggestion {
+    pub node_path: String,
+    pub intent: String,
+    pub preview: String,
+    pub score: f32,
+}
+
+/// Definitions at least this similar are reported as duplicates
+#[cfg(feature = "modernbert")]
+const DUPLICATE_SIMILARITY: f32 = 0.97;
+
+/// Marker a node's content must contain for `complete_code` to fill in
+pub const MASK_TOKEN: &str = "[MASK]";
+
+/// One candidate for the masked position, best first
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CompletionSuggestion {
+    pub token: String,
+    pub confidence: f32,
+    /// The node content with the mask replaced by `token`
+    pub completed: String,
+}
+
 pub struct AiManager {
     model_cache_dir: PathBuf,
+    /// Device the model methods load onto; see `with_device`
+    device: DeviceType,
     #[allow(dead_code)]
     project_root: PathBuf,
     /// Cached model — loaded once, reused across all calls within this process.
     /// Uses OnceLock for thread-safety (MCP server is async multi-threaded).
     #[cfg(feature = "modernbert")]
-    cached_model: std::sync::OnceLock<ModernBertModel>,
+    cached_model: std::sync::OnceLock<((AiModel, DeviceType), ModernBertModel)>,
+    #[cfg(feature = "modernbert")]
+    cached_mlm: std::sync::OnceLock<((AiModel, DeviceType), MaskedLmModel)>,
 }
 
 impl AiManager {
@@ -70,7 +186,8 @@ impl AiManager {
         let local_cache = project_root.join(".gnawtreewriter_ai").join("models");
         
         // Try local first, then global home dir
-        let model_cache_dir = if local_cache.exists() && local_cache.join("modernbert").exists() {
+        let has_local = local_cache.join("modernbert").exists() || local_cache.join(ACTIVE_MODEL_FILE).exists();
+        let model_cache_dir = if has_local {
             local_cache
         } else {
             let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
@@ -84,23 +201,185 @@ impl AiManager {
 
         Ok(Self { 
             model_cache_dir,
+            device: DeviceType::Cpu,
             project_root: project_root.to_path_buf(),
             #[cfg(feature = "modernbert")]
             cached_model: std::sync::OnceLock::new(),
+            #[cfg(feature = "modernbert")]
+            cached_mlm: std::sync::OnceLock::new(),
         })
     }
 
+    /// Use an explicit model cache instead of the project-local or home one
+    pub fn with_cache_dir(project_root: &Path, model_cache_dir: PathBuf) -> Self {
+        Self {
+            model_cache_dir,
+            device: DeviceType::Cpu,
+            project_root: project_root.to_path_buf(),
+            #[cfg(feature = "modernbert")]
+            cached_model: std::sync::OnceLock::new(),
+            #[cfg(feature = "modernbert")]
+            cached_mlm: std::sync::OnceLock::new(),
+        }
+    }
+
+    /// Run `sense`, `complete_code`, `suggest_refactor` and friends on
+    /// `device` instead of the CPU
+    pub fn with_device(mut self, device: DeviceType) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn device(&self) -> DeviceType {
+        self.device
+    }
+
+    /// The model chosen by the last `ai setup`, or ModernBERT-base
+    pub fn active_model(&self) -> AiModel {
+        fs::read_to_string(self.model_cache_dir.join(ACTIVE_MODEL_FILE))
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default()
+    }
+
     #[cfg(feature = "modernbert")]
     pub fn load_model(&self, model_type: AiModel, device_type: DeviceType) -> Result<&ModernBertModel> {
         // OnceLock doesn't have get_or_try_init on stable Rust yet.
         // Use get_or_init with interior error handling — if model fails to load,
         // we panic (this is acceptable: missing model = broken installation).
-        if let Some(model) = self.cached_model.get() {
+        if let Some(((loaded, loaded_device), model)) = self.cached_model.get() {
+            if *loaded != model_type || *loaded_device != device_type {
+                anyhow::bail!(
+                    "{} is already loaded on {:?} in this process; cannot switch to {} on {:?}",
+                    loaded.repo_id(),
+                    loaded_device,
+                    model_type.repo_id(),
+                    device_type
+                );
+            }
             return Ok(model);
         }
         
         // Load the model (not cached yet)
-        let model_dir = self.get_model_path(&model_type);
+        let (config, tokenizer, device, vb) = self.open_model_files(&model_type, device_type)?;
+        let model = ModernBert::load(vb, &config)?;
+        let loaded = ModernBertModel { model, tokenizer, device };
+        
+        // Store in cache (get_or_init for the first call wins; subsequent calls reuse)
+        // If another thread loaded meanwhile, that's fine — we just return the cached one
+        self.cached_model.set(((model_type, device_type), loaded)).ok().expect("Model cache already set");
+        
+        Ok(&self.cached_model.get().unwrap().1)
+    }
+
+    /// Refactoring suggestions for the definitions in `node_path` (or the
+    /// whole file): long or deeply nested bodies, missing docs, and
+    /// near-duplicate definitions found by comparing embeddings. Without a
+    /// downloaded model only the structural suggestions are made.
+    #[cfg(feature = "modernbert")]
+    pub fn suggest_refactor(&self, file_path: &str, node_path: Option<&str>) -> Result<Vec<RefactorSuggestion>> {
+        use crate::llm::heuristics;
+
+        let writer = crate::core::GnawTreeWriter::new(file_path)?;
+        let tree = writer.analyze();
+        let scope = match node_path {
+            Some(p) => writer
+                .resolve_path(p)
+                .ok_or_else(|| crate::core::GnawError::node_not_found(p, tree))?,
+            None => tree,
+        };
+        let lines: Vec<&str> = tree.content.lines().collect();
+        let mut defs = heuristics::definitions(scope);
+        if defs.is_empty() {
+            defs.push(scope);
+        }
+
+        let mut suggestions: Vec<RefactorSuggestion> = heuristics::structural_issues(&defs, &lines)
+            .into_iter()
+            .map(|(node, issue)| {
+                let name = node.get_name().unwrap_or_else(|| node.path.clone());
+                let (kind, message, confidence) = match issue {
+                    heuristics::Issue::Long { lines } => (
+                        "extract-function",
+                        format!("'{}' is {} lines; split it into smaller functions", name, lines),
+                        (lines as f32 / (2 * heuristics::LONG_NODE_LINES) as f32).min(1.0),
+                    ),
+                    heuristics::Issue::DeepNesting { depth } => (
+                        "flatten-nesting",
+                        format!("'{}' nests {} levels deep; use early returns or helpers", name, depth),
+                        (depth as f32 / (2 * heuristics::MAX_NESTING) as f32).min(1.0),
+                    ),
+                    heuristics::Issue::MissingDoc => ("add-docs", format!("'{}' has no doc comment", name), 0.5),
+                };
+                RefactorSuggestion { path: node.path.clone(), kind: kind.into(), message, confidence }
+            })
+            .collect();
+
+        // Near-duplicates need the model; compare each definition with the
+        // ones before it. Without a model the structural suggestions stand alone.
+        match self.load_model(self.active_model(), self.device) {
+            Ok(model) => {
+                let mut seen: Vec<(&crate::parser::TreeNode, Vec<f32>)> = Vec::new();
+                for node in defs.iter().filter(|n| n.content.lines().count() >= 3).take(200) {
+                    let vector: Vec<f32> = model.get_embedding(&node.content)?.to_vec1()?;
+                    if let Some((original, similarity)) = seen
+                        .iter()
+                        .map(|(n, v)| (n, crate::llm::cosine_similarity(&vector, v)))
+                        .max_by(|a, b| a.1.total_cmp(&b.1))
+                    {
+                        if similarity >= DUPLICATE_SIMILARITY {
+                            suggestions.push(RefactorSuggestion {
+                                path: node.path.clone(),
+                                kind: "merge-duplicate".into(),
+                                message: format!("Nearly identical to node {}; consider sharing one implementation", original.path),
+                                confidence: similarity,
+                            });
+                        }
+                    }
+                    seen.push((node, vector));
+                }
+            }
+            Err(e) => eprintln!("⚠️  Skipping the near-duplicate check: {:#}", e),
+        }
+
+        suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        Ok(suggestions)
+    }
+
+    /// Rank the definitions in a file by how well they match `intent`, as
+    /// candidates for one batch of edits (e.g. "add error handling")
+    #[cfg(feature = "modernbert")]
+    pub fn suggest_batch_edits(&self, file_path: &str, intent: &str) -> Result<Vec<BatchEditSuggestion>> {
+        let writer = crate::core::GnawTreeWriter::new(file_path)?;
+        let model = self.load_model(self.active_model(), self.device)?;
+        let intent_vector: Vec<f32> = model.get_embedding(intent)?.to_vec1()?;
+
+        let mut suggestions = Vec::new();
+        for node in crate::llm::heuristics::definitions(writer.analyze()) {
+            let vector: Vec<f32> = model.get_embedding(&node.content)?.to_vec1()?;
+            suggestions.push(BatchEditSuggestion {
+                node_path: node.path.clone(),
+                intent: intent.to_string(),
+                preview: crate::llm::heuristics::preview(&node.content),
+                score: crate::llm::cosine_similarity(&intent_vector, &vector),
+            });
+        }
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+        suggestions.truncate(10);
+        Ok(suggestions)
+    }
+
+    /// Config, tokenizer, device and weights of an installed model
+    #[cfg(feature = "modernbert")]
+    fn open_model_files(&self, model_type: &AiModel, device_type: DeviceType) -> Result<(Config, Tokenizer, Device, VarBuilder<'static>)> {
+        use anyhow::Context;
+        self.read_model_files(model_type, device_type)
+            .with_context(|| format!("Loading {} on {:?}", model_type.repo_id(), device_type))
+    }
+
+    #[cfg(feature = "modernbert")]
+    fn read_model_files(&self, model_type: &AiModel, device_type: DeviceType) -> Result<(Config, Tokenizer, Device, VarBuilder<'static>)> {
+        let model_dir = self.get_model_path(model_type);
         
         let config_path = model_dir.join("config.json");
         let tokenizer_path = model_dir.join("tokenizer.json");
@@ -120,20 +399,83 @@ impl AiManager {
         };
         
         let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_path], DType::F32, &device)? };
-        let model = ModernBert::load(vb, &config)?;
-        let loaded = ModernBertModel { model, tokenizer, device };
-        
-        // Store in cache (get_or_init for the first call wins; subsequent calls reuse)
-        // If another thread loaded meanwhile, that's fine — we just return the cached one
-        self.cached_model.set(loaded).ok().expect("Model cache already set");
-        
-        Ok(self.cached_model.get().unwrap())
+        Ok((config, tokenizer, device, vb))
+    }
+
+    /// Load the fill-mask variant of the model (same weights, plus the head)
+    #[cfg(feature = "modernbert")]
+    pub fn load_masked_lm(&self, model_type: AiModel, device_type: DeviceType) -> Result<&MaskedLmModel> {
+        if let Some(((loaded, loaded_device), model)) = self.cached_mlm.get() {
+            if *loaded != model_type || *loaded_device != device_type {
+                anyhow::bail!(
+                    "{} is already loaded on {:?} in this process; cannot switch to {} on {:?}",
+                    loaded.repo_id(),
+                    loaded_device,
+                    model_type.repo_id(),
+                    device_type
+                );
+            }
+            return Ok(model);
+        }
+        let (config, tokenizer, device, vb) = self.open_model_files(&model_type, device_type)?;
+        let model = ModernBertForMaskedLM::load(vb, &config)?;
+        let _ = self.cached_mlm.set(((model_type, device_type), MaskedLmModel { model, tokenizer, device }));
+        Ok(&self.cached_mlm.get().unwrap().1)
+    }
+
+    /// Fill the `[MASK]` in a node's content using the model's fill-mask
+    /// head and return the `top_k` most likely tokens
+    #[cfg(feature = "modernbert")]
+    pub fn complete_code(&self, file_path: &str, node_path: &str, top_k: usize) -> Result<Vec<CompletionSuggestion>> {
+        let writer = crate::core::GnawTreeWriter::new(file_path)?;
+        let content = writer.show_node(node_path)?;
+        if !content.contains(MASK_TOKEN) {
+            anyhow::bail!("Node {} has no {} marker to complete", node_path, MASK_TOKEN);
+        }
+
+        let mlm = self.load_masked_lm(self.active_model(), self.device)?;
+        let mask_id = mlm
+            .tokenizer
+            .token_to_id(MASK_TOKEN)
+            .ok_or_else(|| anyhow::anyhow!("Tokenizer has no {} token", MASK_TOKEN))?;
+        let tokens = mlm.tokenizer.encode(content.as_str(), true).map_err(anyhow::Error::msg)?;
+        let mask_pos = tokens
+            .get_ids()
+            .iter()
+            .position(|&id| id == mask_id)
+            .ok_or_else(|| anyhow::anyhow!("{} was not kept as a single token", MASK_TOKEN))?;
+
+        let input_ids = Tensor::new(tokens.get_ids(), &mlm.device)?.unsqueeze(0)?;
+        let mask = input_ids.ones_like()?;
+        let logits = mlm.model.forward(&input_ids, &mask)?.squeeze(0)?.get(mask_pos)?;
+        let probs: Vec<f32> = candle_nn::ops::softmax_last_dim(&logits)?.to_vec1()?;
+
+        let mut ranked: Vec<(usize, f32)> = probs.into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut suggestions = Vec::new();
+        for (id, confidence) in ranked {
+            if suggestions.len() == top_k {
+                break;
+            }
+            let token = mlm.tokenizer.decode(&[id as u32], true).map_err(anyhow::Error::msg)?;
+            let token = token.trim().to_string();
+            if token.is_empty() {
+                continue;
+            }
+            suggestions.push(CompletionSuggestion {
+                completed: content.replacen(MASK_TOKEN, &token, 1),
+                token,
+                confidence,
+            });
+        }
+        Ok(suggestions)
     }
 
     #[cfg(feature = "modernbert")]
     pub async fn generate_semantic_report(&self, file_path: &str) -> Result<SemanticReport> {
         eprintln!("[DEBUG] Starting semantic report for: {}", file_path);
-        let _model = self.load_model(AiModel::ModernBert, DeviceType::Cpu)?;
+        let _model = self.load_model(self.active_model(), self.device)?;
         eprintln!("[DEBUG] Model loaded successfully");
         
         let mut label_mgr = LabelManager::load(&self.project_root)?;
@@ -153,12 +495,7 @@ impl AiManager {
         let tree = parser.parse(&content)?;
         eprintln!("[DEBUG] AST parsed");
 
-        let mut nodes = Vec::new();
-        fn collect(n: &crate::parser::TreeNode, acc: &mut Vec<crate::parser::TreeNode>) {
-            acc.push(n.clone());
-            for c in &n.children { collect(c, acc); }
-        }
-        collect(&tree, &mut nodes);
+        let nodes: Vec<_> = tree.iter().collect();
         eprintln!("[DEBUG] Collected {} nodes", nodes.len());
 
         let mut findings = Vec::new();
@@ -186,41 +523,58 @@ impl AiManager {
         })
     }
 
-    pub async fn setup(&self, _model: AiModel, _device: DeviceType, _force: bool) -> Result<()> {
+    pub async fn setup(&self, model: AiModel, _device: DeviceType, force: bool) -> Result<()> {
         #[cfg(feature = "modernbert")]
         {
-            let model_id = "answerdotai/ModernBERT-base";
-            let model_dir = self.get_model_path(&AiModel::ModernBert);
-            if !model_dir.exists() { fs::create_dir_all(&model_dir)?; }
-            for file in ["config.json", "model.safetensors", "tokenizer.json"] {
-                let dest = model_dir.join(file);
-                if !dest.exists() || _force {
-                    let url = format!("https://huggingface.co/{}/resolve/main/{}", model_id, file);
-                    println!("  Downloading {}...", file);
-                    let resp = ureq::get(&url).call()
-                        .map_err(|e| anyhow::anyhow!("HTTP download failed: {}", e))?;
-                    let mut reader = resp.into_reader();
-                    let mut out = std::fs::File::create(&dest)
-                        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dest, e))?;
-                    std::io::copy(&mut reader, &mut out)?;
-                }
-            }
+            self.setup_with(&model, force, |url, dest| {
+                let resp = ureq::get(url).call()
+                    .map_err(|e| anyhow::anyhow!("HTTP download failed: {}", e))?;
+                let mut reader = resp.into_reader();
+                let mut out = std::fs::File::create(dest)
+                    .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dest, e))?;
+                std::io::copy(&mut reader, &mut out)?;
+                Ok(())
+            })?;
         }
+        #[cfg(not(feature = "modernbert"))]
+        let _ = (model, force);
         Ok(())
     }
 
+    /// Download `model` into its cache directory with `fetch(url, dest)` and
+    /// make it the active model. Files already present are kept unless `force`.
+    pub fn setup_with<F>(&self, model: &AiModel, force: bool, mut fetch: F) -> Result<PathBuf>
+    where
+        F: FnMut(&str, &Path) -> Result<()>,
+    {
+        let model_dir = self.get_model_path(model);
+        if !model_dir.exists() { fs::create_dir_all(&model_dir)?; }
+        for file in MODEL_FILES {
+            let dest = model_dir.join(file);
+            if !dest.exists() || force {
+                let url = format!("https://huggingface.co/{}/resolve/main/{}", model.repo_id(), file);
+                println!("  Downloading {}...", file);
+                fetch(&url, &dest)?;
+            }
+        }
+        fs::write(self.model_cache_dir.join(ACTIVE_MODEL_FILE), model.repo_id())?;
+        Ok(model_dir)
+    }
 
     pub fn get_status(&self) -> Result<AiStatus> {
-        let modern_bert_installed = self.get_model_path(&AiModel::ModernBert).join("config.json").exists();
+        let model = self.active_model();
+        let model_dir = self.get_model_path(&model);
         Ok(AiStatus { 
-            modern_bert_installed, 
+            modern_bert_installed: model_dir.join("config.json").exists(),
+            model,
+            model_dir,
             cache_dir: self.model_cache_dir.clone(), 
-            available_devices: vec![DeviceType::Cpu] 
+            available_devices: probe_devices(),
         })
     }
 
-    fn get_model_path(&self, model: &AiModel) -> PathBuf {
-        match model { AiModel::ModernBert => self.model_cache_dir.join("modernbert") }
+    pub fn get_model_path(&self, model: &AiModel) -> PathBuf {
+        self.model_cache_dir.join(model.cache_dir_name())
     }
 }
 
@@ -241,7 +595,91 @@ pub struct QualityFinding {
 
 #[derive(Debug, Clone)]
 pub struct AiStatus {
+    /// Whether the active model's files are present
     pub modern_bert_installed: bool,
+    pub model: AiModel,
+    pub model_dir: PathBuf,
     pub cache_dir: PathBuf,
     pub available_devices: Vec<DeviceType>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_devices_always_includes_cpu() {
+        let devices = probe_devices();
+        assert_eq!(devices.first(), Some(&DeviceType::Cpu));
+
+        let dir = tempfile::tempdir().unwrap();
+        let status = AiManager::with_cache_dir(dir.path(), dir.path().join("models")).get_status().unwrap();
+        assert_eq!(status.available_devices, devices);
+    }
+
+    #[cfg(feature = "modernbert")]
+    #[test]
+    fn test_device_selection_reaches_load_model() {
+        // An empty cache stands in for the model: loading fails, and the
+        // error says which device it was asked to load onto
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.py");
+        fs::write(&file, "def f():\n    return 1\n").unwrap();
+        let mgr = AiManager::with_cache_dir(dir.path(), dir.path().join("models")).with_device(DeviceType::Metal);
+        assert_eq!(mgr.device(), DeviceType::Metal);
+
+        let err = mgr.suggest_batch_edits(file.to_str().unwrap(), "anything").unwrap_err();
+        assert!(format!("{:#}", err).contains("on Metal"), "{:#}", err);
+
+        let default = AiManager::with_cache_dir(dir.path(), dir.path().join("models"));
+        let err = default.suggest_batch_edits(file.to_str().unwrap(), "anything").unwrap_err();
+        assert!(format!("{:#}", err).contains("on Cpu"), "{:#}", err);
+    }
+
+    #[cfg(feature = "modernbert")]
+    #[test]
+    fn test_suggest_refactor_without_model_falls_back_to_heuristics() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.py");
+        let body: String = (0..60).map(|i| format!("    x{} = {}\n", i, i)).collect();
+        fs::write(&file, format!("def short():\n    return 1\n\ndef long():\n{}", body)).unwrap();
+        let mgr = AiManager::with_cache_dir(dir.path(), dir.path().join("models"));
+
+        let suggestions = mgr.suggest_refactor(file.to_str().unwrap(), Some("@long")).unwrap();
+        assert!(suggestions.iter().any(|s| s.kind == "extract-function"), "{:?}", suggestions);
+        assert!(suggestions.iter().all(|s| s.kind != "merge-duplicate"));
+
+        let err = mgr.suggest_refactor(file.to_str().unwrap(), Some("@missing")).unwrap_err();
+        assert_eq!(crate::core::GnawError::classify(&err).map(|k| k.kind), Some("node_not_found"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_setup_second_model_uses_own_cache_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("models");
+        let mgr = AiManager::with_cache_dir(dir.path(), cache.clone());
+        assert_eq!(mgr.active_model(), AiModel::ModernBert);
+
+        let model: AiModel = "acme/ModernBERT-small-q8".parse().unwrap();
+        let mut urls = Vec::new();
+        let model_dir = mgr
+            .setup_with(&model, false, |url, dest| {
+                urls.push(url.to_string());
+                fs::write(dest, "{}")?;
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(model_dir, cache.join("acme--ModernBERT-small-q8"));
+        for file in MODEL_FILES {
+            assert!(model_dir.join(file).exists(), "{} missing", file);
+        }
+        assert_eq!(urls[0], "https://huggingface.co/acme/ModernBERT-small-q8/resolve/main/config.json");
+        assert_eq!(mgr.active_model(), model);
+        assert_eq!(mgr.get_model_path(&AiModel::ModernBertLarge), cache.join("modernbert-large"));
+        assert!(!cache.join("modernbert").exists());
+
+        // Present files are not fetched again
+        mgr.setup_with(&model, false, |url, _| panic!("unexpected download of {}", url)).unwrap();
+    }
+}
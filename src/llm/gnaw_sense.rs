@@ -1,7 +1,9 @@
 use anyhow::Result;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 #[cfg(feature = "modernbert")]
-use crate::llm::{AiManager, AiModel, DeviceType, SemanticIndex, RelationalIndexer, RelationType, NodeEmbedding};
-#[cfg(feature = "modernbert")]
+use crate::llm::{AiManager, SemanticIndex, RelationalIndexer, RelationType, NodeEmbedding};
+use crate::llm::heuristics;
 use crate::parser::TreeNode;
 #[cfg(feature = "modernbert")]
 use std::fs;
@@ -72,6 +74,43 @@ pub struct EditProposal {
     pub confidence: f32,
 }
 
+/// Score `nodes` against `query` without embeddings, best first. Names are
+/// matched fuzzily, so `parse cfg` finds `parse_config`; a node whose name
+/// does not match still scores half a point per query word in its content.
+pub fn fuzzy_sense(query: &str, nodes: &[&TreeNode]) -> Vec<NodeMatch> {
+    let query_words = heuristics::words(query);
+    if query_words.is_empty() {
+        return Vec::new();
+    }
+    let pattern = query_words.concat();
+    let matcher = SkimMatcherV2::default();
+    // An exact name match scores 1.0
+    let best = matcher.fuzzy_match(&pattern, &pattern).unwrap_or(1).max(1) as f32;
+
+    let mut matches: Vec<NodeMatch> = nodes
+        .iter()
+        .filter_map(|node| {
+            let name_score = node
+                .get_name()
+                .and_then(|name| matcher.fuzzy_match(&heuristics::words(&name).concat(), &pattern))
+                .map(|s| (s as f32 / best).min(1.0))
+                .unwrap_or(0.0);
+            let content = node.content.to_lowercase();
+            let found = query_words.iter().filter(|w| content.contains(w.as_str())).count();
+            let content_score = 0.5 * found as f32 / query_words.len() as f32;
+
+            let score = name_score.max(content_score);
+            (score > 0.0).then(|| NodeMatch {
+                path: node.path.clone(),
+                preview: heuristics::preview(&node.content),
+                score,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches
+}
+
 impl GnawSenseBroker {
     pub fn new(project_root: &Path) -> Result<Self> {
         Ok(Self {
@@ -84,13 +123,50 @@ impl GnawSenseBroker {
         })
     }
 
+    /// Load the embedding model onto `device` instead of the CPU
+    pub fn with_device(mut self, device: crate::llm::DeviceType) -> Self {
+        self.ai_manager = self.ai_manager.with_device(device);
+        self
+    }
+
     pub fn get_manager(&self) -> &crate::llm::AiManager {
         &self.ai_manager
     }
 
-    #[cfg(feature = "modernbert")]
+    /// Semantic search with the embedding model, or fuzzy name matching
+    /// (`fuzzy_sense`) when the model is not compiled in or cannot be loaded
     pub async fn sense(&self, query: &str, file_context: Option<&str>) -> Result<SenseResponse> {
-        let model = self.ai_manager.load_model(AiModel::ModernBert, DeviceType::Cpu)?;
+        #[cfg(feature = "modernbert")]
+        match self.sense_with_model(query, file_context).await {
+            Ok(response) => return Ok(response),
+            Err(e) => eprintln!("⚠️  GnawSense model unavailable ({}), using fuzzy matching", e),
+        }
+        self.sense_fuzzy(query, file_context)
+    }
+
+    /// `sense` without the model: fuzzy name matching only
+    pub(crate) fn sense_fuzzy(&self, query: &str, file_context: Option<&str>) -> Result<SenseResponse> {
+        if let Some(file_path) = file_context {
+            let writer = crate::core::GnawTreeWriter::new(file_path)?;
+            let mut nodes = fuzzy_sense(query, &heuristics::definitions(writer.analyze()));
+            nodes.truncate(5);
+            Ok(SenseResponse::Zoom {
+                file_path: file_path.to_string(),
+                nodes,
+                impact: None,
+            })
+        } else {
+            Ok(SenseResponse::Satelite {
+                matches: heuristics::match_project(query, &self.project_root, 10),
+            })
+        }
+    }
+
+    /// `sense` without the fuzzy fallback: an error when the model is not
+    /// available
+    #[cfg(feature = "modernbert")]
+    pub(crate) async fn sense_with_model(&self, query: &str, file_context: Option<&str>) -> Result<SenseResponse> {
+        let model = self.ai_manager.load_model(self.ai_manager.active_model(), self.ai_manager.device())?;
         let query_vector_tensor = model.get_embedding(query)?;
         let query_vector: Vec<f32> = query_vector_tensor.to_vec1()?;
 
@@ -152,7 +228,7 @@ impl GnawSenseBroker {
 
     #[cfg(feature = "modernbert")]
     pub async fn propose_edit(&self, anchor_query: &str, file_path: &str, intent: &str) -> Result<EditProposal> {
-        let model = self.ai_manager.load_model(AiModel::ModernBert, DeviceType::Cpu)?;
+        let model = self.ai_manager.load_model(self.ai_manager.active_model(), self.ai_manager.device())?;
         let index = self.index_file(file_path, model).await?;
         
         let query_vector_tensor = model.get_embedding(anchor_query)?;
@@ -164,44 +240,50 @@ impl GnawSenseBroker {
         }
         
         let (anchor_node, score) = results[0];
-        
+        Self::proposal_for_anchor(&anchor_node.node_path, intent, score)
+    }
+
+    /// Turn an anchor node and an intent (after, before, inside, replace)
+    /// into a concrete edit location
+    pub fn proposal_for_anchor(anchor_path: &str, intent: &str, confidence: f32) -> Result<EditProposal> {
+        let anchor_path = anchor_path.to_string();
         // Logic to determine placement based on intent
         let proposal = match intent.to_lowercase().as_str() {
             "after" => {
                 EditProposal {
-                    anchor_path: anchor_node.node_path.clone(),
+                    anchor_path: anchor_path.clone(),
                     suggested_op: "insert".into(),
-                    parent_path: self.get_parent_path(&anchor_node.node_path),
-                    position: self.get_next_index(&anchor_node.node_path),
-                    confidence: score,
+                    parent_path: Self::get_parent_path(&anchor_path),
+                    position: Self::get_next_index(&anchor_path),
+                    confidence,
                 }
             }
             "before" => {
                 EditProposal {
-                    anchor_path: anchor_node.node_path.clone(),
+                    anchor_path: anchor_path.clone(),
                     suggested_op: "insert".into(),
-                    parent_path: self.get_parent_path(&anchor_node.node_path),
-                    position: self.get_current_index(&anchor_node.node_path),
-                    confidence: score,
+                    parent_path: Self::get_parent_path(&anchor_path),
+                    position: Self::get_current_index(&anchor_path),
+                    confidence,
                 }
             }
             "inside" => {
                 // Insert as the last child of the anchor node
                 EditProposal {
-                    anchor_path: anchor_node.node_path.clone(),
+                    anchor_path: anchor_path.clone(),
                     suggested_op: "insert".into(),
-                    parent_path: anchor_node.node_path.clone(),
+                    parent_path: anchor_path.clone(),
                     position: 1, // bottom of the anchor node
-                    confidence: score,
+                    confidence,
                 }
             }
             "replace" => {
                 EditProposal {
-                    anchor_path: anchor_node.node_path.clone(),
+                    anchor_path: anchor_path.clone(),
                     suggested_op: "edit".into(),
-                    parent_path: anchor_node.node_path.clone(),
+                    parent_path: anchor_path.clone(),
                     position: 0,
-                    confidence: score,
+                    confidence,
                 }
             }
             _ => anyhow::bail!("Unsupported intent: {}. Supported: after, before, inside, replace", intent),
@@ -210,9 +292,7 @@ impl GnawSenseBroker {
         Ok(proposal)
     }
 
-    #[allow(dead_code)]
-    #[cfg(feature = "modernbert")]
-    fn get_parent_path(&self, path: &str) -> String {
+    fn get_parent_path(path: &str) -> String {
         if let Some(last_dot) = path.rfind('.') {
             path[..last_dot].to_string()
         } else {
@@ -220,9 +300,7 @@ impl GnawSenseBroker {
         }
     }
 
-    #[allow(dead_code)]
-    #[cfg(feature = "modernbert")]
-    fn get_next_index(&self, path: &str) -> usize {
+    fn get_next_index(path: &str) -> usize {
         let last_part = if let Some(last_dot) = path.rfind('.') {
             &path[last_dot + 1..]
         } else {
@@ -238,9 +316,7 @@ impl GnawSenseBroker {
     /// Get the position for inserting BEFORE a node (used for "before" intent).
     /// Returns 0 (top of parent) if anchor is the first child, otherwise
     /// inserts after the previous sibling.
-    #[allow(dead_code)]
-    #[cfg(feature = "modernbert")]
-    fn get_current_index(&self, path: &str) -> usize {
+    fn get_current_index(path: &str) -> usize {
         let last_part = if let Some(last_dot) = path.rfind('.') {
             &path[last_dot + 1..]
         } else {
@@ -389,4 +465,22 @@ impl GnawSenseBroker {
         }
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::get_parser;
+
+    #[test]
+    fn test_fuzzy_sense_ranks_abbreviated_name() {
+        let source = "def load_file(path):\n    return open(path).read()\n\ndef parse_config(text):\n    return text\n\ndef render(page):\n    return page\n";
+        let tree = get_parser(Path::new("app.py")).unwrap().parse(source).unwrap();
+        let nodes: Vec<&TreeNode> = tree.children.iter().collect();
+
+        let matches = fuzzy_sense("parse cfg", &nodes);
+        assert_eq!(matches[0].path, "1");
+        assert!(matches[0].score > 0.5, "score {}", matches[0].score);
+        assert!(matches.iter().all(|m| m.path != "2"));
+    }
+}
@@ -0,0 +1,288 @@
+//! Non-AI fallbacks for the semantic tools.
+//!
+//! Used when the `modernbert` feature is not compiled in, or when the model
+//! is not installed, so `sense`, `semantic_edit` and `get_semantic_report`
+//! still return something useful in default builds.
+
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::core::gnaw_ignore::GnawIgnore;
+use crate::llm::{fuzzy_sense, FileMatch, NodeMatch, QualityFinding, SemanticReport};
+use crate::parser::{get_parser, TreeNode};
+
+/// Definitions longer than this many lines are flagged
+pub(crate) const LONG_NODE_LINES: usize = 60;
+/// Definitions with more nested blocks than this are flagged
+pub(crate) const MAX_NESTING: usize = 4;
+/// Definitions shorter than this don't need a doc comment
+pub(crate) const DOC_MIN_LINES: usize = 5;
+
+/// Nodes worth reporting on or matching against: functions, classes, items
+pub fn is_definition(node: &TreeNode) -> bool {
+    let t = node.node_type.as_str();
+    t.contains("definition")
+        || t.contains("item")
+        || t.contains("declaration")
+        || t.contains("function")
+        || t.contains("method")
+        || t.contains("class")
+}
+
+/// Every named definition in `tree`, outermost first
+pub fn definitions(tree: &TreeNode) -> Vec<&TreeNode> {
+    tree.iter().filter(|n| is_definition(n) && n.get_name().is_some()).collect()
+}
+
+/// A structural problem with a definition that needs no model to find
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Issue {
+    /// Longer than `LONG_NODE_LINES`
+    Long { lines: usize },
+    /// Blocks nested deeper than `MAX_NESTING`
+    DeepNesting { depth: usize },
+    /// At least `DOC_MIN_LINES` long without a doc comment or docstring
+    MissingDoc,
+}
+
+/// The structural issues of each node in `defs`, in order. `lines` are the
+/// lines of the whole file, used to look for doc comments above a node.
+pub(crate) fn structural_issues<'a>(defs: &[&'a TreeNode], lines: &[&str]) -> Vec<(&'a TreeNode, Issue)> {
+    let mut issues = Vec::new();
+    for &node in defs {
+        let span = node.end_line.saturating_sub(node.start_line) + 1;
+        if span > LONG_NODE_LINES {
+            issues.push((node, Issue::Long { lines: span }));
+        }
+        let depth = nesting_depth(node);
+        if depth > MAX_NESTING {
+            issues.push((node, Issue::DeepNesting { depth }));
+        }
+        if span >= DOC_MIN_LINES && !has_doc(node, lines) {
+            issues.push((node, Issue::MissingDoc));
+        }
+    }
+    issues
+}
+
+/// Structural report without embeddings: long definitions, deep nesting and
+/// missing doc comments.
+pub fn heuristic_report(file_path: &str, tree: &TreeNode) -> SemanticReport {
+    let lines: Vec<&str> = tree.content.lines().collect();
+    let defs = definitions(tree);
+
+    let findings: Vec<QualityFinding> = structural_issues(&defs, &lines)
+        .into_iter()
+        .map(|(node, issue)| {
+            let name = node.get_name().unwrap_or_default();
+            let (severity, category, message) = match issue {
+                Issue::Long { lines } => ("Warning", "Complexity", format!("'{}' is {} lines long", name, lines)),
+                Issue::DeepNesting { depth } => {
+                    ("Warning", "Complexity", format!("'{}' nests blocks {} levels deep", name, depth))
+                }
+                Issue::MissingDoc => ("Info", "Documentation", format!("'{}' has no doc comment or docstring", name)),
+            };
+            QualityFinding {
+                path: node.path.clone(),
+                severity: severity.into(),
+                category: category.into(),
+                message,
+            }
+        })
+        .collect();
+
+    SemanticReport {
+        file_path: file_path.to_string(),
+        summary: format!(
+            "Heuristic analysis of {} definitions: {} findings.",
+            defs.len(),
+            findings.len()
+        ),
+        findings,
+    }
+}
+
+/// Deepest chain of nested block-like nodes below `node`
+fn nesting_depth(node: &TreeNode) -> usize {
+    let is_block = |t: &str| t.contains("block") || t.contains("body") || t == "compound_statement";
+    node.children
+        .iter()
+        .map(|c| nesting_depth(c) + usize::from(is_block(&c.node_type)))
+        .max()
+        .unwrap_or(0)
+}
+
+/// A doc comment directly above the node, or a docstring as its first
+/// statement (Python)
+fn has_doc(node: &TreeNode, lines: &[&str]) -> bool {
+    if node.start_line >= 2 {
+        if let Some(prev) = lines.get(node.start_line - 2) {
+            let prev = prev.trim_start();
+            if ["///", "//", "#", "/*", "*", "--", "\"\"\""].iter().any(|m| prev.starts_with(m)) {
+                return true;
+            }
+        }
+    }
+    node.content
+        .lines()
+        .skip(1)
+        .find(|l| !l.trim().is_empty())
+        .map(|l| {
+            let l = l.trim_start();
+            l.starts_with("\"\"\"") || l.starts_with("'''") || l.starts_with("///") || l.starts_with("//!")
+        })
+        .unwrap_or(false)
+}
+
+/// Split an identifier or query into lowercase words (`parseConfig`,
+/// `parse_config` and `parse config` all give `["parse", "config"]`)
+pub fn words(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in text.chars() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                out.push(std::mem::take(&mut current));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !current.is_empty() {
+            out.push(std::mem::take(&mut current));
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+/// The definitions in `tree` that best match `query`, scored by
+/// `fuzzy_sense`
+pub fn match_nodes(query: &str, tree: &TreeNode, limit: usize) -> Vec<NodeMatch> {
+    let mut matches = fuzzy_sense(query, &definitions(tree));
+    matches.truncate(limit);
+    matches
+}
+
+/// Lowest `match_nodes` score a node needs before `semantic-edit` rewrites
+/// it without the model. Query words in a node's body alone score at most
+/// 0.5, so the node's name has to match the query.
+pub const MIN_EDIT_SCORE: f32 = 0.6;
+
+/// The node a heuristic `semantic-edit` of `query` should change: the best
+/// `match_nodes` hit, if it scores at least `MIN_EDIT_SCORE`. The error says
+/// why there is none, naming the best candidate when it scored too low.
+pub fn edit_target(query: &str, tree: &TreeNode) -> Result<NodeMatch, String> {
+    match match_nodes(query, tree, 1).into_iter().next() {
+        Some(best) if best.score >= MIN_EDIT_SCORE => Ok(best),
+        Some(best) => Err(format!(
+            "No confident match for '{}': the best candidate is node {} (score {:.2}, needs {:.2}). \
+             Name the node path and edit it directly instead",
+            query, best.path, best.score, MIN_EDIT_SCORE
+        )),
+        None => Err(format!("Could not find a semantic match for '{}'", query)),
+    }
+}
+
+/// Project-wide `match_nodes` over every supported file under `project_root`
+pub fn match_project(query: &str, project_root: &Path, limit: usize) -> Vec<FileMatch> {
+    let ignore = GnawIgnore::load(project_root);
+    let mut matches = Vec::new();
+
+    for entry in WalkDir::new(project_root)
+        .into_iter()
+        .filter_entry(|e| {
+            let hidden = e.depth() > 0 && e.file_name().to_string_lossy().starts_with('.');
+            !hidden && !ignore.is_ignored(e.path(), e.file_type().is_dir())
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .take(5000)
+    {
+        let path = entry.path();
+        let Ok(parser) = get_parser(path) else { continue };
+        let Ok((content, _)) = crate::core::read_source_file(path) else { continue };
+        let Ok(tree) = parser.parse(&content) else { continue };
+
+        let file_path = path.strip_prefix(project_root).unwrap_or(path).to_string_lossy().to_string();
+        for m in match_nodes(query, &tree, limit) {
+            matches.push(FileMatch {
+                file_path: file_path.clone(),
+                node_path: Some(m.path),
+                score: m.score,
+            });
+        }
+    }
+
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    matches
+}
+
+pub(crate) fn preview(content: &str) -> String {
+    if content.chars().count() > 100 {
+        format!("{}...", content.chars().take(97).collect::<String>())
+    } else {
+        content.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, source: &str) -> TreeNode {
+        get_parser(Path::new(name)).unwrap().parse(source).unwrap()
+    }
+
+    #[test]
+    fn test_words_split_identifiers() {
+        assert_eq!(words("parseConfig"), vec!["parse", "config"]);
+        assert_eq!(words("parse_config"), vec!["parse", "config"]);
+        assert_eq!(words("  parse  config "), vec!["parse", "config"]);
+    }
+
+    #[test]
+    fn test_match_nodes_prefers_name_matches() {
+        let tree = parse(
+            "app.py",
+            "def load_file():\n    return parse(config)\n\ndef parse_config():\n    return {}\n",
+        );
+        let matches = match_nodes("parse config", &tree, 5);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].path, "1");
+        assert!(matches[0].score > matches[1].score);
+    }
+
+    #[test]
+    fn test_edit_target_needs_a_name_match() {
+        let tree = parse(
+            "app.py",
+            "def load_file():\n    return parse(config)\n\ndef parse_config():\n    return {}\n",
+        );
+        assert_eq!(edit_target("parse config", &tree).unwrap().path, "1");
+
+        // Only the body of `load_file` mentions "config"
+        let err = edit_target("config", &tree.children[0]).unwrap_err();
+        assert!(err.contains("No confident match"), "{}", err);
+    }
+
+    #[test]
+    fn test_heuristic_report_flags_missing_docs_and_nesting() {
+        let source = "def nested(x):\n    if x:\n        for i in x:\n            while i:\n                if i > 1:\n                    with open(i) as f:\n                        return f\n\ndef documented():\n    \"\"\"Explained.\"\"\"\n    a = 1\n    b = 2\n    return a + b\n";
+        let tree = parse("app.py", source);
+        let report = heuristic_report("app.py", &tree);
+
+        let for_node = |path: &str, category: &str| {
+            report.findings.iter().any(|f| f.path == path && f.category == category)
+        };
+        assert!(for_node("0", "Documentation"));
+        assert!(for_node("0", "Complexity"));
+        assert!(!for_node("1", "Documentation"));
+    }
+}
@@ -0,0 +1,377 @@
+//! File watcher that keeps the relational and semantic indexes fresh during
+//! long sessions.
+//!
+//! Events from `notify` are debounced into batches; each batch re-indexes the
+//! touched files and reports which symbols were added, removed or changed.
+//! Files whose symbols changed are re-embedded if the project has a semantic
+//! index; when no model can be loaded their stale embeddings are dropped
+//! instead, so `sense` never answers from outdated code.
+
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::core::gnaw_ignore::GnawIgnore;
+use crate::core::transaction_log::calculate_content_hash;
+use crate::llm::relational_index::collect_definitions;
+use crate::llm::{RelationalIndexer, SemanticIndexManager};
+use crate::parser::get_parser;
+
+/// Longest a batch is held back while events keep arriving
+const MAX_BATCH_WAIT: Duration = Duration::from_secs(5);
+
+/// What changed in one file after it was re-indexed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeReport {
+    pub file_path: PathBuf,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub deleted: bool,
+}
+
+impl ChangeReport {
+    pub fn is_empty(&self) -> bool {
+        !self.deleted && self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub struct IndexWatcher {
+    project_root: PathBuf,
+    ignore: GnawIgnore,
+    indexer: RelationalIndexer,
+    /// File -> (symbol name -> content hash), used to diff re-indexed files
+    symbols: HashMap<PathBuf, HashMap<String, String>>,
+    /// Only set when the project already has a semantic index to keep fresh
+    semantic: Option<SemanticIndexManager>,
+    #[cfg(feature = "modernbert")]
+    embedder: Option<crate::llm::ProjectIndexer>,
+    /// Set once we have warned that changed files could not be re-embedded
+    embed_warned: bool,
+}
+
+impl IndexWatcher {
+    pub fn new(project_root: &Path) -> Self {
+        let indexed = SemanticIndexManager::storage_path(project_root)
+            .join("model_info.json")
+            .exists();
+        Self {
+            project_root: fs::canonicalize(project_root).unwrap_or_else(|_| project_root.to_path_buf()),
+            ignore: GnawIgnore::load(project_root),
+            indexer: RelationalIndexer::new(project_root),
+            symbols: HashMap::new(),
+            semantic: indexed.then(|| SemanticIndexManager::new(project_root)),
+            #[cfg(feature = "modernbert")]
+            embedder: None,
+            embed_warned: false,
+        }
+    }
+
+    /// Record the current symbols under `dir` so later changes can be diffed
+    /// against them. Returns the number of files seen.
+    pub fn prime(&mut self, dir: &Path, recursive: bool) -> usize {
+        let depth = if recursive { usize::MAX } else { 1 };
+        let files: Vec<PathBuf> = WalkDir::new(dir)
+            .max_depth(depth)
+            .into_iter()
+            .filter_entry(|e| !self.skip(e.path(), e.file_type().is_dir()))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+
+        let mut count = 0;
+        for path in files {
+            if let Some(symbols) = symbol_hashes(&path) {
+                self.symbols.insert(path, symbols);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Re-index each changed path and report its symbol changes. This is what
+    /// the watch loop calls after debouncing; it can also be triggered directly.
+    pub fn process_changes(&mut self, paths: &[PathBuf]) -> Vec<ChangeReport> {
+        let unique: BTreeSet<&PathBuf> = paths.iter().collect();
+        let mut reports = Vec::new();
+
+        for path in unique {
+            if self.skip(path, false) {
+                continue;
+            }
+            let old = self.symbols.remove(path).unwrap_or_default();
+
+            if !path.exists() {
+                let _ = self.indexer.remove_file(path);
+                if let Some(semantic) = &self.semantic {
+                    let _ = semantic.remove_index(&self.relative(path));
+                }
+                if !old.is_empty() {
+                    let mut removed: Vec<String> = old.into_keys().collect();
+                    removed.sort();
+                    reports.push(ChangeReport {
+                        file_path: path.clone(),
+                        removed,
+                        deleted: true,
+                        ..ChangeReport::default()
+                    });
+                }
+                continue;
+            }
+
+            let new = match symbol_hashes(path) {
+                Some(symbols) => symbols,
+                None => continue,
+            };
+            if let Err(e) = self.indexer.index_file(path) {
+                eprintln!("⚠️  Could not re-index {}: {}", path.display(), e);
+            }
+
+            let mut report = ChangeReport {
+                file_path: path.clone(),
+                ..ChangeReport::default()
+            };
+            for (name, hash) in &new {
+                match old.get(name) {
+                    None => report.added.push(name.clone()),
+                    Some(old_hash) if old_hash != hash => report.changed.push(name.clone()),
+                    _ => {}
+                }
+            }
+            report.removed = old.keys().filter(|n| !new.contains_key(*n)).cloned().collect();
+            report.added.sort();
+            report.changed.sort();
+            report.removed.sort();
+
+            self.symbols.insert(path.clone(), new);
+            if !report.is_empty() {
+                self.refresh_embeddings(path);
+                reports.push(report);
+            }
+        }
+
+        reports
+    }
+
+    /// Re-embed `path` in the semantic index, or drop its embeddings when
+    /// that is not possible so searches do not return outdated code.
+    fn refresh_embeddings(&mut self, path: &Path) {
+        if self.semantic.is_none() {
+            return;
+        }
+
+        #[cfg(feature = "modernbert")]
+        let error = {
+            if self.embedder.is_none() {
+                self.embedder = crate::llm::ProjectIndexer::new(&self.project_root).ok();
+            }
+            match &self.embedder {
+                Some(embedder) => match embedder.index_file(path) {
+                    Ok(_) => return,
+                    Err(e) => e.to_string(),
+                },
+                None => "could not start the indexer".to_string(),
+            }
+        };
+        #[cfg(not(feature = "modernbert"))]
+        let error = "built without the modernbert feature".to_string();
+
+        let relative = self.relative(path);
+        if let Err(e) = self.semantic.as_ref().map_or(Ok(()), |s| s.remove_index(&relative)) {
+            eprintln!("⚠️  Could not drop stale embeddings for {}: {}", relative, e);
+        }
+        if !self.embed_warned {
+            eprintln!("⚠️  Semantic index not refreshed ({}); dropped stale embeddings, run 'gnawtreewriter ai index' to rebuild", error);
+            self.embed_warned = true;
+        }
+    }
+
+    /// `path` relative to the project root, the key the semantic index uses
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.project_root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Watch `dir` until the event channel closes, calling `on_change` for
+    /// every file whose symbols changed. Events are batched until `debounce`
+    /// passes without a new one, or at most `MAX_BATCH_WAIT` so a steady
+    /// stream of events does not hold back re-indexing forever.
+    pub fn watch<F>(&mut self, dir: &Path, recursive: bool, debounce: Duration, mut on_change: F) -> Result<()>
+    where
+        F: FnMut(&ChangeReport),
+    {
+        let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(dir, mode)?;
+
+        while let Some(batch) = next_batch(&rx, debounce, MAX_BATCH_WAIT.max(debounce)) {
+            for report in self.process_changes(&batch) {
+                on_change(&report);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip ignored paths and GnawTreeWriter's own state (backups, index and
+    /// logs live in dot-directories under the project root).
+    fn skip(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(&self.project_root).unwrap_or(path);
+        let hidden = relative
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .any(|c| c.starts_with('.') && c != "." && c != "..");
+        hidden || self.ignore.is_ignored(path, is_dir)
+    }
+}
+
+/// Block for the next event, then gather the paths of the events that
+/// follow until `debounce` passes without one or `max_wait` has passed since
+/// the first. `None` once the channel is closed.
+fn next_batch(rx: &mpsc::Receiver<notify::Result<notify::Event>>, debounce: Duration, max_wait: Duration) -> Option<Vec<PathBuf>> {
+    let first = rx.recv().ok()?;
+    let deadline = Instant::now() + max_wait;
+    let mut batch = Vec::new();
+    collect_paths(first, &mut batch);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        match rx.recv_timeout(debounce.min(remaining)) {
+            Ok(event) => collect_paths(event, &mut batch),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn collect_paths(event: notify::Result<notify::Event>, acc: &mut Vec<PathBuf>) {
+    if let Ok(event) = event {
+        if !event.kind.is_access() {
+            acc.extend(event.paths.into_iter().map(|p| fs::canonicalize(&p).unwrap_or(p)));
+        }
+    }
+}
+
+/// Definitions in a file keyed by name, with a hash of each one's content.
+/// `None` if the file is not supported or does not parse.
+fn symbol_hashes(path: &Path) -> Option<HashMap<String, String>> {
+    let parser = get_parser(path).ok()?;
+    let content = fs::read_to_string(path).ok()?;
+    let tree = parser.parse(&content).ok()?;
+
+    let mut definitions = HashMap::new();
+    collect_definitions(&tree, &mut definitions);
+    Some(
+        definitions
+            .into_iter()
+            .map(|(name, node)| (name, calculate_content_hash(&node.content)))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_file_triggers_reindex_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let file = root.join("lib.py");
+        fs::write(&file, "def keep():\n    return 1\n\ndef old():\n    return 2\n").unwrap();
+
+        let mut watcher = IndexWatcher::new(&root);
+        assert_eq!(watcher.prime(&root, true), 1);
+
+        fs::write(&file, "def keep():\n    return 10\n\ndef fresh():\n    return 3\n").unwrap();
+        let reports = watcher.process_changes(&[file.clone(), file.clone()]);
+
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.file_path, file);
+        assert_eq!(report.added, vec!["fresh".to_string()]);
+        assert_eq!(report.removed, vec!["old".to_string()]);
+        assert_eq!(report.changed, vec!["keep".to_string()]);
+
+        let graphs = RelationalIndexer::new(&root).load_all_graphs().unwrap();
+        assert!(graphs.iter().any(|g| g.definitions.contains_key("fresh")));
+
+        // Nothing changed since the last pass
+        assert!(watcher.process_changes(&[file]).is_empty());
+    }
+
+    #[test]
+    fn steady_events_are_flushed_after_the_max_wait() {
+        let (tx, rx) = mpsc::channel();
+        let feeder = std::thread::spawn(move || {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(2) {
+                let event = notify::Event::new(notify::EventKind::Any).add_path(PathBuf::from("lib.py"));
+                if tx.send(Ok(event)).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        // Events arrive well inside the debounce, so only the max wait ends the batch
+        let start = Instant::now();
+        let batch = next_batch(&rx, Duration::from_millis(200), Duration::from_millis(300)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+        assert!(!batch.is_empty());
+
+        drop(rx);
+        feeder.join().unwrap();
+    }
+
+    #[test]
+    fn edited_and_deleted_files_leave_no_stale_embeddings() {
+        use crate::llm::NodeEmbedding;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let file = root.join("lib.py");
+        fs::write(&file, "def old():\n    return 2\n").unwrap();
+
+        let semantic = SemanticIndexManager::new(&root);
+        semantic.save_model_info("ModernBERT-base-v1", 768).unwrap();
+        semantic
+            .save_index("lib.py", vec![NodeEmbedding {
+                file_path: "lib.py".to_string(),
+                node_path: "0".to_string(),
+                content_preview: "def old():".to_string(),
+                vector: vec![1.0, 0.0],
+            }])
+            .unwrap();
+        let stale = |root: &Path| {
+            SemanticIndexManager::new(root)
+                .load_project_index()
+                .unwrap()
+                .entries
+                .iter()
+                .any(|e| e.content_preview.contains("old"))
+        };
+
+        let mut watcher = IndexWatcher::new(&root);
+        watcher.prime(&root, true);
+
+        // Re-embedded if a model is installed, dropped otherwise
+        fs::write(&file, "def fresh():\n    return 3\n").unwrap();
+        assert_eq!(watcher.process_changes(std::slice::from_ref(&file)).len(), 1);
+        assert!(!stale(&root));
+
+        fs::remove_file(&file).unwrap();
+        watcher.process_changes(&[file]);
+        assert!(SemanticIndexManager::new(&root).load_project_index().unwrap().entries.is_empty());
+    }
+}
@@ -153,7 +153,7 @@ pub fn get_node_context(file_path: &str, node_path: &str) -> Result<NodeContext>
     let tree = writer.analyze();
 
     let node = find_node(tree, node_path)
-        .ok_or_else(|| anyhow::anyhow!("Node not found: {}", node_path))?;
+        .ok_or_else(|| crate::core::GnawError::node_not_found(node_path, tree))?;
 
     let parent_path = find_parent_path(tree, node_path);
     let sibling_context = get_sibling_content(tree, node_path);
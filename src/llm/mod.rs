@@ -1,6 +1,8 @@
 pub mod ai_manager;
 pub mod batch;
 pub mod gnaw_sense;
+pub mod heuristics;
+pub mod index_watcher;
 pub mod llm_integration;
 #[cfg(feature = "modernbert")]
 pub mod project_indexer;
@@ -10,6 +12,7 @@ pub mod semantic_index;
 pub use ai_manager::*;
 pub use batch::*;
 pub use gnaw_sense::*;
+pub use index_watcher::*;
 pub use llm_integration::*;
 #[cfg(feature = "modernbert")]
 pub use project_indexer::*;
@@ -2,9 +2,10 @@
 
 use anyhow::Result;
 use std::path::{Path, PathBuf};
-use crate::llm::{GnawSenseBroker, SemanticIndexManager, NodeEmbedding, AiModel, DeviceType};
+use crate::llm::{GnawSenseBroker, SemanticIndexManager, NodeEmbedding};
 use crate::parser::{get_parser, TreeNode};
 use walkdir::WalkDir;
+use crate::core::gnaw_ignore::GnawIgnore;
 use std::fs;
 
 pub struct ProjectIndexer {
@@ -25,7 +26,8 @@ impl ProjectIndexer {
     /// Crawl the project and index supported source files starting from target_path
     pub async fn index_all(&self, target_path: &Path) -> Result<usize> {
         let mut total_files = 0;
-        let model = self.broker.get_manager().load_model(AiModel::ModernBert, DeviceType::Cpu)?;
+        let manager = self.broker.get_manager();
+        let model = manager.load_model(manager.active_model(), manager.device())?;
         
         // Canonicalize target_path to ensure strip_prefix works
         let target_path = if target_path.is_relative() {
@@ -34,8 +36,10 @@ impl ProjectIndexer {
             target_path.to_path_buf()
         };
 
+        let ignore = GnawIgnore::load(&self.project_root);
         for entry in WalkDir::new(&target_path)
             .into_iter()
+            .filter_entry(|e| !ignore.is_ignored(e.path(), e.file_type().is_dir()))
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file()) 
         {
@@ -83,6 +87,30 @@ impl ProjectIndexer {
         Ok(total_files)
     }
 
+    /// Re-embed a single file, replacing whatever was stored for it.
+    /// Returns the number of embeddings written.
+    pub fn index_file(&self, path: &Path) -> Result<usize> {
+        let manager = self.broker.get_manager();
+        let model = manager.load_model(manager.active_model(), manager.device())?;
+
+        let file_path_str = path.strip_prefix(&self.project_root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string();
+        let content = fs::read_to_string(path)?;
+        let tree = get_parser(path)?.parse(&content)?;
+
+        let mut entries = Vec::new();
+        self.collect_embeddings(&tree, &file_path_str, model, &mut entries)?;
+        let count = entries.len();
+        if entries.is_empty() {
+            self.index_manager.remove_index(&file_path_str)?;
+        } else {
+            self.index_manager.save_index(&file_path_str, entries)?;
+        }
+        Ok(count)
+    }
+
     fn collect_embeddings(
         &self, 
         node: &TreeNode, 
@@ -7,9 +7,13 @@ use crate::parser::TreeNode;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum RelationType {
-    Call,       // Function or method call
-    Definition, // Where a symbol is defined
-    Reference,  // General usage/reference
+    Call,        // Function or method call
+    Definition,  // Where a symbol is defined
+    Reference,   // General usage/reference
+    Import,      // `import x` / `use a::x`
+    Inherit,     // Base class or supertrait
+    Implement,   // Implemented trait or interface
+    FieldAccess, // `obj.field` that is not a method call
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -28,6 +32,124 @@ pub struct FileGraph {
     pub definitions: HashMap<String, String>, // Name -> Path within file
 }
 
+/// The relations of one or more `FileGraph`s as a node/edge graph, the form
+/// visualization and call-graph tools expect
+#[derive(Debug, Default, Serialize)]
+pub struct RelationGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// A definition, or a name that is used but not defined in the indexed files
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct GraphNode {
+    /// `file#name` for definitions, the bare name for unresolved symbols
+    pub id: String,
+    pub name: String,
+    pub file: Option<String>,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct GraphEdge {
+    /// The definition the relation occurs in, or the file for top-level code
+    pub from: String,
+    pub to: String,
+    pub relation_type: RelationType,
+    /// Path of the node the relation was found at, e.g. the call expression
+    pub from_path: String,
+}
+
+impl RelationGraph {
+    pub fn from_graphs(graphs: &[FileGraph]) -> Self {
+        let mut nodes: HashMap<String, GraphNode> = HashMap::new();
+        for graph in graphs {
+            for (name, path) in &graph.definitions {
+                let id = format!("{}#{}", graph.file_path, name);
+                nodes.insert(id.clone(), GraphNode {
+                    id,
+                    name: name.clone(),
+                    file: Some(graph.file_path.clone()),
+                    path: Some(path.clone()),
+                });
+            }
+        }
+
+        let mut edges = Vec::new();
+        for graph in graphs {
+            for rel in &graph.relations {
+                let from = enclosing_definition(graph, &rel.from_path)
+                    .map(|name| format!("{}#{}", graph.file_path, name))
+                    .unwrap_or_else(|| graph.file_path.clone());
+                if !nodes.contains_key(&from) {
+                    nodes.insert(from.clone(), GraphNode {
+                        id: from.clone(),
+                        name: from.clone(),
+                        file: Some(graph.file_path.clone()),
+                        path: None,
+                    });
+                }
+
+                let resolved = rel.to_file.as_ref().map(|file| format!("{}#{}", file, rel.to_name));
+                let to = match resolved {
+                    Some(id) if nodes.contains_key(&id) => id,
+                    _ => {
+                        nodes.entry(rel.to_name.clone()).or_insert_with(|| GraphNode {
+                            id: rel.to_name.clone(),
+                            name: rel.to_name.clone(),
+                            file: None,
+                            path: None,
+                        });
+                        rel.to_name.clone()
+                    }
+                };
+
+                edges.push(GraphEdge {
+                    from,
+                    to,
+                    relation_type: rel.relation_type.clone(),
+                    from_path: rel.from_path.clone(),
+                });
+            }
+        }
+
+        let mut nodes: Vec<GraphNode> = nodes.into_values().collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        edges.sort_by(|a, b| (&a.from, &a.to, &a.from_path).cmp(&(&b.from, &b.to, &b.from_path)));
+        Self { nodes, edges }
+    }
+}
+
+/// Named definitions under `node`, keyed by name; a later definition of a
+/// name replaces an earlier one
+pub(crate) fn collect_definitions<'a>(node: &'a TreeNode, acc: &mut HashMap<String, &'a TreeNode>) {
+    if node.node_type.contains("definition") || node.node_type.contains("item") {
+        if let Some(name) = node.get_name() {
+            acc.insert(name, node);
+        }
+    }
+    for child in &node.children {
+        collect_definitions(child, acc);
+    }
+}
+
+/// Each definition's name mapped to its node path, as stored in `FileGraph`
+fn definition_paths(tree: &TreeNode) -> HashMap<String, String> {
+    let mut defs = HashMap::new();
+    collect_definitions(tree, &mut defs);
+    defs.into_iter().map(|(name, node)| (name, node.path.clone())).collect()
+}
+
+/// Name of the innermost definition in `graph` whose node contains `path`
+fn enclosing_definition<'a>(graph: &'a FileGraph, path: &str) -> Option<&'a str> {
+    graph
+        .definitions
+        .iter()
+        .filter(|(_, def)| path == def.as_str() || path.starts_with(&format!("{}.", def)))
+        .max_by_key(|(_, def)| def.len())
+        .map(|(name, _)| name.as_str())
+}
+
 pub struct RelationalIndexer {
     storage_dir: PathBuf,
     symbol_table: HashMap<String, Vec<String>>, // Name -> List of files where defined
@@ -35,7 +157,7 @@ pub struct RelationalIndexer {
 
 impl RelationalIndexer {
     pub fn new(project_root: &Path) -> Self {
-        let storage_dir = project_root.join(".gnawtreewriter_ai").join("graph");
+        let storage_dir = Self::storage_path(project_root);
         if !storage_dir.exists() {
             let _ = fs::create_dir_all(&storage_dir);
         }
@@ -45,14 +167,25 @@ impl RelationalIndexer {
         }
     }
 
+    /// Where the relation graphs of `project_root` are kept
+    pub fn storage_path(project_root: &Path) -> PathBuf {
+        project_root.join(".gnawtreewriter_ai").join("graph")
+    }
+
     /// Scan a directory and build relations between files recursively
     pub fn index_directory(&mut self, dir_path: &Path) -> Result<Vec<FileGraph>> {
         let mut graphs = Vec::new();
         use walkdir::WalkDir;
         
+        let ignore = crate::core::gnaw_ignore::GnawIgnore::load(&crate::core::find_project_root(dir_path));
+
         // 1. First pass: Collect all definitions in the directory recursively
         for entry in WalkDir::new(dir_path)
             .into_iter()
+            .filter_entry(|e| {
+                let abs = fs::canonicalize(e.path()).unwrap_or_else(|_| e.path().to_path_buf());
+                !ignore.is_ignored(&abs, e.file_type().is_dir())
+            })
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
         {
@@ -72,8 +205,7 @@ impl RelationalIndexer {
             if let Ok(content) = fs::read_to_string(path) {
                 if let Ok(parser) = crate::parser::get_parser(path) {
                     if let Ok(tree) = parser.parse(&content) {
-                        let mut defs = HashMap::new();
-                        self.collect_definitions(&tree, &mut defs);
+                        let defs = definition_paths(&tree);
                         
                         let file_str = path.to_string_lossy().to_string();
                         for name in defs.keys() {
@@ -108,40 +240,146 @@ impl RelationalIndexer {
         Ok(final_graphs)
     }
 
-    fn collect_definitions(&self, node: &TreeNode, acc: &mut HashMap<String, String>) {
-        if node.node_type.contains("definition") || node.node_type.contains("item") {
-            if let Some(name) = node.get_name() {
-                acc.insert(name, node.path.clone());
+    /// Re-index a single file against the graphs already on disk, so one
+    /// changed file can be refreshed without rescanning the project.
+    /// Returns `None` for files no parser supports.
+    pub fn index_file(&mut self, path: &Path) -> Result<Option<FileGraph>> {
+        if self.symbol_table.is_empty() {
+            for graph in self.load_all_graphs()? {
+                for name in graph.definitions.keys() {
+                    self.symbol_table.entry(name.clone())
+                        .or_default()
+                        .push(graph.file_path.clone());
+                }
             }
         }
-        for child in &node.children {
-            self.collect_definitions(child, acc);
+
+        let parser = match crate::parser::get_parser(path) {
+            Ok(parser) => parser,
+            Err(_) => return Ok(None),
+        };
+        let content = fs::read_to_string(path)?;
+        let tree = parser.parse(&content)?;
+
+        let defs = definition_paths(&tree);
+
+        let file_str = path.to_string_lossy().to_string();
+        for files in self.symbol_table.values_mut() {
+            files.retain(|f| f != &file_str);
+        }
+        for name in defs.keys() {
+            self.symbol_table.entry(name.clone())
+                .or_default()
+                .push(file_str.clone());
         }
+
+        let mut relations = HashSet::new();
+        self.extract_relations(&tree, &file_str, &mut relations);
+
+        let graph = FileGraph {
+            file_path: file_str,
+            relations,
+            definitions: defs,
+        };
+        self.save_graph(&graph)?;
+        Ok(Some(graph))
+    }
+
+    /// Index `target` (a file or a directory) and return its relations as a
+    /// `RelationGraph`
+    pub fn relation_graph(&mut self, target: &Path) -> Result<RelationGraph> {
+        if let Err(e) = fs::metadata(target) {
+            return Err(crate::core::GnawError::io(format!("Failed to read {}", target.display()), e).into());
+        }
+        let graphs = if target.is_dir() {
+            self.index_directory(target)?
+        } else {
+            self.index_file(target)?.into_iter().collect()
+        };
+        Ok(RelationGraph::from_graphs(&graphs))
+    }
+
+    /// Drop the stored graph of a file that no longer exists
+    pub fn remove_file(&mut self, path: &Path) -> Result<()> {
+        let file_str = path.to_string_lossy().to_string();
+        for files in self.symbol_table.values_mut() {
+            files.retain(|f| f != &file_str);
+        }
+        let file_hash = crate::core::transaction_log::calculate_content_hash(&file_str);
+        let save_path = self.storage_dir.join(format!("{}.json", file_hash));
+        if save_path.exists() {
+            fs::remove_file(save_path)?;
+        }
+        Ok(())
     }
 
     fn extract_relations(&self, node: &TreeNode, current_file: &str, acc: &mut HashSet<Relation>) {
-        if node.node_type.contains("call") || node.node_type.contains("usage") {
+        self.extract_relations_at(node, current_file, false, acc);
+    }
+
+    /// `is_callee` is set for the function part of a call, so `obj.method()`
+    /// counts as a call and not also as a field access
+    fn extract_relations_at(&self, node: &TreeNode, current_file: &str, is_callee: bool, acc: &mut HashSet<Relation>) {
+        let mut add = |name: String, relation_type: RelationType| {
+            // Check if we know where this is defined
+            let to_file = self.symbol_table.get(&name)
+                .and_then(|files| files.first()) // Simplified: take first match
+                .cloned();
+            acc.insert(Relation {
+                from_file: current_file.to_string(),
+                from_path: node.path.clone(),
+                to_file,
+                to_name: name,
+                relation_type,
+            });
+        };
+
+        let node_type = node.node_type.as_str();
+        if IMPORT_TYPES.contains(&node_type) {
+            let mut names = Vec::new();
+            imported_names(node, &mut names);
+            if names.is_empty() {
+                names.extend(node.get_name());
+            }
+            for name in names {
+                add(name, RelationType::Import);
+            }
+            return;
+        }
+
+        if node_type.contains("call") || node_type.contains("usage") {
             if let Some(name) = node.get_name() {
-                // Check if we know where this is defined
-                let to_file = self.symbol_table.get(&name)
-                    .and_then(|files| files.first()) // Simplified: take first match
-                    .cloned();
-
-                acc.insert(Relation {
-                    from_file: current_file.to_string(),
-                    from_path: node.path.clone(),
-                    to_file,
-                    to_name: name,
-                    relation_type: RelationType::Call,
-                });
+                add(name, RelationType::Call);
+            }
+        } else if FIELD_ACCESS_TYPES.contains(&node_type) && !is_callee {
+            if let Some(field) = node.children.iter().rev().find(|c| c.node_type.ends_with("identifier")) {
+                add(field.content.clone(), RelationType::FieldAccess);
             }
         }
 
-        for child in &node.children {
-            self.extract_relations(child, current_file, acc);
+        for (name, relation_type) in supertypes(node) {
+            add(name, relation_type);
+        }
+
+        let is_call = node_type.contains("call");
+        for (i, child) in node.children.iter().enumerate() {
+            self.extract_relations_at(child, current_file, is_call && i == 0, acc);
         }
     }
 
+    /// Every stored relation that points at `name`, of any type, ordered by
+    /// file and path
+    pub fn find_references(&self, name: &str) -> Result<Vec<Relation>> {
+        let mut found: Vec<Relation> = self
+            .load_all_graphs()?
+            .into_iter()
+            .flat_map(|graph| graph.relations)
+            .filter(|rel| rel.to_name == name)
+            .collect();
+        found.sort_by(|a, b| (&a.from_file, &a.from_path).cmp(&(&b.from_file, &b.from_path)));
+        Ok(found)
+    }
+
     pub fn save_graph(&self, graph: &FileGraph) -> Result<()> {
         let file_hash = crate::core::transaction_log::calculate_content_hash(&graph.file_path);
         let save_path = self.storage_dir.join(format!("{}.json", file_hash));
@@ -166,4 +404,169 @@ impl RelationalIndexer {
         }
         Ok(graphs)
     }
-}
\ No newline at end of file
+}
+
+const IMPORT_TYPES: &[&str] = &["import_statement", "import_from_statement", "use_declaration", "import_declaration"];
+
+const FIELD_ACCESS_TYPES: &[&str] = &["attribute", "field_expression", "member_expression", "field_access"];
+
+/// The names an import brings into scope: `os.path` for `import os.path`,
+/// `Base` for `from models import Base`, `HashMap` and `HashSet` for
+/// `use std::collections::{HashMap, HashSet}`
+fn imported_names(node: &TreeNode, acc: &mut Vec<String>) {
+    match node.node_type.as_str() {
+        "import_from_statement" => {
+            // Everything after the `import` keyword; before it is the module
+            let imported = node.children.iter().skip_while(|c| c.node_type != "import").skip(1);
+            for child in imported {
+                imported_names(child, acc);
+            }
+        }
+        "dotted_name" => acc.push(node.content.clone()),
+        "aliased_import" | "use_as_clause" => {
+            if let Some(first) = node.children.first() {
+                imported_names(first, acc);
+            }
+        }
+        "identifier" | "type_identifier" => acc.push(node.content.clone()),
+        "scoped_identifier" | "scoped_type_identifier" => {
+            if let Some(last) = node.children.iter().rev().find(|c| c.node_type.ends_with("identifier")) {
+                acc.push(last.content.clone());
+            }
+        }
+        // `use a::{b, c}`: only the list names what is imported
+        "scoped_use_list" => {
+            if let Some(list) = node.children.iter().find(|c| c.node_type == "use_list") {
+                imported_names(list, acc);
+            }
+        }
+        "import_statement" | "use_declaration" | "use_list" => {
+            for child in &node.children {
+                imported_names(child, acc);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Base classes, supertraits and implemented interfaces declared by `node`
+fn supertypes(node: &TreeNode) -> Vec<(String, RelationType)> {
+    let mut out = Vec::new();
+    let mut push_types = |clause: &TreeNode, relation_type: RelationType| {
+        let mut names = Vec::new();
+        type_names(clause, &mut names);
+        out.extend(names.into_iter().map(|name| (name, relation_type.clone())));
+    };
+    match node.node_type.as_str() {
+        // Python: `class User(Base, Mixin):`
+        "class_definition" => {
+            if let Some(bases) = node.children.iter().find(|c| c.node_type == "argument_list") {
+                push_types(bases, RelationType::Inherit);
+            }
+        }
+        // Rust: `trait Named: Shape`
+        "trait_item" => {
+            if let Some(bounds) = node.children.iter().find(|c| c.node_type == "trait_bounds") {
+                push_types(bounds, RelationType::Inherit);
+            }
+        }
+        // Rust: `impl Shape for Square`
+        "impl_item" => {
+            if let Some(i) = node.children.iter().position(|c| c.node_type == "for") {
+                if let Some(trait_node) = i.checked_sub(1).and_then(|i| node.children.get(i)) {
+                    push_types(trait_node, RelationType::Implement);
+                }
+            }
+        }
+        // Java, TypeScript and JavaScript classes
+        "class_declaration" | "class" | "abstract_class_declaration" | "interface_declaration" => {
+            let clauses = node.children.iter().flat_map(|c| {
+                if c.node_type == "class_heritage" { c.children.iter().collect() } else { vec![c] }
+            });
+            for clause in clauses {
+                match clause.node_type.as_str() {
+                    "superclass" | "extends_clause" | "extends_interfaces" => push_types(clause, RelationType::Inherit),
+                    "super_interfaces" | "implements_clause" => push_types(clause, RelationType::Implement),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    out
+}
+
+/// Names of the types listed in a base class or trait bound clause, without
+/// their generic arguments
+fn type_names(node: &TreeNode, acc: &mut Vec<String>) {
+    match node.node_type.as_str() {
+        "identifier" | "type_identifier" => acc.push(node.content.clone()),
+        "attribute" | "scoped_type_identifier" | "scoped_identifier" | "member_expression" => {
+            if let Some(last) = node.children.iter().rev().find(|c| c.node_type.ends_with("identifier")) {
+                acc.push(last.content.clone());
+            }
+        }
+        "generic_type" => {
+            if let Some(first) = node.children.first() {
+                type_names(first, acc);
+            }
+        }
+        // `metaclass=ABCMeta` is not a base class
+        "keyword_argument" | "type_arguments" => {}
+        _ => {
+            for child in &node.children {
+                type_names(child, acc);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relations(name: &str, source: &str) -> Vec<(RelationType, String)> {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join(name);
+        fs::write(&file, source).unwrap();
+        let graph = RelationalIndexer::new(dir.path()).index_file(&file).unwrap().unwrap();
+        let mut found: Vec<_> = graph.relations.into_iter().map(|r| (r.relation_type, r.to_name)).collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1));
+        found
+    }
+
+    fn has(found: &[(RelationType, String)], relation_type: RelationType, name: &str) -> bool {
+        found.iter().any(|(t, n)| *t == relation_type && n == name)
+    }
+
+    #[test]
+    fn python_bases_imports_and_fields_are_recorded() {
+        let found = relations(
+            "app.py",
+            "from models import Base, Mixin as M\n\nclass User(Base, metaclass=Meta):\n    def save(self):\n        self.name = 1\n        self.store()\n",
+        );
+        assert!(has(&found, RelationType::Inherit, "Base"), "{:?}", found);
+        assert!(!has(&found, RelationType::Inherit, "Meta"), "{:?}", found);
+        assert!(has(&found, RelationType::Import, "Base"), "{:?}", found);
+        assert!(has(&found, RelationType::Import, "Mixin"), "{:?}", found);
+        assert!(!has(&found, RelationType::Import, "models"), "{:?}", found);
+        assert!(has(&found, RelationType::FieldAccess, "name"), "{:?}", found);
+        // A method call is a call, not a field access
+        assert!(!has(&found, RelationType::FieldAccess, "store"), "{:?}", found);
+    }
+
+    #[test]
+    fn rust_supertraits_impls_and_uses_are_recorded() {
+        let found = relations(
+            "lib.rs",
+            "use std::collections::{HashMap, HashSet};\n\ntrait Named: Shape {}\n\nstruct Square { side: u32 }\n\nimpl Shape for Square {\n    fn area(&self) -> u32 { self.side * self.side.pow(1) }\n}\n",
+        );
+        assert!(has(&found, RelationType::Inherit, "Shape"), "{:?}", found);
+        assert!(has(&found, RelationType::Implement, "Shape"), "{:?}", found);
+        assert!(has(&found, RelationType::Import, "HashMap"), "{:?}", found);
+        assert!(has(&found, RelationType::Import, "HashSet"), "{:?}", found);
+        assert!(has(&found, RelationType::FieldAccess, "side"), "{:?}", found);
+        assert!(has(&found, RelationType::Call, "pow"), "{:?}", found);
+        assert!(!has(&found, RelationType::FieldAccess, "pow"), "{:?}", found);
+    }
+}
@@ -30,13 +30,18 @@ pub struct SemanticIndexManager {
 
 impl SemanticIndexManager {
     pub fn new(project_root: &Path) -> Self {
-        let storage_dir = project_root.join(".gnawtreewriter_ai").join("index");
+        let storage_dir = Self::storage_path(project_root);
         if !storage_dir.exists() {
             let _ = fs::create_dir_all(&storage_dir);
         }
         Self { storage_dir }
     }
 
+    /// Where the embeddings of `project_root` are kept
+    pub fn storage_path(project_root: &Path) -> PathBuf {
+        project_root.join(".gnawtreewriter_ai").join("index")
+    }
+
     pub fn get_storage_dir(&self) -> &Path {
         &self.storage_dir
     }
@@ -72,6 +77,38 @@ impl SemanticIndexManager {
         Ok(())
     }
 
+    /// Drop the stored embeddings of `file_path`, if there are any
+    pub fn remove_index(&self, file_path: &str) -> Result<()> {
+        let file_hash = crate::core::transaction_log::calculate_content_hash(file_path);
+        let index_path = self.storage_dir.join(format!("{}.json", file_hash));
+        if index_path.exists() {
+            fs::remove_file(index_path)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the stored embeddings of every file under `dir`. Stored paths are
+    /// relative to `project_root`. Returns how many files were dropped.
+    pub fn remove_under(&self, project_root: &Path, dir: &Path) -> Result<usize> {
+        let mut removed = 0;
+        if !self.storage_dir.exists() { return Ok(removed); }
+
+        for entry in fs::read_dir(&self.storage_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(entries) = serde_json::from_str::<Vec<NodeEmbedding>>(&fs::read_to_string(&path)?) else {
+                continue;
+            };
+            if entries.iter().any(|e| project_root.join(&e.file_path).starts_with(dir)) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
     pub fn load_project_index(&self) -> Result<SemanticIndex> {
         let mut index = SemanticIndex::default();
         if !self.storage_dir.exists() { return Ok(index); }
@@ -133,6 +170,27 @@ pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn remove_under_keeps_files_outside_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SemanticIndexManager::new(dir.path());
+        for file in ["src/a.rs", "src/sub/b.rs", "tests/c.rs"] {
+            let entry = NodeEmbedding {
+                file_path: file.to_string(),
+                node_path: "0".to_string(),
+                content_preview: String::new(),
+                vector: vec![1.0],
+            };
+            manager.save_index(file, vec![entry]).unwrap();
+        }
+        manager.save_model_info("ModernBERT-base-v1", 768).unwrap();
+
+        assert_eq!(manager.remove_under(dir.path(), &dir.path().join("src")).unwrap(), 2);
+        let left: Vec<String> = manager.load_project_index().unwrap().entries.into_iter().map(|e| e.file_path).collect();
+        assert_eq!(left, vec!["tests/c.rs".to_string()]);
+        assert!(manager.get_model_info().unwrap().is_some());
+    }
+
     #[test]
     fn test_cosine_similarity() {
         let a = vec![1.0, 0.0];
@@ -4,7 +4,7 @@
 
 use anyhow::Result;
 use clap::Parser;
-use gnawtreewriter::cli::Cli;
+use gnawtreewriter::cli::{exit_code, Cli};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -21,7 +21,8 @@ async fn main() -> Result<()> {
                 eprintln!("\n💡 [GnawTip]: Every surgical edit needs a target. Use 'gnawtreewriter list <file>' to find node paths.");
             }
             
-            std::process::exit(1);
+            // --help and --version exit 0, usage errors EXIT_ERROR
+            std::process::exit(e.exit_code());
         }
     };
 
@@ -47,7 +48,7 @@ async fn main() -> Result<()> {
             }
         }
         
-        std::process::exit(1);
+        std::process::exit(exit_code(&err));
     }
     
     Ok(())
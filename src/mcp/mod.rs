@@ -8,14 +8,15 @@
 
 #[cfg(feature = "mcp")]
 pub mod mcp_server {
-    use crate::core::{EditOperation, GnawTreeWriter, LabelManager};
-    use crate::parser::TreeNode;
-    use anyhow::Result;
+    use crate::core::{EditOperation, EditOptions, GnawError, GnawTreeWriter, LabelManager};
+    use crate::llm::DeviceType;
+    use crate::parser::{NodeKind, TreeNode};
+    use anyhow::{Context, Result};
     use axum::{
         extract::{Json, State},
         http::{HeaderMap, StatusCode},
         response::IntoResponse,
-        routing::post,
+        routing::{get, post},
         Router,
     };
     use serde::{Deserialize, Serialize};
@@ -29,8 +30,161 @@ pub mod mcp_server {
     struct AppState {
         token: Option<String>,
         project_root: std::path::PathBuf,
+        started: std::time::Instant,
+        /// Permits for in-flight HTTP requests, if `--max-concurrency` is set
+        limiter: Option<Arc<tokio::sync::Semaphore>>,
+        timeouts: ToolTimeouts,
+        pulse: PulseOptions,
+        parse_cache: ParseCache,
     }
 
+    type CachedParse = (u64, Arc<GnawTreeWriter>);
+
+    /// Most files `ParseCache` holds before it starts over
+    const PARSE_CACHE_FILES: usize = 64;
+
+    /// Files parsed by the read-only tools, kept so that reading an unchanged
+    /// file again skips the parse. Entries are keyed by path (and whether
+    /// the parse was tolerant) and only reused while the hash of the file's
+    /// bytes still matches, so a change made outside the server is picked up
+    /// too.
+    #[derive(Default)]
+    struct ParseCache {
+        /// (path, tolerant) -> (hash of the file's bytes, parsed file)
+        entries: std::sync::Mutex<std::collections::HashMap<(String, bool), CachedParse>>,
+        /// Number of parses the cache could not avoid
+        parses: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ParseCache {
+        fn load(&self, file_path: &str, tolerant: bool) -> anyhow::Result<Arc<GnawTreeWriter>> {
+            // Let the writer report a missing, unreadable or oversized file
+            // before anything is read
+            match std::fs::metadata(file_path) {
+                Ok(meta) if meta.len() <= crate::core::max_file_size() => {}
+                _ => return load_for_reading(file_path, tolerant).map(Arc::new),
+            }
+            let Ok(bytes) = std::fs::read(file_path) else {
+                return load_for_reading(file_path, tolerant).map(Arc::new);
+            };
+            let hash = {
+                use std::hash::{Hash, Hasher};
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                bytes.hash(&mut hasher);
+                hasher.finish()
+            };
+            let key = (file_path.to_string(), tolerant);
+            if let Some((cached, writer)) = self.entries.lock().unwrap().get(&key) {
+                if *cached == hash {
+                    return Ok(writer.clone());
+                }
+            }
+
+            let writer = Arc::new(GnawTreeWriter::from_file_bytes(file_path, bytes, tolerant)?);
+            self.parses.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= PARSE_CACHE_FILES {
+                entries.clear();
+            }
+            entries.insert(key, (hash, writer.clone()));
+            Ok(writer)
+        }
+
+        /// Drop `file_path`'s entries, e.g. because a tool is about to edit it
+        fn invalidate(&self, file_path: &str) {
+            self.entries.lock().unwrap().retain(|(path, _), _| path != file_path);
+        }
+
+        /// Drop every entry, for tools that may change any file
+        fn clear(&self) {
+            self.entries.lock().unwrap().clear();
+        }
+    }
+
+    /// Upper bounds for a single `tools/call`
+    #[derive(Debug, Clone, Copy)]
+    pub struct ToolTimeouts {
+        /// Tools that run the model (`sense`, `get_semantic_report`, ...)
+        pub ai: std::time::Duration,
+        /// Every other tool
+        pub tool: std::time::Duration,
+    }
+
+    impl Default for ToolTimeouts {
+        fn default() -> Self {
+            Self {
+                ai: std::time::Duration::from_secs(60),
+                tool: std::time::Duration::from_secs(15),
+            }
+        }
+    }
+
+    /// Where the pulse looks for callers of an edited symbol
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum PulseScope {
+        /// Index the edited file's directory (fast, misses callers elsewhere)
+        #[default]
+        Parent,
+        /// Index the whole project root
+        Project,
+    }
+
+    /// The "pulse" of related nodes, test files and hints that edit tools
+    /// attach to their result
+    #[derive(Debug, Clone, Copy)]
+    pub struct PulseOptions {
+        /// Off: no pulse for any call, whatever `include_pulse` says
+        pub enabled: bool,
+        pub scope: PulseScope,
+    }
+
+    impl Default for PulseOptions {
+        fn default() -> Self {
+            Self { enabled: true, scope: PulseScope::Parent }
+        }
+    }
+
+    /// Tuning knobs for the HTTP server
+    #[derive(Debug, Clone, Default)]
+    pub struct ServerOptions {
+        /// Requests allowed in flight at once; more are rejected with 429
+        pub max_concurrency: Option<usize>,
+        pub timeouts: ToolTimeouts,
+        pub pulse: PulseOptions,
+    }
+
+    /// Tools that may load and run the embedding model
+    const AI_TOOLS: &[&str] = &[
+        "sense",
+        "semantic_insert",
+        "semantic_edit",
+        "get_semantic_report",
+        "complete_code",
+        "suggest_refactor",
+        "suggest_batch",
+    ];
+
+    /// AI tools with no heuristic fallback; left out of `tools/list` when the
+    /// `modernbert` feature is off
+    const MODEL_ONLY_TOOLS: &[&str] = &["complete_code", "suggest_refactor", "suggest_batch"];
+
+    /// Tools that change files. Their `ParseCache` entries are dropped: the
+    /// files they name, or every entry for the ones that name none
+    const EDIT_TOOLS: &[&str] = &[
+        "edit_node",
+        "insert_node",
+        "replace_in_node",
+        "comment_node",
+        "uncomment_node",
+        "move_node",
+        "semantic_edit",
+        "semantic_insert",
+        "batch",
+        "undo",
+        "restore_session",
+        "restore_by_client",
+    ];
+
     /// A JSON-RPC request shape.
     #[derive(Debug, Deserialize, Serialize)]
     struct JsonRpcRequest {
@@ -51,6 +205,12 @@ pub mod mcp_server {
     // Standard JSON-RPC error codes
     const INVALID_PARAMS_CODE: i64 = -32602;
     const METHOD_NOT_FOUND_CODE: i64 = -32601;
+    const INTERNAL_ERROR_CODE: i64 = -32603;
+    const SERVER_BUSY_CODE: i64 = -32005;
+    const TOOL_TIMEOUT_CODE: i64 = -32007;
+
+    #[cfg(not(feature = "modernbert"))]
+    const AI_DISABLED: &str = "ModernBERT feature not enabled";
 
     fn build_jsonrpc_error(
         id: Option<Value>,
@@ -72,11 +232,94 @@ pub mod mcp_server {
         }
     }
 
+    /// Verbosity of the per-request access log, from `GNAW_MCP_LOG`:
+    /// `off`, `error` (default: failed requests only), `info` (every
+    /// request) or `debug` (every request, with truncated params)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    enum LogLevel {
+        Off,
+        Error,
+        Info,
+        Debug,
+    }
+
+    fn log_level() -> LogLevel {
+        static LEVEL: std::sync::OnceLock<LogLevel> = std::sync::OnceLock::new();
+        *LEVEL.get_or_init(|| match std::env::var("GNAW_MCP_LOG").unwrap_or_default().to_lowercase().as_str() {
+            "off" | "none" => LogLevel::Off,
+            "info" => LogLevel::Info,
+            "debug" | "trace" => LogLevel::Debug,
+            _ => LogLevel::Error,
+        })
+    }
+
+    const LOG_PARAMS_CHARS: usize = 200;
+
+    /// Run a request and write one JSON access-log line for it to stderr
+    async fn process_logged(state: Arc<AppState>, req: JsonRpcRequest, transport: &str) -> Result<Value, Value> {
+        let level = log_level();
+        if level == LogLevel::Off {
+            return process_request(state, req).await;
+        }
+
+        let id = req.id.clone();
+        let method = req.method.clone();
+        let tool = req.params.as_ref().and_then(|p| p.get("name")).and_then(Value::as_str).map(str::to_string);
+        let params = req.params.as_ref().map(|p| {
+            let text = p.to_string();
+            if text.chars().count() > LOG_PARAMS_CHARS {
+                format!("{}...", text.chars().take(LOG_PARAMS_CHARS).collect::<String>())
+            } else {
+                text
+            }
+        });
+
+        let start = std::time::Instant::now();
+        let result = process_request(state, req).await;
+        let status = match &result {
+            Ok(res) if res.get("isError").and_then(Value::as_bool) == Some(true) => "tool_error",
+            Ok(_) => "ok",
+            Err(_) => "error",
+        };
+        if status != "ok" || level >= LogLevel::Info {
+            let mut entry = json!({
+                "ts": chrono::Utc::now().to_rfc3339(),
+                "transport": transport,
+                "id": id,
+                "method": method,
+                "tool": tool,
+                "status": status,
+                "duration_ms": start.elapsed().as_millis() as u64,
+            });
+            if level >= LogLevel::Debug {
+                entry["params"] = json!(params);
+            }
+            eprintln!("{}", entry);
+        }
+        result
+    }
+
+    /// Requests that could not even be parsed are logged unless logging is off
+    fn log_parse_error(transport: &str, raw: &str) {
+        if log_level() >= LogLevel::Error {
+            let snippet: String = raw.chars().take(LOG_PARAMS_CHARS).collect();
+            eprintln!("{}", json!({
+                "ts": chrono::Utc::now().to_rfc3339(),
+                "transport": transport,
+                "status": "parse_error",
+                "body": snippet,
+            }));
+        }
+    }
+
     // --- Core Logic (Transport Agnostic) ---
 
     async fn process_request(state: Arc<AppState>, req: JsonRpcRequest) -> Result<Value, Value> {
         match req.method.as_str() {
             "initialize" => {
+                // Read-only: a handshake must not write to the project. In a
+                // fresh project the session starts with the first edit.
+                let session_id = crate::core::TransactionLog::current_session_id(&state.project_root);
                 Ok(json!({ 
                     "protocolVersion": "2024-11-05",
                     "serverInfo": {
@@ -84,292 +327,480 @@ pub mod mcp_server {
                         "version": env!("CARGO_PKG_VERSION")
                     },
                     "capabilities": {
-                        "tools": { "listChanged": true }
-                    }
+                        "tools": { "listChanged": true },
+                        "experimental": {
+                            "gnawtreewriter": {
+                                "analysis": true,
+                                "editing": true,
+                                "sessions": true,
+                                // Without the model, sense and the semantic tools use heuristics
+                                "semantic": cfg!(feature = "modernbert"),
+                                "completion": cfg!(feature = "modernbert")
+                            }
+                        }
+                    },
+                    "session_id": session_id
                 }))
             }
 
             "tools/list" => {
-                Ok(json!({
-                    "tools": [
-                        {
-                            "name": "analyze",
-                            "title": "Analyze file structure",
-                            "description": "Analyze a file and return its full AST structure.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" }
-                                },
-                                "required": ["file_path"]
-                            }
-                        },
-                        {
-                            "name": "list_nodes",
-                            "title": "List nodes in file",
-                            "description": "Get a flat list of important nodes.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" }
-                                },
-                                "required": ["file_path"]
-                            }
-                        },
-                        {
-                            "name": "get_skeleton",
-                            "title": "Get skeletal view",
-                            "description": "Get a high-level hierarchical overview of definitions.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "max_depth": { "type": "integer" }
-                                },
-                                "required": ["file_path"]
-                            }
-                        },
-                        {
-                            "name": "get_semantic_report",
-                            "title": "Generate semantic quality report",
-                            "description": "Analyze code quality using AI.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" }
-                                },
-                                "required": ["file_path"]
-                            }
-                        },
-                        {
-                            "name": "search_nodes",
-                            "title": "Search nodes by text",
-                            "description": "Find nodes containing specific text pattern.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "pattern": { "type": "string" }
-                                },
-                                "required": ["file_path", "pattern"]
-                            }
-                        },
-                        {
-                            "name": "read_node",
-                            "title": "Read node content",
-                            "description": "Get source code of a specific node.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "node_path": { "type": "string" }
-                                },
-                                "required": ["file_path", "node_path"]
-                            }
-                        },
-                        {
-                            "name": "edit_node",
-                            "title": "Edit node content",
-                            "description": "Replace node content safely.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "node_path": { "type": "string" },
-                                    "content": { "type": "string" }
-                                },
-                                "required": ["file_path", "node_path", "content"]
-                            }
-                        },
-                        {
-                            "name": "move_node",
-                            "title": "Move node to new location",
-                            "description": "Delete a node from one location and insert it at another. Atomically moves code across files.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "source_file": { "type": "string" },
-                                    "source_path": { "type": "string" },
-                                    "target_file": { "type": "string" },
-                                    "target_path": { "type": "string" }
-                                },
-                                "required": ["source_file", "source_path", "target_path"]
-                            }
-                        },
-                        {
-                            "name": "insert_node",
-                            "title": "Insert new content",
-                            "description": "Insert code into a parent node.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "parent_path": { "type": "string" },
-                                    "position": { "type": "integer" },
-                                    "content": { "type": "string" }
-                                },
-                                "required": ["file_path", "parent_path", "position", "content"]
-                            }
-                        },
-                        {
-                            "name": "preview_edit",
-                            "title": "Preview edit",
-                            "description": "Show a diff of what an edit would change without applying it.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "node_path": { "type": "string" },
-                                    "content": { "type": "string" }
-                                },
-                                "required": ["file_path", "node_path", "content"]
-                            }
-                        },
-                        {
-                            "name": "sense",
-                            "title": "Semantic Search (GnawSense)",
-                            "description": "Search for code semantically using AI. Good for finding where something is implemented when you only have a vague description.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "query": { "type": "string", "description": "Semantic query (e.g., 'how is backup handled?')" },
-                                    "file_path": { "type": "string", "description": "Optional: Limit search to this file (Zoom mode)" }
-                                },
-                                "required": ["query"]
-                            }
-                        },
-                        {
-                            "name": "semantic_insert",
-                            "title": "Semantic Insert (GnawSense)",
-                            "description": "Insert code near a semantic anchor point. Use this when you know WHAT the surrounding code does, but don't know the exact path.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "anchor_query": { "type": "string", "description": "Description of the code where you want to insert near (e.g., 'the backup initialization')" },
-                                    "content": { "type": "string", "description": "The new code to insert" },
-                                    "intent": { "type": "string", "description": "Where to insert: 'after' (default), 'before', or 'inside'" }
-                                },
-                                "required": ["file_path", "anchor_query", "content"]
-                            }
-                        },
-                        {
-                            "name": "semantic_edit",
-                            "title": "Semantic Edit (GnawSense)",
-                            "description": "Find a node semantically (e.g. 'the main loop') and replace its content. Perfect for surgical edits when you don't want to hunt for node paths.",
-                            "inputSchema": {
-                                "type": "object",
-                                "properties": {
-                                    "file_path": { "type": "string" },
-                                    "query": { "type": "string", "description": "Semantic description of what to edit (e.g. 'the backup initialization')" },
-                                    "content": { "type": "string", "description": "The new code content" }
+                let mut tools = json!([
+                    {
+                        "name": "analyze",
+                        "title": "Analyze file structure",
+                        "description": "Analyze a file and return its AST structure in `data`, a TreeNode (schema: `gnawtreewriter analyze --schema`). Large trees are pruned to stay under a size cap.",
+                        "inputSchema": {
+                            "$schema": "http://json-schema.org/draft-07/schema#",
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "max_depth": { "type": "integer", "description": "Omit children below this depth (root is depth 0)" },
+                                "fields": {
+                                    "type": "array",
+                                    "items": { "type": "string" },
+                                    "description": "Node fields to include, e.g. [\"path\", \"node_type\", \"start_line\"]. Defaults to all fields."
                                 },
-                                "required": ["file_path", "query", "content"]
+                                "tolerant": { "type": "boolean", "description": "Return a partial tree for a file with syntax errors, with the broken code in `error` nodes, instead of failing" }
+                            },
+                            "required": ["file_path"]
+                        }
+                    },
+                    {
+                        "name": "list_nodes",
+                        "title": "List nodes in file",
+                        "description": "Get a flat list of important nodes.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "kind": { "type": "string", "enum": ["function", "class", "method", "import", "comment", "property"], "description": "Only nodes of this language-independent kind" },
+                                "preview_chars": { "type": "integer", "minimum": 0, "description": "Include the first N characters of each node's content as `preview`" },
+                                "tolerant": { "type": "boolean", "description": "List a file with syntax errors from its partial tree, with the broken code in `error` nodes, instead of failing" }
+                            },
+                            "required": ["file_path"]
+                        }
+                    },
+                    {
+                        "name": "get_skeleton",
+                        "title": "Get skeletal view",
+                        "description": "Get a high-level hierarchical overview of definitions.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "max_depth": { "type": "integer" },
+                                "signatures": { "type": "boolean", "description": "Show the signature line of functions, methods and classes instead of just their name" }
+                            },
+                            "required": ["file_path"]
+                        }
+                    },
+                    {
+                        "name": "get_semantic_report",
+                        "title": "Generate semantic quality report",
+                        "description": "Analyze code quality using AI. Falls back to structural heuristics (long or deeply nested definitions, missing docs) when the model is unavailable.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" }
+                            },
+                            "required": ["file_path"]
+                        }
+                    },
+                    {
+                        "name": "search_nodes",
+                        "title": "Search nodes by text",
+                        "description": "Find nodes containing specific text pattern.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "pattern": { "type": "string" },
+                                "kind": { "type": "string", "enum": ["function", "class", "method", "import", "comment", "property"], "description": "Only nodes of this language-independent kind" },
+                                "context": { "type": "integer", "description": "Include this many lines of the node's content around each match, plus the matched line number" },
+                                "include_parents": { "type": "boolean", "description": "Add each match's enclosing nodes, root first, as `parents` (`path`, `type`, and `name` for definitions)" }
+                            },
+                            "required": ["file_path", "pattern"]
+                        }
+                    },
+                    {
+                        "name": "node_at_line",
+                        "title": "Find node at line",
+                        "description": "Map a 1-based line number (e.g. an editor cursor) to the deepest node containing it.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "line": { "type": "integer", "description": "1-based line number" }
+                            },
+                            "required": ["file_path", "line"]
+                        }
+                    },
+                    {
+                        "name": "diff_nodes",
+                        "title": "Diff two nodes",
+                        "description": "Diff the source of a node in one file against a node in another (or the same) file.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_a": { "type": "string" },
+                                "path_a": { "type": "string" },
+                                "file_b": { "type": "string" },
+                                "path_b": { "type": "string" }
+                            },
+                            "required": ["file_a", "path_a", "file_b", "path_b"]
+                        }
+                    },
+                    {
+                        "name": "get_relations",
+                        "title": "Get relation graph",
+                        "description": "Index a file or directory and return its call graph as nodes (definitions and unresolved names) and edges.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string", "description": "File or directory" }
+                            },
+                            "required": ["path"]
+                        }
+                    },
+                    {
+                        "name": "find_references",
+                        "title": "Find references",
+                        "description": "Find where a symbol is called, imported, inherited from, implemented or accessed as a field.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string", "description": "Symbol name, e.g. a function, class or field" },
+                                "path": { "type": "string", "description": "File or directory to index first; otherwise the existing index is searched" }
+                            },
+                            "required": ["name"]
+                        }
+                    },
+                    {
+                        "name": "read_node",
+                        "title": "Read node content",
+                        "description": "Get source code of a specific node.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "line_numbers": { "type": "boolean", "description": "Prefix each line with its line number in the file" },
+                                "include_children": { "type": "boolean", "description": "Also return the node's members (e.g. every method of a class) as `children`, each with its path and content, after the node itself as `node`" }
+                            },
+                            "required": ["file_path", "node_path"]
+                        }
+                    },
+                    {
+                        "name": "edit_node",
+                        "title": "Edit node content",
+                        "description": "Replace node content safely.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "content": { "type": "string" },
+                                "no_backup": { "type": "boolean", "description": "UNSAFE: skip the backup, so the edit cannot be undone" },
+                                "no_validate": { "type": "boolean", "description": "UNSAFE: write the result even if it does not parse" },
+                                "strict_type": { "type": "boolean", "description": "Reject the edit if the node at node_path would change type (e.g. a function becoming a class)" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "node_path", "content"]
+                        }
+                    },
+                    {
+                        "name": "replace_in_node",
+                        "title": "Find/replace within a node",
+                        "description": "Replace text only inside one node's span, validate the file still parses, and return a diff. Cheaper than edit_node for small changes.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "find": { "type": "string" },
+                                "replace": { "type": "string" },
+                                "count": { "type": "integer", "description": "Maximum number of replacements (default: all)" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "node_path", "find", "replace"]
+                        }
+                    },
+                    {
+                        "name": "comment_node",
+                        "title": "Comment out node",
+                        "description": "Safely disable a node by commenting out its lines in the file's comment syntax.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "node_path"]
+                        }
+                    },
+                    {
+                        "name": "uncomment_node",
+                        "title": "Uncomment node",
+                        "description": "Remove comment markers from the lines of a commented-out node. Only the node's own lines change, so each line comment is uncommented separately.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "node_path"]
+                        }
+                    },
+                    {
+                        "name": "move_node",
+                        "title": "Move node to new location",
+                        "description": "Delete a node from one location and insert it at another. Atomically moves code across files.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "source_file": { "type": "string" },
+                                "source_path": { "type": "string" },
+                                "target_file": { "type": "string" },
+                                "target_path": { "type": "string" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["source_file", "source_path", "target_path"]
+                        }
+                    },
+                    {
+                        "name": "insert_node",
+                        "title": "Insert new content",
+                        "description": "Insert code into a parent node.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "parent_path": { "type": "string" },
+                                "position": { "type": "integer", "description": "0: top of the parent, 1: end of the parent (default), 2: after its last QML property or import, 3+: after child position-3" },
+                                "content": { "type": "string" },
+                                "no_backup": { "type": "boolean", "description": "UNSAFE: skip the backup, so the edit cannot be undone" },
+                                "no_validate": { "type": "boolean", "description": "UNSAFE: write the result even if it does not parse" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "parent_path", "content"]
+                        }
+                    },
+                    {
+                        "name": "preview_edit",
+                        "title": "Preview edit",
+                        "description": "Show a diff of what an edit would change without applying it.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" },
+                                "content": { "type": "string" },
+                                "include_content": { "type": "boolean", "description": "Also return old_content, new_content and the node's span (start_line, end_line, start_col, end_col), plus old_lines and new_lines: the node's whole lines before and after the edit, for clients that render their own diff" }
+                            },
+                            "required": ["file_path", "node_path", "content"]
+                        }
+                    },
+                    {
+                        "name": "preview_insert",
+                        "title": "Preview insert",
+                        "description": "Show a diff of what an insert would change without applying it.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "parent_path": { "type": "string" },
+                                "position": { "type": "integer", "description": "0: top of the parent, 1: end of the parent (default), 2: after its last QML property or import, 3+: after child position-3" },
+                                "content": { "type": "string" }
+                            },
+                            "required": ["file_path", "parent_path", "content"]
+                        }
+                    },
+                    {
+                        "name": "preview_delete",
+                        "title": "Preview delete",
+                        "description": "Show a diff of what deleting a node would change without applying it.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string" }
+                            },
+                            "required": ["file_path", "node_path"]
+                        }
+                    },
+                    {
+                        "name": "sense",
+                        "title": "Semantic Search (GnawSense)",
+                        "description": "Search for code semantically using AI. Good for finding where something is implemented when you only have a vague description. Falls back to name/content matching when the model is unavailable.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "query": { "type": "string", "description": "Semantic query (e.g., 'how is backup handled?')" },
+                                "file_path": { "type": "string", "description": "Optional: Limit search to this file (Zoom mode)" },
+                                "device": { "type": "string", "enum": ["cpu", "cuda", "metal"], "description": "Device to run the model on (default cpu)" }
+                            },
+                            "required": ["query"]
+                        }
+                    },
+                    {
+                        "name": "complete_code",
+                        "title": "Complete Code (fill-mask)",
+                        "description": "Suggest the most likely tokens for a [MASK] marker in a node, ranked by confidence. Requires the ModernBERT model.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string", "description": "Node whose content contains [MASK]" },
+                                "top_k": { "type": "integer", "description": "Number of suggestions (default 5)" },
+                                "device": { "type": "string", "enum": ["cpu", "cuda", "metal"], "description": "Device to run the model on (default cpu)" }
+                            },
+                            "required": ["file_path", "node_path"]
+                        }
+                    },
+                    {
+                        "name": "suggest_refactor",
+                        "title": "Suggest Refactorings",
+                        "description": "Suggest refactorings (extract function, flatten nesting, add docs, merge near-duplicates) for a file or one node. Requires the ModernBERT model.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "node_path": { "type": "string", "description": "Optional: only look inside this node" },
+                                "device": { "type": "string", "enum": ["cpu", "cuda", "metal"], "description": "Device to run the model on (default cpu)" }
+                            },
+                            "required": ["file_path"]
+                        }
+                    },
+                    {
+                        "name": "suggest_batch",
+                        "title": "Suggest Batch Targets",
+                        "description": "Rank the definitions in a file by how well they match an edit intent, as targets for one batch. Requires the ModernBERT model.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "intent": { "type": "string", "description": "What the batch should do (e.g., 'add error handling')" },
+                                "device": { "type": "string", "enum": ["cpu", "cuda", "metal"], "description": "Device to run the model on (default cpu)" }
+                            },
+                            "required": ["file_path", "intent"]
+                        }
+                    },
+                    {
+                        "name": "semantic_insert",
+                        "title": "Semantic Insert (GnawSense)",
+                        "description": "Insert code near a semantic anchor point. Use this when you know WHAT the surrounding code does, but don't know the exact path.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "anchor_query": { "type": "string", "description": "Description of the code where you want to insert near (e.g., 'the backup initialization')" },
+                                "content": { "type": "string", "description": "The new code to insert" },
+                                "intent": { "type": "string", "description": "Where to insert: 'after' (default), 'before', or 'inside'" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "anchor_query", "content"]
+                        }
+                    },
+                    {
+                        "name": "semantic_edit",
+                        "title": "Semantic Edit (GnawSense)",
+                        "description": "Find a node semantically (e.g. 'the main loop') and replace its content. Perfect for surgical edits when you don't want to hunt for node paths.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "file_path": { "type": "string" },
+                                "query": { "type": "string", "description": "Semantic description of what to edit (e.g. 'the backup initialization')" },
+                                "content": { "type": "string", "description": "The new code content" },
+                                "include_pulse": { "type": "boolean", "description": "Attach the pulse of related nodes and tests (default true)" },
+                                "client_id": { "type": "string", "description": "Who is editing; recorded on the transaction so restore_by_client can revert just this client's edits" }
+                            },
+                            "required": ["file_path", "query", "content"]
+                        }
+                    },
+                    {
+                        "name": "restore_session",
+                        "title": "Restore session",
+                        "description": "Revert every file touched in a session to its state before the session started. Use the session_id from initialize (null until the first edit) or session_status.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "session_id": { "type": "string", "description": "Session id or alias" },
+                                "preview": { "type": "boolean", "description": "List affected files without restoring" }
+                            },
+                            "required": ["session_id"]
+                        }
+                    },
+                    {
+                        "name": "restore_by_client",
+                        "title": "Restore a client's edits",
+                        "description": "Revert every file edited with the given client_id to its state before that client's first edit, leaving files only other clients touched alone. Refused when another client edited one of those files later, unless force is set.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "client_id": { "type": "string", "description": "The client_id passed to the edit tools" },
+                                "preview": { "type": "boolean", "description": "List affected files, and later edits by other clients, without restoring" },
+                                "force": { "type": "boolean", "description": "Restore even if other clients edited the same files later, discarding their edits" }
+                            },
+                            "required": ["client_id"]
+                        }
+                    },
+                    {
+                        "name": "session_start",
+                        "title": "Start a new session",
+                        "description": "End the current session and start a fresh one so a run's edits can be grouped and later restored together.",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string", "description": "Optional human-readable alias for the session" }
                             }
-                        },
-                        { "name": "batch", "description": "Apply batch", "inputSchema": {"type":"object"} },
-                        { "name": "undo", "description": "Undo", "inputSchema": {"type":"object"} }
-                    ]
-                }))
+                        }
+                    },
+                    {
+                        "name": "session_status",
+                        "title": "Session status",
+                        "description": "Show the active session id, its operation count and undo/redo availability.",
+                        "inputSchema": { "type": "object", "properties": {} }
+                    },
+                    { "name": "batch", "description": "Apply batch", "inputSchema": {"type":"object"} },
+                    { "name": "undo", "description": "Undo", "inputSchema": {"type":"object"} }
+                ]);
+                if !cfg!(feature = "modernbert") {
+                    if let Some(list) = tools.as_array_mut() {
+                        list.retain(|t| !MODEL_ONLY_TOOLS.contains(&t["name"].as_str().unwrap_or_default()));
+                    }
+                }
+                Ok(json!({ "tools": tools }))
             }
 
             "tools/call" => {
                 let params = req.params.unwrap_or_else(|| json!({}));
-                let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
-                let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
-
-                let validate_arg = |key: &str| -> Result<&str, Value> {
-                    arguments.get(key).and_then(Value::as_str).ok_or_else(|| {
-                       let err = build_jsonrpc_error(
-                           req.id.clone(), 
-                           INVALID_PARAMS_CODE, 
-                           "Invalid parameters", 
-                           Some(json!({"field": key}))
-                       );
-                       serde_json::to_value(err).unwrap()
-                   })
+                let name = params.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+                let limit = if AI_TOOLS.contains(&name.as_str()) { state.timeouts.ai } else { state.timeouts.tool };
+                // Tools block while they work, so run them on a blocking thread
+                // where the timeout can still fire. The thread keeps the
+                // request's `--max-concurrency` permit until it is done.
+                let handle = tokio::runtime::Handle::current();
+                let id = req.id.clone();
+                let permit = PERMIT.try_with(Clone::clone).ok().flatten();
+                let call = move || {
+                    let _permit = permit;
+                    handle.block_on(call_tool(state, id, params))
                 };
-
-                match name {
-                    "analyze" => {
-                        let fp = validate_arg("file_path")?;
-                        Ok(handle_analyze(fp))
-                    },
-                    "list_nodes" => {
-                        let fp = validate_arg("file_path")?;
-                        let filter = arguments.get("filter").and_then(Value::as_str);
-                        let max_depth = arguments.get("max_depth").and_then(Value::as_u64).map(|d| d as usize);
-                        Ok(handle_list_nodes(state, fp, filter, max_depth, false))
-                    },
-                    "get_skeleton" => {
-                        let fp = validate_arg("file_path")?;
-                        let max_depth = arguments.get("max_depth").and_then(Value::as_u64).unwrap_or(2) as usize;
-                        Ok(handle_get_skeleton(fp, max_depth))
-                    },
-                    "get_semantic_report" => {
-                        let fp = validate_arg("file_path")?;
-                        Ok(handle_get_semantic_report(state, fp).await)
-                    },
-                    "search_nodes" => {
-                        let fp = validate_arg("file_path")?;
-                        let pattern = validate_arg("pattern")?;
-                        Ok(handle_search_nodes(fp, pattern))
-                    },
-                    "read_node" => {
-                        let fp = validate_arg("file_path")?;
-                        let np = validate_arg("node_path")?;
-                        Ok(handle_read_node(fp, np))
-                    },
-                    "edit_node" => {
-                        let fp = validate_arg("file_path")?;
-                        let np = validate_arg("node_path")?;
-                        let c = validate_arg("content")?;
-                        Ok(handle_edit_node_internal(state, fp, np, c))
-                    },
-                    "preview_edit" => {
-                        let fp = validate_arg("file_path")?;
-                        let np = validate_arg("node_path")?;
-                        let c = validate_arg("content")?;
-                        Ok(handle_preview_edit(fp, np, c))
-                    },
-                    "move_node" => {
-                        let sf = validate_arg("source_file")?;
-                        let sp = validate_arg("source_path")?;
-                        let tf = arguments.get("target_file").and_then(Value::as_str).unwrap_or(sf);
-                        let tp = validate_arg("target_path")?;
-                        Ok(handle_move_node(state, sf, sp, tf, tp))
-                    },
-                    "insert_node" => {
-                         let fp = validate_arg("file_path")?;
-                         let pp = validate_arg("parent_path")?;
-                         let c = validate_arg("content")?;
-                         let pos = arguments.get("position").and_then(Value::as_u64).unwrap_or(1) as usize;
-                         Ok(handle_insert_node(state, fp, pp, pos, c))
-                    },
-                    "sense" => {
-                        let query = validate_arg("query")?;
-                        let fp = arguments.get("file_path").and_then(Value::as_str);
-                        Ok(handle_sense(state, query, fp).await)
-                    },
-                    "semantic_insert" => {
-                        let fp = validate_arg("file_path")?;
-                        let anchor = validate_arg("anchor_query")?;
-                        let content = validate_arg("content")?;
-                        let intent = arguments.get("intent").and_then(Value::as_str).unwrap_or("after");
-                        Ok(handle_semantic_insert(state, fp, anchor, content, intent).await)
-                    },
-                    "semantic_edit" => {
-                        let fp = validate_arg("file_path")?;
-                        let query = validate_arg("query")?;
-                        let content = validate_arg("content")?;
-                        Ok(handle_semantic_edit(state, fp, query, content).await)
-                    },
-                    "batch" => Ok(json!({ "content": [{ "type": "text", "text": "Batch executed" }] })),
-                    "undo" => Ok(json!({ "content": [{ "type": "text", "text": "Undo executed" }] })),
-                    _ => {
-                        let err = build_jsonrpc_error(req.id, METHOD_NOT_FOUND_CODE, "Unknown tool", None);
-                        Err(serde_json::to_value(err).unwrap())
-                    }
+                if EDIT_TOOLS.contains(&name.as_str()) {
+                    // An abandoned edit would still land after the client was
+                    // told it failed, and a retry would apply it twice
+                    run_to_completion(req.id, call).await
+                } else {
+                    run_with_timeout(req.id, &name, limit, call).await
                 }
             }
             _ => {
@@ -379,30 +810,502 @@ pub mod mcp_server {
         }
     }
 
+    /// Dispatch one `tools/call`
+    async fn call_tool(state: Arc<AppState>, id: Option<Value>, params: Value) -> Result<Value, Value> {
+        let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+        let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+
+        let validate_arg = |key: &str| -> Result<&str, Value> {
+            arguments.get(key).and_then(Value::as_str).ok_or_else(|| {
+               let err = build_jsonrpc_error(
+                   id.clone(), 
+                   INVALID_PARAMS_CODE, 
+                   "Invalid parameters", 
+                   Some(json!({"field": key}))
+               );
+               serde_json::to_value(err).unwrap()
+           })
+        };
+        let kind_arg = || -> Result<Option<NodeKind>, Value> {
+            let Some(kind) = arguments.get("kind").and_then(Value::as_str) else {
+                return Ok(None);
+            };
+            kind.parse().map(Some).map_err(|e: String| {
+                let err = build_jsonrpc_error(
+                    id.clone(),
+                    INVALID_PARAMS_CODE,
+                    "Invalid parameters",
+                    Some(json!({"field": "kind", "error": e}))
+                );
+                serde_json::to_value(err).unwrap()
+            })
+        };
+
+        // Edit tools attach a pulse unless asked not to
+        let include_pulse = arguments.get("include_pulse").and_then(Value::as_bool).unwrap_or(true);
+        // ...and record the client that asked for them, if it says
+        let client = client_options(&arguments);
+        if EDIT_TOOLS.contains(&name) {
+            let files: Vec<&str> = ["file_path", "source_file", "target_file"]
+                .iter()
+                .filter_map(|key| arguments.get(*key).and_then(Value::as_str))
+                .collect();
+            if files.is_empty() {
+                state.parse_cache.clear();
+            }
+            for file in files {
+                state.parse_cache.invalidate(file);
+            }
+        }
+
+        match name {
+            "analyze" => {
+                let fp = validate_arg("file_path")?;
+                let max_depth = arguments.get("max_depth").and_then(Value::as_u64).map(|d| d as usize);
+                let fields: Option<Vec<String>> = arguments.get("fields").and_then(Value::as_array).map(|a| {
+                    a.iter().filter_map(Value::as_str).map(String::from).collect()
+                });
+                let tolerant = arguments.get("tolerant").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_analyze(state, fp, max_depth, fields.as_deref(), tolerant))
+            },
+            "list_nodes" => {
+                let fp = validate_arg("file_path")?;
+                let filter = arguments.get("filter").and_then(Value::as_str);
+                let max_depth = arguments.get("max_depth").and_then(Value::as_u64).map(|d| d as usize);
+                let kind = kind_arg()?;
+                let preview_chars = arguments.get("preview_chars").and_then(Value::as_u64).map(|n| n as usize);
+                let tolerant = arguments.get("tolerant").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_list_nodes(state, fp, filter, kind, max_depth, preview_chars, tolerant, false))
+            },
+            "get_skeleton" => {
+                let fp = validate_arg("file_path")?;
+                let max_depth = arguments.get("max_depth").and_then(Value::as_u64).unwrap_or(2) as usize;
+                let signatures = arguments.get("signatures").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_get_skeleton(fp, max_depth, signatures))
+            },
+            "get_semantic_report" => {
+                let fp = validate_arg("file_path")?;
+                Ok(handle_get_semantic_report(state, fp).await)
+            },
+            "search_nodes" => {
+                let fp = validate_arg("file_path")?;
+                let pattern = validate_arg("pattern")?;
+                let context = arguments.get("context").and_then(Value::as_u64).map(|c| c as usize);
+                let kind = kind_arg()?;
+                let include_parents = arguments.get("include_parents").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_search_nodes(state, fp, pattern, kind, context, include_parents))
+            },
+            "node_at_line" => {
+                let fp = validate_arg("file_path")?;
+                let line = arguments.get("line").and_then(Value::as_u64).ok_or_else(|| {
+                    let err = build_jsonrpc_error(
+                        id.clone(),
+                        INVALID_PARAMS_CODE,
+                        "Invalid parameters",
+                        Some(json!({"field": "line"}))
+                    );
+                    serde_json::to_value(err).unwrap()
+                })?;
+                Ok(handle_node_at_line(fp, line as usize))
+            },
+            "diff_nodes" => {
+                let fa = validate_arg("file_a")?;
+                let pa = validate_arg("path_a")?;
+                let fb = validate_arg("file_b")?;
+                let pb = validate_arg("path_b")?;
+                Ok(handle_diff_nodes(fa, pa, fb, pb))
+            },
+            "get_relations" => {
+                let path = validate_arg("path")?;
+                Ok(handle_get_relations(state, path))
+            },
+            "find_references" => {
+                let symbol = validate_arg("name")?;
+                let path = arguments.get("path").and_then(Value::as_str);
+                Ok(handle_find_references(state, symbol, path))
+            },
+            "read_node" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let line_numbers = arguments.get("line_numbers").and_then(Value::as_bool).unwrap_or(false);
+                let include_children = arguments.get("include_children").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_read_node(state, fp, np, line_numbers, include_children))
+            },
+            "edit_node" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let c = validate_arg("content")?;
+                Ok(handle_edit_node_internal(state, fp, np, c, edit_options(&arguments), include_pulse))
+            },
+            "replace_in_node" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let find = validate_arg("find")?;
+                let replace = validate_arg("replace")?;
+                let count = arguments.get("count").and_then(Value::as_u64).map(|c| c as usize);
+                Ok(handle_replace_in_node(state, fp, np, find, replace, count, client, include_pulse))
+            },
+            "comment_node" | "uncomment_node" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?.to_string();
+                let op = if name == "comment_node" {
+                    EditOperation::Comment { node_path: np.clone() }
+                } else {
+                    EditOperation::Uncomment { node_path: np.clone() }
+                };
+                Ok(handle_apply_operation(state, fp, &np, op, client, include_pulse))
+            },
+            "preview_edit" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let c = validate_arg("content")?;
+                let include_content = arguments.get("include_content").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_preview_edit(fp, np, c, include_content))
+            },
+            "preview_insert" => {
+                let fp = validate_arg("file_path")?;
+                let pp = validate_arg("parent_path")?;
+                let c = validate_arg("content")?;
+                let pos = arguments.get("position").and_then(Value::as_u64).unwrap_or(1) as usize;
+                let op = EditOperation::Insert { parent_path: pp.to_string(), position: pos, content: c.to_string() };
+                Ok(handle_preview_operation(fp, op, "insert"))
+            },
+            "preview_delete" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let op = EditOperation::Delete { node_path: np.to_string() };
+                Ok(handle_preview_operation(fp, op, "delete"))
+            },
+            "move_node" => {
+                let sf = validate_arg("source_file")?;
+                let sp = validate_arg("source_path")?;
+                let tf = arguments.get("target_file").and_then(Value::as_str).unwrap_or(sf);
+                let tp = validate_arg("target_path")?;
+                Ok(handle_move_node(state, sf, sp, tf, tp, client, include_pulse))
+            },
+            "insert_node" => {
+                 let fp = validate_arg("file_path")?;
+                 let pp = validate_arg("parent_path")?;
+                 let c = validate_arg("content")?;
+                 let pos = arguments.get("position").and_then(Value::as_u64).unwrap_or(1) as usize;
+                 Ok(handle_insert_node(state, fp, pp, pos, c, edit_options(&arguments), include_pulse))
+            },
+            "sense" => {
+                let query = validate_arg("query")?;
+                let fp = arguments.get("file_path").and_then(Value::as_str);
+                Ok(handle_sense(state, query, fp, device_arg(&arguments)).await)
+            },
+            "complete_code" => {
+                let fp = validate_arg("file_path")?;
+                let np = validate_arg("node_path")?;
+                let top_k = arguments.get("top_k").and_then(Value::as_u64).unwrap_or(5) as usize;
+                Ok(handle_complete_code(state, fp, np, top_k, device_arg(&arguments)))
+            },
+            "suggest_refactor" => {
+                let fp = validate_arg("file_path")?;
+                let np = arguments.get("node_path").and_then(Value::as_str);
+                Ok(handle_suggest_refactor(state, fp, np, device_arg(&arguments)))
+            },
+            "suggest_batch" => {
+                let fp = validate_arg("file_path")?;
+                let intent = validate_arg("intent")?;
+                Ok(handle_suggest_batch(state, fp, intent, device_arg(&arguments)))
+            },
+            "semantic_insert" => {
+                let fp = validate_arg("file_path")?;
+                let anchor = validate_arg("anchor_query")?;
+                let content = validate_arg("content")?;
+                let intent = arguments.get("intent").and_then(Value::as_str).unwrap_or("after");
+                Ok(handle_semantic_insert(state, fp, anchor, content, intent, client, include_pulse).await)
+            },
+            "semantic_edit" => {
+                let fp = validate_arg("file_path")?;
+                let query = validate_arg("query")?;
+                let content = validate_arg("content")?;
+                Ok(handle_semantic_edit(state, fp, query, content, client, include_pulse).await)
+            },
+            "restore_session" => {
+                let sid = validate_arg("session_id")?;
+                let preview = arguments.get("preview").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_restore_session(state, sid, preview))
+            },
+            "restore_by_client" => {
+                let client_id = validate_arg("client_id")?;
+                let preview = arguments.get("preview").and_then(Value::as_bool).unwrap_or(false);
+                let force = arguments.get("force").and_then(Value::as_bool).unwrap_or(false);
+                Ok(handle_restore_by_client(state, client_id, preview, force))
+            },
+            "session_start" => {
+                let name = arguments.get("name").and_then(Value::as_str).map(str::to_string);
+                Ok(handle_session_start(state, name))
+            },
+            "session_status" => Ok(handle_session_status(state)),
+            "batch" => Ok(tool_success("Batch executed".to_string(), None)),
+            "undo" => Ok(tool_success("Undo executed".to_string(), None)),
+            _ => {
+                let err = build_jsonrpc_error(id, METHOD_NOT_FOUND_CODE, "Unknown tool", None);
+                Err(serde_json::to_value(err).unwrap())
+            }
+        }
+    }
+
+    /// Run `call` on a blocking thread and give up after `limit`. The thread
+    /// itself cannot be cancelled; it runs on and its result is discarded.
+    async fn run_with_timeout<F>(id: Option<Value>, tool: &str, limit: std::time::Duration, call: F) -> Result<Value, Value>
+    where
+        F: FnOnce() -> Result<Value, Value> + Send + 'static,
+    {
+        match tokio::time::timeout(limit, tokio::task::spawn_blocking(call)).await {
+            Ok(joined) => blocking_result(id, joined),
+            Err(_) => {
+                let err = build_jsonrpc_error(
+                    id,
+                    TOOL_TIMEOUT_CODE,
+                    "Tool call timed out; it may still complete in the background",
+                    Some(json!({"tool": tool, "timeout_secs": limit.as_secs_f64()})),
+                );
+                Err(serde_json::to_value(err).unwrap())
+            }
+        }
+    }
+
+    /// Run `call` on a blocking thread and wait for it however long it takes
+    async fn run_to_completion<F>(id: Option<Value>, call: F) -> Result<Value, Value>
+    where
+        F: FnOnce() -> Result<Value, Value> + Send + 'static,
+    {
+        blocking_result(id, tokio::task::spawn_blocking(call).await)
+    }
+
+    fn blocking_result(
+        id: Option<Value>,
+        joined: Result<Result<Value, Value>, tokio::task::JoinError>,
+    ) -> Result<Value, Value> {
+        joined.unwrap_or_else(|e| {
+            let err = build_jsonrpc_error(id, INTERNAL_ERROR_CODE, &format!("Tool panicked: {}", e), None);
+            Err(serde_json::to_value(err).unwrap())
+        })
+    }
+
+    fn authorized(state: &AppState, headers: &HeaderMap) -> bool {
+        match &state.token {
+            Some(expected) => headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|s| s == format!("Bearer {}", expected)),
+            None => true,
+        }
+    }
+
+    fn unauthorized() -> (StatusCode, Json<Value>) {
+        (StatusCode::UNAUTHORIZED, Json(json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": { "code": -32001, "message": "Unauthorized" }
+        })))
+    }
+
+    tokio::task_local! {
+        /// The `--max-concurrency` permit of the request being handled
+        static PERMIT: Option<Arc<tokio::sync::OwnedSemaphorePermit>>;
+    }
+
+    /// Reject requests beyond `--max-concurrency` before their body is read.
+    /// The permit is held until the response has been built, and past that
+    /// by a tool call that outlived its timeout, see `PERMIT`.
+    async fn limit_concurrency(
+        State(state): State<Arc<AppState>>,
+        req: axum::extract::Request,
+        next: axum::middleware::Next,
+    ) -> axum::response::Response {
+        let permit = match &state.limiter {
+            Some(limiter) => match limiter.clone().try_acquire_owned() {
+                Ok(permit) => Some(Arc::new(permit)),
+                Err(_) => {
+                    return (StatusCode::TOO_MANY_REQUESTS, Json(json!({
+                        "jsonrpc": "2.0",
+                        "id": null,
+                        "error": { "code": SERVER_BUSY_CODE, "message": "Server busy: too many concurrent requests" }
+                    })))
+                    .into_response()
+                }
+            },
+            None => None,
+        };
+        PERMIT.scope(permit, next.run(req)).await
+    }
+
+    /// Liveness probe for orchestrators; needs no token
+    async fn health_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+        Json(json!({
+            "status": "ok",
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime_secs": state.started.elapsed().as_secs()
+        }))
+    }
+
+    /// The `tools/list` result as a plain GET, for clients without JSON-RPC
+    async fn tools_handler(State(state): State<Arc<AppState>>, headers: HeaderMap) -> impl IntoResponse {
+        if !authorized(&state, &headers) {
+            return unauthorized();
+        }
+        let req = JsonRpcRequest {
+            id: None,
+            jsonrpc: Some("2.0".into()),
+            method: "tools/list".into(),
+            params: None,
+        };
+        match process_request(state, req).await {
+            Ok(res) => (StatusCode::OK, Json(res)),
+            Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err)),
+        }
+    }
+
+    /// Body of `POST /analyze_stream`: the `analyze` tool's arguments
+    #[derive(Deserialize)]
+    struct AnalyzeStreamRequest {
+        file_path: String,
+        max_depth: Option<usize>,
+        fields: Option<Vec<String>>,
+    }
+
+    /// `analyze` as NDJSON: one line per node in preorder, each without its
+    /// `children` but with its `depth`. Lines are sent as they are
+    /// serialized, so a large file is never built into one response.
+    ///
+    /// Parsing and the walk run on one blocking thread that holds the
+    /// request's `--max-concurrency` permit until it is done. The walk stops
+    /// at the tool timeout and ends the stream with an error line.
+    async fn analyze_stream_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(req): Json<AnalyzeStreamRequest>,
+    ) -> axum::response::Response {
+        if !authorized(&state, &headers) {
+            return unauthorized().into_response();
+        }
+        let limit = state.timeouts.tool;
+        let permit = PERMIT.try_with(Clone::clone).ok().flatten();
+        let (parsed_tx, parsed_rx) = tokio::sync::oneshot::channel::<Value>();
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(64);
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let started = std::time::Instant::now();
+            let writer = match GnawTreeWriter::new(&req.file_path) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    let _ = parsed_tx.send(tool_failure(e.to_string(), &e));
+                    return;
+                }
+            };
+            if parsed_tx.send(Value::Null).is_err() {
+                return;
+            }
+
+            let fields = req.fields.as_deref();
+            let mut open = true;
+            writer.analyze().walk(|node, depth| {
+                if !open || req.max_depth.is_some_and(|max| depth > max) {
+                    return false;
+                }
+                let mut line = if started.elapsed() > limit {
+                    open = false;
+                    json!({"error": {"code": TOOL_TIMEOUT_CODE, "message": "analyze_stream timed out; the output is incomplete", "timeout_secs": limit.as_secs_f64()}})
+                } else {
+                    let mut obj = own_fields(node, fields);
+                    obj.insert("depth".to_string(), json!(depth));
+                    Value::Object(obj)
+                }
+                .to_string();
+                line.push('\n');
+                // The client went away
+                open = tx.blocking_send(Ok(line)).is_ok() && open;
+                open
+            });
+        });
+
+        match tokio::time::timeout(limit, parsed_rx).await {
+            Ok(Ok(Value::Null)) => (
+                [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+                axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+            )
+                .into_response(),
+            Ok(Ok(failure)) => (StatusCode::UNPROCESSABLE_ENTITY, Json(failure)).into_response(),
+            Ok(Err(_)) => (StatusCode::INTERNAL_SERVER_ERROR, "analyze_stream worker stopped").into_response(),
+            Err(_) => {
+                let err = build_jsonrpc_error(
+                    None,
+                    TOOL_TIMEOUT_CODE,
+                    "Parsing timed out; it may still complete in the background",
+                    Some(json!({"tool": "analyze_stream", "timeout_secs": limit.as_secs_f64()})),
+                );
+                (StatusCode::GATEWAY_TIMEOUT, Json(serde_json::to_value(err).unwrap())).into_response()
+            }
+        }
+    }
+
     async fn rpc_handler(
         State(state): State<Arc<AppState>>,
         headers: HeaderMap,
         Json(req): Json<Value>,
-    ) -> impl IntoResponse {
-        if let Some(expected) = &state.token {
-            match headers.get("authorization").and_then(|v| v.to_str().ok()) {
-                Some(s) if s == format!("Bearer {}", expected) => {} // Corrected: escaped curly brace
-                _ => return (StatusCode::UNAUTHORIZED, Json(json!({ // Corrected: escaped curly brace
-                    "jsonrpc": "2.0",
-                    "id": null,
-                    "error": { "code": -32001, "message": "Unauthorized" }
-                }))),
+    ) -> axum::response::Response {
+        if !authorized(&state, &headers) {
+            return unauthorized().into_response();
+        }
+
+        // JSON-RPC 2.0 batch: answer every request that has an id, in order,
+        // and every element that is not a request at all
+        if let Value::Array(batch) = req {
+            if batch.is_empty() {
+                return (StatusCode::BAD_REQUEST, Json(invalid_request("Invalid Request: empty batch"))).into_response();
+            }
+            let mut responses = Vec::new();
+            for item in batch {
+                if serde_json::from_value::<JsonRpcRequest>(item.clone()).is_err() {
+                    log_parse_error("http", &item.to_string());
+                    responses.push(invalid_request("Invalid Request"));
+                    continue;
+                }
+                let is_notification = item.get("id").is_none();
+                let (_, Json(response)) = handle_single(state.clone(), item).await;
+                if !is_notification {
+                    responses.push(response);
+                }
+            }
+            if responses.is_empty() {
+                return StatusCode::NO_CONTENT.into_response();
             }
+            return (StatusCode::OK, Json(Value::Array(responses))).into_response();
         }
 
-        let parsed: JsonRpcRequest = match serde_json::from_value(req) {
+        // A notification gets no response, just an empty 204
+        let is_notification = req.get("id").is_none() && serde_json::from_value::<JsonRpcRequest>(req.clone()).is_ok();
+        let response = handle_single(state, req).await;
+        if is_notification {
+            return StatusCode::NO_CONTENT.into_response();
+        }
+        response.into_response()
+    }
+
+    /// The `-32600` error for a message that is not a JSON-RPC request
+    fn invalid_request(message: &str) -> Value {
+        json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32600, "message": message}})
+    }
+
+    async fn handle_single(state: Arc<AppState>, req: Value) -> (StatusCode, Json<Value>) {
+        let parsed: JsonRpcRequest = match serde_json::from_value(req.clone()) {
             Ok(r) => r,
-            Err(_) => return (StatusCode::BAD_REQUEST, Json(json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}}))),
+            Err(_) => {
+                log_parse_error("http", &req.to_string());
+                return (StatusCode::BAD_REQUEST, Json(json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}})));
+            }
         };
         
         let id = parsed.id.clone();
-        match process_request(state, parsed).await {
-            Ok(res) => (StatusCode::OK, Json(json!({"jsonrpc": "2.0", "id": id, "result": res}))), // Corrected: escaped curly brace
+        match process_logged(state, parsed, "http").await {
+            Ok(res) => (StatusCode::OK, Json(json!({"jsonrpc": "2.0", "id": id, "result": res}))),
             Err(err) => {
                 let code = err.get("error").and_then(|e| e.get("code")).and_then(|c| c.as_i64()).unwrap_or(0);
                 let status = match code {
@@ -421,7 +1324,7 @@ pub mod mcp_server {
         let mut stdin = BufReader::new(tokio::io::stdin());
         let mut stdout = tokio::io::stdout();
         let project_root = std::env::current_dir()?;
-        let state = Arc::new(AppState { token: None, project_root });
+        let state = Arc::new(AppState { token: None, project_root, started: std::time::Instant::now(), limiter: None, timeouts: ToolTimeouts::default(), pulse: PulseOptions::default(), parse_cache: ParseCache::default() });
 
         let mut line = String::new();
         while stdin.read_line(&mut line).await? > 0 {
@@ -434,13 +1337,14 @@ pub mod mcp_server {
             let req: JsonRpcRequest = match serde_json::from_str(trimmed) {
                 Ok(r) => r,
                 Err(_) => {
+                    log_parse_error("stdio", trimmed);
                     line.clear();
                     continue;
                 }
             };
 
             let id = req.id.clone();
-            match process_request(state.clone(), req).await {
+            match process_logged(state.clone(), req, "stdio").await {
                 Ok(result) => {
                     let resp = json!({"jsonrpc": "2.0", "id": id, "result": result});
                     if let Ok(resp_str) = serde_json::to_string(&resp) {
@@ -461,23 +1365,58 @@ pub mod mcp_server {
     }
 
     fn tool_error(msg: String) -> Value { json!({"content": [{ "type": "text", "text": msg }], "isError": true}) }
-    fn tool_success(msg: String, data: Option<Value>) -> Value {
-        let mut res = json!({"content": [{ "type": "text", "text": msg }]});
-        if let Some(d) = data {
-            if let Some(obj) = d.as_object() {
-                res.as_object_mut().unwrap().extend(obj.clone());
-            }
+
+    /// Like `tool_error`, but attaches the JSON-RPC code and kind of the
+    /// underlying `GnawError` so clients can branch on the failure type.
+    fn tool_failure(msg: String, err: &anyhow::Error) -> Value {
+        let kind = GnawError::classify(err);
+        let mut res = tool_error(msg);
+        res["error"] = json!({
+            "code": kind.map_or(INTERNAL_ERROR_CODE, |k| k.code),
+            "kind": kind.map_or("internal", |k| k.kind),
+            "message": err.to_string()
+        });
+        if let Some(GnawError::NodeNotFound { suggestions, .. }) =
+            err.chain().find_map(|cause| cause.downcast_ref::<GnawError>())
+        {
+            res["error"]["suggestions"] = json!(suggestions);
         }
         res
     }
+    /// A successful tool result: `content` carries the text for humans and
+    /// `structuredContent` the same data as an object. The data fields are
+    /// also merged into the result itself, as older clients expect.
+    fn tool_success(msg: String, data: Option<Value>) -> Value {
+        let mut res = json!({"content": [{ "type": "text", "text": msg }]});
+        let obj = res.as_object_mut().unwrap();
+        let structured = match data {
+            Some(Value::Object(fields)) => {
+                obj.extend(fields.clone());
+                fields
+            }
+            Some(other) => serde_json::Map::from_iter([("data".to_string(), other)]),
+            None => serde_json::Map::from_iter([("text".to_string(), json!(msg))]),
+        };
+        obj.insert("structuredContent".to_string(), Value::Object(structured));
+        res
+    }
 
-    fn tool_success_with_pulse(msg: String, data: Option<Value>, pulse: Value) -> Value {
+    fn tool_success_with_pulse(msg: String, data: Option<Value>, pulse: Option<Value>) -> Value {
         let mut res = tool_success(msg, data);
-        res.as_object_mut().unwrap().insert("pulse".to_string(), pulse);
+        if let Some(pulse) = pulse {
+            res["structuredContent"]["pulse"] = pulse.clone();
+            res.as_object_mut().unwrap().insert("pulse".to_string(), pulse);
+        }
         res
     }
 
-    fn generate_pulse(state: Arc<AppState>, file_path: &str, node_path: &str) -> Value {
+    /// The pulse for an edit at `node_path`, unless the server or the call
+    /// (`include_pulse: false`) turned it off. Building it indexes the
+    /// configured `PulseScope`, which is the slow part.
+    fn generate_pulse(state: Arc<AppState>, include_pulse: bool, file_path: &str, node_path: &str) -> Option<Value> {
+        if !include_pulse || !state.pulse.enabled {
+            return None;
+        }
         let mut pulse = json!({
             "related_nodes": [],
             "test_files": [],
@@ -499,96 +1438,236 @@ pub mod mcp_server {
             // 2. Search for callers via RelationalIndexer
             let mut indexer = crate::llm::RelationalIndexer::new(&state.project_root);
             
-            // JIT: Index parent directory to catch local callers immediately
-            if let Some(parent) = std::path::Path::new(file_path).parent() {
-                let _ = indexer.index_directory(parent);
+            // JIT: Index the configured scope to catch callers immediately
+            match state.pulse.scope {
+                PulseScope::Parent => {
+                    if let Some(parent) = std::path::Path::new(file_path).parent() {
+                        let _ = indexer.index_directory(parent);
+                    }
+                }
+                PulseScope::Project => {
+                    let _ = indexer.index_directory(&state.project_root);
+                }
             }
 
-            if let Ok(graphs) = indexer.load_all_graphs() {
-                let mut callers = Vec::new();
-                for graph in graphs {
-                    for rel in graph.relations {
-                        if rel.to_name == n && rel.relation_type == crate::llm::RelationType::Call {
-                             callers.push(json!({"file": graph.file_path, "path": rel.from_path}));
-                        }
-                    }
+            if let Ok(references) = indexer.find_references(&n) {
+                let calls = references.iter().filter(|rel| rel.relation_type == crate::llm::RelationType::Call).count();
+                let related: Vec<Value> = references
+                    .iter()
+                    .map(|rel| json!({"file": rel.from_file, "path": rel.from_path, "relation": rel.relation_type}))
+                    .collect();
+                pulse["related_nodes"] = json!(related);
+                if calls > 0 {
+                    pulse["hints"].as_array_mut().unwrap().push(json!(format!("Symbol '{}' is called in {} places. Consider verifying impact.", n, calls)));
                 }
-                pulse["related_nodes"] = json!(callers);
-                if !callers.is_empty() {
-                    pulse["hints"].as_array_mut().unwrap().push(json!(format!("Symbol '{}' is called in {} places. Consider verifying impact.", n, callers.len())));
+                if related.len() > calls {
+                    pulse["hints"].as_array_mut().unwrap().push(json!(format!("Symbol '{}' is also imported, inherited from or accessed in {} places.", n, related.len() - calls)));
                 }
             }
         }
 
         // 3. Search for tests
-        let file_name = std::path::Path::new(file_path).file_stem().and_then(|s| s.to_str()).unwrap_or("");
-        let test_patterns = vec![
-            format!("test_{}.rs", file_name),
-            format!("{}_test.rs", file_name),
-            format!("test_{}.py", file_name),
-            format!("{}_test.py", file_name),
-            format!("tests/test_{}.rs", file_name),
-        ];
-        
-        let mut found_tests = Vec::new();
-        for p in test_patterns {
-            let path = state.project_root.join(p);
-            if path.exists() {
-                found_tests.push(path.to_string_lossy().to_string());
-            }
-        }
+        let patterns = crate::core::config::Config::load(&state.project_root)
+            .unwrap_or_default()
+            .mcp
+            .test_patterns
+            .unwrap_or_else(|| DEFAULT_TEST_PATTERNS.iter().map(|p| p.to_string()).collect());
+        let found_tests = find_test_files(&state.project_root, std::path::Path::new(file_path), &patterns);
         pulse["test_files"] = json!(found_tests);
         if !found_tests.is_empty() {
             pulse["hints"].as_array_mut().unwrap().push(json!("Found associated test files. Remember to update or run tests."));
         }
 
-        pulse
+        Some(pulse)
     }
 
-    fn handle_analyze(file_path: &str) -> Value {
-        match GnawTreeWriter::new(file_path) {
-            Ok(w) => json!({"content": [{ "type": "text", "text": format!("Analyzed {}", file_path)}], "data": w.analyze()}), // Corrected: escaped curly brace
-            Err(e) => tool_error(format!("IO error: {}", e)), // Corrected: escaped curly brace
+    /// Where the pulse looks for a file's tests, unless `mcp.test_patterns`
+    /// is configured. `{stem}` is the file name without its extension; each
+    /// pattern is tried next to the file and under the project root.
+    const DEFAULT_TEST_PATTERNS: &[&str] = &[
+        "test_{stem}.rs",
+        "{stem}_test.rs",
+        "tests/test_{stem}.rs",
+        "test_{stem}.py",
+        "{stem}_test.py",
+        "{stem}.test.ts",
+        "{stem}.spec.ts",
+        "{stem}.test.js",
+        "{stem}.spec.js",
+        "__tests__/{stem}.ts",
+        "__tests__/{stem}.test.ts",
+        "__tests__/{stem}.js",
+        "__tests__/{stem}.test.js",
+        "{stem}Test.php",
+        "tests/{stem}Test.php",
+        "{stem}_test.go",
+    ];
+
+    /// Existing files matching `patterns` for `file_path`, without duplicates
+    fn find_test_files(project_root: &std::path::Path, file_path: &std::path::Path, patterns: &[String]) -> Vec<String> {
+        let stem = file_path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let dirs = [file_path.parent().unwrap_or(project_root), project_root];
+        let mut seen = std::collections::HashSet::new();
+        let mut found = Vec::new();
+        for pattern in patterns {
+            let relative = pattern.replace("{stem}", stem);
+            for dir in dirs {
+                let path = dir.join(&relative);
+                if path.is_file() && seen.insert(std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+                    found.push(path.to_string_lossy().to_string());
+                }
+            }
+        }
+        found
+    }
+
+    /// Upper bound on the serialized size of an `analyze` response.
+    const MAX_ANALYZE_BYTES: usize = 1_000_000;
+
+    /// The writer for read-only tools; `tolerant` accepts files with syntax
+    /// errors, see `GnawTreeWriter::new_tolerant`
+    fn load_for_reading(file_path: &str, tolerant: bool) -> anyhow::Result<GnawTreeWriter> {
+        if tolerant {
+            GnawTreeWriter::new_tolerant(file_path)
+        } else {
+            GnawTreeWriter::new(file_path)
         }
     }
 
+    fn handle_analyze(
+        state: Arc<AppState>,
+        file_path: &str,
+        max_depth: Option<usize>,
+        fields: Option<&[String]>,
+        tolerant: bool,
+    ) -> Value {
+        match state.parse_cache.load(file_path, tolerant) {
+            Ok(w) => {
+                let mut budget = MAX_ANALYZE_BYTES;
+                let mut truncated = false;
+                let tree = prune_tree(w.analyze(), 0, max_depth, fields, &mut budget, &mut truncated);
+                let mut text = format!("Analyzed {}", file_path);
+                if truncated {
+                    text.push_str(&format!(
+                        " (response truncated at {} bytes; nodes marked with \"children_truncated\" were cut, use max_depth or fields to narrow the result)",
+                        MAX_ANALYZE_BYTES
+                    ));
+                }
+                tool_success(text, Some(json!({"data": tree, "truncated": truncated})))
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    /// Serialize `node` with only the requested fields, dropping children below
+    /// `max_depth` and once the byte budget is spent. Pruned nodes keep a
+    /// `children_truncated` count so clients can tell what is missing.
+    /// The node's own fields (those in `fields`, if given), without
+    /// `children`. Serializing the node itself would serialize its whole
+    /// subtree again at every level.
+    fn own_fields(node: &TreeNode, fields: Option<&[String]>) -> serde_json::Map<String, Value> {
+        let mut obj = serde_json::Map::new();
+        let own = [
+            ("id", json!(node.id)),
+            ("path", json!(node.path)),
+            ("node_type", json!(node.node_type)),
+            ("content", json!(node.content)),
+            ("start_line", json!(node.start_line)),
+            ("end_line", json!(node.end_line)),
+            ("start_col", json!(node.start_col)),
+            ("end_col", json!(node.end_col)),
+        ];
+        for (key, value) in own {
+            if fields.is_none_or(|fields| fields.iter().any(|f| f == key)) {
+                obj.insert(key.into(), value);
+            }
+        }
+        obj
+    }
+
+    fn prune_tree(
+        node: &TreeNode,
+        depth: usize,
+        max_depth: Option<usize>,
+        fields: Option<&[String]>,
+        budget: &mut usize,
+        truncated: &mut bool,
+    ) -> Value {
+        let mut obj = own_fields(node, fields);
+        let own_size = serde_json::to_string(&obj).map(|s| s.len()).unwrap_or(0);
+        *budget = budget.saturating_sub(own_size);
+
+        if !node.children.is_empty() {
+            let depth_allows = max_depth.is_none_or(|d| depth < d);
+            if depth_allows && *budget > 0 {
+                let mut children = Vec::new();
+                for (i, child) in node.children.iter().enumerate() {
+                    if *budget == 0 {
+                        *truncated = true;
+                        obj.insert("children_truncated".into(), json!(node.children.len() - i));
+                        break;
+                    }
+                    children.push(prune_tree(child, depth + 1, max_depth, fields, budget, truncated));
+                }
+                obj.insert("children".into(), Value::Array(children));
+            } else {
+                if depth_allows {
+                    *truncated = true;
+                }
+                obj.insert("children_truncated".into(), json!(node.children.len()));
+            }
+        } else {
+            obj.insert("children".into(), json!([]));
+        }
+        Value::Object(obj)
+    }
+
     
 
-        fn handle_list_nodes(state: Arc<AppState>, file_path: &str, filter: Option<&str>, max_depth: Option<usize>, all: bool) -> Value {
-        match GnawTreeWriter::new(file_path) {
+    /// `preview_chars` adds the start of each node's content, enough to tell
+    /// nodes apart without reading each one
+    #[allow(clippy::too_many_arguments)]
+    fn handle_list_nodes(
+        state: Arc<AppState>,
+        file_path: &str,
+        filter: Option<&str>,
+        kind: Option<NodeKind>,
+        max_depth: Option<usize>,
+        preview_chars: Option<usize>,
+        tolerant: bool,
+        all: bool,
+    ) -> Value {
+        match state.parse_cache.load(file_path, tolerant) {
             Ok(w) => {
                 let label_mgr = LabelManager::load(&state.project_root).ok();
                 let mut nodes = Vec::new();
                 let effective_max_depth = if all { usize::MAX } else { max_depth.unwrap_or(3) };
                 
-                fn collect(
-                    n: &TreeNode, 
-                    acc: &mut Vec<Value>, 
-                    fp: &str, 
-                    lm: &Option<LabelManager>, 
-                    filter: Option<&str>, 
-                    depth: usize, 
-                    max_d: usize
-                ) {
-                    if depth > max_d || acc.len() >= 5000 { return; }
-                    
-                    if filter.is_none() || filter.unwrap() == n.node_type {
-                        let labels = lm.as_ref().map(|mgr| mgr.get_labels(fp, &n.content)).unwrap_or_default();
-                        acc.push(json!({
+                let of_kind = kind.map(|k| w.analyze().paths_of_kind(k));
+                let keep = |n: &TreeNode| {
+                    filter.is_none_or(|f| f == n.node_type)
+                        && of_kind.as_ref().is_none_or(|paths| paths.contains(n.path.as_str()))
+                };
+                w.analyze().walk(|n, depth| {
+                    if depth > effective_max_depth || nodes.len() >= 5000 {
+                        return false;
+                    }
+                    if keep(n) {
+                        let labels = label_mgr.as_ref().map(|mgr| mgr.get_labels(file_path, &n.content)).unwrap_or_default();
+                        let mut node = json!({
                             "path": n.path, 
                             "type": n.node_type, 
                             "name": n.get_name(), 
                             "start": n.start_line, 
                             "labels": labels
-                        }));
-                    }
-                    
-                    for c in &n.children { 
-                        collect(c, acc, fp, lm, filter, depth + 1, max_d); 
+                        });
+                        if let Some(chars) = preview_chars {
+                            node["preview"] = json!(n.content.chars().take(chars).collect::<String>());
+                        }
+                        nodes.push(node);
                     }
-                }
-                
-                collect(w.analyze(), &mut nodes, file_path, &label_mgr, filter, 0, effective_max_depth);
+                    true
+                });
                 
                 let mut msg = format!("Found {} nodes", nodes.len());
                 if nodes.len() >= 1000 {
@@ -596,102 +1675,214 @@ pub mod mcp_server {
                 }
                 tool_success(msg, Some(json!({"nodes": nodes})))
             }
-            Err(e) => tool_error(format!("IO error: {}", e)),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-                fn handle_get_skeleton(file_path: &str, max_depth: usize) -> Value {
+                fn handle_get_skeleton(file_path: &str, max_depth: usize, signatures: bool) -> Value {
         match GnawTreeWriter::new(file_path) {
             Ok(w) => {
-                let mut s = String::new();
-                let mut count = 0;
-                fn build(n: &TreeNode, out: &mut String, d: usize, md: usize, count: &mut usize) {
-                    if d > md || *count >= 500 { return; }
-                    *count += 1;
-                    out.push_str(&format!("{}{} [{}] {}\n", "  ".repeat(d), n.path, n.node_type, n.get_name().unwrap_or_default()));
-                    if *count == 500 {
-                        out.push_str("... (limit reached)\n");
-                        return;
-                    }
-                    for c in &n.children { build(c, out, d + 1, md, count); }
-                }
-                build(w.analyze(), &mut s, 0, max_depth, &mut count);
+                let s = crate::parser::outline::skeleton(w.analyze(), max_depth, signatures);
                 tool_success(format!("Skeleton of {}", file_path), Some(json!({"skeleton": s})))
             }
-            Err(e) => tool_error(format!("IO error: {}", e)),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
 
     async fn handle_get_semantic_report(state: Arc<AppState>, file_path: &str) -> Value {
         #[cfg(feature = "modernbert")]
-        {
-            let mgr = match crate::llm::ai_manager::AiManager::new(&state.project_root) {
-                Ok(m) => m,
-                Err(e) => return tool_error(e.to_string()),
-            };
-            match mgr.generate_semantic_report(file_path).await {
-                Ok(report) => tool_success("Semantic report generated".into(), Some(json!({"report": report}))),
-                Err(e) => tool_error(e.to_string()),
+        let ai_error = {
+            match crate::llm::ai_manager::AiManager::new(&state.project_root) {
+                Ok(mgr) => match mgr.generate_semantic_report(file_path).await {
+                    Ok(report) => return tool_success("Semantic report generated".into(), Some(json!({"report": report}))),
+                    Err(e) => e.to_string(),
+                },
+                Err(e) => e.to_string(),
             }
-        }
+        };
         #[cfg(not(feature = "modernbert"))]
-        {
-            let _ = state;
-            let _ = file_path;
-            tool_error("ModernBERT feature not enabled.".into())
+        let ai_error = {
+            let _ = &state;
+            AI_DISABLED.to_string()
+        };
+
+        match GnawTreeWriter::new(file_path) {
+            Ok(w) => {
+                let report = crate::llm::heuristics::heuristic_report(file_path, w.analyze());
+                tool_success(
+                    format!("Heuristic report generated (AI unavailable: {})", ai_error),
+                    Some(json!({"report": report, "mode": "heuristic"})),
+                )
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-        fn handle_search_nodes(file_path: &str, pattern: &str) -> Value {
-        match GnawTreeWriter::new(file_path) {
+    fn handle_search_nodes(
+        state: Arc<AppState>,
+        file_path: &str,
+        pattern: &str,
+        kind: Option<NodeKind>,
+        context: Option<usize>,
+        include_parents: bool,
+    ) -> Value {
+        match state.parse_cache.load(file_path, false) {
             Ok(w) => {
                 let mut m = Vec::new();
-                fn find(n: &TreeNode, acc: &mut Vec<Value>, p: &str) {
-                    if acc.len() >= 500 { return; }
-                    if n.content.contains(p) {
-                        acc.push(json!({"path": n.path, "type": n.node_type, "name": n.get_name()}));
+                let of_kind = kind.map(|k| w.analyze().paths_of_kind(k));
+                let find = |n: &TreeNode, ancestors: &[&TreeNode], acc: &mut Vec<Value>| {
+                    if of_kind.as_ref().is_some_and(|paths| !paths.contains(n.path.as_str())) { return; }
+                    if let Some(offset) = n.content.find(pattern) {
+                        let mut hit = json!({"path": n.path, "type": n.node_type, "name": n.get_name()});
+                        if let Some(ctx) = context {
+                            let lines: Vec<&str> = n.content.lines().collect();
+                            let idx = n.content[..offset].matches('\n').count();
+                            // Small nodes are returned whole; larger ones are windowed
+                            let (start, end) = if lines.len() <= 2 * ctx + 1 {
+                                (0, lines.len())
+                            } else {
+                                (idx.saturating_sub(ctx), (idx + ctx + 1).min(lines.len()))
+                            };
+                            hit["line"] = json!(n.start_line + idx);
+                            hit["context_start_line"] = json!(n.start_line + start);
+                            hit["context"] = json!(lines[start..end].join("\n"));
+                        }
+                        if include_parents {
+                            let parents: Vec<Value> = ancestors
+                                .iter()
+                                .map(|a| json!({"path": a.path, "type": a.node_type, "name": a.kind().and_then(|_| a.get_name())}))
+                                .collect();
+                            hit["parents"] = json!(parents);
+                        }
+                        acc.push(hit);
                     }
-                    for c in &n.children { find(c, acc, p); }
-                }
-                find(w.analyze(), &mut m, pattern);
+                };
+                // Nodes on the way down from the root to the one being visited
+                let mut ancestors: Vec<&TreeNode> = Vec::new();
+                w.analyze().walk(|n, depth| {
+                    if m.len() >= 500 {
+                        return false;
+                    }
+                    ancestors.truncate(depth);
+                    find(n, &ancestors, &mut m);
+                    ancestors.push(n);
+                    true
+                });
                 let mut msg = format!("Found {} matches", m.len());
                 if m.len() >= 500 {
                     msg.push_str(" (limit reached)");
                 }
                 tool_success(msg, Some(json!({"matches": m})))
             }
-            Err(e) => tool_error(format!("IO error: {}", e)),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-    async fn handle_sense(state: Arc<AppState>, query: &str, file_path: Option<&str>) -> Value {
+    async fn handle_sense(state: Arc<AppState>, query: &str, file_path: Option<&str>, device: DeviceType) -> Value {
+        use crate::llm::{GnawSenseBroker, SenseResponse};
+        let broker = match GnawSenseBroker::new(&state.project_root) {
+            Ok(b) => b.with_device(device),
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+
+        #[cfg(feature = "modernbert")]
+        let ai_error = match broker.sense_with_model(query, file_path).await {
+            Ok(SenseResponse::Satelite { matches }) => {
+                return tool_success("Satelite search results".into(), Some(json!({"matches": matches})));
+            }
+            Ok(SenseResponse::Zoom { file_path, nodes, impact }) => {
+                return tool_success(format!("Zoom search results for {}", file_path), Some(json!({"nodes": nodes, "impact": impact})));
+            }
+            Err(e) => e.to_string(),
+        };
+        #[cfg(not(feature = "modernbert"))]
+        let ai_error = AI_DISABLED.to_string();
+
+        // Without the model, fall back to fuzzy name matching and say so in `mode`
+        match broker.sense_fuzzy(query, file_path) {
+            Ok(SenseResponse::Satelite { matches }) => tool_success(
+                format!("Project-wide name/content matches (AI unavailable: {})", ai_error),
+                Some(json!({"matches": matches, "mode": "heuristic"})),
+            ),
+            Ok(SenseResponse::Zoom { file_path, nodes, impact }) => tool_success(
+                format!("Name matches for {} (AI unavailable: {})", file_path, ai_error),
+                Some(json!({"nodes": nodes, "impact": impact, "mode": "heuristic"})),
+            ),
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    /// Best heuristic match for `query` in a file, used when the AI anchor
+    /// search is unavailable
+    fn heuristic_anchor(file_path: &str, query: &str) -> anyhow::Result<Option<crate::llm::NodeMatch>> {
+        let w = GnawTreeWriter::new(file_path)?;
+        Ok(crate::llm::heuristics::match_nodes(query, w.analyze(), 1).into_iter().next())
+    }
+
+    fn handle_complete_code(state: Arc<AppState>, file_path: &str, node_path: &str, top_k: usize, device: DeviceType) -> Value {
         #[cfg(feature = "modernbert")]
         {
-            use crate::llm::{GnawSenseBroker, SenseResponse};
-            let broker = match GnawSenseBroker::new(&state.project_root) {
-                Ok(b) => b,
-                Err(e) => return tool_error(e.to_string()),
+            let mgr = match crate::llm::ai_manager::AiManager::new(&state.project_root) {
+                Ok(m) => m.with_device(device),
+                Err(e) => return tool_failure(e.to_string(), &e),
             };
+            match mgr.complete_code(file_path, node_path, top_k) {
+                Ok(suggestions) => tool_success(
+                    format!("{} completions for {}", suggestions.len(), node_path),
+                    Some(json!({"suggestions": suggestions})),
+                ),
+                Err(e) => tool_failure(e.to_string(), &e),
+            }
+        }
+        #[cfg(not(feature = "modernbert"))]
+        {
+            let _ = (state, file_path, node_path, top_k, device);
+            tool_error(AI_DISABLED.into())
+        }
+    }
 
-            match broker.sense(query, file_path).await {
-                Ok(response) => {
-                    match response {
-                        SenseResponse::Satelite { matches } => {
-                            tool_success("Satelite search results".into(), Some(json!({"matches": matches})))
-                        }
-                        SenseResponse::Zoom { file_path, nodes, impact } => {
-                            tool_success(format!("Zoom search results for {}", file_path), Some(json!({"nodes": nodes, "impact": impact})))
-                        }
-                    }
-                }
-                Err(e) => tool_error(e.to_string()),
+    fn handle_suggest_refactor(state: Arc<AppState>, file_path: &str, node_path: Option<&str>, device: DeviceType) -> Value {
+        #[cfg(feature = "modernbert")]
+        {
+            let mgr = match crate::llm::ai_manager::AiManager::new(&state.project_root) {
+                Ok(m) => m.with_device(device),
+                Err(e) => return tool_failure(e.to_string(), &e),
+            };
+            match mgr.suggest_refactor(file_path, node_path) {
+                Ok(suggestions) => tool_success(
+                    format!("{} refactoring suggestions for {}", suggestions.len(), file_path),
+                    Some(json!({"suggestions": suggestions})),
+                ),
+                Err(e) => tool_failure(e.to_string(), &e),
+            }
+        }
+        #[cfg(not(feature = "modernbert"))]
+        {
+            let _ = (state, file_path, node_path, device);
+            tool_error(AI_DISABLED.into())
+        }
+    }
+
+    fn handle_suggest_batch(state: Arc<AppState>, file_path: &str, intent: &str, device: DeviceType) -> Value {
+        #[cfg(feature = "modernbert")]
+        {
+            let mgr = match crate::llm::ai_manager::AiManager::new(&state.project_root) {
+                Ok(m) => m.with_device(device),
+                Err(e) => return tool_failure(e.to_string(), &e),
+            };
+            match mgr.suggest_batch_edits(file_path, intent) {
+                Ok(suggestions) => tool_success(
+                    format!("{} candidate nodes for '{}'", suggestions.len(), intent),
+                    Some(json!({"suggestions": suggestions})),
+                ),
+                Err(e) => tool_failure(e.to_string(), &e),
             }
         }
         #[cfg(not(feature = "modernbert"))]
         {
-            let _ = (state, query, file_path);
-            tool_error("ModernBERT feature not enabled.".into())
+            let _ = (state, file_path, intent, device);
+            tool_error(AI_DISABLED.into())
         }
     }
 
@@ -701,48 +1892,53 @@ pub mod mcp_server {
         anchor_query: &str,
         content: &str,
         intent: &str,
+        options: EditOptions,
+        include_pulse: bool,
     ) -> Value {
-        #[cfg(feature = "modernbert")]
-        {
-            use crate::llm::GnawSenseBroker;
-            let broker = match GnawSenseBroker::new(&state.project_root) {
-                Ok(b) => b,
-                Err(e) => return tool_error(e.to_string()),
-            };
+        use crate::llm::GnawSenseBroker;
 
-            match broker.propose_edit(anchor_query, file_path, intent).await {
-                Ok(proposal) => {
-                    let mut writer = match GnawTreeWriter::new(file_path) {
-                        Ok(w) => w,
-                        Err(e) => return tool_error(e.to_string()),
-                    };
-                    let op = EditOperation::Insert {
-                        parent_path: proposal.parent_path,
-                        position: proposal.position,
-                        content: content.to_string(),
-                    };
-                    match writer.edit(op, false) {
-                        Ok(_) => {
-                            let pulse = generate_pulse(state, file_path, &proposal.anchor_path);
-                            tool_success_with_pulse(
-                                format!(
-                                    "Successfully inserted code near anchor '{}' (confidence: {:.2})",
-                                    proposal.anchor_path, proposal.confidence
-                                ),
-                                None,
-                                pulse,
-                            )
-                        },
-                        Err(e) => tool_error(e.to_string()),
-                    }
-                }
-                Err(e) => tool_error(e.to_string()),
-            }
-        }
+        #[cfg(feature = "modernbert")]
+        let ai_proposal = match GnawSenseBroker::new(&state.project_root) {
+            Ok(broker) => broker.propose_edit(anchor_query, file_path, intent).await.ok(),
+            Err(_) => None,
+        };
         #[cfg(not(feature = "modernbert"))]
-        {
-            let _ = (state, file_path, anchor_query, content, intent);
-            tool_error("ModernBERT feature not enabled.".into())
+        let ai_proposal: Option<crate::llm::EditProposal> = None;
+
+        let proposal = match ai_proposal {
+            Some(p) => p,
+            None => match heuristic_anchor(file_path, anchor_query) {
+                Ok(Some(anchor)) => match GnawSenseBroker::proposal_for_anchor(&anchor.path, intent, anchor.score) {
+                    Ok(p) => p,
+                    Err(e) => return tool_failure(e.to_string(), &e),
+                },
+                Ok(None) => return tool_error(format!("Could not find an anchor for '{}' in {}", anchor_query, file_path)),
+                Err(e) => return tool_failure(e.to_string(), &e),
+            },
+        };
+
+        let mut writer = match GnawTreeWriter::new(file_path) {
+            Ok(w) => w,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        let op = EditOperation::Insert {
+            parent_path: proposal.parent_path,
+            position: proposal.position,
+            content: content.to_string(),
+        };
+        match writer.edit_with_options(op, options) {
+            Ok(_) => {
+                let pulse = generate_pulse(state, include_pulse, file_path, &proposal.anchor_path);
+                tool_success_with_pulse(
+                    format!(
+                        "Successfully inserted code near anchor '{}' (confidence: {:.2})",
+                        proposal.anchor_path, proposal.confidence
+                    ),
+                    None,
+                    pulse,
+                )
+            },
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
@@ -751,35 +1947,156 @@ pub mod mcp_server {
         file_path: &str,
         query: &str,
         content: &str,
+        options: EditOptions,
+        include_pulse: bool,
     ) -> Value {
         #[cfg(feature = "modernbert")]
-        {
+        let target = {
             use crate::llm::{GnawSenseBroker, SenseResponse};
-            let broker = match GnawSenseBroker::new(&state.project_root) {
-                Ok(b) => b,
-                Err(e) => return tool_error(e.to_string()),
-            };
-
-            match broker.sense(query, Some(file_path)).await {
-                Ok(SenseResponse::Zoom { nodes, .. }) if !nodes.is_empty() => {
-                    let best_node = &nodes[0];
-                    handle_edit_node_internal(state, file_path, &best_node.path, content)
+            match GnawSenseBroker::new(&state.project_root) {
+                Ok(broker) => match broker.sense_with_model(query, Some(file_path)).await {
+                    Ok(SenseResponse::Zoom { nodes, .. }) => nodes.into_iter().next().map(|best| (best, "model")),
+                    _ => None,
                 },
-                Ok(_) => tool_error(format!("Could not find a semantic match for '{}' in {}", query, file_path)),
-                Err(e) => tool_error(e.to_string()),
+                Err(_) => None,
             }
-        }
+        };
         #[cfg(not(feature = "modernbert"))]
-        {
-            let _ = (state, file_path, query, content);
-            tool_error("ModernBERT feature not enabled.".into())
+        let target: Option<(crate::llm::NodeMatch, &str)> = None;
+
+        let (best, mode) = match target {
+            Some(target) => target,
+            None => {
+                let tree = match GnawTreeWriter::new(file_path) {
+                    Ok(w) => w.analyze().clone(),
+                    Err(e) => return tool_failure(e.to_string(), &e),
+                };
+                match crate::llm::heuristics::edit_target(query, &tree) {
+                    Ok(best) => (best, "heuristic"),
+                    Err(msg) => return tool_error(format!("{} in {}", msg, file_path)),
+                }
+            }
+        };
+
+        let mut res = handle_edit_node_internal(state, file_path, &best.path, content, options, include_pulse);
+        if res.get("isError").is_none() {
+            for (key, value) in [("mode", json!(mode)), ("node_path", json!(best.path)), ("score", json!(best.score))] {
+                res["structuredContent"][key] = value.clone();
+                res[key] = value;
+            }
         }
+        res
     }
 
-    fn handle_read_node(file_path: &str, node_path: &str) -> Value {
+    fn handle_node_at_line(file_path: &str, line: usize) -> Value {
         match GnawTreeWriter::new(file_path) {
-            Ok(w) => w.show_node(node_path).map_or_else(|e| tool_error(e.to_string()), |c| tool_success(c, None)),
-            Err(e) => tool_error(format!("IO error: {}", e)), // Corrected: escaped curly brace
+            Ok(w) => match w.node_at_line(line) {
+                Some(n) => tool_success(
+                    format!("Line {} is in {} [{}]", line, n.path, n.node_type),
+                    Some(json!({
+                        "path": n.path,
+                        "type": n.node_type,
+                        "name": n.get_name(),
+                        "start_line": n.start_line,
+                        "end_line": n.end_line
+                    })),
+                ),
+                None => tool_error(format!("No node contains line {} in {}", line, file_path)),
+            },
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    fn handle_read_node(
+        state: Arc<AppState>,
+        file_path: &str,
+        node_path: &str,
+        line_numbers: bool,
+        include_children: bool,
+    ) -> Value {
+        match state.parse_cache.load(file_path, false) {
+            Ok(w) if include_children => match w.node_members(node_path) {
+                Ok(nodes) => {
+                    let section = |m: &TreeNode| {
+                        let content =
+                            if line_numbers { crate::core::number_lines(&m.content, m.start_line) } else { m.content.clone() };
+                        json!({
+                            "path": m.path,
+                            "type": m.node_type,
+                            "name": m.get_name(),
+                            "start": m.start_line,
+                            "end": m.end_line,
+                            "content": content
+                        })
+                    };
+                    let children: Vec<Value> = nodes[1..].iter().map(|m| section(m)).collect();
+                    tool_success(
+                        crate::core::show_sections(&nodes, line_numbers),
+                        Some(json!({"node": section(nodes[0]), "children": children})),
+                    )
+                }
+                Err(e) => tool_failure(e.to_string(), &e),
+            },
+            Ok(w) => {
+                let content = if line_numbers {
+                    w.show_node_with_line_numbers(node_path)
+                } else {
+                    w.show_node(node_path)
+                };
+                content.map_or_else(|e| tool_failure(e.to_string(), &e), |c| tool_success(c, None))
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    fn handle_diff_nodes(file_a: &str, path_a: &str, file_b: &str, path_b: &str) -> Value {
+        let show = |file: &str, path: &str| GnawTreeWriter::new(file).and_then(|w| w.show_node(path));
+        let (a, b) = match (show(file_a, path_a), show(file_b, path_b)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(e), _) | (_, Err(e)) => return tool_failure(e.to_string(), &e),
+        };
+        let identical = a == b;
+        let summary = if identical {
+            format!("{}:{} and {}:{} are identical", file_a, path_a, file_b, path_b)
+        } else {
+            format!("Diff of {}:{} against {}:{}", file_a, path_a, file_b, path_b)
+        };
+        tool_success(summary, Some(json!({"diff": generate_diff_string(&a, &b), "identical": identical})))
+    }
+
+    fn handle_get_relations(state: Arc<AppState>, path: &str) -> Value {
+        let mut indexer = crate::llm::RelationalIndexer::new(&state.project_root);
+        match indexer.relation_graph(std::path::Path::new(path)) {
+            Ok(graph) => {
+                let summary = format!(
+                    "{} nodes and {} edges in {}",
+                    graph.nodes.len(),
+                    graph.edges.len(),
+                    path
+                );
+                tool_success(summary, Some(json!(graph)))
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    fn handle_find_references(state: Arc<AppState>, name: &str, path: Option<&str>) -> Value {
+        let mut indexer = crate::llm::RelationalIndexer::new(&state.project_root);
+        if let Some(path) = path {
+            if let Err(e) = indexer.relation_graph(std::path::Path::new(path)) {
+                return tool_failure(e.to_string(), &e);
+            }
+        }
+        match indexer.find_references(name) {
+            Ok(references) => {
+                let summary = format!("{} references to '{}'", references.len(), name);
+                let references: Vec<Value> = references
+                    .iter()
+                    .map(|rel| json!({"file": rel.from_file, "path": rel.from_path, "relation": rel.relation_type}))
+                    .collect();
+                tool_success(summary, Some(json!({"references": references})))
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
@@ -797,61 +2114,225 @@ pub mod mcp_server {
         output
     }
 
-    fn handle_preview_edit(file_path: &str, node_path: &str, content: &str) -> Value {
+    fn handle_preview_edit(file_path: &str, node_path: &str, content: &str, include_content: bool) -> Value {
+        let op = EditOperation::Edit { node_path: node_path.to_string(), content: content.to_string() };
+        if !include_content {
+            return handle_preview_operation(file_path, op, "edit");
+        }
+        let writer = match GnawTreeWriter::new(file_path) {
+            Ok(w) => w,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        let Some(node) = writer.resolve_path(node_path) else {
+            let e = anyhow::Error::from(GnawError::node_not_found(node_path, writer.analyze()));
+            return tool_failure(e.to_string(), &e);
+        };
+        match writer.preview_edit(op) {
+            Ok(new_source) => {
+                let diff = generate_diff_string(writer.get_source(), &new_source);
+                // The node's lines as they will read after the edit: a node
+                // that starts or ends mid-line keeps the rest of its lines.
+                // Lines outside the node are untouched, so count them off
+                // both ends of the new source.
+                let old_lines: Vec<&str> = writer.get_source().lines().collect();
+                let new_lines: Vec<&str> = new_source.lines().collect();
+                let after = old_lines.len().saturating_sub(node.end_line);
+                let start = node.start_line.saturating_sub(1).min(new_lines.len());
+                let end = new_lines.len().saturating_sub(after).max(start);
+                tool_success(
+                    format!("Preview of edit:\n{}", diff),
+                    Some(json!({
+                        "diff": diff,
+                        "old_content": node.content,
+                        "new_content": content,
+                        "start_line": node.start_line,
+                        "end_line": node.end_line,
+                        "start_col": node.start_col,
+                        "end_col": node.end_col,
+                        "old_lines": old_lines.get(node.start_line.saturating_sub(1)..node.end_line).unwrap_or_default(),
+                        "new_lines": &new_lines[start..end]
+                    })),
+                )
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    /// Diff the result of `op` against the file without writing anything
+    fn handle_preview_operation(file_path: &str, op: EditOperation, label: &str) -> Value {
         match GnawTreeWriter::new(file_path) {
             Ok(writer) => {
                 let old_source = writer.get_source().to_string();
-                let op = EditOperation::Edit { node_path: node_path.to_string(), content: content.to_string() };
                 match writer.preview_edit(op) {
                     Ok(new_source) => {
                         let diff = generate_diff_string(&old_source, &new_source);
-                        tool_success(format!("Preview of edit:\n{}", diff), Some(json!({"diff": diff})))
+                        tool_success(format!("Preview of {}:\n{}", label, diff), Some(json!({"diff": diff})))
                     },
-                    Err(e) => tool_error(e.to_string()),
+                    Err(e) => tool_failure(e.to_string(), &e),
                 }
             },
-            Err(e) => tool_error(format!("IO error: {}", e)),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-    fn handle_edit_node_internal(state: Arc<AppState>, file_path: &str, node_path: &str, content: &str) -> Value {
+    /// The optional `device` argument of the AI tools; CPU unless asked
+    fn device_arg(arguments: &Value) -> DeviceType {
+        arguments.get("device").and_then(Value::as_str).map(DeviceType::from).unwrap_or_default()
+    }
+
+    /// Read the unsafe `no_backup` / `no_validate` switches and
+    /// `strict_type` from tool arguments
+    fn edit_options(arguments: &Value) -> EditOptions {
+        let flag = |key: &str| arguments.get(key).and_then(Value::as_bool).unwrap_or(false);
+        EditOptions {
+            force: false,
+            no_backup: flag("no_backup"),
+            no_validate: flag("no_validate"),
+            strict_type: flag("strict_type"),
+            ..client_options(arguments)
+        }
+    }
+
+    /// What the unsafe switches in `options` skipped, returned with the
+    /// result so the agent sees it and not just the server's stderr
+    fn unsafe_warnings(options: &EditOptions) -> Vec<&'static str> {
+        let mut warnings = Vec::new();
+        if options.no_backup {
+            warnings.push("UNSAFE no_backup: no backup was written, so this edit cannot be undone");
+        }
+        if options.no_validate {
+            warnings.push("UNSAFE no_validate: the result was written without syntax validation");
+        }
+        warnings
+    }
+
+    /// Default `EditOptions` carrying the call's `client_id`. The HTTP
+    /// transport keeps no state between requests, so clients send it with
+    /// every edit rather than once in `initialize`.
+    fn client_options(arguments: &Value) -> EditOptions {
+        EditOptions {
+            client_id: arguments.get("client_id").and_then(Value::as_str).map(str::to_string),
+            ..EditOptions::default()
+        }
+    }
+
+    fn handle_edit_node_internal(
+        state: Arc<AppState>,
+        file_path: &str,
+        node_path: &str,
+        content: &str,
+        options: EditOptions,
+        include_pulse: bool,
+    ) -> Value {
         match GnawTreeWriter::new(file_path) {
             Ok(mut w) => {
                 let old_source = w.get_source().to_string();
+                let warnings = unsafe_warnings(&options);
                 let op = EditOperation::Edit { node_path: node_path.to_string(), content: content.to_string() };
-                if let Err(e) = w.edit(op, false) { return tool_error(e.to_string()); }
+                if let Err(e) = w.edit_with_options(op, options) { return tool_failure(e.to_string(), &e); }
                 
                 let new_source_loaded = std::fs::read_to_string(file_path).unwrap_or_default();
                 let diff = generate_diff_string(&old_source, &new_source_loaded);
-                let pulse = generate_pulse(state, file_path, node_path);
-                tool_success_with_pulse(format!("Node edited.\nDiff:\n{}", diff), Some(json!({"diff": diff})), pulse)
+                let pulse = generate_pulse(state, include_pulse, file_path, node_path);
+                let text = warnings.iter().fold(String::from("Node edited.\n"), |text, w| text + "⚠️ " + w + "\n");
+                tool_success_with_pulse(format!("{}Diff:\n{}", text, diff), Some(json!({"diff": diff, "warnings": warnings})), pulse)
+            },
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    fn handle_apply_operation(
+        state: Arc<AppState>,
+        file_path: &str,
+        node_path: &str,
+        op: EditOperation,
+        options: EditOptions,
+        include_pulse: bool,
+    ) -> Value {
+        match GnawTreeWriter::new(file_path) {
+            Ok(mut w) => {
+                let old_source = w.get_source().to_string();
+                if let Err(e) = w.edit_with_options(op, options) { return tool_failure(e.to_string(), &e); }
+
+                let diff = generate_diff_string(&old_source, w.get_source());
+                let pulse = generate_pulse(state, include_pulse, file_path, node_path);
+                tool_success_with_pulse(format!("Node updated.\nDiff:\n{}", diff), Some(json!({"diff": diff})), pulse)
+            },
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_replace_in_node(
+        state: Arc<AppState>,
+        file_path: &str,
+        node_path: &str,
+        find: &str,
+        replace: &str,
+        count: Option<usize>,
+        options: EditOptions,
+        include_pulse: bool,
+    ) -> Value {
+        match GnawTreeWriter::new(file_path) {
+            Ok(mut w) => {
+                let old_source = w.get_source().to_string();
+                let replaced = match w.replace_in_node_with_options(node_path, find, replace, count, options) {
+                    Ok(n) => n,
+                    Err(e) => return tool_failure(e.to_string(), &e),
+                };
+
+                let diff = generate_diff_string(&old_source, w.get_source());
+                let pulse = generate_pulse(state, include_pulse, file_path, node_path);
+                tool_success_with_pulse(
+                    format!("Replaced {} occurrence(s) in {}.\nDiff:\n{}", replaced, node_path, diff),
+                    Some(json!({"diff": diff, "replacements": replaced})),
+                    pulse,
+                )
             },
-            Err(e) => tool_error(format!("IO error: {}", e)),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-    fn handle_insert_node(state: Arc<AppState>, file_path: &str, parent_path: &str, position: usize, content: &str) -> Value {
+    fn handle_insert_node(
+        state: Arc<AppState>,
+        file_path: &str,
+        parent_path: &str,
+        position: usize,
+        content: &str,
+        options: EditOptions,
+        include_pulse: bool,
+    ) -> Value {
         match GnawTreeWriter::new(file_path) {
             Ok(mut w) => {
                 let old_source = w.get_source().to_string();
+                let warnings = unsafe_warnings(&options);
                 let op = EditOperation::Insert { parent_path: parent_path.to_string(), position, content: content.to_string() };
-                if let Err(e) = w.edit(op, false) { return tool_error(e.to_string()); }
+                if let Err(e) = w.edit_with_options(op, options) { return tool_failure(e.to_string(), &e); }
                 
                 let new_source_loaded = std::fs::read_to_string(file_path).unwrap_or_default();
                 let diff = generate_diff_string(&old_source, &new_source_loaded);
-                let pulse = generate_pulse(state, file_path, parent_path); // Pulse for parent
-                tool_success_with_pulse(format!("Content inserted.\nDiff:\n{}", diff), Some(json!({"diff": diff})), pulse)
+                let pulse = generate_pulse(state, include_pulse, file_path, parent_path); // Pulse for parent
+                let text = warnings.iter().fold(String::from("Content inserted.\n"), |text, w| text + "⚠️ " + w + "\n");
+                tool_success_with_pulse(format!("{}Diff:\n{}", text, diff), Some(json!({"diff": diff, "warnings": warnings})), pulse)
             },
-            Err(e) => tool_error(format!("IO error: {}", e)), // Corrected: escaped curly brace
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
-    fn handle_move_node(state: Arc<AppState>, source_file: &str, source_path: &str, target_file: &str, target_path: &str) -> Value {
+    fn handle_move_node(
+        state: Arc<AppState>,
+        source_file: &str,
+        source_path: &str,
+        target_file: &str,
+        target_path: &str,
+        options: EditOptions,
+        include_pulse: bool,
+    ) -> Value {
         match GnawTreeWriter::new(source_file) {
             Ok(mut src_w) => {
                 let old_source = src_w.get_source().to_string();
                 let delete_op = EditOperation::Delete { node_path: source_path.to_string() };
-                if let Err(e) = src_w.edit(delete_op, false) { return tool_error(e.to_string()); }
+                if let Err(e) = src_w.edit_with_options(delete_op, options.clone()) { return tool_failure(e.to_string(), &e); }
 
                 let insert_op = EditOperation::Insert {
                     parent_path: target_path.to_string(),
@@ -861,19 +2342,165 @@ pub mod mcp_server {
                 match GnawTreeWriter::new(target_file) {
                     Ok(mut tgt_w) => {
                         let old_target = tgt_w.get_source().to_string();
-                        if let Err(e) = tgt_w.edit(insert_op, false) { return tool_error(e.to_string()); }
+                        if let Err(e) = tgt_w.edit_with_options(insert_op, options) { return tool_failure(e.to_string(), &e); }
                         let new_target = std::fs::read_to_string(target_file).unwrap_or_default();
                         let diff = generate_diff_string(&old_target, &new_target);
-                        let pulse = generate_pulse(state, target_file, target_path);
+                        let pulse = generate_pulse(state, include_pulse, target_file, target_path);
                         tool_success_with_pulse(format!("Moved from {} [{}] to {} [{}].\nDiff:\n{}", source_file, source_path, target_file, target_path, diff), Some(json!({"diff": diff})), pulse)
                     },
-                    Err(e) => tool_error(format!("IO error on target: {}", e)),
+                    Err(e) => tool_failure(format!("Target: {}", e), &e),
                 }
             },
-            Err(e) => tool_error(format!("IO error on source: {}", e)),
+            Err(e) => tool_failure(format!("Source: {}", e), &e),
+        }
+    }
+
+    fn handle_session_start(state: Arc<AppState>, name: Option<String>) -> Value {
+        let mut log = match crate::core::TransactionLog::load(&state.project_root) {
+            Ok(l) => l,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        match log.start_new_session(name.clone()) {
+            Ok(()) => {
+                let session_id = log.get_current_session_id().to_string();
+                tool_success(
+                    format!("New session started: {}", session_id),
+                    Some(json!({"session_id": session_id, "name": name})),
+                )
+            }
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    fn handle_session_status(state: Arc<AppState>) -> Value {
+        let manager = match crate::core::UndoRedoManager::new(&state.project_root) {
+            Ok(m) => m,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        let log = match crate::core::TransactionLog::load(&state.project_root) {
+            Ok(l) => l,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        let undo_state = manager.get_state();
+        let session_id = log.get_current_session_id().to_string();
+        let operations = log.get_session_history().len();
+
+        tool_success(
+            format!(
+                "Session {}: {} operations, {} undoable, {} redoable",
+                session_id, operations, undo_state.undo_available, undo_state.redo_available
+            ),
+            Some(json!({
+                "session_id": session_id,
+                "operations": operations,
+                "undo_available": undo_state.undo_available,
+                "redo_available": undo_state.redo_available,
+                "last_undo": undo_state.last_undo,
+                "last_redo": undo_state.last_redo
+            })),
+        )
+    }
+
+    fn handle_restore_session(state: Arc<AppState>, session_id: &str, preview: bool) -> Value {
+        let log = match crate::core::TransactionLog::load(&state.project_root) {
+            Ok(l) => l,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        let actual_id = log.resolve_session_alias(session_id);
+
+        if preview {
+            return match log.get_session_files(&actual_id) {
+                Ok(files) => {
+                    let files: Vec<String> = files.iter().map(|f| f.to_string_lossy().to_string()).collect();
+                    tool_success(
+                        format!("Session {} would restore {} files", actual_id, files.len()),
+                        Some(json!({"session_id": actual_id, "files": files})),
+                    )
+                }
+                Err(e) => tool_failure(e.to_string(), &e),
+            };
+        }
+
+        let engine = match crate::core::RestorationEngine::new(&state.project_root) {
+            Ok(e) => e,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        match engine.restore_session(&actual_id) {
+            Ok(result) => restoration_report(result, &format!("session {}", actual_id), json!({"session_id": actual_id})),
+            Err(e) => tool_failure(e.to_string(), &e),
         }
     }
 
+    fn handle_restore_by_client(state: Arc<AppState>, client_id: &str, preview: bool, force: bool) -> Value {
+        let engine = match crate::core::RestorationEngine::new(&state.project_root) {
+            Ok(e) => e,
+            Err(e) => return tool_failure(e.to_string(), &e),
+        };
+        if preview {
+            let log = match crate::core::TransactionLog::load(&state.project_root) {
+                Ok(l) => l,
+                Err(e) => return tool_failure(e.to_string(), &e),
+            };
+            return match (log.get_client_history(client_id), engine.client_conflicts(client_id)) {
+                (Ok(transactions), Ok(conflicts)) => {
+                    let files: Vec<String> = crate::core::edits_by_file(transactions)
+                        .iter()
+                        .map(|(file, _)| file.to_string_lossy().to_string())
+                        .collect();
+                    let conflicts: Vec<Value> = conflicts
+                        .iter()
+                        .map(|t| {
+                            json!({
+                                "file": t.file_path.to_string_lossy(),
+                                "transaction_id": t.id,
+                                "client_id": t.metadata.get(crate::core::transaction_log::CLIENT_ID_KEY)
+                            })
+                        })
+                        .collect();
+                    tool_success(
+                        format!(
+                            "Client {} would restore {} files, discarding {} later edits by other clients",
+                            client_id,
+                            files.len(),
+                            conflicts.len()
+                        ),
+                        Some(json!({"client_id": client_id, "files": files, "conflicts": conflicts})),
+                    )
+                }
+                (Err(e), _) | (_, Err(e)) => tool_failure(e.to_string(), &e),
+            };
+        }
+
+        match engine.restore_client(client_id, force) {
+            Ok(result) => restoration_report(result, &format!("client {}", client_id), json!({"client_id": client_id})),
+            Err(e) => tool_failure(e.to_string(), &e),
+        }
+    }
+
+    /// The tool result for a finished restore of `what`; `data` gets the
+    /// restored and failed files added
+    fn restoration_report(result: crate::core::RestorationResult, what: &str, mut data: Value) -> Value {
+        let restored: Vec<String> = result
+            .restored_files
+            .iter()
+            .map(|f| f.to_string_lossy().to_string())
+            .collect();
+        let failed: Vec<Value> = result
+            .failed_files
+            .iter()
+            .map(|(f, err)| json!({"file": f.to_string_lossy(), "error": err}))
+            .collect();
+        let msg = format!("Restored {} of {} files from {}", restored.len(), result.total_files, what);
+        data["restored_files"] = json!(restored);
+        data["failed_files"] = json!(failed);
+        data["success"] = json!(result.success);
+        let mut res = tool_success(msg, Some(data));
+        if !result.success {
+            res["isError"] = json!(true);
+        }
+        res
+    }
+
     pub async fn serve_with_shutdown<F>(
         listener: TcpListener,
         token: Option<String>,
@@ -883,27 +2510,412 @@ pub mod mcp_server {
         F: std::future::Future<Output = ()> + Send + 'static,
     {
         let project_root = std::env::current_dir()?;
+        serve_with_shutdown_at(listener, token, project_root, shutdown_signal).await
+    }
+
+    /// Like `serve_with_shutdown`, but rooted at an explicit project directory
+    /// instead of the current working directory.
+    pub async fn serve_with_shutdown_at<F>(
+        listener: TcpListener,
+        token: Option<String>,
+        project_root: std::path::PathBuf,
+        shutdown_signal: F,
+    ) -> Result<()>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        serve_with_options(listener, token, project_root, ServerOptions::default(), shutdown_signal).await
+    }
+
+    /// `serve_with_shutdown_at` with explicit `ServerOptions`
+    pub async fn serve_with_options<F>(
+        listener: TcpListener,
+        token: Option<String>,
+        project_root: std::path::PathBuf,
+        options: ServerOptions,
+        shutdown_signal: F,
+    ) -> Result<()>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let limiter = options.max_concurrency.map(|n| Arc::new(tokio::sync::Semaphore::new(n)));
+        let state = Arc::new(AppState {
+            token,
+            project_root,
+            started: std::time::Instant::now(),
+            limiter,
+            timeouts: options.timeouts,
+            pulse: options.pulse,
+            parse_cache: ParseCache::default(),
+        });
         let app = Router::new()
-            .route("/", post(rpc_handler))
-            .with_state(Arc::new(AppState { token, project_root }));
+            .route(
+                "/",
+                post(rpc_handler).route_layer(axum::middleware::from_fn_with_state(state.clone(), limit_concurrency)),
+            )
+            .route(
+                "/analyze_stream",
+                post(analyze_stream_handler)
+                    .route_layer(axum::middleware::from_fn_with_state(state.clone(), limit_concurrency)),
+            )
+            .route("/health", get(health_handler))
+            .route("/tools", get(tools_handler))
+            .with_state(state);
         axum::serve(listener, app)
             .with_graceful_shutdown(shutdown_signal)
             .await?;
         Ok(())
     }
 
-    pub async fn serve(addr: &str, token: Option<String>) -> Result<()> {
+    /// Pick the server token from `--token-file`, then `MCP_TOKEN`, then
+    /// `--token` (which shows up in process listings). A source that is
+    /// present but empty is an error rather than silently disabling auth.
+    pub fn resolve_token(
+        token_file: Option<&std::path::Path>,
+        env_token: Option<String>,
+        flag_token: Option<String>,
+    ) -> Result<Option<String>> {
+        let (source, token) = if let Some(path) = token_file {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read token file {}: {}", path.display(), e))?;
+            (format!("Token file {}", path.display()), text)
+        } else if let Some(token) = env_token {
+            ("MCP_TOKEN".to_string(), token)
+        } else if let Some(token) = flag_token {
+            ("--token".to_string(), token)
+        } else {
+            return Ok(None);
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            anyhow::bail!("{} is empty; refusing to start without a token", source);
+        }
+        Ok(Some(token.to_string()))
+    }
+
+    pub async fn serve(addr: &str, token: Option<String>, options: ServerOptions) -> Result<()> {
         let listener = TcpListener::bind(addr).await?;
         eprintln!("Starting MCP server on http://{}", listener.local_addr()?); // Fixed: redirected to stderr
-        serve_with_shutdown(listener, token, async { let _ = signal::ctrl_c().await; }).await
+        let project_root = std::env::current_dir()?;
+        serve_with_options(listener, token, project_root, options, async { let _ = signal::ctrl_c().await; }).await
+    }
+
+    /// What `mcp status` found at a server URL
+    #[derive(Debug, Default, Serialize)]
+    pub struct ServerStatus {
+        pub url: String,
+        pub reachable: bool,
+        /// `ok`, `failed` (the server answered 401) or `unknown` when it could not be reached
+        pub auth: &'static str,
+        pub server_name: Option<String>,
+        pub version: Option<String>,
+        pub protocol_version: Option<String>,
+        /// Number of tools in `tools/list`
+        pub tools: Option<usize>,
+        pub error: Option<String>,
+    }
+
+    impl ServerStatus {
+        pub fn ok(&self) -> bool {
+            self.reachable && self.auth == "ok" && self.error.is_none()
+        }
     }
 
-    pub async fn status(url: &str, token: Option<String>) -> Result<()> {
+    /// Send `initialize` and `tools/list` to the server at `url`
+    pub async fn check_status(url: &str, token: Option<&str>) -> ServerStatus {
+        let mut status = ServerStatus { url: url.to_string(), auth: "unknown", ..Default::default() };
         let client = reqwest::Client::new();
-        let mut req = client.post(url);
-        if let Some(t) = token { req = req.header("Authorization", format!("Bearer {}", t)); } // Corrected: escaped curly brace
-        let _ = req.json(&json!({"jsonrpc":"2.0","method":"initialize","id":1})).send().await?;
-        eprintln!("✓ Server ready");
+        let call = |method: &'static str| client_call(&client, url, token, method, None);
+
+        let resp = match call("initialize").await {
+            Ok(resp) => resp,
+            Err(e) => {
+                status.error = Some(format!("Cannot reach {}: {}", url, e));
+                return status;
+            }
+        };
+        status.reachable = true;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            status.auth = "failed";
+            status.error = Some("Unauthorized: check --token or MCP_TOKEN".to_string());
+            return status;
+        }
+        status.auth = "ok";
+        let body: Value = match resp.json().await {
+            Ok(body) => body,
+            Err(e) => {
+                status.error = Some(format!("Invalid initialize response: {}", e));
+                return status;
+            }
+        };
+        let result = &body["result"];
+        status.server_name = result["serverInfo"]["name"].as_str().map(String::from);
+        status.version = result["serverInfo"]["version"].as_str().map(String::from);
+        status.protocol_version = result["protocolVersion"].as_str().map(String::from);
+        if status.server_name.is_none() {
+            status.error = Some(format!("Unexpected initialize response: {}", body));
+            return status;
+        }
+
+        match call("tools/list").await {
+            Ok(resp) => match resp.json::<Value>().await {
+                Ok(body) => status.tools = body["result"]["tools"].as_array().map(Vec::len),
+                Err(e) => status.error = Some(format!("Invalid tools/list response: {}", e)),
+            },
+            Err(e) => status.error = Some(format!("tools/list failed: {}", e)),
+        }
+        status
+    }
+
+    /// POST a JSON-RPC `method` without params to the server at `url`
+    async fn client_call(
+        client: &reqwest::Client,
+        url: &str,
+        token: Option<&str>,
+        method: &str,
+        params: Option<Value>,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut body = json!({"jsonrpc": "2.0", "method": method, "id": 1});
+        if let Some(params) = params {
+            body["params"] = params;
+        }
+        let mut req = client.post(url).json(&body);
+        if let Some(t) = token {
+            req = req.header("Authorization", format!("Bearer {}", t));
+        }
+        req.send().await
+    }
+
+    /// The `tools/list` entries of the server at `url`
+    pub async fn fetch_tools(url: &str, token: Option<&str>) -> Result<Vec<Value>> {
+        let resp = client_call(&reqwest::Client::new(), url, token, "tools/list", None)
+            .await
+            .with_context(|| format!("Cannot reach {}", url))?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            anyhow::bail!("Unauthorized: check --token or MCP_TOKEN");
+        }
+        let body: Value = resp.json().await.context("Invalid tools/list response")?;
+        match body["result"]["tools"].as_array() {
+            Some(tools) => Ok(tools.clone()),
+            None => anyhow::bail!("Unexpected tools/list response: {}", body),
+        }
+    }
+
+    /// One line per tool: its name, required arguments and description
+    pub fn format_tool_table(tools: &[Value]) -> String {
+        let rows: Vec<(&str, String, &str)> = tools
+            .iter()
+            .map(|tool| {
+                let required: Vec<&str> = tool["inputSchema"]["required"]
+                    .as_array()
+                    .map(|r| r.iter().filter_map(Value::as_str).collect())
+                    .unwrap_or_default();
+                (
+                    tool["name"].as_str().unwrap_or("?"),
+                    required.join(", "),
+                    tool["description"].as_str().unwrap_or(""),
+                )
+            })
+            .collect();
+        let name_width = rows.iter().map(|(name, ..)| name.len()).chain([4]).max().unwrap_or(4);
+        let args_width = rows.iter().map(|(_, args, _)| args.len()).chain([8]).max().unwrap_or(8);
+
+        let mut out = format!("{:name_width$}  {:args_width$}  DESCRIPTION\n", "TOOL", "REQUIRED");
+        for (name, args, description) in &rows {
+            out.push_str(format!("{:name_width$}  {:args_width$}  {}", name, args, description).trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Print the tools of the server at `url` as a table or, with `format`
+    /// `json`, as the raw `tools/list` entries
+    pub async fn list_tools(url: &str, token: Option<String>, format: &str) -> Result<()> {
+        let tools = fetch_tools(url, token.as_deref()).await?;
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&tools)?);
+        } else {
+            print!("{}", format_tool_table(&tools));
+        }
+        Ok(())
+    }
+
+    /// The `arguments` object for `tools/call`: the `json_args` object, if
+    /// any, with each `key=value` pair set on top. A value is a string unless
+    /// the tool's `input_schema` gives its key another type and it parses as
+    /// JSON (`max_depth=2`), so `content=42` stays text; `key:=json` always
+    /// sends the value as JSON.
+    pub fn build_call_args(pairs: &[String], json_args: Option<&str>, input_schema: &Value) -> Result<Value> {
+        let mut args = match json_args {
+            Some(raw) => serde_json::from_str(raw).context("--json-args is not valid JSON")?,
+            None => json!({}),
+        };
+        let Some(map) = args.as_object_mut() else {
+            anyhow::bail!("--json-args must be a JSON object");
+        };
+        for pair in pairs {
+            let Some((key, value)) = pair.split_once('=') else {
+                anyhow::bail!("Expected key=value, got '{}'", pair);
+            };
+            if let Some(key) = key.strip_suffix(':') {
+                let value = serde_json::from_str(value).with_context(|| format!("{}:= needs a JSON value", key))?;
+                map.insert(key.to_string(), value);
+                continue;
+            }
+            let typed = input_schema["properties"][key]["type"].as_str().is_some_and(|t| t != "string");
+            let value = match serde_json::from_str(value) {
+                Ok(json) if typed => json,
+                _ => Value::String(value.to_string()),
+            };
+            map.insert(key.to_string(), value);
+        }
+        Ok(args)
+    }
+
+    /// Send `tools/call` for `tool` with `arguments` to the server at `url`
+    /// and return the whole JSON-RPC response
+    pub async fn remote_call_tool(url: &str, token: Option<&str>, tool: &str, arguments: Value) -> Result<Value> {
+        let params = json!({"name": tool, "arguments": arguments});
+        let resp = client_call(&reqwest::Client::new(), url, token, "tools/call", Some(params))
+            .await
+            .with_context(|| format!("Cannot reach {}", url))?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            anyhow::bail!("Unauthorized: check --token or MCP_TOKEN");
+        }
+        resp.json().await.context("Invalid tools/call response")
+    }
+
+    /// Print the response of calling `tool`, failing when the server or the
+    /// tool reports an error
+    pub async fn call(
+        url: &str,
+        token: Option<String>,
+        tool: &str,
+        pairs: &[String],
+        json_args: Option<&str>,
+    ) -> Result<()> {
+        // Only the schema can tell `content=42` (text) from `max_depth=2`
+        let input_schema = if pairs.is_empty() {
+            Value::Null
+        } else {
+            let tools = fetch_tools(url, token.as_deref()).await?;
+            tools.into_iter().find(|t| t["name"] == tool).map_or(Value::Null, |t| t["inputSchema"].clone())
+        };
+        let arguments = build_call_args(pairs, json_args, &input_schema)?;
+        let resp = remote_call_tool(url, token.as_deref(), tool, arguments).await?;
+        println!("{}", serde_json::to_string_pretty(&resp)?);
+        if let Some(message) = resp["error"]["message"].as_str() {
+            anyhow::bail!("{}", message);
+        }
+        if resp["result"]["isError"] == json!(true) {
+            anyhow::bail!("Tool '{}' failed", tool);
+        }
         Ok(())
     }
+
+    /// Print the `check_status` of `url` as text or JSON (`format`), failing
+    /// when the server is unreachable, rejects the token or answers oddly
+    pub async fn status(url: &str, token: Option<String>, format: &str) -> Result<()> {
+        let status = check_status(url, token.as_deref()).await;
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&status)?);
+        } else if status.ok() {
+            println!(
+                "✓ Server ready: {} {} at {}",
+                status.server_name.as_deref().unwrap_or("?"),
+                status.version.as_deref().unwrap_or("?"),
+                url
+            );
+            println!("  Protocol: {}", status.protocol_version.as_deref().unwrap_or("?"));
+            println!("  Tools:    {}", status.tools.map_or("?".to_string(), |n| n.to_string()));
+            println!("  Auth:     {}", status.auth);
+        }
+        match status.error {
+            Some(error) => anyhow::bail!(error),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn run_with_timeout_fires_for_slow_tool() {
+            let slow = || {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                Ok(json!({"content": []}))
+            };
+            let err = run_with_timeout(Some(json!(9)), "slow_stub", std::time::Duration::from_millis(50), slow)
+                .await
+                .unwrap_err();
+            assert_eq!(err["error"]["code"], TOOL_TIMEOUT_CODE);
+            assert_eq!(err["error"]["data"]["tool"], "slow_stub");
+            assert_eq!(err["id"], 9);
+
+            let fast = || Ok(json!({"content": []}));
+            assert!(run_with_timeout(None, "fast_stub", std::time::Duration::from_secs(5), fast).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn timed_out_call_keeps_its_permit_until_it_finishes() {
+            let limiter = Arc::new(tokio::sync::Semaphore::new(1));
+            let permit = limiter.clone().try_acquire_owned().unwrap();
+            let slow = move || {
+                let _permit = permit;
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                Ok(json!({"content": []}))
+            };
+            let err = run_with_timeout(None, "slow_stub", std::time::Duration::from_millis(20), slow)
+                .await
+                .unwrap_err();
+            assert!(err["error"]["message"].as_str().unwrap().contains("may still complete"));
+            assert_eq!(limiter.available_permits(), 0);
+
+            let _again = tokio::time::timeout(std::time::Duration::from_secs(5), limiter.acquire()).await.unwrap();
+        }
+
+        #[test]
+        fn prune_tree_without_limits_matches_the_serialized_tree() {
+            let parser = crate::parser::get_parser(std::path::Path::new("app.py")).unwrap();
+            let tree = parser.parse("class A:\n    def f(self):\n        return 1\n").unwrap();
+            let mut budget = usize::MAX;
+            let mut truncated = false;
+            let pruned = prune_tree(&tree, 0, None, None, &mut budget, &mut truncated);
+            assert_eq!(pruned, serde_json::to_value(&tree).unwrap());
+            assert!(!truncated);
+
+            let fields = ["path".to_string(), "node_type".to_string()];
+            let pruned = prune_tree(&tree, 0, Some(0), Some(&fields), &mut budget, &mut truncated);
+            assert_eq!(pruned, json!({"path": tree.path, "node_type": tree.node_type, "children_truncated": tree.children.len()}));
+        }
+
+        #[test]
+        fn parse_cache_parses_an_unchanged_file_once() {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::create_dir(dir.path().join(".git")).unwrap();
+            let file = dir.path().join("app.py");
+            std::fs::write(&file, "x = 1\n").unwrap();
+            let fp = file.to_str().unwrap();
+            let parses = |cache: &ParseCache| cache.parses.load(std::sync::atomic::Ordering::Relaxed);
+
+            let cache = ParseCache::default();
+            let first = cache.load(fp, false).unwrap();
+            let second = cache.load(fp, false).unwrap();
+            assert_eq!(parses(&cache), 1);
+            assert!(Arc::ptr_eq(&first, &second));
+
+            std::fs::write(&file, "x = 2\n").unwrap();
+            assert!(cache.load(fp, false).unwrap().get_source().contains("x = 2"));
+            assert_eq!(parses(&cache), 2);
+
+            cache.invalidate(fp);
+            cache.load(fp, false).unwrap();
+            assert_eq!(parses(&cache), 3);
+
+            cache.clear();
+            cache.load(fp, false).unwrap();
+            assert_eq!(parses(&cache), 4);
+        }
+    }
 }
\ No newline at end of file
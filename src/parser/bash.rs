@@ -68,10 +68,11 @@ impl ParserEngineLegacy for BashParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(source_code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, source_code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse Bash code"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), source_code, String::new())
     }
 
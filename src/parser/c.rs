@@ -68,14 +68,54 @@ impl ParserEngineLegacy for CParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(source_code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, source_code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse C code"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), source_code, String::new())
     }
 
     fn get_supported_extensions(&self) -> Vec<&'static str> {
-        vec!["c", "h"]
+        vec!["c"]
+    }
+}
+
+/// `.h` files are shared by C and C++. Parse as C, and switch to the C++
+/// grammar when C can't make sense of the header (classes, namespaces,
+/// templates) but C++ can.
+pub struct HeaderParser;
+
+impl Default for HeaderParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeaderParser {
+    pub fn new() -> Self {
+        Self
     }
-}
\ No newline at end of file
+}
+
+impl ParserEngineLegacy for HeaderParser {
+    fn parse_legacy(&self, source_code: &str) -> anyhow::Result<TreeNode> {
+        fn has_error(node: &TreeNode) -> bool {
+            node.node_type == "ERROR" || node.children.iter().any(has_error)
+        }
+
+        let tree = CParser::new().parse_legacy(source_code)?;
+        if has_error(&tree) {
+            if let Ok(cpp) = crate::parser::cpp::CppParser::new().parse_legacy(source_code) {
+                if !has_error(&cpp) {
+                    return Ok(cpp);
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    fn get_supported_extensions(&self) -> Vec<&'static str> {
+        vec!["h"]
+    }
+}
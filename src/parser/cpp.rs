@@ -68,14 +68,15 @@ impl ParserEngineLegacy for CppParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(source_code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, source_code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse C++ code"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), source_code, String::new())
     }
 
     fn get_supported_extensions(&self) -> Vec<&'static str> {
-        vec!["cpp", "hpp", "cc", "cxx", "hxx", "h++"]
+        vec!["cpp", "hpp", "cc", "hh", "cxx", "hxx", "h++"]
     }
 }
\ No newline at end of file
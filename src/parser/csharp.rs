@@ -26,10 +26,11 @@ impl ParserEngineLegacy for CSharpParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse C#"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), code, "".to_string())
     }
 
@@ -18,7 +18,8 @@ impl GenericParser {
 }
 
 impl ParserEngine for GenericParser {
-    /// Parse the entire file as a single node.
+    /// Parse the entire file as a single node. There is no loop to check
+    /// `parse_cancelled` in: the work is one copy of the source.
     fn parse(&self, code: &str) -> ParseResult<TreeNode> {
         let lines = code.lines().collect::<Vec<&str>>();
         let line_count = lines.len();
@@ -64,10 +64,11 @@ impl ParserEngineLegacy for GoParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(source_code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, source_code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse Go code"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), source_code, String::new())
     }
 
@@ -1,3 +1,5 @@
+use crate::parser::css::CssParser;
+use crate::parser::javascript::JavaScriptParser;
 use crate::parser::{TreeNode, ParserEngineLegacy};
 use tree_sitter::Parser;
 use anyhow::Result;
@@ -26,10 +28,11 @@ impl ParserEngineLegacy for HtmlParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse HTML"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), code, "".to_string())
     }
 
@@ -61,6 +64,12 @@ impl HtmlParser {
             } else {
                 format!("{}.{}", path, i)
             };
+            if child.kind() == "raw_text" {
+                if let Some(embedded) = Self::build_embedded(&node_type, &child, source, &child_path) {
+                    children.push(embedded);
+                    continue;
+                }
+            }
             children.push(Self::build_tree(&child, source, child_path)?);
         }
 
@@ -76,4 +85,124 @@ impl HtmlParser {
             children, 
         })
     }
-}
\ No newline at end of file
+
+    /// Parse the body of a `<script>` or `<style>` element with the matching
+    /// language parser. The raw_text node keeps its content, but its children
+    /// become the embedded nodes, re-pathed under it and shifted to file
+    /// lines, and on the element's first line to file columns.
+    fn build_embedded(
+        element_type: &str,
+        raw: &tree_sitter::Node,
+        source: &str,
+        path: &str,
+    ) -> Option<TreeNode> {
+        let content = source.get(raw.start_byte()..raw.end_byte())?;
+        let embedded = match element_type {
+            "script_element" => JavaScriptParser::new().parse_with_columns(content).ok()?,
+            "style_element" => CssParser::new().parse_legacy(content).ok()?,
+            _ => return None,
+        };
+
+        let line_offset = raw.start_position().row;
+        // Characters before the embedded code on its first line
+        let col_offset = source
+            .get(raw.start_byte() - raw.start_position().column..raw.start_byte())
+            .map_or(0, |prefix| prefix.chars().count());
+        let children = embedded
+            .children
+            .into_iter()
+            .map(|mut child| {
+                Self::reroot(&mut child, path, line_offset, col_offset);
+                child
+            })
+            .collect();
+
+        Some(TreeNode { start_col: 0, end_col: 0,
+            id: path.to_string(),
+            path: path.to_string(),
+            node_type: raw.kind().to_string(),
+            content: content.to_string(),
+            start_line: raw.start_position().row + 1,
+            end_line: raw.end_position().row + 1,
+            children,
+        })
+    }
+
+    fn reroot(node: &mut TreeNode, prefix: &str, line_offset: usize, col_offset: usize) {
+        node.path = if node.path.is_empty() {
+            prefix.to_string()
+        } else {
+            format!("{}.{}", prefix, node.path)
+        };
+        node.id = node.path.clone();
+        // Only the embedded code's first line shares a line with the host;
+        // a column of 0 means the parser did not record one
+        if node.start_line == 1 && node.start_col > 0 {
+            node.start_col += col_offset;
+        }
+        if node.end_line == 1 && node.end_col > 0 {
+            node.end_col += col_offset;
+        }
+        node.start_line += line_offset;
+        node.end_line += line_offset;
+        for child in &mut node.children {
+            Self::reroot(child, prefix, line_offset, col_offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_contents_are_parsed_as_javascript() {
+        let html = "<html>\n<body>\n<script>\nfunction greet() {\n  return 1;\n}\n</script>\n</body>\n</html>\n";
+        let tree = HtmlParser::new().parse_legacy(html).expect("parse should succeed");
+
+        fn find<'a>(node: &'a TreeNode, kind: &str) -> Option<&'a TreeNode> {
+            if node.node_type == kind {
+                return Some(node);
+            }
+            node.children.iter().find_map(|c| find(c, kind))
+        }
+
+        let script = find(&tree, "script_element").expect("script element");
+        let raw = script
+            .children
+            .iter()
+            .find(|c| c.node_type == "raw_text")
+            .expect("raw_text child");
+        let func = raw
+            .children
+            .iter()
+            .find(|c| c.node_type == "function_declaration")
+            .expect("nested function node");
+
+        assert!(func.path.starts_with(&format!("{}.", raw.path)));
+        assert_eq!(func.start_line, 4);
+        assert_eq!(func.end_line, 6);
+        assert!(func.content.starts_with("function greet()"));
+        assert_eq!(tree.find_path(&func.path).map(|n| n.node_type.as_str()), Some("function_declaration"));
+    }
+
+    #[test]
+    fn inline_script_nodes_get_file_columns() {
+        let html = "<div><script>x()</script></div>\n";
+        let tree = HtmlParser::new().parse_legacy(html).expect("parse should succeed");
+
+        fn find<'a>(node: &'a TreeNode, kind: &str) -> Option<&'a TreeNode> {
+            if node.node_type == kind {
+                return Some(node);
+            }
+            node.children.iter().find_map(|c| find(c, kind))
+        }
+
+        let call = find(&tree, "call_expression").expect("embedded call");
+        assert_eq!((call.start_line, call.end_line), (1, 1));
+        assert_eq!((call.start_col, call.end_col), (14, 17));
+        let line = html.lines().next().unwrap();
+        let span: String = line.chars().skip(call.start_col - 1).take(call.end_col - call.start_col).collect();
+        assert_eq!(span, "x()");
+    }
+}
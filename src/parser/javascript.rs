@@ -18,6 +18,23 @@ impl JavaScriptParser {
 
 impl ParserEngineLegacy for JavaScriptParser {
     fn parse_legacy(&self, code: &str) -> anyhow::Result<TreeNode> {
+        Self::parse_source(code, false)
+    }
+
+    fn get_supported_extensions(&self) -> Vec<&'static str> {
+        vec!["js", "jsx", "mjs", "cjs"]
+    }
+}
+
+impl JavaScriptParser {
+    /// Like `parse_legacy`, but every node also gets its start and end
+    /// column (1-based, in characters), for a script that shares its lines
+    /// with HTML
+    pub(crate) fn parse_with_columns(&self, code: &str) -> Result<TreeNode> {
+        Self::parse_source(code, true)
+    }
+
+    fn parse_source(code: &str, columns: bool) -> Result<TreeNode> {
         let mut parser = Parser::new();
         let language = unsafe {
             std::mem::transmute::<tree_sitter_language::LanguageFn, fn() -> tree_sitter::Language>(
@@ -26,20 +43,15 @@ impl ParserEngineLegacy for JavaScriptParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse JavaScript"))?;
 
-        Self::build_tree(&tree.root_node(), code, "".to_string())
-    }
+        crate::parser::check_syntax_depth(tree.root_node())?;
 
-    fn get_supported_extensions(&self) -> Vec<&'static str> {
-        vec!["js", "jsx", "mjs", "cjs"]
+        Self::build_tree(&tree.root_node(), code, "".to_string(), columns)
     }
-}
 
-impl JavaScriptParser {
-    fn build_tree(node: &tree_sitter::Node, source: &str, path: String) -> Result<TreeNode> {
+    fn build_tree(node: &tree_sitter::Node, source: &str, path: String, columns: bool) -> Result<TreeNode> {
         let start_byte = node.start_byte();
         let end_byte = node.end_byte();
         let content = if let Some(s) = source.get(start_byte..end_byte) {
@@ -51,6 +63,14 @@ impl JavaScriptParser {
         let node_type = node.kind().to_string();
         let start_line = node.start_position().row + 1;
         let end_line = node.end_position().row + 1;
+        let (start_col, end_col) = if columns {
+            (
+                char_column(source, node.start_byte(), node.start_position().column),
+                char_column(source, node.end_byte(), node.end_position().column),
+            )
+        } else {
+            (0, 0)
+        };
 
         let mut children = Vec::new();
         let mut cursor = node.walk();
@@ -61,14 +81,14 @@ impl JavaScriptParser {
             } else {
                 format!("{}.{}", path, i)
             };
-            children.push(Self::build_tree(&child, source, child_path)?);
+            children.push(Self::build_tree(&child, source, child_path, columns)?);
         }
 
         let id = path.clone();
 
         Ok(TreeNode {
-            start_col: 0,
-            end_col: 0,
+            start_col,
+            end_col,
             id,
             path,
             node_type,
@@ -79,3 +99,9 @@ impl JavaScriptParser {
         })
     }
 }
+
+/// 1-based character column of the byte offset `byte`, which is
+/// `byte_column` bytes into its line
+fn char_column(source: &str, byte: usize, byte_column: usize) -> usize {
+    source.get(byte - byte_column..byte).map_or(byte_column, |prefix| prefix.chars().count()) + 1
+}
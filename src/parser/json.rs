@@ -1,4 +1,4 @@
-use crate::parser::{TreeNode, ParserEngineLegacy};
+use crate::parser::{parse_cancelled, TreeNode, ParserEngineLegacy};
 use anyhow::Result;
 use serde_json::Value;
 
@@ -26,6 +26,10 @@ impl ParserEngineLegacy for JsonParser {
         Ok(root)
     }
 
+    fn recovers_from_errors(&self) -> bool {
+        false
+    }
+
     fn get_supported_extensions(&self) -> Vec<&'static str> {
         vec!["json"]
     }
@@ -40,6 +44,11 @@ impl JsonParser {
         start_line: usize,
         end_line: usize,
     ) -> Result<TreeNode> {
+        // The caller has given up on this parse. The deserializer before this
+        // cannot be interrupted, but it is linear in the input.
+        if parse_cancelled() {
+            anyhow::bail!("parse cancelled");
+        }
         let (node_type, children) = match value {
             Value::String(_) => ("string".to_string(), vec![]),
             Value::Number(_) => ("number".to_string(), vec![]),
@@ -0,0 +1,274 @@
+//! Language-independent node kinds.
+//!
+//! Each grammar has its own `node_type` names (`function_item` in Rust,
+//! `function_definition` in Python, `method_declaration` in Java...).
+//! `NodeKind` maps them onto a handful of kinds that mean the same thing in
+//! every language, so filters don't need to know the raw names.
+
+use serde::Serialize;
+
+use crate::parser::TreeNode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Function,
+    Class,
+    Method,
+    Import,
+    Comment,
+    Property,
+}
+
+impl NodeKind {
+    pub const ALL: [NodeKind; 6] = [
+        NodeKind::Function,
+        NodeKind::Class,
+        NodeKind::Method,
+        NodeKind::Import,
+        NodeKind::Comment,
+        NodeKind::Property,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NodeKind::Function => "function",
+            NodeKind::Class => "class",
+            NodeKind::Method => "method",
+            NodeKind::Import => "import",
+            NodeKind::Comment => "comment",
+            NodeKind::Property => "property",
+        }
+    }
+
+    /// The kind for a raw `node_type`. Some names (`class`, `method`,
+    /// `import`...) are also keyword tokens, which have no children; those
+    /// only count when `has_children` is set.
+    pub fn from_node_type(node_type: &str, has_children: bool) -> Option<NodeKind> {
+        let kind = match node_type {
+            "function_definition" | "function_item" | "function_declaration" | "function_signature_item"
+            | "generator_function_declaration" | "function_expression" | "arrow_function" | "func_literal"
+            | "function_signature" => NodeKind::Function,
+            // JavaScript function expressions in older grammars
+            "function" if has_children => NodeKind::Function,
+
+            "method_definition" | "method_declaration" | "constructor_declaration" | "method_signature"
+            | "singleton_method" => NodeKind::Method,
+            // Ruby
+            "method" if has_children => NodeKind::Method,
+
+            "class_definition" | "class_declaration" | "class_specifier" | "struct_specifier" | "union_specifier"
+            | "enum_specifier" | "struct_item" | "enum_item" | "union_item" | "trait_item" | "interface_declaration"
+            | "enum_declaration" | "struct_declaration" | "protocol_declaration" | "object_declaration" => {
+                NodeKind::Class
+            }
+            // Ruby
+            "class" if has_children => NodeKind::Class,
+
+            "import_statement" | "import_from_statement" | "import_declaration" | "use_declaration"
+            | "extern_crate_declaration" | "preproc_include" | "using_directive" | "namespace_use_declaration"
+            | "import_or_export" | "ui_import" => NodeKind::Import,
+            // Kotlin
+            "import" if has_children => NodeKind::Import,
+
+            "comment" | "line_comment" | "block_comment" => NodeKind::Comment,
+
+            "field_declaration" | "field_definition" | "public_field_definition" | "property_declaration"
+            | "property_signature" | "ui_property" | "ui_binding" | "ui_id" => NodeKind::Property,
+
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+impl std::str::FromStr for NodeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NodeKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = NodeKind::ALL.iter().map(|k| k.as_str()).collect();
+                format!("unknown kind '{}': expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+impl std::fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TreeNode {
+    /// The language-independent kind of this node, if it is one of the
+    /// `NodeKind`s. This looks at the node alone: a Python or Rust function
+    /// inside a class is a `Function` here, see `kinds` for the tree-aware
+    /// version.
+    pub fn kind(&self) -> Option<NodeKind> {
+        // Go: `type Point struct {...}`
+        if self.node_type == "type_spec"
+            && self.children.iter().any(|c| matches!(c.node_type.as_str(), "struct_type" | "interface_type"))
+        {
+            return Some(NodeKind::Class);
+        }
+        // Ruby `module Name`, but not the root `module` of a Python file
+        if self.node_type == "module" {
+            return self.children.iter().any(|c| c.node_type == "constant").then_some(NodeKind::Class);
+        }
+        NodeKind::from_node_type(&self.node_type, !self.children.is_empty())
+    }
+
+    /// Every node below (and including) this one that has a kind, in source
+    /// order. Functions whose nearest enclosing definition is a class (or a
+    /// Rust `impl` block) are reported as methods.
+    pub fn kinds(&self) -> Vec<(&TreeNode, NodeKind)> {
+        let mut out = Vec::new();
+        walk_kinds(self, false, 0, &mut |node, kind, _| {
+            if let Some(kind) = kind {
+                out.push((node, kind));
+            }
+        });
+        out
+    }
+
+    /// The parts this node is made of, for reading them one by one: its
+    /// direct children, leaving out keyword and punctuation tokens. A child
+    /// that only wraps definitions (a class's `block` or `class_body`, an
+    /// `impl`'s `declaration_list`) is replaced by what it holds, so the
+    /// members of a class are its methods and fields.
+    pub fn members(&self) -> Vec<&TreeNode> {
+        let is_body = |c: &TreeNode| c.kind().is_none() && c.children.iter().any(|g| g.kind().is_some());
+        self.children
+            .iter()
+            .flat_map(|c| if is_body(c) { c.children.iter().collect() } else { vec![c] })
+            .filter(|c| !is_anonymous_token(c))
+            .collect()
+    }
+
+    /// Paths of the nodes `kinds` reports as `kind`
+    pub fn paths_of_kind(&self, kind: NodeKind) -> std::collections::HashSet<&str> {
+        self.kinds()
+            .into_iter()
+            .filter(|(_, k)| *k == kind)
+            .map(|(node, _)| node.path.as_str())
+            .collect()
+    }
+}
+
+/// Keywords and punctuation: tree-sitter names these anonymous tokens after
+/// their own text (`def`, `:`), where named leaves have a type of their own
+fn is_anonymous_token(node: &TreeNode) -> bool {
+    node.children.is_empty() && node.node_type == node.content
+}
+
+/// Rust `impl` blocks hold methods but are not classes themselves
+pub(crate) fn is_impl_block(node: &TreeNode) -> bool {
+    node.node_type == "impl_item"
+}
+
+/// Depth-first walk behind `TreeNode::kinds`. `visit` gets each node with a
+/// kind, and each `impl` block (with `None`), along with the number of such
+/// nodes enclosing it.
+pub(crate) fn walk_kinds<'a>(
+    node: &'a TreeNode,
+    in_class: bool,
+    depth: usize,
+    visit: &mut dyn FnMut(&'a TreeNode, Option<NodeKind>, usize),
+) {
+    let kind = match node.kind() {
+        Some(NodeKind::Function) if in_class => Some(NodeKind::Method),
+        kind => kind,
+    };
+    if kind.is_some() || is_impl_block(node) {
+        visit(node, kind, depth);
+    }
+    let (in_class, depth) = match kind {
+        Some(NodeKind::Class) => (true, depth + 1),
+        Some(NodeKind::Function | NodeKind::Method) => (false, depth + 1),
+        _ if is_impl_block(node) => (true, depth + 1),
+        _ => (in_class, depth),
+    };
+    for child in &node.children {
+        walk_kinds(child, in_class, depth, visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::get_parser;
+    use std::path::Path;
+
+    fn kinds_by_name(file: &str, source: &str) -> Vec<(String, NodeKind)> {
+        let tree = get_parser(Path::new(file)).unwrap().parse(source).unwrap();
+        tree.kinds()
+            .into_iter()
+            .map(|(node, kind)| (node.get_name().unwrap_or_default(), kind))
+            .collect()
+    }
+
+    #[test]
+    fn python_and_rust_functions_are_both_functions() {
+        let python = get_parser(Path::new("app.py")).unwrap().parse("def greet():\n    pass\n").unwrap();
+        assert_eq!(python.children[0].node_type, "function_definition");
+        assert_eq!(python.children[0].kind(), Some(NodeKind::Function));
+
+        let rust = get_parser(Path::new("lib.rs")).unwrap().parse("fn greet() {}\n").unwrap();
+        assert_eq!(rust.children[0].node_type, "function_item");
+        assert_eq!(rust.children[0].kind(), Some(NodeKind::Function));
+    }
+
+    #[test]
+    fn functions_in_classes_are_methods() {
+        let python = kinds_by_name("app.py", "import os\n# note\nclass A:\n    def m(self):\n        def inner():\n            pass\n\ndef f():\n    pass\n");
+        assert_eq!(
+            python,
+            [
+                ("os".to_string(), NodeKind::Import),
+                (String::new(), NodeKind::Comment),
+                ("A".to_string(), NodeKind::Class),
+                ("m".to_string(), NodeKind::Method),
+                ("inner".to_string(), NodeKind::Function),
+                ("f".to_string(), NodeKind::Function),
+            ]
+        );
+
+        let java = kinds_by_name("A.java", "class A {\n    int x;\n    void m() {}\n}\n");
+        assert_eq!(
+            java,
+            [
+                ("A".to_string(), NodeKind::Class),
+                ("x".to_string(), NodeKind::Property),
+                ("m".to_string(), NodeKind::Method),
+            ]
+        );
+
+        // Keyword tokens named like a kind are not nodes of that kind
+        let ruby = kinds_by_name("a.rb", "class A\n  def m\n  end\nend\n");
+        assert_eq!(ruby, [("A".to_string(), NodeKind::Class), ("m".to_string(), NodeKind::Method)]);
+    }
+
+    #[test]
+    fn class_members_are_its_methods_not_its_tokens() {
+        let tree = get_parser(Path::new("app.py"))
+            .unwrap()
+            .parse("class A:\n    x = 1\n\n    def m(self):\n        pass\n\n    def n(self):\n        pass\n")
+            .unwrap();
+        let members: Vec<&str> = tree.children[0].members().iter().map(|m| m.node_type.as_str()).collect();
+        assert_eq!(members, ["identifier", "expression_statement", "function_definition", "function_definition"]);
+
+        // Named leaves such as comments are members too
+        let tree = get_parser(Path::new("app.rs")).unwrap().parse("struct S {\n    // note\n    a: u8,\n}\n").unwrap();
+        let members: Vec<&str> = tree.children[0].members().iter().map(|m| m.node_type.as_str()).collect();
+        assert_eq!(members, ["type_identifier", "line_comment", "field_declaration"]);
+    }
+
+    #[test]
+    fn kind_names_parse_case_insensitively() {
+        assert_eq!("Function".parse::<NodeKind>(), Ok(NodeKind::Function));
+        assert!("struct".parse::<NodeKind>().unwrap_err().contains("function, class"));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::parser::{TreeNode, ParserEngineLegacy};
+use crate::parser::{parse_cancelled, TreeNode, ParserEngineLegacy};
 use anyhow::Result;
 use regex::Regex;
 
@@ -41,6 +41,10 @@ impl MarkdownParser {
         let hr_regex = Regex::new(r"^[-*_]{3,}\s*$").unwrap();
 
         while i < lines.len() {
+            // The caller has given up on this parse; what is returned is dropped
+            if parse_cancelled() {
+                break;
+            }
             let line = lines[i];
 
             // Skip empty lines
@@ -277,7 +281,7 @@ impl MarkdownParser {
         let mut remaining = text;
         let _pos = 0;
 
-        while !remaining.is_empty() {
+        while !remaining.is_empty() && !parse_cancelled() {
             let mut found = false;
             let start_pos = 0;
 
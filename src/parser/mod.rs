@@ -10,12 +10,15 @@ pub mod html;
 pub mod java;
 pub mod javascript;
 pub mod json;
+pub mod kind;
 pub mod kotlin;
 pub mod markdown;
+pub mod outline;
 pub mod php;
 pub mod python;
 pub mod qml;
 pub mod qml_tree_sitter;
+pub mod ruby;
 pub mod rust;
 pub mod slint;
 pub mod sql;
@@ -28,10 +31,21 @@ pub mod xml;
 pub mod yaml;
 pub mod error;
 pub use error::{SyntaxError, ParseResult};
+pub use kind::NodeKind;
 pub mod zig;
 
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::cell::RefCell;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Trees nested deeper than this are rejected by `get_parser`'s parsers, so
+/// code that walks a `TreeNode` recursively cannot run out of stack
+pub const MAX_TREE_DEPTH: usize = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TreeNode {
@@ -48,33 +62,277 @@ pub struct TreeNode {
     pub children: Vec<TreeNode>,
 }
 
+/// 64-bit FNV-1a over explicitly encoded bytes, for `assign_stable_ids`.
+/// Unlike `DefaultHasher` (and `Hash`, which writes `usize` in native
+/// width and byte order), the result is fixed across Rust releases and
+/// platforms, so stored ids stay valid.
+#[derive(Debug, Clone, Copy)]
+struct IdHasher(u64);
+
+impl Default for IdHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl IdHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// Type and name, each terminated, so ("ab", None) and ("a", Some("b"))
+    /// do not collide
+    fn write_key(&mut self, (node_type, name): &(String, Option<String>)) {
+        self.write(node_type.as_bytes());
+        self.write(&[0xff]);
+        match name {
+            Some(name) => {
+                self.write(&[1]);
+                self.write(name.as_bytes());
+                self.write(&[0xff]);
+            }
+            None => self.write(&[0]),
+        }
+    }
+}
+
 impl TreeNode {
-    /// Recursively find a node by its path string.
-    pub fn find_path(&self, target_path: &str) -> Option<&TreeNode> {
-        if self.path == target_path {
-            return Some(self);
+    /// JSON schema (draft-07) for a serialized `TreeNode`, as printed by
+    /// `analyze --schema`. `children` refers back to the root, so the schema
+    /// covers the whole tree.
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "TreeNode",
+            "description": "A node in the syntax tree returned by `gnawtreewriter analyze`",
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "description": "Hash of the node's type and name and those of its ancestors; unlike path, it survives edits to unrelated siblings" },
+                "path": { "type": "string", "description": "Dot-separated child indices from the root, e.g. \"0.2.1\"" },
+                "node_type": { "type": "string", "description": "Parser-specific node kind, e.g. function_definition" },
+                "content": { "type": "string", "description": "Source text covered by the node" },
+                "start_line": { "type": "integer", "minimum": 1, "description": "First line, 1-based" },
+                "end_line": { "type": "integer", "minimum": 1, "description": "Last line, 1-based, inclusive" },
+                "start_col": { "type": "integer", "minimum": 0 },
+                "end_col": { "type": "integer", "minimum": 0 },
+                "children": { "type": "array", "items": { "$ref": "#" } }
+            },
+            "required": ["id", "path", "node_type", "content", "start_line", "end_line", "children"]
+        })
+    }
+
+    /// Number of nodes below this one, not counting itself
+    pub fn descendant_count(&self) -> usize {
+        self.children.iter().map(|c| 1 + c.descendant_count()).sum()
+    }
+
+    /// Levels of nesting below this node; a leaf has depth 0
+    pub fn max_depth(&self) -> usize {
+        self.children.iter().map(|c| 1 + c.max_depth()).max().unwrap_or(0)
+    }
+
+    /// This node and all its descendants, depth-first in source order
+    pub fn iter(&self) -> TreeIter<'_> {
+        TreeIter { stack: vec![self] }
+    }
+
+    /// Like `iter`, but `visit` also gets each node's depth below `self`
+    /// and returns whether to descend into its children. Uses a heap stack,
+    /// so any depth is safe.
+    pub fn walk<'a>(&'a self, mut visit: impl FnMut(&'a TreeNode, usize) -> bool) {
+        let mut stack = vec![(self, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            if visit(node, depth) {
+                stack.extend(node.children.iter().rev().map(|c| (c, depth + 1)));
+            }
         }
-        for child in &self.children {
-            if let Some(found) = child.find_path(target_path) {
-                return Some(found);
+    }
+
+    /// Give every node below (and including) this one a stable `id`: a hash
+    /// of its `node_type`, its name if it is a definition (one with a
+    /// `kind`), its position among siblings with the same type and name, and
+    /// the same for each ancestor. Inserting or removing an unrelated sibling
+    /// shifts `path` but leaves `id` alone.
+    pub fn assign_stable_ids(&mut self) {
+        let mut hasher = IdHasher::default();
+        hasher.write_key(&self.id_key());
+        self.assign_ids_from(hasher);
+    }
+
+    /// What identifies a node among its siblings for `assign_stable_ids`.
+    /// Other nodes' `get_name` can come from whatever child happens to be
+    /// first, so only definitions contribute a name.
+    fn id_key(&self) -> (String, Option<String>) {
+        let name = self.kind().and_then(|_| self.get_name());
+        (self.node_type.clone(), name)
+    }
+
+    fn assign_ids_from(&mut self, hasher: IdHasher) {
+        self.id = format!("{:016x}", hasher.0);
+        let mut seen: std::collections::HashMap<(String, Option<String>), usize> = std::collections::HashMap::new();
+        for child in &mut self.children {
+            let key = child.id_key();
+            let mut child_hasher = hasher;
+            child_hasher.write_key(&key);
+            let occurrence = seen.entry(key).or_default();
+            child_hasher.write(&(*occurrence as u64).to_le_bytes());
+            *occurrence += 1;
+            child.assign_ids_from(child_hasher);
+        }
+    }
+
+    /// Rename tree-sitter's `ERROR` nodes below (and including) this one to
+    /// `error`, the node type partial trees use for unparsable code
+    pub fn mark_error_nodes(&mut self) {
+        if self.node_type == "ERROR" {
+            self.node_type = "error".to_string();
+        }
+        for child in &mut self.children {
+            child.mark_error_nodes();
+        }
+    }
+
+    /// Fails on the first node nested more than `MAX_TREE_DEPTH` levels
+    /// below `self`
+    pub fn check_depth(&self) -> ParseResult<()> {
+        let mut too_deep = None;
+        self.walk(|node, depth| {
+            if too_deep.is_some() {
+                return false;
             }
+            if depth > MAX_TREE_DEPTH {
+                too_deep = Some(node);
+                return false;
+            }
+            true
+        });
+        match too_deep {
+            Some(node) => Err(SyntaxError {
+                message: format!("syntax tree is nested more than {} levels deep", MAX_TREE_DEPTH),
+                line: node.start_line,
+                column: node.start_col,
+                expected: None,
+            }),
+            None => Ok(()),
         }
-        None
+    }
+
+    /// Find a node by its path string.
+    pub fn find_path(&self, target_path: &str) -> Option<&TreeNode> {
+        self.iter().find(|n| n.path == target_path)
+    }
+
+    /// Up to `limit` existing paths that look like `query`, for "did you
+    /// mean" hints when it does not resolve. Numeric paths are compared by
+    /// edit distance, preferring paths that share a longer prefix (so the
+    /// siblings of a stale `0.9` come first). For `@name` and `@kind:name`
+    /// queries the name is compared against node names instead.
+    pub fn similar_paths(&self, query: &str, limit: usize) -> Vec<String> {
+        let (prefix, wanted) = match query.strip_prefix('@') {
+            Some(rest) => match rest.split_once(':') {
+                Some((kind, name)) => (format!("@{}:", kind), name),
+                None => ("@".to_string(), rest),
+            },
+            None => (String::new(), query),
+        };
+        let mut candidates: Vec<String> = if prefix.is_empty() {
+            self.iter().filter(|n| !n.path.is_empty()).map(|n| n.path.clone()).collect()
+        } else {
+            self.iter().filter_map(|n| n.get_name()).collect()
+        };
+        let mut seen = std::collections::HashSet::new();
+        candidates.retain(|c| seen.insert(c.clone()));
+
+        let max_distance = wanted.chars().count() / 2 + 1;
+        let mut scored: Vec<(usize, std::cmp::Reverse<usize>, usize, String)> = candidates
+            .into_iter()
+            .enumerate()
+            .filter_map(|(order, candidate)| {
+                let distance = edit_distance(wanted, &candidate);
+                let shared = wanted.chars().zip(candidate.chars()).take_while(|(a, b)| a == b).count();
+                (distance > 0 && distance <= max_distance).then_some((distance, std::cmp::Reverse(shared), order, candidate))
+            })
+            .collect();
+        scored.sort();
+        scored.into_iter().take(limit).map(|(.., candidate)| format!("{}{}", prefix, candidate)).collect()
     }
 
     /// Attempts to extract a descriptive name for this node (e.g., function name, class name).
     /// It looks for common identifier-like children.
     pub fn get_name(&self) -> Option<String> {
+        // `constant` is how Ruby names classes and modules
+        let is_name = |t: &str| {
+            matches!(t, "identifier" | "name" | "type_identifier" | "field_identifier" | "constant")
+        };
         let nt = self.node_type.to_lowercase();
         // If the node itself is an identifier, return its content
-        if nt == "identifier" || nt == "name" || nt == "type_identifier" || nt == "field_identifier" {
+        if is_name(&nt) {
             return Some(self.content.clone());
         }
+
+        // QML: an object is named by its `id:`, falling back to its type
+        if nt == "ui_object_definition" {
+            let id = self
+                .children
+                .iter()
+                .filter(|c| c.node_type == "ui_object_initializer")
+                .flat_map(|init| &init.children)
+                .find(|c| c.node_type == "ui_id")
+                .and_then(|binding| binding.children.last())
+                .and_then(|value| value.get_name());
+            if id.is_some() {
+                return id;
+            }
+        }
+
+        // QML: an import is named by its module (or directory, for a quoted
+        // path), not by its `as` alias
+        if nt == "ui_import" {
+            if let Some(source) = self.children.get(1) {
+                let module = match source.node_type.as_str() {
+                    "string" => source.content.trim_matches(|c| c == '"' || c == '\''),
+                    _ => &source.content,
+                };
+                return Some(module.to_string());
+            }
+        }
+
+        // Shell: `name() {}` and `function name {}` name the function with a
+        // plain `word`; commands and assignments have their own name nodes
+        let shell_name = match nt.as_str() {
+            "function_definition" => Some("word"),
+            "command" => Some("command_name"),
+            "variable_assignment" => Some("variable_name"),
+            _ => None,
+        };
+        if let Some(kind) = shell_name {
+            if let Some(child) = self.children.iter().find(|c| c.node_type == kind) {
+                return Some(child.content.clone());
+            }
+        }
+
+        // JavaScript/TypeScript class members are named by a `property_identifier`
+        if matches!(
+            nt.as_str(),
+            "method_definition" | "method_signature" | "abstract_method_signature" | "public_field_definition"
+                | "field_definition" | "property_signature"
+        ) {
+            if let Some(child) = self
+                .children
+                .iter()
+                .find(|c| matches!(c.node_type.as_str(), "property_identifier" | "private_property_identifier"))
+            {
+                return Some(child.content.clone());
+            }
+        }
         
         // Look for identifiers in immediate children
         for child in &self.children {
             let cnt = child.node_type.to_lowercase();
-            if cnt == "identifier" || cnt == "name" || cnt == "type_identifier" || cnt == "field_identifier" {
+            if is_name(&cnt) {
                 return Some(child.content.clone());
             }
         }
@@ -83,7 +341,7 @@ impl TreeNode {
         for child in &self.children {
             for subchild in &child.children {
                 let scnt = subchild.node_type.to_lowercase();
-                if scnt == "identifier" || scnt == "name" || scnt == "type_identifier" || scnt == "field_identifier" {
+                if is_name(&scnt) {
                     return Some(subchild.content.clone());
                 }
             }
@@ -94,17 +352,17 @@ impl TreeNode {
     /// Find the deepest node that contains the given 1-based line number.
     /// Returns the most specific (deepest) node whose start_line..=end_line range includes `line`.
     pub fn find_node_at_line(&self, line: usize) -> Option<&TreeNode> {
-        if line >= self.start_line && line <= self.end_line {
-            // Recurse into children first — prefer deeper (more specific) nodes
-            for child in &self.children {
-                if let Some(found) = child.find_node_at_line(line) {
-                    return Some(found);
-                }
-            }
-            Some(self)
-        } else {
-            None
+        let contains = |n: &TreeNode| line >= n.start_line && line <= n.end_line;
+        if !contains(self) {
+            return None;
         }
+        // Descend into the first child containing the line — prefer deeper
+        // (more specific) nodes
+        let mut node = self;
+        while let Some(child) = node.children.iter().find(|c| contains(c)) {
+            node = child;
+        }
+        Some(node)
     }
 
     /// Find the best parent node for inserting at a given 1-based line number.
@@ -164,8 +422,52 @@ impl TreeNode {
     }
 }
 
+/// Levenshtein distance between two strings, counted in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Depth-first iterator returned by `TreeNode::iter`
+pub struct TreeIter<'a> {
+    stack: Vec<&'a TreeNode>,
+}
+
+impl<'a> Iterator for TreeIter<'a> {
+    type Item = &'a TreeNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
 pub trait ParserEngine {
     fn parse(&self, code: &str) -> ParseResult<TreeNode>;
+    /// Best-effort parse that keeps going past syntax errors, for reading a
+    /// file that is being fixed. Parsers that cannot recover fail as `parse`
+    /// does.
+    fn parse_tolerant(&self, code: &str) -> ParseResult<TreeNode> {
+        self.parse(code)
+    }
+    /// Whether `parse_tolerant` gives a tree for code with syntax errors.
+    /// Python, Rust and Slint reject errors in `parse` and recover in
+    /// `parse_tolerant`; the other tree-sitter grammars never reject them.
+    /// JSON, TOML and YAML cannot recover.
+    fn recovers_from_errors(&self) -> bool {
+        true
+    }
     fn get_supported_extensions(&self) -> Vec<&'static str>;
 }
 
@@ -188,6 +490,9 @@ impl<P: ParserEngineLegacy> ParserEngine for LegacyParserWrapper<P> {
     fn parse(&self, code: &str) -> ParseResult<TreeNode> {
         to_parse_result(self.inner.parse_legacy(code))
     }
+    fn recovers_from_errors(&self) -> bool {
+        self.inner.recovers_from_errors()
+    }
     fn get_supported_extensions(&self) -> Vec<&'static str> {
         self.inner.get_supported_extensions()
     }
@@ -195,9 +500,233 @@ impl<P: ParserEngineLegacy> ParserEngine for LegacyParserWrapper<P> {
 
 pub trait ParserEngineLegacy {
     fn parse_legacy(&self, code: &str) -> anyhow::Result<TreeNode>;
+    /// See `ParserEngine::recovers_from_errors`
+    fn recovers_from_errors(&self) -> bool {
+        true
+    }
     fn get_supported_extensions(&self) -> Vec<&'static str>;
 }
+
+/// Language names accepted by `--lang`, with the extension whose parser
+/// they select
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("python", "py"),
+    ("rust", "rs"),
+    ("javascript", "js"),
+    ("typescript", "ts"),
+    ("go", "go"),
+    ("ruby", "rb"),
+    ("c", "c"),
+    ("cpp", "cpp"),
+    ("c++", "cpp"),
+    ("bash", "sh"),
+    ("shell", "sh"),
+    ("java", "java"),
+    ("kotlin", "kt"),
+    ("swift", "swift"),
+    ("csharp", "cs"),
+    ("dart", "dart"),
+    ("php", "php"),
+    ("html", "html"),
+    ("css", "css"),
+    ("xml", "xml"),
+    ("markdown", "md"),
+    ("toml", "toml"),
+    ("json", "json"),
+    ("yaml", "yaml"),
+    ("qml", "qml"),
+    ("slint", "slint"),
+    ("svelte", "svelte"),
+    ("sql", "sql"),
+    ("zig", "zig"),
+    ("text", "txt"),
+];
+
+/// The file extension for a language name (`rust`) or an extension (`rs`),
+/// for building a path that `get_parser` will recognize. Anything else is an
+/// error listing the names.
+pub fn extension_for_language(lang: &str) -> anyhow::Result<String> {
+    let lang = lang.trim().trim_start_matches('.').to_ascii_lowercase();
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(name, ext)| *name == lang || *ext == lang)
+        .map(|(_, ext)| ext.to_string())
+        .ok_or_else(|| {
+            let names: Vec<&str> = LANGUAGE_NAMES.iter().map(|(name, _)| *name).collect();
+            anyhow::anyhow!("Unknown language '{}'; use one of: {}", lang, names.join(", "))
+        })
+}
+
+/// The `node_type` for a tree-sitter node: its kind, or `error` for the
+/// nodes tree-sitter adds when it recovers from a syntax error (`ERROR`, and
+/// tokens it had to assume were there)
+pub(crate) fn node_type_of(node: &tree_sitter::Node) -> String {
+    if node.is_error() || node.is_missing() {
+        "error".to_string()
+    } else {
+        node.kind().to_string()
+    }
+}
+
+/// Fails if `root`'s syntax tree is nested more than `MAX_TREE_DEPTH`
+/// levels deep. Tree-sitter parsers call this before converting the tree,
+/// since their conversion recurses once per level.
+pub(crate) fn check_syntax_depth(root: tree_sitter::Node) -> ParseResult<()> {
+    let mut cursor = root.walk();
+    let mut depth = 0;
+    loop {
+        if depth > MAX_TREE_DEPTH {
+            let pos = cursor.node().start_position();
+            return Err(SyntaxError {
+                message: format!("syntax tree is nested more than {} levels deep", MAX_TREE_DEPTH),
+                line: pos.row + 1,
+                column: pos.column + 1,
+                expected: None,
+            });
+        }
+        if cursor.goto_first_child() {
+            depth += 1;
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if depth == 0 || !cursor.goto_parent() {
+                return Ok(());
+            }
+            depth -= 1;
+        }
+    }
+}
+
+thread_local! {
+    /// Set on the thread a budgeted parse runs on, and raised once the
+    /// caller has stopped waiting for it
+    static PARSE_CANCEL: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Whether the parse running on this thread has run out of time. Parsers
+/// that loop or recurse over their input check it and give up early, so an
+/// abandoned parse stops instead of running on in the background.
+pub(crate) fn parse_cancelled() -> bool {
+    PARSE_CANCEL.with(|cancel| cancel.borrow().as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)))
+}
+
+/// `parser.parse(code, None)`, stopped as soon as `parse_cancelled` is set
+pub(crate) fn parse_cancellable(parser: &mut tree_sitter::Parser, code: &str) -> Option<tree_sitter::Tree> {
+    let bytes = code.as_bytes();
+    let mut progress = |_: &tree_sitter::ParseState| {
+        if parse_cancelled() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    };
+    parser.parse_with_options(
+        &mut |i, _| bytes.get(i..).unwrap_or_default(),
+        None,
+        Some(tree_sitter::ParseOptions::new().progress_callback(&mut progress)),
+    )
+}
+
+/// Default for `parse_timeout`
+pub const DEFAULT_PARSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Stack for the thread a budgeted parse runs on
+const PARSE_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// `parse_timeout` in milliseconds, 0 for none
+static PARSE_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_PARSE_TIMEOUT.as_millis() as u64);
+
+/// Set the process-wide parse time budget, e.g. from `--parse-timeout`;
+/// `None` lets parses run as long as they take.
+pub fn set_parse_timeout(timeout: Option<Duration>) {
+    let ms = timeout.map_or(0, |t| (t.as_millis() as u64).max(1));
+    PARSE_TIMEOUT_MS.store(ms, Ordering::Relaxed);
+}
+
+pub fn parse_timeout() -> Option<Duration> {
+    match PARSE_TIMEOUT_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// The parser for `file_path`'s extension. Its trees are checked against
+/// `MAX_TREE_DEPTH`, and parses that run past `parse_timeout` fail.
 pub fn get_parser(file_path: &Path) -> anyhow::Result<Box<dyn ParserEngine>> {
+    Ok(Box::new(DepthLimited(Arc::from(parser_for_extension(file_path)?))))
+}
+
+/// Sources up to this size are parsed on the calling thread even with a
+/// `parse_timeout`: they are over long before a thread could be started
+const INLINE_PARSE_BYTES: usize = 1024;
+
+/// Rejects trees nested deeper than `MAX_TREE_DEPTH`, and gives the trees
+/// it passes on their stable ids. The parser is shared with the thread a
+/// budgeted parse runs on.
+struct DepthLimited(Arc<dyn ParserEngine + Send + Sync>);
+
+impl DepthLimited {
+    /// `self.0`'s parse, run on another thread when there is a
+    /// `parse_timeout` and the source is not tiny. A parse that runs out of
+    /// time is told to stop through `parse_cancelled`, and the caller gets
+    /// its error right away instead of hanging on a pathological input.
+    fn parse_within_budget(&self, code: &str, tolerant: bool) -> ParseResult<TreeNode> {
+        let timeout = match parse_timeout() {
+            Some(timeout) if code.len() > INLINE_PARSE_BYTES => timeout,
+            _ => return if tolerant { self.0.parse_tolerant(code) } else { self.0.parse(code) },
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let parser = Arc::clone(&self.0);
+        let code = code.to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = Arc::clone(&cancel);
+        std::thread::Builder::new()
+            .name("gnaw-parse".into())
+            // Parsers recurse once per level of nesting; give them the stack
+            // a main thread gets rather than the smaller spawned default
+            .stack_size(PARSE_STACK_SIZE)
+            .spawn(move || {
+                PARSE_CANCEL.with(|c| *c.borrow_mut() = Some(thread_cancel));
+                let result = if tolerant { parser.parse_tolerant(&code) } else { parser.parse(&code) };
+                let _ = tx.send(result);
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to start parser thread: {}", e))?;
+        let message = match rx.recv_timeout(timeout) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Timeout) => {
+                cancel.store(true, Ordering::Relaxed);
+                format!("parse timed out after {:?} (raise it with --parse-timeout)", timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => "the parser crashed".to_string(),
+        };
+        Err(SyntaxError {
+            message,
+            line: 0,
+            column: 0,
+            expected: None,
+        })
+    }
+}
+
+impl ParserEngine for DepthLimited {
+    fn parse(&self, code: &str) -> ParseResult<TreeNode> {
+        let mut tree = self.parse_within_budget(code, false)?;
+        tree.check_depth()?;
+        tree.assign_stable_ids();
+        Ok(tree)
+    }
+    fn parse_tolerant(&self, code: &str) -> ParseResult<TreeNode> {
+        let mut tree = self.parse_within_budget(code, true)?;
+        tree.check_depth()?;
+        tree.mark_error_nodes();
+        tree.assign_stable_ids();
+        Ok(tree)
+    }
+    fn recovers_from_errors(&self) -> bool {
+        self.0.recovers_from_errors()
+    }
+    fn get_supported_extensions(&self) -> Vec<&'static str> {
+        self.0.get_supported_extensions()
+    }
+}
+
+fn parser_for_extension(file_path: &Path) -> anyhow::Result<Box<dyn ParserEngine + Send + Sync>> {
     let extension = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
     match extension {
@@ -216,8 +745,10 @@ pub fn get_parser(file_path: &Path) -> anyhow::Result<Box<dyn ParserEngine>> {
         "php" => Ok(Box::new(LegacyParserWrapper::new(php::PhpParser::new()))),
         "html" | "htm" => Ok(Box::new(LegacyParserWrapper::new(html::HtmlParser::new()))),
         "go" => Ok(Box::new(LegacyParserWrapper::new(go::GoParser::new()))),
-        "c" | "h" => Ok(Box::new(LegacyParserWrapper::new(c::CParser::new()))),
-        "cpp" | "hpp" | "cc" | "cxx" | "hxx" | "h++" => Ok(Box::new(LegacyParserWrapper::new(cpp::CppParser::new()))),
+        "rb" => Ok(Box::new(LegacyParserWrapper::new(ruby::RubyParser::new()))),
+        "c" => Ok(Box::new(LegacyParserWrapper::new(c::CParser::new()))),
+        "h" => Ok(Box::new(LegacyParserWrapper::new(c::HeaderParser::new()))),
+        "cpp" | "hpp" | "cc" | "hh" | "cxx" | "hxx" | "h++" => Ok(Box::new(LegacyParserWrapper::new(cpp::CppParser::new()))),
         "sh" | "bash" => Ok(Box::new(LegacyParserWrapper::new(bash::BashParser::new()))),
         "java" => Ok(Box::new(LegacyParserWrapper::new(java::JavaParser::new()))),
         "zig" => Ok(Box::new(LegacyParserWrapper::new(zig::ZigParser::new()))),
@@ -232,4 +763,206 @@ pub fn get_parser(file_path: &Path) -> anyhow::Result<Box<dyn ParserEngine>> {
             Ok(Box::new(generic::GenericParser::new()))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Checks the subset of JSON schema that `TreeNode::json_schema` uses
+    fn validate(schema: &Value, root: &Value, value: &Value) -> Result<(), String> {
+        let schema = if schema.get("$ref").is_some() { root } else { schema };
+        let ok = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_u64() || value.is_i64(),
+            _ => true,
+        };
+        if !ok {
+            return Err(format!("expected {} at {}", schema["type"], value));
+        }
+        if let Some(min) = schema["minimum"].as_i64() {
+            if value.as_i64().unwrap_or(min) < min {
+                return Err(format!("{} is below {}", value, min));
+            }
+        }
+        for key in schema["required"].as_array().into_iter().flatten() {
+            let key = key.as_str().unwrap();
+            if value.get(key).is_none() {
+                return Err(format!("missing {}", key));
+            }
+        }
+        if let Some(props) = schema["properties"].as_object() {
+            for (key, prop) in props {
+                if let Some(v) = value.get(key) {
+                    validate(prop, root, v)?;
+                }
+            }
+        }
+        if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+            for v in values {
+                validate(items, root, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn node(path: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode { path: path.into(), children, ..TreeNode::default() }
+    }
+
+    #[test]
+    fn stable_ids_match_golden_values() {
+        // Published FNV-1a test vectors
+        let mut hasher = IdHasher::default();
+        assert_eq!(hasher.0, 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+
+        // Stored ids must not change with the toolchain or platform
+        let tree = get_parser(Path::new("app.py")).unwrap().parse("def f():\n    pass\n\nx = 1\n").unwrap();
+        assert_eq!(tree.id, "0f79dc30a6f91572");
+        assert_eq!(tree.children[0].id, "4a5581ec7652b8b5");
+        assert_eq!(tree.children[1].id, "a45b77b1eaa9b959");
+        assert_eq!(tree.children[1].children[0].children[2].id, "34a078cc5d33be1c");
+    }
+
+    #[test]
+    fn tree_metrics_on_known_tree() {
+        // 0 ─┬─ 0.0 ── 0.0.0 ── 0.0.0.0
+        //    └─ 0.1
+        let tree = node("0", vec![
+            node("0.0", vec![node("0.0.0", vec![node("0.0.0.0", vec![])])]),
+            node("0.1", vec![]),
+        ]);
+        assert_eq!(tree.descendant_count(), 4);
+        assert_eq!(tree.max_depth(), 3);
+        assert_eq!(tree.children[1].descendant_count(), 0);
+        assert_eq!(tree.children[1].max_depth(), 0);
+
+        let order: Vec<&str> = tree.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(order, ["0", "0.0", "0.0.0", "0.0.0.0", "0.1"]);
+    }
+
+    #[test]
+    fn json_schema_validates_analyze_output() {
+        let tree = get_parser(Path::new("app.py"))
+            .unwrap()
+            .parse("class A:\n    def f(self):\n        return 1\n")
+            .unwrap();
+        let schema = TreeNode::json_schema();
+        let value = serde_json::to_value(&tree).unwrap();
+        validate(&schema, &schema, &value).unwrap();
+
+        let mut broken = value.clone();
+        broken["children"][0]["start_line"] = Value::from("one");
+        assert!(validate(&schema, &schema, &broken).is_err());
+        broken.as_object_mut().unwrap().remove("node_type");
+        assert!(validate(&schema, &schema, &broken).is_err());
+    }
+
+    #[test]
+    fn similar_paths_prefers_siblings_and_matches_names() {
+        let tree = get_parser(Path::new("app.py")).unwrap().parse("def greet():\n    pass\n\nx = 1\n").unwrap();
+        assert_eq!(tree.similar_paths("0.9", 3), ["0.0", "0.1", "0.2"]);
+        assert_eq!(tree.similar_paths("@gret", 3), ["@greet"]);
+        assert_eq!(tree.similar_paths("@fn:greeet", 3), ["@fn:greet"]);
+        assert!(tree.similar_paths("42.17.3", 3).is_empty());
+    }
+
+    #[test]
+    fn qml_ids_and_signal_handlers_have_their_own_node_types() {
+        let source = "Rectangle {\n    id: button\n    width: 100\n    onClicked: {\n        console.log(\"hi\")\n    }\n}\n";
+        let tree = get_parser(Path::new("main.qml")).unwrap().parse(source).unwrap();
+        let rect = tree.iter().find(|n| n.node_type == "ui_object_definition").unwrap();
+        assert_eq!(rect.get_name().as_deref(), Some("button"));
+
+        let bindings: Vec<(&str, String)> = rect.children[1]
+            .children
+            .iter()
+            .filter(|n| n.node_type.starts_with("ui_") || n.node_type == "signal_handler")
+            .map(|n| (n.node_type.as_str(), n.get_name().unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            bindings,
+            [
+                ("ui_id", "id".to_string()),
+                ("ui_binding", "width".to_string()),
+                ("signal_handler", "onClicked".to_string()),
+            ]
+        );
+
+        // Without an id the object is named by its type
+        let tree = get_parser(Path::new("main.qml")).unwrap().parse("Text {\n    text: \"x\"\n}\n").unwrap();
+        let text = tree.iter().find(|n| n.node_type == "ui_object_definition").unwrap();
+        assert_eq!(text.get_name().as_deref(), Some("Text"));
+    }
+
+    #[test]
+    fn trees_nested_too_deep_are_rejected_gracefully() {
+        let mut node = TreeNode { path: "leaf".into(), node_type: "leaf".into(), start_line: 1, end_line: 1, ..Default::default() };
+        for _ in 0..MAX_TREE_DEPTH + 10 {
+            node = TreeNode { node_type: "wrap".into(), start_line: 1, end_line: 1, children: vec![node], ..Default::default() };
+        }
+        let err = node.check_depth().unwrap_err();
+        assert!(err.message.contains("nested more than 1000 levels"), "{}", err.message);
+        // Walks over the tree still work at that depth
+        assert_eq!(node.find_path("leaf").map(|n| n.node_type.as_str()), Some("leaf"));
+        assert_eq!(node.find_node_at_line(1).map(|n| n.path.as_str()), Some("leaf"));
+        let mut deepest = 0;
+        node.walk(|_, depth| {
+            deepest = deepest.max(depth);
+            true
+        });
+        assert_eq!(deepest, MAX_TREE_DEPTH + 10);
+
+        // Deeply nested source is a syntax error, not a stack overflow
+        let source = format!("x = {}1{}\n", "(".repeat(20_000), ")".repeat(20_000));
+        let err = get_parser(Path::new("deep.py")).unwrap().parse(&source).unwrap_err();
+        assert!(err.message.contains("nested more than"), "{}", err.message);
+        let source = format!("{}1{}", "[".repeat(20_000), "]".repeat(20_000));
+        assert!(get_parser(Path::new("deep.json")).unwrap().parse(&source).is_err());
+    }
+
+    #[test]
+    fn cancelled_parses_stop() {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&tree_sitter_rust::LANGUAGE.into()).unwrap();
+        // Tree-sitter only reports progress every so often, so give it some work
+        let code = "fn f() { let x = 1; }\n".repeat(10_000);
+        assert!(parse_cancellable(&mut parser, &code).is_some());
+
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = Some(Arc::new(AtomicBool::new(true))));
+        assert!(parse_cancelled());
+        assert!(parse_cancellable(&mut parser, &code).is_none());
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = None);
+    }
+
+    #[test]
+    fn cancelled_legacy_parses_stop() {
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = Some(Arc::new(AtomicBool::new(true))));
+        for (ext, code) in [("json", "{\"a\": [1, 2]}"), ("toml", "a = [1, 2]\n"), ("yaml", "a: [1, 2]\n")] {
+            let parser = parser_for_extension(Path::new(&format!("f.{}", ext))).unwrap();
+            assert!(parser.parse(code).is_err(), "{} parse was not cancelled", ext);
+        }
+        for ext in ["md", "txt"] {
+            let parser = parser_for_extension(Path::new(&format!("f.{}", ext))).unwrap();
+            let tree = parser.parse("# a\n\nb\n\nc\n").unwrap();
+            assert!(tree.children.is_empty(), "{} parse was not cancelled", ext);
+        }
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = None);
+    }
+
+    #[test]
+    fn only_sources_past_the_inline_size_get_their_own_thread() {
+        // A parse on this thread sees its cancel flag; one on a budget thread does not
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = Some(Arc::new(AtomicBool::new(true))));
+        let parser = get_parser(Path::new("f.json")).unwrap();
+        assert!(parser.parse("[1, 2]").is_err());
+        let big = format!("[{}1]", "1, ".repeat(INLINE_PARSE_BYTES));
+        assert_eq!(parser.parse(&big).unwrap().children.len(), INLINE_PARSE_BYTES + 1);
+        PARSE_CANCEL.with(|c| *c.borrow_mut() = None);
+    }
+}
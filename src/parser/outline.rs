@@ -0,0 +1,223 @@
+//! Indented overviews of a tree: the raw `skeleton` (every node down to a
+//! depth) and the symbol `outline` (only functions, classes and methods),
+//! plus the whole tree as a Graphviz `dot` graph.
+
+use serde::Serialize;
+
+use crate::parser::kind::walk_kinds;
+use crate::parser::{NodeKind, TreeNode};
+
+/// Most lines a skeleton or outline prints
+pub const MAX_ENTRIES: usize = 500;
+
+/// A function, class, method or Rust `impl` block in an outline
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineEntry {
+    /// 1 for top-level symbols, 2 for their members, ...
+    pub depth: usize,
+    pub path: String,
+    /// `function`, `class`, `method` or `impl`
+    pub kind: String,
+    pub name: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// `path [node_type] name` for every node down to `max_depth` levels below
+/// the root, indented by depth. With `signatures`, functions, methods and
+/// classes show their `signature` instead of just the name.
+pub fn skeleton(tree: &TreeNode, max_depth: usize, signatures: bool) -> String {
+    let mut out = String::new();
+    let mut count = 0;
+    tree.walk(|n, d| {
+        if d > max_depth || count >= MAX_ENTRIES {
+            return false;
+        }
+        count += 1;
+        let name = signatures.then(|| signature(n)).flatten().or_else(|| n.get_name()).unwrap_or_default();
+        push_line(&mut out, d, &n.path, &n.node_type, &name, None);
+        if count == MAX_ENTRIES {
+            out.push_str(&format!("... (limit of {} nodes reached)\n", MAX_ENTRIES));
+        }
+        true
+    });
+    out
+}
+
+/// The functions, classes and methods in `tree`, nested at most `max_depth`
+/// levels deep; `max_depth` 1 gives only the top-level symbols
+pub fn outline(tree: &TreeNode, max_depth: usize) -> Vec<OutlineEntry> {
+    let mut entries = Vec::new();
+    walk_kinds(tree, false, 0, &mut |node, kind, depth| {
+        let kind = match kind {
+            Some(k @ (NodeKind::Function | NodeKind::Class | NodeKind::Method)) => k.as_str(),
+            Some(_) => return,
+            None => "impl",
+        };
+        if depth >= max_depth || entries.len() >= MAX_ENTRIES {
+            return;
+        }
+        entries.push(OutlineEntry {
+            depth: depth + 1,
+            path: node.path.clone(),
+            kind: kind.to_string(),
+            name: node.get_name().unwrap_or_default(),
+            start_line: node.start_line,
+            end_line: node.end_line,
+        });
+    });
+    entries
+}
+
+/// `outline` entries in the same layout as `skeleton`, with line ranges
+pub fn render_outline(entries: &[OutlineEntry]) -> String {
+    let mut out = String::new();
+    for e in entries {
+        push_line(&mut out, e.depth - 1, &e.path, &e.kind, &e.name, Some((e.start_line, e.end_line)));
+    }
+    if entries.len() >= MAX_ENTRIES {
+        out.push_str(&format!("... (limit of {} symbols reached)\n", MAX_ENTRIES));
+    }
+    out
+}
+
+/// The header of a function, method or class definition, e.g.
+/// `pub fn add(a: i32, b: i32) -> i32`, without the body opener (`{` or
+/// `:`). A parameter list spread over several lines is joined into one.
+pub fn signature(node: &TreeNode) -> Option<String> {
+    if !matches!(node.kind(), Some(NodeKind::Function | NodeKind::Method | NodeKind::Class)) {
+        return None;
+    }
+    let mut header = String::new();
+    let mut depth = 0i32;
+    for line in node.content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if line.starts_with([')', ']']) {
+            // Drop a trailing comma before the closing bracket
+            header.truncate(header.trim_end_matches(',').len());
+        } else if !header.is_empty() && !header.ends_with(['(', '[']) {
+            header.push(' ');
+        }
+        header.push_str(line);
+        depth += line.matches(['(', '[']).count() as i32 - line.matches([')', ']']).count() as i32;
+        // The header ends on the line that closes its brackets
+        if depth <= 0 {
+            break;
+        }
+    }
+    let header = header.strip_suffix(['{', ':']).unwrap_or(&header).trim_end();
+    (!header.is_empty()).then(|| header.to_string())
+}
+
+/// A Graphviz digraph of each file's tree, one cluster per file, with an
+/// edge from every node to each of its children. Nodes are labelled with
+/// their type, plus the name for named definitions and identifiers.
+pub fn dot(files: &[(&str, &TreeNode)]) -> String {
+    let mut out = String::from("digraph gnawtreewriter {\n    node [shape=box, fontname=\"monospace\"];\n");
+    let mut next_id = 0;
+    for (i, (file, tree)) in files.iter().enumerate() {
+        out.push_str(&format!("    subgraph cluster_{} {{\n        label=\"{}\";\n", i, dot_escape(file)));
+        push_dot_node(&mut out, tree, &mut next_id);
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Declare `node` and the edges to its children, depth first; returns the
+/// node's id
+fn push_dot_node(out: &mut String, node: &TreeNode, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let name = (node.kind().is_some() || node.children.is_empty())
+        .then(|| node.get_name())
+        .flatten();
+    let label = match name {
+        Some(name) => format!("{} {}", node.node_type, name),
+        None => node.node_type.clone(),
+    };
+    out.push_str(&format!("        n{} [label=\"{}\"];\n", id, dot_escape(&label)));
+    for child in &node.children {
+        let child_id = push_dot_node(out, child, next_id);
+        out.push_str(&format!("        n{} -> n{};\n", id, child_id));
+    }
+    id
+}
+
+/// `text` for use inside a double-quoted DOT string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn push_line(out: &mut String, depth: usize, path: &str, label: &str, name: &str, lines: Option<(usize, usize)>) {
+    out.push_str(&format!("{}{} [{}] {}", "  ".repeat(depth), path, label, name));
+    if let Some((start, end)) = lines {
+        out.push_str(&format!(" (lines {}-{})", start, end));
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::get_parser;
+    use std::path::Path;
+
+    #[test]
+    fn depth_one_lists_only_top_level_symbols() {
+        let source = "import os\n\nclass Greeter:\n    def greet(self):\n        pass\n\ndef main():\n    x = 1\n";
+        let tree = get_parser(Path::new("app.py")).unwrap().parse(source).unwrap();
+
+        let top = outline(&tree, 1);
+        let top: Vec<(&str, &str)> = top.iter().map(|e| (e.kind.as_str(), e.name.as_str())).collect();
+        assert_eq!(top, [("class", "Greeter"), ("function", "main")]);
+
+        let all = outline(&tree, 2);
+        assert_eq!(all.len(), 3);
+        assert_eq!((all[1].depth, all[1].kind.as_str(), all[1].name.as_str()), (2, "method", "greet"));
+        assert_eq!((all[1].start_line, all[1].end_line), (4, 5));
+        assert!(render_outline(&all).contains(&format!("  {} [method] greet (lines 4-5)\n", all[1].path)));
+    }
+
+    #[test]
+    fn dot_has_a_node_per_tree_node_and_an_edge_per_child() {
+        let source = "def greet(name):\n    return \"hi \" + name\n";
+        let tree = get_parser(Path::new("app.py")).unwrap().parse(source).unwrap();
+        let graph = dot(&[("say \"hi\".py", &tree)]);
+
+        assert!(graph.starts_with("digraph gnawtreewriter {\n"), "{}", graph);
+        assert!(graph.ends_with("}\n"));
+        assert_eq!(graph.matches('{').count(), graph.matches('}').count());
+        let body: Vec<&str> = graph.lines().filter(|l| !l.ends_with('{') && l.trim() != "}").collect();
+        assert!(body.iter().all(|l| l.ends_with(';')), "{}", graph);
+
+        let count = tree.iter().count();
+        assert_eq!(graph.matches(" [label=").count(), count);
+        assert_eq!(graph.matches(" -> ").count(), count - 1);
+        assert!(graph.contains("[label=\"function_definition greet\"]"), "{}", graph);
+        assert!(graph.contains("label=\"say \\\"hi\\\".py\";"), "{}", graph);
+    }
+
+    #[test]
+    fn skeleton_can_show_signatures() {
+        let source = "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n";
+        let tree = get_parser(Path::new("lib.rs")).unwrap().parse(source).unwrap();
+
+        let plain = skeleton(&tree, 1, false);
+        assert!(plain.contains("[function_item] add\n"), "{}", plain);
+        let with_signatures = skeleton(&tree, 1, true);
+        assert!(
+            with_signatures.contains("[function_item] pub fn add(a: i32, b: i32) -> i32\n"),
+            "{}",
+            with_signatures
+        );
+
+        // Parameters spread over several lines end up on one
+        let source = "fn add(\n    a: i32,\n    b: i32,\n) -> i32 {\n    a + b\n}\n";
+        let tree = get_parser(Path::new("lib.rs")).unwrap().parse(source).unwrap();
+        assert_eq!(signature(&tree.children[0]).as_deref(), Some("fn add(a: i32, b: i32) -> i32"));
+
+        let source = "def greet(\n    name,\n    loud=False,\n):\n    pass\n";
+        let tree = get_parser(Path::new("app.py")).unwrap().parse(source).unwrap();
+        assert_eq!(signature(&tree.children[0]).as_deref(), Some("def greet(name, loud=False)"));
+    }
+}
@@ -36,7 +36,7 @@ impl PythonParser {
             String::new()
         };
 
-        let node_type = node.kind().to_string();
+        let node_type = crate::parser::node_type_of(node);
         let start_line = node.start_position().row + 1;
         let end_line = node.end_position().row + 1;
 
@@ -68,6 +68,21 @@ impl PythonParser {
 
 impl ParserEngine for PythonParser {
     fn parse(&self, code: &str) -> ParseResult<TreeNode> {
+        self.parse_tree(code, true)
+    }
+
+    fn parse_tolerant(&self, code: &str) -> ParseResult<TreeNode> {
+        self.parse_tree(code, false)
+    }
+
+    fn get_supported_extensions(&self) -> Vec<&'static str> {
+        vec!["py"]
+    }
+}
+
+impl PythonParser {
+    /// Parse `code`; with `strict`, any syntax error fails the parse
+    fn parse_tree(&self, code: &str, strict: bool) -> ParseResult<TreeNode> {
         let mut parser = tree_sitter::Parser::new();
         let language = unsafe {
             std::mem::transmute::<tree_sitter_language::LanguageFn, fn() -> tree_sitter::Language>(
@@ -78,11 +93,12 @@ impl ParserEngine for PythonParser {
             return Err(SyntaxError::from(anyhow::anyhow!("Failed to set Python language: {}", e)));
         }
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| SyntaxError::from(anyhow::anyhow!("Failed to parse Python: No tree returned")))?;
 
-        if tree.root_node().has_error() {
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
+        if strict && tree.root_node().has_error() {
             let mut cursor = tree.walk();
             if let Some(err_node) = self.find_error(&tree.root_node(), &mut cursor) {
                 return Err(SyntaxError {
@@ -96,8 +112,4 @@ impl ParserEngine for PythonParser {
 
         crate::parser::to_parse_result(Self::build_tree(&tree.root_node(), code, "".to_string()))
     }
-
-    fn get_supported_extensions(&self) -> Vec<&'static str> {
-        vec!["py"]
-    }
 }
\ No newline at end of file
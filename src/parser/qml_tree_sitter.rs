@@ -30,10 +30,11 @@ impl ParserEngineLegacy for QmlTreeSitterParser {
             .set_language(&language)
             .expect("Failed to load QMLJS grammar");
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse QML"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), code, "".to_string())
     }
 
@@ -43,6 +44,26 @@ impl ParserEngineLegacy for QmlTreeSitterParser {
 }
 
 impl QmlTreeSitterParser {
+    /// The grammar's node kind, except that `ui_binding`s are split into
+    /// `ui_id` (`id: name`) and `signal_handler` (`onClicked: ...`)
+    fn node_type(node: &tree_sitter::Node, source: &str) -> String {
+        let kind = node.kind();
+        if kind != "ui_binding" {
+            return kind.to_string();
+        }
+        let name = node
+            .child_by_field_name("name")
+            .and_then(|n| source.get(n.start_byte()..n.end_byte()))
+            .unwrap_or_default();
+        if name == "id" {
+            "ui_id".to_string()
+        } else if is_signal_handler(name) {
+            "signal_handler".to_string()
+        } else {
+            kind.to_string()
+        }
+    }
+
     fn build_tree(node: &tree_sitter::Node, source: &str, path: String) -> Result<TreeNode> {
         let start_byte = node.start_byte();
         let end_byte = node.end_byte();
@@ -53,7 +74,7 @@ impl QmlTreeSitterParser {
             String::new()
         };
 
-        let node_type = node.kind().to_string();
+        let node_type = Self::node_type(node, source);
         let start_line = node.start_position().row + 1;
         let end_line = node.end_position().row + 1;
 
@@ -90,4 +111,12 @@ impl QmlTreeSitterParser {
             children, 
         })
     }
-}
\ No newline at end of file
+}
+
+/// `on` followed by an upper-case letter, e.g. `onClicked` or
+/// `onWidthChanged`
+fn is_signal_handler(name: &str) -> bool {
+    name.strip_prefix("on")
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|c| c.is_ascii_uppercase())
+}
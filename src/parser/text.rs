@@ -1,4 +1,4 @@
-use crate::parser::{TreeNode, ParserEngineLegacy};
+use crate::parser::{parse_cancelled, TreeNode, ParserEngineLegacy};
 
 pub struct TextParser;
 
@@ -20,6 +20,10 @@ impl ParserEngineLegacy for TextParser {
         let mut root_children = Vec::new();
 
         for (i, line) in lines.iter().enumerate() {
+            // The caller has given up on this parse; what is returned is dropped
+            if parse_cancelled() {
+                break;
+            }
             root_children.push(TreeNode { start_col: 0, end_col: 0, 
                 id: format!("line_{}", i),
                 path: i.to_string(),
@@ -1,4 +1,4 @@
-use crate::parser::{TreeNode, ParserEngineLegacy};
+use crate::parser::{parse_cancelled, TreeNode, ParserEngineLegacy};
 use anyhow::Result;
 use toml::Value;
 
@@ -27,6 +27,10 @@ impl ParserEngineLegacy for TomlParser {
         Ok(root)
     }
 
+    fn recovers_from_errors(&self) -> bool {
+        false
+    }
+
     fn get_supported_extensions(&self) -> Vec<&'static str> {
         vec!["toml"]
     }
@@ -41,6 +45,11 @@ impl TomlParser {
         start_line: usize,
         end_line: usize,
     ) -> Result<TreeNode> {
+        // The caller has given up on this parse. The deserializer before this
+        // cannot be interrupted, but it is linear in the input.
+        if parse_cancelled() {
+            anyhow::bail!("parse cancelled");
+        }
         let (node_type, children) = match value {
             Value::String(_) => ("string".to_string(), vec![]),
             Value::Integer(_) => ("integer".to_string(), vec![]),
@@ -26,10 +26,11 @@ impl ParserEngineLegacy for TypeScriptParser {
         };
         parser.set_language(&language)?;
 
-        let tree = parser
-            .parse(code, None)
+        let tree = crate::parser::parse_cancellable(&mut parser, code)
             .ok_or_else(|| anyhow::anyhow!("Failed to parse TypeScript"))?;
 
+        crate::parser::check_syntax_depth(tree.root_node())?;
+
         Self::build_tree(&tree.root_node(), code, "".to_string())
     }
 
@@ -38,9 +39,25 @@ impl ParserEngineLegacy for TypeScriptParser {
     }
 }
 
+/// Class members a decorator can precede. In a class body the grammar makes
+/// `@HostListener(...)` a sibling of the method it decorates.
+const DECORATED_MEMBERS: &[&str] = &["method_definition", "method_signature", "abstract_method_signature"];
+
 impl TypeScriptParser {
     fn build_tree(node: &tree_sitter::Node, source: &str, path: String) -> Result<TreeNode> {
-        let start_byte = node.start_byte();
+        Self::build_decorated(node, &[], source, path)
+    }
+
+    /// Build `node` with the `decorators` written before it folded in, so its
+    /// content and line range start at the first decorator
+    fn build_decorated(
+        node: &tree_sitter::Node,
+        decorators: &[tree_sitter::Node],
+        source: &str,
+        path: String,
+    ) -> Result<TreeNode> {
+        let first = decorators.first().unwrap_or(node);
+        let start_byte = first.start_byte();
         let end_byte = node.end_byte();
         let content = if let Some(s) = source.get(start_byte..end_byte) {
             s.to_string()
@@ -49,19 +66,36 @@ impl TypeScriptParser {
         };
 
         let node_type = node.kind().to_string();
-        let start_line = node.start_position().row + 1;
+        let start_line = first.start_position().row + 1;
         let end_line = node.end_position().row + 1;
 
+        let child_path = |i: usize| if path.is_empty() { i.to_string() } else { format!("{}.{}", path, i) };
         let mut children = Vec::new();
-        let mut cursor = node.walk();
+        for decorator in decorators {
+            children.push(Self::build_tree(decorator, source, child_path(children.len()))?);
+        }
 
-        for (i, child) in node.children(&mut cursor).enumerate() {
-            let child_path = if path.is_empty() {
-                i.to_string()
-            } else {
-                format!("{}.{}", path, i)
-            };
-            children.push(Self::build_tree(&child, source, child_path)?);
+        let mut cursor = node.walk();
+        let kids: Vec<tree_sitter::Node> = node.children(&mut cursor).collect();
+        let mut pending = Vec::new();
+        for child in kids {
+            if node.kind() == "class_body" && child.kind() == "decorator" {
+                pending.push(child);
+                continue;
+            }
+            if DECORATED_MEMBERS.contains(&child.kind()) {
+                children.push(Self::build_decorated(&child, &pending, source, child_path(children.len()))?);
+                pending.clear();
+                continue;
+            }
+            // A decorator not followed by a member keeps its own node
+            for decorator in pending.drain(..) {
+                children.push(Self::build_tree(&decorator, source, child_path(children.len()))?);
+            }
+            children.push(Self::build_tree(&child, source, child_path(children.len()))?);
+        }
+        for decorator in pending {
+            children.push(Self::build_tree(&decorator, source, child_path(children.len()))?);
         }
 
         let id = path.clone();
@@ -76,4 +110,51 @@ impl TypeScriptParser {
             children, 
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "interface User {\n  name: string;\n}\ntype Id = string | number;\nenum Color { Red, Green }\n\n@Component({ selector: 'app' })\nclass AppComponent {\n  @Input() title: string;\n\n  @HostListener('click')\n  onClick(): void {\n    console.log(1);\n  }\n}\n";
+
+    fn find<'a>(node: &'a TreeNode, kind: &str, name: &str) -> Option<&'a TreeNode> {
+        if node.node_type == kind && node.get_name().as_deref() == Some(name) {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find(c, kind, name))
+    }
+
+    #[test]
+    fn interfaces_type_aliases_and_enums_are_named() {
+        let tree = TypeScriptParser::new().parse_legacy(SOURCE).unwrap();
+        assert!(find(&tree, "interface_declaration", "User").is_some());
+        assert!(find(&tree, "type_alias_declaration", "Id").is_some());
+        assert!(find(&tree, "enum_declaration", "Color").is_some());
+    }
+
+    #[test]
+    fn decorators_belong_to_the_class_or_method_they_decorate() {
+        let tree = TypeScriptParser::new().parse_legacy(SOURCE).unwrap();
+
+        let class = find(&tree, "class_declaration", "AppComponent").expect("class");
+        assert!(class.content.starts_with("@Component({ selector: 'app' })\nclass AppComponent"));
+        assert_eq!(class.start_line, 7);
+
+        let method = find(&tree, "method_definition", "onClick").expect("method");
+        assert!(method.content.starts_with("@HostListener('click')\n  onClick(): void {"), "{}", method.content);
+        assert_eq!((method.start_line, method.end_line), (11, 14));
+        assert_eq!(method.children[0].node_type, "decorator");
+        assert_eq!(tree.find_path(&method.path).map(|n| n.start_line), Some(11));
+
+        let field = find(&tree, "public_field_definition", "title").expect("field");
+        assert!(field.content.starts_with("@Input() title"));
+
+        // No decorator is left behind as a sibling in the class body
+        let body = class.children.iter().find(|c| c.node_type == "class_body").unwrap();
+        assert!(body.children.iter().all(|c| c.node_type != "decorator"));
+        for (i, child) in body.children.iter().enumerate() {
+            assert_eq!(child.path, format!("{}.{}", body.path, i));
+        }
+    }
+}
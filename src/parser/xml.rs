@@ -1,4 +1,4 @@
-use crate::parser::{TreeNode, ParserEngine, ParseResult};
+use crate::parser::{parse_cancelled, ParseResult, ParserEngine, SyntaxError, TreeNode, MAX_TREE_DEPTH};
 use xmltree::{Element, XMLNode};
 
 pub struct XmlParser;
@@ -17,6 +17,7 @@ impl XmlParser {
 
 impl ParserEngine for XmlParser {
     fn parse(&self, code: &str) -> ParseResult<TreeNode> {
+        let lines = LineStarts::new(code);
         let mut top_children = Vec::new();
         let mut current_pos = 0;
 
@@ -25,7 +26,7 @@ impl ParserEngine for XmlParser {
             if let Some(end) = code[start..].find("?>") {
                 let abs_end = start + end + 2;
                 let content = &code[start..abs_end];
-                let line = code[..start].chars().filter(|c| *c == '\n').count() + 1;
+                let line = lines.line(start);
                 top_children.push(TreeNode {
                     id: "xml_declaration".to_string(),
                     path: "0.xml_declaration".to_string(),
@@ -45,7 +46,7 @@ impl ParserEngine for XmlParser {
             if let Some(end) = code[abs_start..].find('>') {
                 let abs_end = abs_start + end + 1;
                 let content = &code[abs_start..abs_end];
-                let line = code[..abs_start].chars().filter(|c| *c == '\n').count() + 1;
+                let line = lines.line(abs_start);
                 top_children.push(TreeNode {
                     id: "doctype".to_string(),
                     path: format!("{}.doctype", top_children.len()),
@@ -68,8 +69,8 @@ impl ParserEngine for XmlParser {
                 if let Some(end) = code[abs_start..].find("-->") {
                     let abs_end = abs_start + end + 3;
                     let content = &code[abs_start..abs_end];
-                    let line_start = code[..abs_start].chars().filter(|c| *c == '\n').count() + 1;
-                    let line_end = code[..abs_end].chars().filter(|c| *c == '\n').count() + 1;
+                    let line_start = lines.line(abs_start);
+                    let line_end = lines.line(abs_end);
                     top_children.push(TreeNode {
                         id: format!("comment_{}", top_children.len()),
                         path: top_children.len().to_string(),
@@ -94,7 +95,9 @@ impl ParserEngine for XmlParser {
             break;
         }
 
-        // 4. Parse Root Element
+        // 4. Parse Root Element. Both xmltree and the span mapping below
+        // recurse once per level, so refuse documents nested too deep first
+        check_nesting(code, &lines)?;
         let remaining = &code[current_pos..];
         let elem = Element::parse(&mut std::io::Cursor::new(remaining.as_bytes()))
             .map_err(|e| anyhow::anyhow!("XML parse error: {}", e))?;
@@ -109,6 +112,7 @@ impl ParserEngine for XmlParser {
                     &elem,
                     top_children.len().to_string(),
                     code,
+                    &lines,
                     root_abs_start,
                     root_abs_end,
                 );
@@ -121,6 +125,7 @@ impl ParserEngine for XmlParser {
                         &elem,
                         top_children.len().to_string(),
                         code,
+                        &lines,
                         root_abs_start,
                         root_abs_end,
                     );
@@ -146,6 +151,73 @@ impl ParserEngine for XmlParser {
     }
 }
 
+/// Byte offset at which each line of a source starts. Maps any byte offset
+/// to its line without slicing the source, so offsets that are not on a
+/// UTF-8 character boundary cannot panic.
+struct LineStarts(Vec<usize>);
+
+impl LineStarts {
+    fn new(source: &str) -> Self {
+        LineStarts(std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect())
+    }
+
+    /// 1-based line containing byte `offset`
+    fn line(&self, offset: usize) -> usize {
+        self.0.partition_point(|&start| start <= offset)
+    }
+}
+
+/// Fails on the first element nested more than `MAX_TREE_DEPTH` levels
+/// deep, scanning the tags linearly without building anything
+fn check_nesting(code: &str, lines: &LineStarts) -> ParseResult<()> {
+    let bytes = code.as_bytes();
+    let mut depth = 0usize;
+    let mut pos = 0;
+    let skip_past = |from: usize, end: &str| code[from..].find(end).map_or(bytes.len(), |i| from + i + end.len());
+    while let Some(rel) = code[pos..].find('<') {
+        let start = pos + rel;
+        let rest = &code[start..];
+        if rest.starts_with("<!--") {
+            pos = skip_past(start, "-->");
+        } else if rest.starts_with("<![CDATA[") {
+            pos = skip_past(start, "]]>");
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            pos = skip_past(start, ">");
+        } else if rest.starts_with("</") {
+            depth = depth.saturating_sub(1);
+            pos = skip_past(start, ">");
+        } else {
+            // An opening tag: find its '>' outside quoted attribute values
+            let mut quote = None;
+            let mut end = bytes.len();
+            for (i, &b) in bytes.iter().enumerate().skip(start + 1) {
+                match (quote, b) {
+                    (Some(q), _) if b == q => quote = None,
+                    (None, b'"' | b'\'') => quote = Some(b),
+                    (None, b'>') => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            if end < bytes.len() && bytes[end - 1] != b'/' {
+                depth += 1;
+                if depth > MAX_TREE_DEPTH {
+                    return Err(SyntaxError {
+                        message: format!("syntax tree is nested more than {} levels deep", MAX_TREE_DEPTH),
+                        line: lines.line(start),
+                        column: 0,
+                        expected: None,
+                    });
+                }
+            }
+            pos = end;
+        }
+    }
+    Ok(())
+}
+
 impl XmlParser {
     #[allow(clippy::only_used_in_recursion)]
     fn element_to_treenode_with_span(
@@ -153,12 +225,13 @@ impl XmlParser {
         el: &Element,
         path: String,
         source: &str,
+        lines: &LineStarts,
         abs_start: usize,
         abs_end: usize,
     ) -> TreeNode {
         // Map byte offsets to line numbers (1-based)
-        let start_line = source[..abs_start].chars().filter(|c| *c == '\n').count() + 1;
-        let end_line = source[..abs_end].chars().filter(|c| *c == '\n').count() + 1;
+        let start_line = lines.line(abs_start);
+        let end_line = lines.line(abs_end);
 
         // Build opening tag text for convenience (name + attributes)
         let mut opening = format!("<{}", el.name);
@@ -206,6 +279,10 @@ impl XmlParser {
         // Walk children and map them to spans (searching within the element's source window)
         let mut search_pos = abs_start;
         for (i, node) in el.children.iter().enumerate() {
+            // The caller has given up on this parse; what is returned is dropped
+            if parse_cancelled() {
+                break;
+            }
             let child_path = format!("{}.{}", path, i);
             match node {
                 XMLNode::Element(child_el) => {
@@ -226,6 +303,7 @@ impl XmlParser {
                                 child_el,
                                 child_path.clone(),
                                 source,
+                                lines,
                                 child_abs_start,
                                 child_abs_end,
                             );
@@ -235,13 +313,8 @@ impl XmlParser {
                             // Self-closing or single-tag fallback: capture opening tag substring
                             let gt_abs = child_abs_start + gt_rel;
                             let full_tag = &source[child_abs_start..=gt_abs];
-                            let s_line = source[..child_abs_start]
-                                .chars()
-                                .filter(|c| *c == '\n')
-                                .count()
-                                + 1;
-                            let e_line =
-                                source[..gt_abs + 1].chars().filter(|c| *c == '\n').count() + 1;
+                            let s_line = lines.line(child_abs_start);
+                            let e_line = lines.line(gt_abs + 1);
                             children.push(TreeNode { start_col: 0, end_col: 0, 
                                 id: child_path.clone(),
                                 path: child_path.clone(),
@@ -273,14 +346,8 @@ impl XmlParser {
                             if let Some(gt_rel2) = source[child_abs_start..abs_end].find('>') {
                                 let gt_abs2 = child_abs_start + gt_rel2;
                                 let full_tag = &source[child_abs_start..=gt_abs2];
-                                let s_line2 = source[..child_abs_start]
-                                    .chars()
-                                    .filter(|c| *c == '\n')
-                                    .count()
-                                    + 1;
-                                let e_line2 =
-                                    source[..gt_abs2 + 1].chars().filter(|c| *c == '\n').count()
-                                        + 1;
+                                let s_line2 = lines.line(child_abs_start);
+                                let e_line2 = lines.line(gt_abs2 + 1);
                                 children.push(TreeNode { start_col: 0, end_col: 0, 
                                     id: child_path.clone(),
                                     path: child_path.clone(),
@@ -322,10 +389,8 @@ impl XmlParser {
                         if let Some(rel_pos) = source[search_pos..abs_end].find(text) {
                             let t_abs_start = search_pos + rel_pos;
                             let t_abs_end = t_abs_start + text.len();
-                            let s_line =
-                                source[..t_abs_start].chars().filter(|c| *c == '\n').count() + 1;
-                            let e_line =
-                                source[..t_abs_end].chars().filter(|c| *c == '\n').count() + 1;
+                            let s_line = lines.line(t_abs_start);
+                            let e_line = lines.line(t_abs_end);
                             children.push(TreeNode { start_col: 0, end_col: 0, 
                                 id: child_path.clone(),
                                 path: child_path.clone(),
@@ -354,9 +419,8 @@ impl XmlParser {
                     if let Some(rel_pos) = source[search_pos..abs_end].find(&cdata) {
                         let c_abs_start = search_pos + rel_pos;
                         let c_abs_end = c_abs_start + cdata.len();
-                        let s_line =
-                            source[..c_abs_start].chars().filter(|c| *c == '\n').count() + 1;
-                        let e_line = source[..c_abs_end].chars().filter(|c| *c == '\n').count() + 1;
+                        let s_line = lines.line(c_abs_start);
+                        let e_line = lines.line(c_abs_end);
                         children.push(TreeNode { start_col: 0, end_col: 0, 
                             id: child_path.clone(),
                             path: child_path.clone(),
@@ -384,9 +448,8 @@ impl XmlParser {
                     if let Some(rel_pos) = source[search_pos..abs_end].find(&comment) {
                         let c_abs_start = search_pos + rel_pos;
                         let c_abs_end = c_abs_start + comment.len();
-                        let s_line =
-                            source[..c_abs_start].chars().filter(|c| *c == '\n').count() + 1;
-                        let e_line = source[..c_abs_end].chars().filter(|c| *c == '\n').count() + 1;
+                        let s_line = lines.line(c_abs_start);
+                        let e_line = lines.line(c_abs_end);
                         children.push(TreeNode { start_col: 0, end_col: 0, 
                             id: child_path.clone(),
                             path: child_path.clone(),
@@ -428,7 +491,8 @@ impl XmlParser {
 
     // Finds the end (byte index relative to slice) of the matching closing tag for `tag`,
     // starting search at `rel_open` (relative index within `slice`). Returns index
-    // of the byte just after the closing '>' of the matching closing tag (i.e., exclusive end).
+    // of the byte just after the closing '>' of the matching closing tag (i.e., exclusive end),
+    // or None if there is none or the parse has been cancelled.
     fn find_matching_close_in_slice(slice: &str, rel_open: usize, tag: &str) -> Option<usize> {
         let open_pat = format!("<{}", tag);
         let close_pat = format!("</{}", tag);
@@ -438,6 +502,9 @@ impl XmlParser {
         let mut depth: i32 = 1;
 
         while pos < slice.len() {
+            if parse_cancelled() {
+                return None;
+            }
             let next_open = slice[pos..].find(&open_pat).map(|p| pos + p);
             let next_close = slice[pos..].find(&close_pat).map(|p| pos + p);
 
@@ -580,4 +647,39 @@ mod tests {
             .iter()
             .any(|c| c.node_type == "element" && c.content.starts_with("<meta")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn line_numbers_after_non_ascii_content() {
+        let xml = "<!-- Grüße, 日本語 ✓ -->\n<root>\n  <name>Åsa Öberg</name>\n  <city>Malmö</city>\n</root>";
+
+        let doc = XmlParser::new().parse(xml).expect("should parse");
+        let root = doc.children.iter().find(|n| n.node_type == "element").expect("root element");
+        assert_eq!((root.start_line, root.end_line), (2, 5));
+
+        let city = root
+            .children
+            .iter()
+            .find(|c| c.content.starts_with("<city"))
+            .expect("<city> element");
+        assert_eq!(city.start_line, 4);
+        let text = city.children.iter().find(|c| c.node_type == "text").expect("text");
+        assert_eq!((text.content.as_str(), text.start_line), ("Malmö", 4));
+
+        let lines = LineStarts::new("å\nb");
+        // Offset 1 is inside the two-byte 'å'
+        assert_eq!((lines.line(1), lines.line(3)), (1, 2));
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_refused_before_mapping_spans() {
+        let depth = MAX_TREE_DEPTH + 1;
+        let xml = format!("<r>\n{}{}</r>", "<a b=\"/>\">".repeat(depth), "</a>".repeat(depth));
+        let err = XmlParser::new().parse(&xml).unwrap_err();
+        assert!(err.message.contains("nested more than"), "{}", err.message);
+        assert_eq!(err.line, 2);
+
+        // Self-closing tags, comments and CDATA do not nest
+        let flat = format!("<r>{}<!-- <a> --><![CDATA[<a>]]></r>", "<a/>".repeat(depth));
+        assert!(XmlParser::new().parse(&flat).is_ok());
+    }
+}
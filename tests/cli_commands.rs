@@ -0,0 +1,432 @@
+//! Tests that drive the `gnawtreewriter` binary: exit codes, global flags and
+//! the command-line options that only show up in a real process.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use gnawtreewriter::core::find_project_root;
+
+/// A temporary project root holding `files`
+fn project(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// The binary with `args`, run in `dir`
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_gnawtreewriter"));
+    cmd.args(args).current_dir(dir);
+    cmd
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+/// Like `run`, for commands that must succeed
+fn run_ok(dir: &Path, args: &[&str]) -> Output {
+    let out = run(dir, args);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    out
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).to_string()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).to_string()
+}
+
+// ── exit codes ───────────────────────────────────────────────────────
+
+#[test]
+fn lint_issues_exit_1_and_errors_exit_2() {
+    let dir = project(&[("good.py", "x = 1\n"), ("broken.py", "def broken(:\n")]);
+    let exit_code = |args: &[&str]| run(dir.path(), args).status.code().unwrap();
+
+    assert_eq!(exit_code(&["lint", "good.py"]), 0);
+    assert_eq!(exit_code(&["lint", "broken.py"]), 1);
+    assert_eq!(exit_code(&["analyze", "missing.py"]), 2);
+    assert_eq!(exit_code(&["lint", "missing.py"]), 2);
+    assert_eq!(exit_code(&["analyze", "good.py", "--no-such-flag"]), 2);
+    assert_eq!(exit_code(&["--help"]), 0);
+}
+
+// ── --fail-fast ──────────────────────────────────────────────────────
+
+fn project_with_broken_file() -> tempfile::TempDir {
+    project(&[
+        ("src/a_good.py", "def ok():\n    return 1\n"),
+        ("src/b_broken.py", "def broken(:\n"),
+        ("src/c_good.py", "x = 2\n"),
+    ])
+}
+
+#[test]
+fn analyze_keeps_going_unless_fail_fast() {
+    let dir = project_with_broken_file();
+
+    let out = run_ok(dir.path(), &["analyze", "src", "--recursive", "--format", "summary"]);
+    assert!(stdout(&out).contains("Analyzed 2 files"));
+    assert!(stderr(&out).contains("b_broken.py"));
+
+    let out = run(dir.path(), &["analyze", "src", "--recursive", "--format", "summary", "--fail-fast"]);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty(), "{}", stdout(&out));
+    assert!(stderr(&out).contains("b_broken.py"));
+}
+
+#[test]
+fn lint_exits_non_zero_on_issues_and_stops_early_with_fail_fast() {
+    let dir = project(&[("good.py", "x = 1\n")]);
+    run_ok(dir.path(), &["lint", "good.py"]);
+
+    let dir = project_with_broken_file();
+    std::fs::write(dir.path().join("src").join("d_broken.py"), "class (\n").unwrap();
+
+    let out = run(dir.path(), &["lint", "src", "--recursive", "--format", "json"]);
+    assert!(!out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["files_checked"], 4);
+    assert_eq!(report["issues_found"], 2);
+
+    let out = run(dir.path(), &["lint", "src", "--recursive", "--format", "json", "--fail-fast"]);
+    assert!(!out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    // Directory order is not fixed, but the second broken file is never reached
+    assert!(report["files_checked"].as_u64().unwrap() < 4, "{}", report);
+    assert_eq!(report["issues_found"], 1);
+}
+
+// ── --output ─────────────────────────────────────────────────────────
+
+#[test]
+fn results_go_to_the_output_file_and_stdout_stays_quiet() {
+    let dir = project(&[("app.py", "def greet():\n    return 1\n")]);
+
+    let out = run_ok(dir.path(), &["analyze", "app.py", "--output", "tree.json"]);
+    assert!(out.stdout.is_empty());
+    let results: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.path().join("tree.json")).unwrap()).unwrap();
+    assert_eq!(results[0]["tree"]["children"][0]["node_type"], "function_definition");
+
+    let out = run_ok(dir.path(), &["--output", "nodes.txt", "list", "app.py"]);
+    assert!(out.stdout.is_empty());
+    let listing = std::fs::read_to_string(dir.path().join("nodes.txt")).unwrap();
+    assert!(listing.contains("[function_definition] greet"));
+
+    let out = run_ok(dir.path(), &["history", "--output", "history.txt"]);
+    assert!(out.stdout.is_empty());
+    assert!(dir.path().join("history.txt").is_file());
+
+    let out = run_ok(dir.path(), &["export", "-o", "history.json"]);
+    assert!(out.stdout.is_empty());
+    let exported: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.path().join("history.json")).unwrap()).unwrap();
+    assert!(exported.is_array());
+}
+
+#[test]
+fn commands_without_a_result_to_write_refuse_output() {
+    let dir = project(&[("app.py", "x = 1\n")]);
+
+    let out = run(dir.path(), &["edit", "app.py", "0", "x = 2", "--output", "out.txt"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("--output only applies to"));
+    assert_eq!(std::fs::read_to_string(dir.path().join("app.py")).unwrap(), "x = 1\n");
+    assert!(!dir.path().join("out.txt").exists());
+
+    // Commands with their own output file take the global one too
+    run_ok(dir.path(), &["--output", "blueprint.md", "blueprint"]);
+    assert!(dir.path().join("blueprint.md").is_file());
+    run_ok(dir.path(), &["blueprint", "--output", "again.md"]);
+    assert!(dir.path().join("again.md").is_file());
+}
+
+// ── --kind ───────────────────────────────────────────────────────────
+
+#[test]
+fn kind_function_selects_functions_in_python_and_rust() {
+    let dir = project(&[
+        ("app.py", "import os\n\nclass Greeter:\n    def greet(self):\n        pass\n\ndef main():\n    pass\n"),
+        ("lib.rs", "use std::fs;\n\nstruct Greeter;\n\nimpl Greeter {\n    fn greet(&self) {}\n}\n\nfn main() {}\n"),
+    ]);
+    let list = |args: &[&str]| stdout(&run_ok(dir.path(), args));
+
+    let python = list(&["list", "app.py", "--kind", "function"]);
+    assert!(python.contains("[function_definition] main"), "{}", python);
+    assert!(!python.contains("greet"), "{}", python);
+
+    let rust = list(&["list", "lib.rs", "--kind", "function"]);
+    assert!(rust.contains("[function_item] main"), "{}", rust);
+    assert!(!rust.contains("greet"), "{}", rust);
+
+    let python = list(&["search", "app.py", "greet", "--kind", "method"]);
+    assert!(python.contains("Found 1 matches"), "{}", python);
+    assert!(python.contains("[function_definition] 'greet'"), "{}", python);
+
+    let rust = list(&["list", "lib.rs", "--kind", "class"]);
+    assert!(rust.contains("[struct_item] Greeter"), "{}", rust);
+
+    let out = run(dir.path(), &["list", "app.py", "--kind", "struct"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("expected one of function"));
+}
+
+// ── --color ──────────────────────────────────────────────────────────
+
+#[test]
+fn hints_are_plain_unless_color_is_on() {
+    let dir = project(&[("app.py", "x = 1\n")]);
+    let edit = |extra: &[&str]| {
+        let out = command(dir.path(), &["edit", "app.py", "0", "x = 2"])
+            .args(extra)
+            .env_remove("GNAW_NO_HINTS")
+            .env_remove("NO_COLOR")
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", stderr(&out));
+        stderr(&out)
+    };
+
+    // Piped, so `auto` means no color
+    let hints = edit(&[]);
+    assert!(hints.contains("[GnawTip]"), "{}", hints);
+    assert!(!hints.contains('\x1b'), "{:?}", hints);
+
+    let hints = edit(&["--color", "always"]);
+    assert!(hints.contains("\x1b[2m[GnawTip]"), "{:?}", hints);
+}
+
+// ── --tolerant ───────────────────────────────────────────────────────
+
+#[test]
+fn tolerant_analyze_and_list_keep_the_valid_functions() {
+    let source = "def first():\n    return 1\n\ndef broken(:\n    pass\n\ndef last():\n    return 2\n";
+    let dir = project(&[("app.py", source)]);
+
+    let out = run(dir.path(), &["analyze", "app.py"]);
+    assert!(stderr(&out).contains("Syntax error"), "{}", stderr(&out));
+    let out = run(dir.path(), &["list", "app.py"]);
+    assert!(!out.status.success());
+
+    let out = run_ok(dir.path(), &["analyze", "app.py", "--tolerant"]);
+    let results: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let text = results.to_string();
+    assert!(text.contains(r#""node_type":"error""#), "{}", text);
+    assert!(!text.contains(r#""node_type":"ERROR""#), "{}", text);
+
+    let listing = stdout(&run_ok(dir.path(), &["list", "app.py", "--tolerant", "--kind", "function"]));
+    assert!(listing.contains("first") && listing.contains("last"), "{}", listing);
+
+    // Edits still parse strictly
+    let out = run(dir.path(), &["edit", "app.py", "0", "def first():\n    return 10"]);
+    assert!(!out.status.success());
+    assert_eq!(std::fs::read_to_string(dir.path().join("app.py")).unwrap(), source);
+}
+
+#[test]
+fn tolerant_warns_for_formats_that_cannot_recover() {
+    let dir = project(&[
+        ("broken.json", "{\"a\": }\n"),
+        ("broken.go", "package main\n\nfunc main( {\n}\n"),
+    ]);
+
+    let out = run(dir.path(), &["list", "broken.json", "--tolerant"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("--tolerant has no effect"));
+
+    // Grammars without a strict mode load broken files with or without it
+    let out = run_ok(dir.path(), &["list", "broken.go", "--tolerant"]);
+    assert!(!stderr(&out).contains("--tolerant has no effect"));
+}
+
+// ── --parse-timeout ──────────────────────────────────────────────────
+
+#[test]
+fn deeply_nested_xml_times_out_instead_of_hanging() {
+    // Matching each closing tag rescans the rest of the document, so the
+    // XML parser is quadratic in the nesting depth; stay under the nesting
+    // limit so it is the timeout that stops it
+    let depth = 900;
+    let xml = format!("<r>{}{}</r>", "<a>".repeat(depth), "</a>".repeat(depth));
+    let dir = project(&[("deep.xml", &xml)]);
+
+    let started = Instant::now();
+    let out = run(dir.path(), &["analyze", "deep.xml", "--parse-timeout", "1"]);
+    assert!(started.elapsed() < Duration::from_secs(20), "took {:?}", started.elapsed());
+    assert!(stderr(&out).contains("parse timed out"), "{}", stderr(&out));
+}
+
+#[test]
+fn xml_nested_past_the_limit_is_refused_without_a_timeout() {
+    let depth = 2000;
+    let xml = format!("<r>{}{}</r>", "<a>".repeat(depth), "</a>".repeat(depth));
+    let dir = project(&[("deep.xml", &xml)]);
+
+    let started = Instant::now();
+    let out = run(dir.path(), &["analyze", "deep.xml", "--parse-timeout", "0"]);
+    assert!(started.elapsed() < Duration::from_secs(20), "took {:?}", started.elapsed());
+    assert!(stderr(&out).contains("nested more than"), "{}", stderr(&out));
+}
+
+// ── analyze from stdin ───────────────────────────────────────────────
+
+fn analyze_stdin(args: &[&str], source: &str) -> Output {
+    let dir = project(&[]);
+    let mut child = command(dir.path(), &["analyze"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn piped_python_source_is_parsed() {
+    let out = analyze_stdin(&["-", "--lang", "python"], "def greet():\n    return 1\n\nclass Box:\n    pass\n");
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let results: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(results[0]["file_path"], "-");
+    let types: Vec<&str> = results[0]["tree"]["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["node_type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["function_definition", "class_definition"]);
+
+    // Extensions work as well as names
+    let out = analyze_stdin(&["--stdin", "--lang", "py", "--format", "summary"], "x = 1\n");
+    assert!(stdout(&out).contains("File: -"));
+
+    let out = analyze_stdin(&["-"], "x = 1\n");
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("--lang"));
+}
+
+#[test]
+fn unknown_languages_and_mixed_paths_are_rejected() {
+    let out = analyze_stdin(&["-", "--lang", "pyhton"], "x = 1\n");
+    assert!(!out.status.success());
+    let err = stderr(&out);
+    assert!(err.contains("Unknown language 'pyhton'") && err.contains("python, rust"), "{}", err);
+
+    let out = analyze_stdin(&["-", "other.py", "--lang", "python"], "x = 1\n");
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("cannot be combined"));
+}
+
+// ── ai index --clear / --rebuild ─────────────────────────────────────
+
+#[test]
+fn clear_removes_index_directories_but_keeps_models() {
+    let dir = project(&[
+        (".gnawtreewriter_ai/index/stale.json", "{}"),
+        (".gnawtreewriter_ai/graph/stale.json", "{}"),
+        (".gnawtreewriter_ai/models/stale.json", "{}"),
+    ]);
+    let ai = dir.path().join(".gnawtreewriter_ai");
+
+    let out = run_ok(dir.path(), &["ai", "index", "--clear"]);
+    assert!(!ai.join("index").exists());
+    assert!(!ai.join("graph").exists());
+    assert!(ai.join("models").join("stale.json").exists());
+    assert!(stdout(&out).contains("Removed"));
+
+    // Clearing again has nothing to do
+    let out = run_ok(dir.path(), &["ai", "index", "--clear"]);
+    assert!(stdout(&out).contains("No index to clear."));
+}
+
+#[test]
+fn rebuild_of_a_directory_keeps_the_embeddings_of_other_files() {
+    use gnawtreewriter::llm::{NodeEmbedding, SemanticIndexManager};
+
+    let dir = project(&[]);
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    let manager = SemanticIndexManager::new(dir.path());
+    for file in ["src/a.py", "other.py"] {
+        let entry = NodeEmbedding {
+            file_path: file.to_string(),
+            node_path: "0".to_string(),
+            content_preview: String::new(),
+            vector: vec![1.0],
+        };
+        manager.save_index(file, vec![entry]).unwrap();
+    }
+
+    // No model is installed, so indexing fails after the rebuild step
+    run(dir.path(), &["ai", "index", "src", "--rebuild"]);
+
+    let left: Vec<String> = manager.load_project_index().unwrap().entries.into_iter().map(|e| e.file_path).collect();
+    if cfg!(feature = "modernbert") {
+        assert_eq!(left, vec!["other.py".to_string()]);
+    } else {
+        // A build that cannot re-index deletes nothing
+        assert_eq!(left.len(), 2);
+    }
+}
+
+// ── project root ─────────────────────────────────────────────────────
+
+#[test]
+fn nearest_marker_wins() {
+    let dir = project(&[("packages/app/src/main.py", "x = 1\n")]);
+    let root = std::fs::canonicalize(dir.path()).unwrap();
+    let sub = root.join("packages").join("app");
+    let file = sub.join("src").join("main.py");
+
+    assert_eq!(find_project_root(&file), root);
+
+    // A `.gnawtreewriter.toml` config file marks a nested project root
+    std::fs::write(sub.join(".gnawtreewriter.toml"), "").unwrap();
+    assert_eq!(find_project_root(&file), sub);
+}
+
+#[test]
+fn env_var_overrides_detected_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkout = dir.path().join("checkout");
+    let state = dir.path().join("state");
+    std::fs::create_dir_all(checkout.join(".git")).unwrap();
+    std::fs::create_dir(&state).unwrap();
+    let file = checkout.join("a.py");
+    std::fs::write(&file, "x = 1\n").unwrap();
+
+    // Run in a child process so the variable doesn't leak into other tests
+    let out = command(&checkout, &["edit", file.to_str().unwrap(), "0", "x = 2"])
+        .env("GNAW_PROJECT_ROOT", &state)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(std::fs::read_to_string(&file).unwrap().contains("x = 2"));
+
+    assert!(state.join(".gnawtreewriter_backups").is_dir());
+    assert!(!checkout.join(".gnawtreewriter_backups").exists());
+}
+
+#[test]
+fn project_below_a_target_directory_is_still_scanned() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("target").join("checkout");
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("src").join("a.py"), "x = 1\n").unwrap();
+
+    let out = run_ok(&root, &["analyze", "src", "--recursive", "--format", "summary"]);
+    assert!(stdout(&out).contains("Analyzed 1 files"));
+}
@@ -0,0 +1,915 @@
+//! Tests for node-scoped operations and lookups on `GnawTreeWriter`.
+
+use std::io::Write;
+
+use gnawtreewriter::core::{EditOperation, EditOptions, GnawError};
+use gnawtreewriter::{GnawTreeWriter, TreeNode};
+
+fn make_project(name: &str, content: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    let path = dir.path().join(name);
+    let mut f = std::fs::File::create(&path).unwrap();
+    f.write_all(content.as_bytes()).unwrap();
+    (dir, path)
+}
+
+fn find_named<'a>(node: &'a TreeNode, node_type: &str, name: &str) -> Option<&'a TreeNode> {
+    if node.node_type == node_type && node.get_name().as_deref() == Some(name) {
+        return Some(node);
+    }
+    node.children
+        .iter()
+        .find_map(|c| find_named(c, node_type, name))
+}
+
+// ── replace_in_node ──────────────────────────────────────────────────
+
+#[test]
+fn replace_in_node_only_touches_target_function() {
+    let src = "fn first() -> i32 {\n    let total = 1;\n    total + total\n}\n\nfn second() -> i32 {\n    let total = 2;\n    total\n}\n";
+    let (_dir, path) = make_project("lib.rs", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let first = find_named(writer.analyze(), "function_item", "first")
+        .expect("first function")
+        .path
+        .clone();
+
+    let replaced = writer
+        .replace_in_node(&first, "total", "sum", None)
+        .unwrap();
+    assert_eq!(replaced, 3);
+
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert!(result.contains("    let sum = 1;\n    sum + sum\n"));
+    assert!(result.contains("    let total = 2;\n    total\n"), "second fn changed:\n{}", result);
+}
+
+#[test]
+fn replace_in_node_respects_count_and_reports_missing() {
+    let src = "def f():\n    a = 1\n    a = a + 1\n    return a\n";
+    let (_dir, path) = make_project("f.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let func = find_named(writer.analyze(), "function_definition", "f")
+        .expect("function")
+        .path
+        .clone();
+
+    assert_eq!(writer.replace_in_node(&func, "a", "b", Some(1)).unwrap(), 1);
+    assert!(std::fs::read_to_string(&path).unwrap().contains("    b = 1\n    a = a + 1\n"));
+
+    assert!(writer.replace_in_node(&func, "missing", "x", None).is_err());
+}
+
+// ── comment / uncomment ──────────────────────────────────────────────
+
+#[test]
+fn comment_and_uncomment_rust_function() {
+    let src = "fn keep() {}\n\nfn disable_me() {\n    println!(\"hi\");\n}\n";
+    let (_dir, path) = make_project("lib.rs", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let target = find_named(writer.analyze(), "function_item", "disable_me")
+        .expect("function")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Comment { node_path: target }, false)
+        .unwrap();
+
+    let commented = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        commented,
+        "fn keep() {}\n\n// fn disable_me() {\n//     println!(\"hi\");\n// }\n"
+    );
+
+    let comment = writer
+        .analyze()
+        .children
+        .iter()
+        .find(|n| n.node_type == "line_comment")
+        .expect("comment node")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Uncomment { node_path: comment }, false)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), src);
+}
+
+#[test]
+fn uncomment_takes_the_whole_commented_block_but_not_the_note_after_it() {
+    let src = "def keep():\n    pass\n\ndef disable_me():\n    x = 1\n\n    return x\n# unrelated note\n";
+    let (_dir, path) = make_project("app.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let target = find_named(writer.analyze(), "function_definition", "disable_me")
+        .expect("function")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Comment { node_path: target }, false)
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "def keep():\n    pass\n\n# def disable_me():\n#     x = 1\n\n#     return x\n# unrelated note\n"
+    );
+
+    let comment = writer
+        .analyze()
+        .children
+        .iter()
+        .find(|n| n.node_type == "comment")
+        .expect("comment node")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Uncomment { node_path: comment }, false)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), src);
+}
+
+#[test]
+fn uncomment_leaves_adjacent_comments_alone() {
+    let src = "fn f() {\n    // keep this note\n    println!(\"hi\");\n    // and this one\n}\n";
+    let (_dir, path) = make_project("lib.rs", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_where<'a>(node: &'a TreeNode, pred: &dyn Fn(&TreeNode) -> bool) -> Option<&'a TreeNode> {
+        if pred(node) {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find_where(c, pred))
+    }
+
+    let stmt = find_where(writer.analyze(), &|n| {
+        n.node_type == "expression_statement" && n.content.starts_with("println!")
+    })
+    .expect("statement")
+    .path
+    .clone();
+    writer
+        .edit(EditOperation::Comment { node_path: stmt }, false)
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "fn f() {\n    // keep this note\n    // println!(\"hi\");\n    // and this one\n}\n"
+    );
+
+    let comment = find_where(writer.analyze(), &|n| {
+        n.node_type == "line_comment" && n.content.contains("println!")
+    })
+    .expect("comment node")
+    .path
+    .clone();
+    writer
+        .edit(EditOperation::Uncomment { node_path: comment }, false)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), src);
+}
+
+#[test]
+fn comment_handles_multibyte_leading_whitespace() {
+    // U+3000 is whitespace three bytes long, so byte-counted indents land inside it
+    let src = "function f(x) {\n  if (x) {\n\u{3000}y();\n  }\n}\n";
+    let (_dir, path) = make_project("a.js", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_type<'a>(node: &'a TreeNode, node_type: &str) -> Option<&'a TreeNode> {
+        if node.node_type == node_type {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find_type(c, node_type))
+    }
+
+    let target = find_type(writer.analyze(), "if_statement")
+        .expect("if statement")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Comment { node_path: target }, false)
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "function f(x) {\n//   if (x) {\n// \u{3000}y();\n//   }\n}\n"
+    );
+}
+
+#[test]
+fn comment_and_uncomment_html_element() {
+    let src = "<div>\n<p>hello</p>\n</div>\n";
+    let (_dir, path) = make_project("index.html", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_where<'a>(node: &'a TreeNode, pred: &dyn Fn(&TreeNode) -> bool) -> Option<&'a TreeNode> {
+        if pred(node) {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find_where(c, pred))
+    }
+
+    let p = find_where(writer.analyze(), &|n| {
+        n.node_type == "element" && n.content.starts_with("<p>")
+    })
+    .expect("p element")
+    .path
+    .clone();
+    writer
+        .edit(EditOperation::Comment { node_path: p }, false)
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "<div>\n<!-- <p>hello</p> -->\n</div>\n"
+    );
+
+    let comment = find_where(writer.analyze(), &|n| n.node_type == "comment")
+        .expect("comment node")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Uncomment { node_path: comment }, false)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), src);
+}
+
+// ── node_at_line ─────────────────────────────────────────────────────
+
+#[test]
+fn node_at_line_returns_deepest_node_in_nested_function() {
+    let src = "def outer():\n    def inner():\n        return 42\n    return inner\n";
+    let (_dir, path) = make_project("nested.py", src);
+    let writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let inner = find_named(writer.analyze(), "function_definition", "inner")
+        .expect("inner function")
+        .path
+        .clone();
+
+    let node = writer.node_at_line(3).expect("node at line 3");
+    assert!(
+        node.path.starts_with(&format!("{}.", inner)),
+        "{} should be nested under inner ({})",
+        node.path,
+        inner
+    );
+    assert!(node.start_line <= 3 && node.end_line >= 3);
+    assert!(
+        node.children.iter().all(|c| c.start_line > 3 || c.end_line < 3),
+        "a child of {} also contains line 3",
+        node.path
+    );
+
+    assert!(writer.node_at_line(99).is_none());
+}
+
+#[test]
+fn show_with_line_numbers_starts_at_node_start_line() {
+    let src = "import os\n\n\ndef greet(name):\n    return name\n";
+    let (_dir, path) = make_project("greet.py", src);
+    let writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let node = find_named(writer.analyze(), "function_definition", "greet").unwrap();
+    let numbered = writer.show_node_with_line_numbers(&node.path).unwrap();
+    let lines: Vec<&str> = numbered.lines().collect();
+    assert_eq!(lines, ["4 | def greet(name):", "5 |     return name"]);
+    let first: usize = lines[0].split(" | ").next().unwrap().trim().parse().unwrap();
+    assert_eq!(first, node.start_line);
+}
+
+// ── Encoding ─────────────────────────────────────────────────────────
+
+#[test]
+fn bom_is_stripped_for_parsing_and_restored_on_write() {
+    let src = "\u{feff}x = 1\ny = 2\n";
+    let (_dir, path) = make_project("bom.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let first = &writer.analyze().children[0];
+    assert_eq!(first.content, "x = 1");
+    let first_path = first.path.clone();
+
+    writer
+        .edit(
+            EditOperation::Edit {
+                node_path: first_path,
+                content: "x = 10".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(bytes.starts_with(&[0xEF, 0xBB, 0xBF]), "BOM should be preserved");
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.starts_with("\u{feff}x = 10\ny = 2"), "got {:?}", text);
+}
+
+#[test]
+fn latin1_file_gives_clear_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("latin1.py");
+    // "café" in Latin-1: 0xE9 is not valid UTF-8 on its own
+    std::fs::write(&path, b"name = 'caf\xe9'\n").unwrap();
+
+    let err = GnawTreeWriter::new(path.to_str().unwrap())
+        .err()
+        .expect("non-UTF-8 file should be rejected")
+        .to_string();
+    assert!(err.contains("not valid UTF-8"), "got: {}", err);
+    assert!(err.contains("offset 11"), "got: {}", err);
+}
+
+#[test]
+fn latin1_file_is_re_encoded_on_write() {
+    let (_dir, path) = make_project("latin1.py", "");
+    std::fs::write(&path, b"name = 'caf\xe9'\ncount = 1\n").unwrap();
+
+    let latin1 = gnawtreewriter::core::parse_encoding("latin1").unwrap();
+    let mut writer = GnawTreeWriter::new_with_encoding(path.to_str().unwrap(), latin1).unwrap();
+    assert!(writer.get_source().starts_with("name = 'café'"));
+
+    let target = writer.analyze().children[1].path.clone();
+    writer
+        .edit(EditOperation::Edit { node_path: target, content: "count = 'né'".into() }, false)
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"name = 'caf\xe9'\ncount = 'n\xe9'");
+
+    // Characters Latin-1 cannot hold are refused instead of being mangled
+    let target = writer.analyze().children[1].path.clone();
+    let err = writer
+        .edit(EditOperation::Edit { node_path: target, content: "count = '→'".into() }, false)
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot be written as windows-1252"), "got: {}", err);
+}
+
+#[test]
+fn undo_of_a_latin1_edit_writes_latin1() {
+    let (_dir, path) = make_project("latin1.py", "");
+    std::fs::write(&path, b"name = 'caf\xe9'\ncount = 1\n").unwrap();
+
+    let latin1 = gnawtreewriter::core::parse_encoding("latin1").unwrap();
+    let mut writer = GnawTreeWriter::new_with_encoding(path.to_str().unwrap(), latin1).unwrap();
+    let target = writer.analyze().children[1].path.clone();
+    writer
+        .edit(EditOperation::Edit { node_path: target, content: "count = 2".into() }, false)
+        .unwrap();
+
+    let root = gnawtreewriter::core::find_project_root(&path);
+    let results = gnawtreewriter::core::undo_redo::UndoRedoManager::new(&root).unwrap().undo(1).unwrap();
+    assert!(results[0].success, "{}", results[0].message);
+    assert_eq!(std::fs::read(&path).unwrap(), b"name = 'caf\xe9'\ncount = 1\n");
+}
+
+#[test]
+fn utf16_is_refused_as_an_encoding() {
+    let err = gnawtreewriter::core::parse_encoding("utf-16le").unwrap_err();
+    assert!(err.contains("cannot be written back"), "got: {}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_replaces_the_file_atomically_and_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dir, path) = make_project("run.py", "def main():\n    return 1\n");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+    let link = dir.path().join("link.py");
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+
+    // Editing through the symlink writes the file it points to
+    let mut writer = GnawTreeWriter::new(link.to_str().unwrap()).unwrap();
+    let target = writer.analyze().children[0].path.clone();
+    let content = "def main():\n    return 2\n".repeat(200);
+    writer.edit(EditOperation::Edit { node_path: target, content: content.clone() }, false).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), content.trim_end());
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+    assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().contains("gnaw-tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "temporary file left behind: {:?}", leftovers);
+}
+
+#[cfg(unix)]
+#[test]
+fn executable_bit_survives_edit_and_restore() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let original = "#!/usr/bin/env python3\nprint('old')\n";
+    let (dir, path) = make_project("tool.py", original);
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let target = writer.analyze().children.last().unwrap().path.clone();
+    writer.edit(EditOperation::Edit { node_path: target, content: "print('new')".into() }, false).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains("print('new')"));
+    assert_eq!(mode(&path), 0o755);
+
+    // The backup taken before the edit brings back the mode along with the content
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let backup = std::fs::read_dir(dir.path().join(".gnawtreewriter_backups"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.file_name().unwrap().to_string_lossy().starts_with("tool.py_backup_"))
+        .expect("backup");
+    gnawtreewriter::core::backup::restore_from_backup(&backup, &path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+    assert_eq!(mode(&path), 0o755);
+
+    // A chmod between loading and writing is kept, not reverted to the
+    // mode the file had when it was loaded
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+    let target = writer.analyze().children.last().unwrap().path.clone();
+    writer.edit(EditOperation::Edit { node_path: target, content: "print('newer')".into() }, false).unwrap();
+    assert_eq!(mode(&path), 0o700);
+}
+
+// ── replace_lines ────────────────────────────────────────────────────
+
+#[test]
+fn replace_lines_swaps_range_and_logs_transaction() {
+    let src = "a = 1\nb = 2\nc = 3\nd = 4\n";
+    let (dir, path) = make_project("vals.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let op = EditOperation::ReplaceLines { start_line: 2, end_line: 3, content: "bc = 23".into() };
+    writer.edit(op, false).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a = 1\nbc = 23\nd = 4\n");
+
+    let log = gnawtreewriter::core::TransactionLog::load(dir.path()).unwrap();
+    let last = log.get_last_n_transactions(1).unwrap();
+    assert_eq!(last[0].description, "Replaced lines 2-3");
+
+    let out_of_range = EditOperation::ReplaceLines { start_line: 3, end_line: 9, content: "x".into() };
+    assert!(writer.edit(out_of_range, false).is_err());
+    let broken = EditOperation::ReplaceLines { start_line: 1, end_line: 1, content: "a = (".into() };
+    assert!(writer.edit(broken, false).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a = 1\nbc = 23\nd = 4\n");
+}
+
+// ── missing paths ────────────────────────────────────────────────────
+
+#[test]
+fn missing_path_error_suggests_nearby_paths() {
+    let (_dir, path) = make_project("app.py", "def f():\n    pass\n");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let op = EditOperation::Edit { node_path: "0.9".into(), content: "pass".into() };
+    let err = writer.edit(op, false).unwrap_err();
+    let message = err.to_string();
+    assert!(message.starts_with("Node not found: 0.9 (did you mean "), "{}", message);
+    assert!(message.contains("0.1") && message.contains("0.2"), "{}", message);
+}
+
+// ── unsafe edit options ──────────────────────────────────────────────
+
+#[test]
+fn no_backup_skips_backup_file() {
+    let (dir, path) = make_project("app.py", "x = 1\ny = 2\n");
+    let backups = dir.path().join(".gnawtreewriter_backups");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    let op = EditOperation::Edit { node_path: "0".into(), content: "x = 10".into() };
+    let options = EditOptions { no_backup: true, ..EditOptions::default() };
+    writer.edit_with_options(op, options).unwrap();
+
+    assert!(std::fs::read_to_string(&path).unwrap().contains("x = 10"));
+    let count = std::fs::read_dir(&backups).map(|d| d.count()).unwrap_or(0);
+    assert_eq!(count, 0, "no backup should be written with no_backup");
+
+    writer
+        .edit(EditOperation::Edit { node_path: "1".into(), content: "y = 20".into() }, false)
+        .unwrap();
+    assert_eq!(std::fs::read_dir(&backups).unwrap().count(), 1);
+}
+
+#[test]
+fn no_validate_writes_unparseable_result() {
+    let (_dir, path) = make_project("lib.rs", "fn ok() {}\n");
+    let broken = "fn ok( {";
+
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let op = EditOperation::Edit { node_path: "0".into(), content: broken.into() };
+    assert!(writer.edit(op.clone(), true).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn ok() {}\n");
+
+    let options = EditOptions { force: true, no_validate: true, ..EditOptions::default() };
+    writer.edit_with_options(op, options).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains(broken));
+}
+
+#[test]
+fn strict_type_rejects_function_replaced_by_class() {
+    let source = "def load():\n    return 1\n\nx = 2\n";
+    let (_dir, path) = make_project("app.py", source);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let strict = EditOptions { strict_type: true, ..EditOptions::default() };
+
+    let to_class = EditOperation::Edit { node_path: "0".into(), content: "class Load:\n    pass".into() };
+    let err = writer.edit_with_options(to_class.clone(), strict.clone()).unwrap_err();
+    assert!(err.to_string().contains("function_definition"), "{}", err);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), source);
+
+    let same_type = EditOperation::Edit { node_path: "0".into(), content: "def load():\n    return 5".into() };
+    writer.edit_with_options(same_type, strict).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains("return 5"));
+
+    // Without strict mode the structural change goes through
+    writer.edit(to_class, true).unwrap();
+    assert_eq!(writer.analyze().children[0].node_type, "class_definition");
+}
+
+#[test]
+fn in_memory_buffer_edit_returns_new_source() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    let hint = dir.path().join("unsaved.py");
+
+    let mut writer =
+        GnawTreeWriter::from_source(hint.to_str().unwrap(), "def a():\n    return 1\n\nb = 2\n".into()).unwrap();
+    assert_eq!(writer.analyze().children[0].node_type, "function_definition");
+
+    let op = EditOperation::Edit { node_path: "1".into(), content: "b = 3".into() };
+    let source = writer.edit_in_memory(op, false).unwrap();
+    assert_eq!(source, "def a():\n    return 1\n\nb = 3");
+    assert_eq!(writer.get_source(), source);
+    assert_eq!(writer.analyze().children[1].content, "b = 3");
+
+    assert!(!hint.exists(), "in-memory edits must not create the file");
+    assert!(!dir.path().join(".gnawtreewriter_backups").exists());
+}
+
+// ── HTML ─────────────────────────────────────────────────────────────
+
+#[test]
+fn edit_inline_script_keeps_the_surrounding_tags() {
+    let src = "<div><script>x()</script></div>\n";
+    let (_dir, path) = make_project("index.html", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_type<'a>(node: &'a TreeNode, node_type: &str) -> Option<&'a TreeNode> {
+        if node.node_type == node_type {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find_type(c, node_type))
+    }
+
+    let call = find_type(writer.analyze(), "call_expression")
+        .expect("embedded call")
+        .path
+        .clone();
+    writer
+        .edit(EditOperation::Edit { node_path: call, content: "y(1)".into() }, false)
+        .unwrap();
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(result.trim_end(), "<div><script>y(1)</script></div>");
+}
+
+// ── Ruby ─────────────────────────────────────────────────────────────
+
+#[test]
+fn ruby_class_lists_methods_and_edits_one() {
+    let src = "module Shop\n  class Cart\n    def self.build\n      new\n    end\n\n    def total\n      1\n    end\n\n    def empty?\n      total.zero?\n    end\n  end\nend\n";
+    let (_dir, path) = make_project("cart.rb", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let tree = writer.analyze();
+
+    let module = find_named(tree, "module", "Shop").expect("module");
+    let class = find_named(module, "class", "Cart").expect("class nested in module");
+    let build = find_named(class, "singleton_method", "build").expect("singleton method");
+    let total = find_named(class, "method", "total").expect("method nested in class");
+    assert!(find_named(class, "method", "empty?").is_some());
+    assert!(total.path.starts_with(&format!("{}.", class.path)));
+    assert!(build.path.starts_with(&format!("{}.", class.path)));
+    assert_eq!((total.start_line, total.end_line), (7, 9));
+
+    let total_path = total.path.clone();
+    writer
+        .edit(
+            EditOperation::Edit {
+                node_path: total_path,
+                content: "    def total\n      42\n    end".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert!(result.contains("    def total\n      42\n    end\n"), "{}", result);
+    assert!(result.contains("    def empty?\n      total.zero?\n    end\n"));
+}
+
+// ── Shell ────────────────────────────────────────────────────────────
+
+#[test]
+fn shell_function_is_named_and_edited_by_path() {
+    let src = "NAME=world\n\ngreet() {\n  echo \"hello $NAME\"\n}\n\nfunction bye {\n  echo bye\n}\n\ngreet\n";
+    let (_dir, path) = make_project("deploy.sh", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let tree = writer.analyze();
+
+    assert!(find_named(tree, "variable_assignment", "NAME").is_some());
+    assert!(find_named(tree, "command", "greet").is_some());
+    assert!(find_named(tree, "function_definition", "greet").is_some());
+    let bye = find_named(tree, "function_definition", "bye").expect("function keyword form");
+    assert_eq!((bye.start_line, bye.end_line), (7, 9));
+
+    let bye_path = bye.path.clone();
+    writer
+        .edit(
+            EditOperation::Edit {
+                node_path: bye_path,
+                content: "function bye {\n  echo \"goodbye $NAME\"\n}".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert!(result.contains("function bye {\n  echo \"goodbye $NAME\"\n}\n"), "{}", result);
+    assert!(result.contains("greet() {\n  echo \"hello $NAME\"\n}\n"));
+}
+
+// ── C / C++ ──────────────────────────────────────────────────────────
+
+#[test]
+fn c_struct_fields_are_listed_and_function_edited() {
+    let src = "#include <stdio.h>\n#define MAX 10\n\nstruct point {\n    int x;\n    int y;\n};\n\nint add(int a, int b) {\n    return a + b;\n}\n";
+    let (_dir, path) = make_project("math.c", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let tree = writer.analyze();
+
+    assert_eq!(tree.children[0].node_type, "preproc_include");
+    assert!(find_named(tree, "preproc_def", "MAX").is_some());
+    let point = find_named(tree, "struct_specifier", "point").expect("struct");
+    let fields: Vec<String> = point
+        .children
+        .iter()
+        .flat_map(|c| &c.children)
+        .filter(|c| c.node_type == "field_declaration")
+        .filter_map(|c| c.get_name())
+        .collect();
+    assert_eq!(fields, vec!["x", "y"]);
+
+    let add_path = find_named(tree, "function_definition", "add").expect("function").path.clone();
+    writer
+        .edit(
+            EditOperation::Edit {
+                node_path: add_path,
+                content: "int add(int a, int b) {\n    return b + a;\n}".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert!(result.contains("struct point {\n    int x;\n    int y;\n};\n"));
+    assert!(result.contains("int add(int a, int b) {\n    return b + a;\n}"), "{:?}", result);
+}
+
+#[test]
+fn header_with_declarations_only_and_cpp_header_parse() {
+    let (_dir, c_header) = make_project("math.h", "int add(int a, int b);\nstruct point;\n");
+    let writer = GnawTreeWriter::new(c_header.to_str().unwrap()).unwrap();
+    assert!(find_named(writer.analyze(), "declaration", "add").is_some());
+    assert!(find_named(writer.analyze(), "struct_specifier", "point").is_some());
+
+    let (_dir, cpp_header) = make_project("shape.h", "namespace geo {\nclass Shape {\npublic:\n    virtual double area() const = 0;\n};\n}\n");
+    let writer = GnawTreeWriter::new(cpp_header.to_str().unwrap()).unwrap();
+    assert!(find_named(writer.analyze(), "class_specifier", "Shape").is_some());
+}
+
+// ── TypeScript ───────────────────────────────────────────────────────
+
+#[test]
+fn typescript_decorated_method_is_edited_by_name() {
+    let src = "interface Props {\n  title: string;\n}\n\n@Component({ selector: 'app' })\nexport class App {\n  @HostListener('click')\n  onClick(): void {\n    console.log('old');\n  }\n\n  render(): string {\n    return '';\n  }\n}\n";
+    let (_dir, path) = make_project("app.ts", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    assert!(find_named(writer.analyze(), "interface_declaration", "Props").is_some());
+    assert!(find_named(writer.analyze(), "method_definition", "render").is_some());
+
+    let op = EditOperation::Edit {
+        node_path: "@method:onClick".into(),
+        content: "  @HostListener('dblclick')\n  onClick(): void {\n    console.log('new');\n  }".into(),
+    };
+    writer.edit(op, false).unwrap();
+
+    let result = std::fs::read_to_string(&path).unwrap();
+    assert!(result.contains("  @HostListener('dblclick')\n  onClick(): void {\n    console.log('new');\n  }\n"), "{}", result);
+    assert!(!result.contains("'click'"), "old decorator left behind:\n{}", result);
+    assert!(result.contains("@Component({ selector: 'app' })\nexport class App {"));
+}
+
+// ── QML add-component ────────────────────────────────────────────────
+
+#[test]
+fn qml_component_with_multiline_content_is_nested() {
+    let (_dir, path) = make_project("main.qml", "Item {\n  Rectangle {\n    width: 10\n  }\n}\n");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let rect = find_named(writer.analyze(), "ui_object_definition", "Rectangle").unwrap().path.clone();
+
+    // Content written with 4-space nesting is re-indented to the file's 2 spaces
+    let op = writer
+        .qml_component_insert(&rect, "Text", Some("text: \"hi\"\nanchors {\n    fill: parent\n}"))
+        .unwrap();
+    writer.edit(op, false).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "Item {\n  Rectangle {\n    width: 10\n    Text {\n      text: \"hi\"\n      anchors {\n        fill: parent\n      }\n    }\n  }\n}"
+    );
+    let rect = find_named(writer.analyze(), "ui_object_definition", "Rectangle").unwrap();
+    assert!(find_named(rect, "ui_object_definition", "Text").is_some());
+
+    // Leading whitespace of different byte lengths is not cut mid-character
+    let op = writer.qml_component_insert(&rect.path, "Text", Some("\u{3000}text: \"hi\"\n  width: 1")).unwrap();
+    let EditOperation::Insert { content, .. } = op else { panic!("{:?}", op) };
+    assert_eq!(content, "Text {\n  \u{3000}text: \"hi\"\n    width: 1\n}");
+
+    let (_dir, py) = make_project("app.py", "x = 1\n");
+    let writer = GnawTreeWriter::new(py.to_str().unwrap()).unwrap();
+    let err = writer.qml_component_insert("0", "Text", None).unwrap_err();
+    assert!(err.to_string().contains("only works on QML"), "{}", err);
+}
+
+#[test]
+fn qml_imports_are_named_by_module_and_new_ones_go_after_them() {
+    let src = "pragma Singleton\nimport QtQuick 2.15\nimport QtQuick.Controls 2.15 as QQC\nimport \"components\"\n\nItem {\n    width: 10\n}\n";
+    let (_dir, path) = make_project("main.qml", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let header: Vec<(String, String)> = writer
+        .analyze()
+        .children
+        .iter()
+        .filter(|n| matches!(n.node_type.as_str(), "ui_import" | "ui_pragma"))
+        .map(|n| (n.node_type.clone(), n.get_name().unwrap_or_default()))
+        .collect();
+    let expected = [
+        ("ui_pragma", "Singleton"),
+        ("ui_import", "QtQuick"),
+        ("ui_import", "QtQuick.Controls"),
+        ("ui_import", "components"),
+    ];
+    assert_eq!(header, expected.map(|(t, n)| (t.to_string(), n.to_string())));
+
+    let op = EditOperation::Insert {
+        parent_path: "root".into(),
+        position: 2,
+        content: "import QtQuick.Layouts 1.15".into(),
+    };
+    writer.edit(op, false).unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        written.starts_with("pragma Singleton\nimport QtQuick 2.15\nimport QtQuick.Controls 2.15 as QQC\nimport \"components\"\nimport QtQuick.Layouts 1.15\n\nItem {"),
+        "{}",
+        written
+    );
+}
+
+// ── stable ids ───────────────────────────────────────────────────────
+
+#[test]
+fn node_id_survives_an_unrelated_sibling_insert() {
+    let (_dir, path) = make_project("app.py", "def first():\n    return 1\n\ndef second():\n    return 2\n");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let before = find_named(writer.analyze(), "function_definition", "second").unwrap().clone();
+    assert_ne!(before.id, before.path);
+
+    let op = EditOperation::Insert {
+        parent_path: String::new(),
+        position: 0,
+        content: "import os\n\ndef helper():\n    pass\n".into(),
+    };
+    writer.edit(op, false).unwrap();
+
+    let writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let after = find_named(writer.analyze(), "function_definition", "second").unwrap();
+    assert_ne!(after.path, before.path);
+    assert_eq!(after.id, before.id);
+    let first = find_named(writer.analyze(), "function_definition", "first").unwrap();
+    assert_ne!(first.id, after.id);
+}
+
+// ── reindent ─────────────────────────────────────────────────────────
+
+#[test]
+fn reindent_moves_a_nested_block_out_one_level() {
+    let src = "def f(xs):\n    for x in xs:\n        total = x\n        if x:\n            print(x)\n\n            print(-x)\n    return xs\n";
+    let (_dir, path) = make_project("app.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    let if_path = writer.analyze().iter().find(|n| n.node_type == "if_statement").unwrap().path.clone();
+
+    let dedent = EditOperation::Reindent { node_path: if_path.clone(), delta: -1 };
+    writer.edit(dedent, false).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "def f(xs):\n    for x in xs:\n        total = x\n    if x:\n        print(x)\n\n        print(-x)\n    return xs\n"
+    );
+
+    // `def` at column 0 cannot lose another level
+    let err = writer.edit(EditOperation::Reindent { node_path: "0".into(), delta: -1 }, false).unwrap_err();
+    assert!(err.to_string().contains("not indented that far"), "{}", err);
+}
+
+#[test]
+fn reindent_leaves_the_inside_of_multiline_strings_alone() {
+    let src = "def f():\n    \"\"\"Doc\n  kept as is\n    \"\"\"\n    s = '''a\nb'''\n    return s\n";
+    let (_dir, path) = make_project("app.py", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    writer.edit(EditOperation::Reindent { node_path: "0".into(), delta: 1 }, false).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "    def f():\n        \"\"\"Doc\n  kept as is\n    \"\"\"\n        s = '''a\nb'''\n        return s\n"
+    );
+
+    let src = "fn f() {\n    let s = r#\"a\n  b\"#;\n}\n";
+    let (_dir, path) = make_project("lib.rs", src);
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    writer.edit(EditOperation::Reindent { node_path: "0".into(), delta: 1 }, false).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "    fn f() {\n        let s = r#\"a\n  b\"#;\n    }\n");
+}
+
+#[test]
+fn reindent_by_a_huge_number_of_levels_is_refused() {
+    let (_dir, path) = make_project("app.py", "x = 1\n");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    for delta in [i32::MAX, i32::MIN] {
+        let err = writer.edit(EditOperation::Reindent { node_path: "0".into(), delta }, false).unwrap_err();
+        assert!(err.to_string().contains("at most"), "{}", err);
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 1\n");
+}
+
+// ── external changes ─────────────────────────────────────────────────
+
+#[test]
+fn edit_is_rejected_when_the_file_changed_on_disk() {
+    let (_dir, path) = make_project("app.py", "x = 1\ny = 2\n");
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    std::fs::write(&path, "x = 1\ny = 2\nz = 3\n").unwrap();
+
+    let edit = || EditOperation::Edit { node_path: "0".into(), content: "x = 10".into() };
+    let err = writer.edit(edit(), false).unwrap_err();
+    assert!(err.to_string().contains("file changed on disk"), "{}", err);
+    // A conflict, not a rejected edit
+    let kind = GnawError::classify(&err).unwrap();
+    assert_eq!((kind.kind, kind.code), ("conflict", -32010));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 1\ny = 2\nz = 3\n");
+
+    // A writer's own writes do not count as external changes
+    let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+    writer.edit(edit(), false).unwrap();
+    writer.edit(EditOperation::Edit { node_path: "1".into(), content: "y = 20".into() }, false).unwrap();
+
+    std::fs::write(&path, "x = 0\n").unwrap();
+    writer.edit(edit(), true).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().starts_with("x = 10"));
+}
+
+// ── insert before a closing delimiter ────────────────────────────────
+
+#[test]
+fn append_into_brace_blocks_lines_up_with_the_body() {
+    fn find_type<'a>(node: &'a TreeNode, node_type: &str) -> Option<&'a TreeNode> {
+        if node.node_type == node_type {
+            return Some(node);
+        }
+        node.children.iter().find_map(|c| find_type(c, node_type))
+    }
+
+    let append = |name: &str, src: &str, block: &str, content: &str| -> String {
+        let (_dir, path) = make_project(name, src);
+        let mut writer = GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+        let parent_path = find_type(writer.analyze(), block).expect(block).path.clone();
+        let op = EditOperation::Insert { parent_path, position: 1, content: content.to_string() };
+        writer.edit(op, false).unwrap();
+        std::fs::read_to_string(&path).unwrap()
+    };
+
+    // Non-empty body: the new statement takes the last statement's indent
+    assert_eq!(
+        append("lib.rs", "fn main() {\n    let a = 1;\n}\n", "block", "let b = 2;").trim_end(),
+        "fn main() {\n    let a = 1;\n    let b = 2;\n}"
+    );
+    // Empty body: one indent unit below the line that opens the block
+    assert_eq!(
+        append("lib.rs", "mod m {\n    fn f() {\n    }\n}\n", "block", "g();").trim_end(),
+        "mod m {\n    fn f() {\n        g();\n    }\n}"
+    );
+    assert_eq!(
+        append("app.js", "function f() {\n  if (x) {\n    a();\n  }\n}\n", "statement_block", "b();").trim_end(),
+        "function f() {\n  if (x) {\n    a();\n  }\n  b();\n}"
+    );
+    assert_eq!(
+        append("main.go", "package main\n\nfunc main() {\n\ta()\n}\n", "block", "b()").trim_end(),
+        "package main\n\nfunc main() {\n\ta()\n\tb()\n}"
+    );
+}
@@ -0,0 +1,88 @@
+//! Tests for the `--max-file-size` guard. Kept in its own binary because the
+//! limit is process-wide.
+
+use gnawtreewriter::core::{set_max_file_size, GnawError};
+use gnawtreewriter::GnawTreeWriter;
+
+#[test]
+fn file_over_limit_is_refused_before_parsing() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    let limit = 1024;
+    set_max_file_size(limit);
+
+    let at_limit = dir.path().join("ok.py");
+    std::fs::write(&at_limit, format!("x = '{}'\n", "a".repeat(limit as usize - 7))).unwrap();
+    assert_eq!(std::fs::metadata(&at_limit).unwrap().len(), limit);
+    assert!(GnawTreeWriter::new(at_limit.to_str().unwrap()).is_ok());
+
+    let over = dir.path().join("big.py");
+    std::fs::write(&over, format!("x = '{}'\n", "a".repeat(limit as usize - 6))).unwrap();
+    let err = match GnawTreeWriter::new(over.to_str().unwrap()) {
+        Ok(_) => panic!("file over the limit should be refused"),
+        Err(e) => e,
+    };
+    assert!(err.to_string().contains("File too large"), "{}", err);
+    assert!(matches!(
+        err.downcast_ref::<GnawError>(),
+        Some(GnawError::FileTooLarge { size, .. }) if *size == limit + 1
+    ));
+}
+
+#[test]
+fn config_limit_applies_unless_flag_overrides_it() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    std::fs::write(dir.path().join(".gnawtreewriter.toml"), "max_file_size = 10\n").unwrap();
+    std::fs::write(dir.path().join("big.py"), "x = 'more than ten bytes'\n").unwrap();
+
+    let analyze = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_gnawtreewriter"))
+            .arg("analyze")
+            .arg("big.py")
+            .args(extra)
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+    };
+
+    // analyze skips files it cannot read with a warning
+    let refused = analyze(&[]);
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("File too large"));
+    assert!(!String::from_utf8_lossy(&refused.stdout).contains("big.py"));
+
+    let allowed = analyze(&["--max-file-size", "1M"]);
+    assert!(allowed.status.success(), "{}", String::from_utf8_lossy(&allowed.stderr));
+    assert!(!String::from_utf8_lossy(&allowed.stderr).contains("File too large"));
+    assert!(String::from_utf8_lossy(&allowed.stdout).contains("big.py"));
+}
+
+#[cfg(feature = "mcp")]
+#[tokio::test]
+async fn mcp_read_tools_refuse_files_over_limit() {
+    use gnawtreewriter::mcp::mcp_server;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    // Same limit as `file_over_limit_is_refused_before_parsing`, which may run alongside
+    set_max_file_size(1024);
+    let big = dir.path().join("big.py");
+    std::fs::write(&big, format!("x = '{}'\n", "a".repeat(2048))).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let root = dir.path().to_path_buf();
+    let server = tokio::spawn(mcp_server::serve_with_shutdown_at(listener, None, root, async move {
+        let _ = rx.await;
+    }));
+
+    let resp = mcp_server::remote_call_tool(&url, None, "analyze", serde_json::json!({"file_path": big}))
+        .await
+        .unwrap();
+    assert_eq!(resp["result"]["isError"], true, "{}", resp);
+    assert!(resp.to_string().contains("File too large"), "{}", resp);
+
+    let _ = tx.send(());
+    server.await.unwrap().unwrap();
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
 /// Regression tests for insert position logic.
 ///
 /// Covers the bug where `insert position=1` (append) would land inside the
@@ -420,3 +422,92 @@ fn insert_source_file_pos1_single_function_no_newline() {
         only_idx
     );
 }
+
+// ── Tab-indented files ───────────────────────────────────────────────
+
+#[test]
+fn insert_multiline_into_tab_indented_python() {
+    let dir = temp_dir();
+    let src = "class Greeter:\n\tdef hello(self):\n\t\treturn 1\n\tdef bye(self):\n\t\treturn 2\n";
+    let path = make_file(dir.path(), "greeter.py", src);
+
+    let writer = gnawtreewriter::GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_block(node: &gnawtreewriter::TreeNode) -> Option<&gnawtreewriter::TreeNode> {
+        if node.node_type == "block" {
+            return Some(node);
+        }
+        node.children.iter().find_map(find_block)
+    }
+    let block = find_block(writer.analyze()).expect("class body block");
+
+    let result = writer
+        .preview_edit(EditOperation::Insert {
+            parent_path: block.path.clone(),
+            position: 3, // after the first method
+            content: "def wave(self):\n    if True:\n        return 3".to_string(),
+        })
+        .unwrap();
+
+    assert!(
+        result.contains("\n\tdef wave(self):\n\t\tif True:\n\t\t\treturn 3\n"),
+        "inserted method should be indented with tabs only:\n{}",
+        result
+    );
+    for line in result.lines() {
+        let ws: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        assert!(!ws.contains(' '), "line mixes spaces into tab indentation: {:?}", line);
+    }
+}
+
+// ── Pre-indented content ─────────────────────────────────────────────
+
+#[test]
+fn insert_preindented_block_is_not_double_indented() {
+    let dir = temp_dir();
+    let src = "fn check(x: i32) -> i32 {\n    let y = x * 2;\n    y\n}\n";
+    let path = make_file(dir.path(), "check.rs", src);
+
+    let writer = gnawtreewriter::GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    fn find_block(node: &gnawtreewriter::TreeNode) -> Option<&gnawtreewriter::TreeNode> {
+        if node.node_type == "block" {
+            return Some(node);
+        }
+        node.children.iter().find_map(find_block)
+    }
+    let block = find_block(writer.analyze()).expect("function body");
+
+    // Content copied from a deeper scope: already indented by 8 spaces
+    let result = writer
+        .preview_edit(EditOperation::Insert {
+            parent_path: block.path.clone(),
+            position: 0,
+            content: "        if x < 0 {\n            return 0;\n        }".to_string(),
+        })
+        .unwrap();
+
+    assert!(
+        result.contains("{\n    if x < 0 {\n        return 0;\n    }\n    let y = x * 2;\n"),
+        "block should be re-indented to the body's depth:\n{}",
+        result
+    );
+}
+
+#[test]
+fn insert_with_multibyte_leading_whitespace_does_not_panic() {
+    let dir = temp_dir();
+    let path = make_file(dir.path(), "check.rs", "fn check() {\n    let y = 1;\n}\n");
+    let writer = gnawtreewriter::GnawTreeWriter::new(path.to_str().unwrap()).unwrap();
+
+    // U+3000 is whitespace three bytes long; the shared indent is empty
+    let result = writer
+        .preview_edit(EditOperation::Insert {
+            parent_path: "0".to_string(),
+            position: 1,
+            content: "\u{3000}// wide\n  let z = 2;".to_string(),
+        })
+        .unwrap();
+    assert!(result.contains("\u{3000}// wide\n"), "{}", result);
+    assert!(result.contains("  let z = 2;"), "{}", result);
+}
@@ -21,14 +21,16 @@ async fn integration_mcp_auth() -> Result<(), Box<dyn std::error::Error>> {
     // oneshot channel to signal server shutdown
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    // Served from a scratch directory so session files never land in the checkout
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     // Spawn the server; it will run until we send on `tx`
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        // Note: serve_with_shutdown is implemented in the mcp module
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -81,6 +83,55 @@ async fn integration_mcp_auth() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[tokio::test]
+async fn integration_mcp_token_file_authenticates() -> Result<(), Box<dyn std::error::Error>> {
+    use gnawtreewriter::mcp::mcp_server::resolve_token;
+
+    let dir = tempfile::tempdir()?;
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "from-file\n")?;
+    // The file wins over the environment and the flag
+    let token = resolve_token(Some(&token_file), Some("from-env".into()), Some("from-flag".into()))?;
+    assert_eq!(token.as_deref(), Some("from-file"));
+
+    std::fs::write(dir.path().join("empty"), "  \n")?;
+    assert!(resolve_token(Some(&dir.path().join("empty")), None, None).is_err());
+    assert!(resolve_token(None, Some(String::new()), Some("from-flag".into())).is_err());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    let (tx, rx) = oneshot::channel::<()>();
+    let root = dir.path().to_path_buf();
+    let server_handle = tokio::spawn(async move {
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, async move {
+            let _ = rx.await;
+        })
+        .await
+        .unwrap();
+    });
+
+    let client = Client::new();
+    let body = json!({"jsonrpc":"2.0","method":"initialize","id":1});
+    let mut status = None;
+    for _ in 0..40 {
+        match client.post(&url).header("Authorization", "Bearer from-file").json(&body).send().await {
+            Ok(res) => {
+                status = Some(res.status());
+                break;
+            }
+            Err(_) => sleep(Duration::from_millis(50)).await,
+        }
+    }
+    assert_eq!(status, Some(reqwest::StatusCode::OK));
+
+    let res = client.post(&url).header("Authorization", "Bearer from-flag").json(&body).send().await?;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
 #[tokio::test]
 async fn integration_mcp_tools_list() -> Result<(), Box<dyn std::error::Error>> {
     // Bind to ephemeral port
@@ -90,13 +141,15 @@ async fn integration_mcp_tools_list() -> Result<(), Box<dyn std::error::Error>>
     // oneshot channel to signal server shutdown
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     // Spawn the server; it will run until we send on `tx`
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -169,13 +222,15 @@ async fn integration_mcp_tools_call_missing_args() -> Result<(), Box<dyn std::er
     // oneshot channel to signal server shutdown
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     // Spawn the server; it will run until we send on `tx`
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -246,12 +301,14 @@ async fn integration_mcp_tools_call_batch() -> Result<(), Box<dyn std::error::Er
     let addr = listener.local_addr()?;
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -309,12 +366,14 @@ async fn integration_mcp_tools_call_unknown_tool() -> Result<(), Box<dyn std::er
     let addr = listener.local_addr()?;
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -378,12 +437,14 @@ async fn integration_mcp_tools_call_file_not_found() -> Result<(), Box<dyn std::
     let addr = listener.local_addr()?;
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -434,7 +495,9 @@ async fn integration_mcp_tools_call_file_not_found() -> Result<(), Box<dyn std::
     assert!(result["content"][0]["text"]
         .as_str()
         .unwrap()
-        .contains("IO error"));
+        .starts_with("Failed to read file: /nonexistent/file.py"));
+    assert_eq!(result["error"]["kind"], "io");
+    assert_eq!(result["error"]["code"], -32008);
 
     let _ = tx.send(());
     server_handle.await?;
@@ -447,12 +510,14 @@ async fn integration_mcp_tools_call_undo() -> Result<(), Box<dyn std::error::Err
     let addr = listener.local_addr()?;
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -510,12 +575,14 @@ async fn integration_mcp_concurrent_requests() -> Result<(), Box<dyn std::error:
     let addr = listener.local_addr()?;
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -586,14 +653,15 @@ async fn integration_mcp_tools_call_analyze() -> Result<(), Box<dyn std::error::
     // oneshot channel to signal server shutdown
     let (tx, rx) = oneshot::channel::<()>();
     let token = Some("secret".to_string());
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().to_path_buf();
 
     // Spawn the server; it will run until we send on `tx`
     let server_handle = tokio::spawn(async move {
         let shutdown_fut = async move {
             let _ = rx.await;
         };
-        // Note: serve_with_shutdown is implemented in the mcp module
-        gnawtreewriter::mcp::mcp_server::serve_with_shutdown(listener, token, shutdown_fut)
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(listener, token, root, shutdown_fut)
             .await
             .unwrap();
     });
@@ -646,7 +714,10 @@ async fn integration_mcp_tools_call_analyze() -> Result<(), Box<dyn std::error::
     let v: serde_json::Value = resp.json().await?;
     assert!(v.get("result").is_some(), "expected JSON-RPC result field");
     let res_obj = v.get("result").unwrap();
-    assert!(res_obj.get("structuredContent").is_some() || res_obj.get("content").is_some());
+    assert!(res_obj.get("content").is_some());
+    let structured = res_obj.get("structuredContent").expect("structuredContent");
+    assert!(structured.is_object());
+    assert_eq!(structured["data"], res_obj["data"]);
 
     // Shutdown server
     let _ = tx.send(());
@@ -654,3 +725,1325 @@ async fn integration_mcp_tools_call_analyze() -> Result<(), Box<dyn std::error::
 
     Ok(())
 }
+
+// ── Helpers for tests that need their own project root ───────────────
+
+/// Start a server rooted at `project_root` and wait until it accepts requests.
+async fn spawn_server_at(
+    project_root: &std::path::Path,
+) -> Result<(String, oneshot::Sender<()>, tokio::task::JoinHandle<()>), Box<dyn std::error::Error>>
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel::<()>();
+    let root = project_root.to_path_buf();
+
+    let server_handle = tokio::spawn(async move {
+        let shutdown_fut = async move {
+            let _ = rx.await;
+        };
+        gnawtreewriter::mcp::mcp_server::serve_with_shutdown_at(
+            listener,
+            Some("secret".to_string()),
+            root,
+            shutdown_fut,
+        )
+        .await
+        .unwrap();
+    });
+
+    let url = format!("http://{}/", addr);
+    let client = Client::new();
+    let mut ready = false;
+    for _ in 0..40 {
+        match client.post(&url).json(&json!({})).send().await {
+            Ok(_) => {
+                ready = true;
+                break;
+            }
+            Err(e) if e.is_connect() => sleep(Duration::from_millis(50)).await,
+            Err(_) => break,
+        }
+    }
+    assert!(ready, "server did not become ready in time");
+
+    Ok((url, tx, server_handle))
+}
+
+/// Send an authenticated JSON-RPC request and return the response body.
+async fn rpc(
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let body = json!({"jsonrpc": "2.0", "method": method, "id": 1, "params": params});
+    let resp = Client::new()
+        .post(url)
+        .header("Authorization", "Bearer secret")
+        .json(&body)
+        .send()
+        .await?;
+    Ok(resp.json().await?)
+}
+
+/// Create a temporary project (marked by `.git`) containing one Python file.
+fn temp_project(
+    file_name: &str,
+    source: &str,
+) -> Result<(tempfile::TempDir, std::path::PathBuf), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join(".git"))?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let file = root.join(file_name);
+    std::fs::write(&file, source)?;
+    Ok((dir, file))
+}
+
+#[tokio::test]
+async fn integration_mcp_restore_session() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "x = 1\ny = 2\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let edit = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "0", "content": "x = 100"}}),
+    )
+    .await?;
+    assert!(edit["result"].get("isError").is_none(), "edit failed: {}", edit);
+    assert!(std::fs::read_to_string(&file)?.contains("x = 100"));
+    let structured = &edit["result"]["structuredContent"];
+    assert_eq!(structured["diff"], edit["result"]["diff"]);
+    assert!(structured["pulse"].is_object());
+
+    let init = rpc(&url, "initialize", json!({})).await?;
+    let session_id = init["result"]["session_id"]
+        .as_str()
+        .expect("initialize should report the active session")
+        .to_string();
+
+    let preview = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_session", "arguments": {"session_id": session_id, "preview": true}}),
+    )
+    .await?;
+    assert_eq!(preview["result"]["files"][0], json!(fp));
+    assert!(std::fs::read_to_string(&file)?.contains("x = 100"));
+
+    let restored = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_session", "arguments": {"session_id": session_id}}),
+    )
+    .await?;
+    assert_eq!(restored["result"]["success"], json!(true), "{}", restored);
+    assert_eq!(std::fs::read_to_string(&file)?, "x = 1\ny = 2\n");
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_restore_by_client_reverts_only_that_clients_edits() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, alice_file) = temp_project("alice.py", "a = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let bob_file = root.join("bob.py");
+    std::fs::write(&bob_file, "b = 1\n")?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    // Two agents sharing the server, interleaving edits in one session
+    for (client, file, content) in [("alice", &alice_file, "a = 2"), ("bob", &bob_file, "b = 2")] {
+        let edit = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "edit_node", "arguments": {
+                "file_path": file.to_str().unwrap(), "node_path": "0", "content": content,
+                "client_id": client, "include_pulse": false
+            }}),
+        )
+        .await?;
+        assert!(edit["result"].get("isError").is_none(), "edit failed: {}", edit);
+    }
+
+    let preview = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_by_client", "arguments": {"client_id": "alice", "preview": true}}),
+    )
+    .await?;
+    assert_eq!(preview["result"]["files"], json!([alice_file.to_str().unwrap()]), "{}", preview);
+
+    let restored = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_by_client", "arguments": {"client_id": "alice"}}),
+    )
+    .await?;
+    assert_eq!(restored["result"]["success"], json!(true), "{}", restored);
+    assert_eq!(std::fs::read_to_string(&alice_file)?, "a = 1\n");
+    assert_eq!(std::fs::read_to_string(&bob_file)?, "b = 2");
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_restore_by_client_refuses_to_discard_other_clients_edits() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("shared.py", "a = 1\nb = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    // Alice edits the file first, then Bob edits another line of it
+    for (client, node, content) in [("alice", "0", "a = 2"), ("bob", "1", "b = 2")] {
+        let edit = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "edit_node", "arguments": {
+                "file_path": fp, "node_path": node, "content": content,
+                "client_id": client, "include_pulse": false
+            }}),
+        )
+        .await?;
+        assert!(edit["result"].get("isError").is_none(), "edit failed: {}", edit);
+    }
+    let edited = std::fs::read_to_string(&file)?;
+    assert!(edited.contains("a = 2") && edited.contains("b = 2"), "{}", edited);
+
+    let preview = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_by_client", "arguments": {"client_id": "alice", "preview": true}}),
+    )
+    .await?;
+    assert_eq!(preview["result"]["conflicts"][0]["client_id"], json!("bob"), "{}", preview);
+
+    let refused = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_by_client", "arguments": {"client_id": "alice"}}),
+    )
+    .await?;
+    assert_eq!(refused["result"]["isError"], json!(true), "{}", refused);
+    assert!(refused["result"]["content"][0]["text"].as_str().unwrap().contains("edited by bob"), "{}", refused);
+    assert_eq!(std::fs::read_to_string(&file)?, edited);
+
+    let forced = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_by_client", "arguments": {"client_id": "alice", "force": true}}),
+    )
+    .await?;
+    assert_eq!(forced["result"]["success"], json!(true), "{}", forced);
+    assert_eq!(std::fs::read_to_string(&file)?, "a = 1\nb = 1\n");
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_initialize_does_not_write_to_a_fresh_project() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "x = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    // Nothing has been edited yet: no session, and the handshake leaves no files behind
+    let init = rpc(&url, "initialize", json!({})).await?;
+    assert!(init["result"]["session_id"].is_null(), "{}", init);
+    assert!(!root.join(".gnawtreewriter_session.json").exists());
+    assert!(!root.join(".gnawtreewriter_session_id").exists());
+
+    let edit = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "0", "content": "x = 2"}}),
+    )
+    .await?;
+    assert!(edit["result"].get("isError").is_none(), "{}", edit);
+
+    // The first edit started the session; initialize now reports it
+    let init = rpc(&url, "initialize", json!({})).await?;
+    let session_id = init["result"]["session_id"].as_str().expect("session id").to_string();
+
+    let restored = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "restore_session", "arguments": {"session_id": session_id}}),
+    )
+    .await?;
+    assert_eq!(restored["result"]["success"], json!(true), "{}", restored);
+    assert_eq!(std::fs::read_to_string(&file)?, "x = 1\n");
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_session_start_changes_session_id() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, _file) = temp_project("app.py", "x = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let first = rpc(&url, "tools/call", json!({"name": "session_start", "arguments": {}})).await?;
+    let first_id = first["result"]["session_id"].as_str().expect("session id").to_string();
+
+    let init = rpc(&url, "initialize", json!({})).await?;
+    assert_eq!(init["result"]["session_id"], json!(first_id));
+
+    let second = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "session_start", "arguments": {"name": "run-2"}}),
+    )
+    .await?;
+    let second_id = second["result"]["session_id"].as_str().expect("session id").to_string();
+    assert_ne!(first_id, second_id);
+
+    let status = rpc(&url, "tools/call", json!({"name": "session_status", "arguments": {}})).await?;
+    assert_eq!(status["result"]["session_id"], json!(second_id));
+    assert_eq!(status["result"]["redo_available"], json!(0));
+    assert_eq!(status["result"]["structuredContent"]["session_id"], json!(second_id));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_analyze_max_depth_and_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def foo():\n    return 1\n\ndef bar():\n    return 2\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "analyze", "arguments": {"file_path": fp, "max_depth": 1, "fields": ["path", "node_type"]}}),
+    )
+    .await?;
+    let data = &resp["result"]["data"];
+    let children = data["children"].as_array().expect("root children");
+    assert!(!children.is_empty());
+    for child in children {
+        assert!(child.get("content").is_none(), "content should be excluded: {}", child);
+        assert!(child.get("path").is_some());
+        let pruned = child["children"].as_array().map(|c| c.is_empty()).unwrap_or(true);
+        assert!(pruned, "children below max_depth should be pruned: {}", child);
+    }
+    assert!(
+        children.iter().any(|c| c["children_truncated"].as_u64().unwrap_or(0) > 0),
+        "pruned nodes should report how many children were cut"
+    );
+    assert_eq!(resp["result"]["truncated"], json!(false));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_status_reports_server_info_and_auth() -> Result<(), Box<dyn std::error::Error>> {
+    use gnawtreewriter::mcp::mcp_server::check_status;
+
+    let (dir, _file) = temp_project("app.py", "x = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let status = check_status(&url, Some("secret")).await;
+    assert!(status.ok(), "{:?}", status);
+    assert_eq!(status.server_name.as_deref(), Some("gnawtreewriter"));
+    assert_eq!(status.version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    assert_eq!(status.protocol_version.as_deref(), Some("2024-11-05"));
+    assert!(status.tools.unwrap() > 10, "{:?}", status);
+    assert_eq!(status.auth, "ok");
+
+    let status = check_status(&url, Some("wrong")).await;
+    assert!(!status.ok());
+    assert!(status.reachable);
+    assert_eq!(status.auth, "failed");
+    assert_eq!(status.tools, None);
+
+    let _ = tx.send(());
+    server_handle.await?;
+
+    let status = check_status(&url, Some("secret")).await;
+    assert!(!status.reachable && !status.ok());
+    assert!(status.error.unwrap().starts_with("Cannot reach"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_list_tools_shows_analyze_with_its_arguments() -> Result<(), Box<dyn std::error::Error>> {
+    use gnawtreewriter::mcp::mcp_server::{fetch_tools, format_tool_table};
+
+    let (dir, _file) = temp_project("app.py", "x = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let tools = fetch_tools(&url, Some("secret")).await?;
+    let table = format_tool_table(&tools);
+    assert!(table.starts_with("TOOL "), "{}", table);
+    let analyze = table.lines().find(|l| l.split_whitespace().next() == Some("analyze")).expect("analyze row");
+    assert!(analyze.contains("file_path"), "{}", analyze);
+    assert_eq!(table.lines().count(), tools.len() + 1);
+
+    let err = fetch_tools(&url, Some("wrong")).await.unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{}", err);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_node_not_found_suggests_nearby_paths() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def f():\n    pass\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "0.9", "content": "pass"}}),
+    )
+    .await?;
+    let error = &resp["result"]["error"];
+    assert_eq!(error["kind"], json!("node_not_found"), "{}", resp);
+    assert_eq!(error["suggestions"], json!(["0.0", "0.1", "0.2"]), "{}", resp);
+    assert!(error["message"].as_str().unwrap().contains("did you mean 0.0, 0.1, 0.2?"), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_node_not_found_has_specific_error_code() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "x = 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "9.9", "content": "y = 2"}}),
+    )
+    .await?;
+    let result = &resp["result"];
+    assert_eq!(result["isError"], json!(true));
+    assert_eq!(result["error"]["kind"], json!("node_not_found"), "{}", resp);
+    assert_eq!(result["error"]["code"], json!(-32003));
+    let text = result["content"][0]["text"].as_str().unwrap();
+    assert!(text.starts_with("Node not found: 9.9"), "{}", text);
+
+    let missing = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "analyze", "arguments": {"file_path": root.join("missing.py").to_str().unwrap()}}),
+    )
+    .await?;
+    assert_eq!(missing["result"]["error"]["kind"], json!("io"));
+    assert_ne!(missing["result"]["error"]["code"], result["error"]["code"]);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_no_validate_writes_invalid_edit_with_warning() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("lib.rs", "fn ok() {}\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let args = json!({"file_path": fp, "node_path": "0", "content": "fn ok( {"});
+    let rejected = rpc(&url, "tools/call", json!({"name": "edit_node", "arguments": args})).await?;
+    assert_eq!(rejected["result"]["isError"], json!(true), "{}", rejected);
+    assert_eq!(std::fs::read_to_string(&file)?, "fn ok() {}\n");
+
+    let mut unsafe_args = args.clone();
+    unsafe_args["no_validate"] = json!(true);
+    let resp = rpc(&url, "tools/call", json!({"name": "edit_node", "arguments": unsafe_args})).await?;
+    let result = &resp["result"];
+    assert_ne!(result["isError"], json!(true), "{}", resp);
+    assert!(std::fs::read_to_string(&file)?.contains("fn ok( {"));
+    let warnings = result["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1, "{}", resp);
+    assert!(warnings[0].as_str().unwrap().contains("no_validate"), "{}", resp);
+    assert!(result["content"][0]["text"].as_str().unwrap().contains("UNSAFE no_validate"));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_search_nodes_with_context() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "def long_function():\n    a = 1\n    b = 2\n    c = 3\n    needle = a + b\n    d = 4\n    e = 5\n    return needle\n";
+    let (dir, file) = temp_project("app.py", source)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "search_nodes", "arguments": {"file_path": fp, "pattern": "needle =", "context": 1}}),
+    )
+    .await?;
+    let matches = resp["result"]["matches"].as_array().expect("matches");
+    let func = matches
+        .iter()
+        .find(|m| m["type"] == json!("function_definition"))
+        .expect("function match");
+    assert_eq!(func["line"], json!(5));
+    assert_eq!(func["context"], json!("    c = 3\n    needle = a + b\n    d = 4"));
+    assert_eq!(func["context_start_line"], json!(4));
+
+    let plain = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "search_nodes", "arguments": {"file_path": fp, "pattern": "needle ="}}),
+    )
+    .await?;
+    assert!(plain["result"]["matches"][0].get("context").is_none());
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_list_nodes_previews_are_truncated() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def greet(name):\n    return 'hello ' + name\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "list_nodes", "arguments": {"file_path": fp, "kind": "function", "preview_chars": 12}}),
+    )
+    .await?;
+    let nodes = resp["result"]["nodes"].as_array().expect("nodes");
+    assert_eq!(nodes[0]["preview"], json!("def greet(na"), "{}", resp);
+
+    // Without the option there is no preview, and short nodes are not padded
+    let resp = rpc(&url, "tools/call", json!({"name": "list_nodes", "arguments": {"file_path": fp}})).await?;
+    assert!(resp["result"]["nodes"][0].get("preview").is_none(), "{}", resp);
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "list_nodes", "arguments": {"file_path": fp, "kind": "function", "preview_chars": 500}}),
+    )
+    .await?;
+    assert_eq!(resp["result"]["nodes"][0]["preview"], json!("def greet(name):\n    return 'hello ' + name"));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_kind_filter_selects_functions() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, py) = temp_project("app.py", "class A:\n    def m(self):\n        pass\n\ndef f():\n    pass\n")?;
+    let rs = dir.path().join("lib.rs");
+    std::fs::write(&rs, "struct S;\n\nimpl S {\n    fn m(&self) {}\n}\n\nfn f() {}\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    for file in [&py, &rs] {
+        let fp = file.to_str().unwrap();
+        let resp = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "list_nodes", "arguments": {"file_path": fp, "kind": "function"}}),
+        )
+        .await?;
+        let names: Vec<&str> = resp["result"]["nodes"]
+            .as_array()
+            .expect("nodes")
+            .iter()
+            .map(|n| n["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["f"], "{}", fp);
+
+        let resp = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "search_nodes", "arguments": {"file_path": fp, "pattern": "m(", "kind": "method"}}),
+        )
+        .await?;
+        let matches = resp["result"]["matches"].as_array().expect("matches");
+        assert_eq!(matches.len(), 1, "{}", fp);
+        assert_eq!(matches[0]["name"], json!("m"));
+    }
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "list_nodes", "arguments": {"file_path": py.to_str().unwrap(), "kind": "struct"}}),
+    )
+    .await?;
+    assert_eq!(resp["error"]["code"], -32602);
+    assert_eq!(resp["error"]["data"]["field"], json!("kind"));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_preview_insert_and_delete_do_not_write() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "x = 1\ny = 2\n";
+    let (dir, file) = temp_project("app.py", source)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let insert = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "preview_insert", "arguments": {"file_path": fp, "parent_path": "", "position": 2, "content": "z = 3"}}),
+    )
+    .await?;
+    assert!(insert["result"].get("isError").is_none(), "{}", insert);
+    assert!(insert["result"]["diff"].as_str().unwrap().contains("+z = 3"), "{}", insert);
+    assert_eq!(std::fs::read_to_string(&file)?, source);
+
+    let delete = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "preview_delete", "arguments": {"file_path": fp, "node_path": "1"}}),
+    )
+    .await?;
+    assert!(delete["result"]["diff"].as_str().unwrap().contains("-y = 2"), "{}", delete);
+    assert_eq!(std::fs::read_to_string(&file)?, source);
+
+    // `position` is optional and defaults to the end of the parent
+    let tools = rpc(&url, "tools/list", json!({})).await?;
+    for name in ["insert_node", "preview_insert"] {
+        let tool = tools["result"]["tools"].as_array().unwrap().iter().find(|t| t["name"] == name).unwrap();
+        assert!(!tool["inputSchema"]["required"].as_array().unwrap().contains(&json!("position")), "{}", tool);
+    }
+    let insert = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "insert_node", "arguments": {"file_path": fp, "parent_path": "", "content": "z = 3"}}),
+    )
+    .await?;
+    assert!(insert["result"].get("isError").is_none(), "{}", insert);
+    assert_eq!(std::fs::read_to_string(&file)?.trim_end(), "x = 1\ny = 2\nz = 3");
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_preview_edit_returns_old_and_new_content() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "x = 1\ndef f():\n    return 2\n";
+    let (dir, file) = temp_project("app.py", source)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+    let new_content = "def f():\n    return 3";
+
+    let preview = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "preview_edit", "arguments": {"file_path": fp, "node_path": "1", "content": new_content, "include_content": true}}),
+    )
+    .await?;
+    let result = &preview["result"];
+    assert!(result.get("isError").is_none(), "{}", preview);
+    assert_eq!(result["old_content"], "def f():\n    return 2");
+    assert_eq!(result["new_content"], new_content);
+    assert_eq!((result["start_line"].as_u64(), result["end_line"].as_u64()), (Some(2), Some(3)));
+    assert_eq!(result["structuredContent"]["old_content"], result["old_content"]);
+    assert!(result["diff"].as_str().unwrap().contains("+    return 3"));
+    assert_eq!(std::fs::read_to_string(&file)?, source);
+
+    // Applying the edit gives the node the previewed content
+    rpc(&url, "tools/call", json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "1", "content": new_content}}))
+        .await?;
+    let after = gnawtreewriter::GnawTreeWriter::new(fp)?;
+    let node = after.analyze().find_path("1").expect("edited node");
+    assert_eq!(node.content, new_content);
+    assert_eq!(node.start_line, 2);
+
+    // Without the flag only the diff is returned
+    let plain = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "preview_edit", "arguments": {"file_path": fp, "node_path": "0", "content": "x = 5"}}),
+    )
+    .await?;
+    assert!(plain["result"].get("old_content").is_none());
+
+    // A node that shares its line shows the whole spliced line
+    let rust = root.join("lib.rs");
+    std::fs::write(&rust, "fn f() { let a = 1; let b = 2; }\n")?;
+    let inline = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "preview_edit", "arguments": {
+            "file_path": rust.to_str().unwrap(), "node_path": "0.3.2", "content": "let b = 3;", "include_content": true
+        }}),
+    )
+    .await?;
+    assert_eq!(inline["result"]["old_lines"], json!(["fn f() { let a = 1; let b = 2; }"]), "{}", inline);
+    assert_eq!(inline["result"]["new_lines"], json!(["fn f() { let a = 1; let b = 3; }"]), "{}", inline);
+    assert_eq!(inline["result"]["start_col"], json!(21), "{}", inline);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_semantic_tools_fall_back_without_model() -> Result<(), Box<dyn std::error::Error>> {
+    let source = "def load_file(path):\n    return open(path).read()\n\ndef parse_config(text):\n    a = 1\n    b = 2\n    c = 3\n    return text\n";
+    let (dir, file) = temp_project("app.py", source)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let report = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "get_semantic_report", "arguments": {"file_path": fp}}),
+    )
+    .await?;
+    assert!(report["result"].get("isError").is_none(), "{}", report);
+    let findings = report["result"]["report"]["findings"].as_array().unwrap();
+    if report["result"]["mode"] == "heuristic" {
+        assert!(findings.iter().any(|f| f["category"] == "Documentation"), "{}", report);
+    }
+
+    let sense = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "sense", "arguments": {"query": "parse config", "file_path": fp}}),
+    )
+    .await?;
+    assert!(sense["result"].get("isError").is_none(), "{}", sense);
+    let nodes = sense["result"]["nodes"].as_array().unwrap();
+    assert!(!nodes.is_empty(), "{}", sense);
+    if sense["result"]["mode"] == "heuristic" {
+        assert_eq!(nodes[0]["path"], "1", "{}", sense);
+    }
+
+    if report["result"]["mode"] == "heuristic" {
+        // Only the body of `load_file` mentions "open": too weak to edit
+        let vague = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "semantic_edit", "arguments": {"file_path": fp, "query": "open", "content": "x = 1", "include_pulse": false}}),
+        )
+        .await?;
+        assert_eq!(vague["result"]["isError"], json!(true), "{}", vague);
+        assert_eq!(std::fs::read_to_string(&file)?, source);
+
+        let edit = rpc(
+            &url,
+            "tools/call",
+            json!({"name": "semantic_edit", "arguments": {
+                "file_path": fp, "query": "parse config", "content": "def parse_config(text):\n    return text", "include_pulse": false
+            }}),
+        )
+        .await?;
+        assert_eq!(edit["result"]["mode"], "heuristic", "{}", edit);
+        assert_eq!(edit["result"]["node_path"], "1", "{}", edit);
+        assert!(edit["result"]["structuredContent"]["score"].as_f64().unwrap() >= 0.6, "{}", edit);
+    }
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_health_and_tools_routes() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let client = Client::new();
+
+    // No token needed for the health probe
+    let resp = client.get(format!("{}health", url)).send().await?;
+    assert_eq!(resp.status(), 200);
+    let health: serde_json::Value = resp.json().await?;
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+    assert!(health["uptime_secs"].is_u64());
+
+    let resp = client.get(format!("{}tools", url)).send().await?;
+    assert_eq!(resp.status(), 401);
+    let resp = client
+        .get(format!("{}tools", url))
+        .header("Authorization", "Bearer secret")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    let tools: serde_json::Value = resp.json().await?;
+    assert!(tools["tools"].as_array().unwrap().iter().any(|t| t["name"] == "analyze"));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_batch_request() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let batch = json!([
+        {"jsonrpc": "2.0", "method": "initialize", "id": 1},
+        {"jsonrpc": "2.0", "method": "notifications/initialized"},
+        {"jsonrpc": "2.0", "method": "tools/list", "id": 2}
+    ]);
+    let resp = Client::new()
+        .post(&url)
+        .header("Authorization", "Bearer secret")
+        .json(&batch)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await?;
+    let responses = body.as_array().expect("batch response is an array");
+    assert_eq!(responses.len(), 2, "{}", body);
+    assert_eq!(responses[0]["id"], 1);
+    assert!(responses[0]["result"]["serverInfo"].is_object());
+    assert_eq!(responses[1]["id"], 2);
+    assert!(responses[1]["result"]["tools"].is_array());
+
+    // Elements that are not requests each get an Invalid Request error
+    let batch = json!([1, {"jsonrpc": "2.0", "id": 3}, {"jsonrpc": "2.0", "method": "tools/list", "id": 4}]);
+    let resp = Client::new().post(&url).header("Authorization", "Bearer secret").json(&batch).send().await?;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await?;
+    let responses = body.as_array().expect("batch response is an array");
+    assert_eq!(responses.len(), 3, "{}", body);
+    for invalid in &responses[..2] {
+        assert_eq!(invalid["error"]["code"], -32600, "{}", invalid);
+        assert!(invalid["id"].is_null());
+    }
+    assert_eq!(responses[2]["id"], 4);
+
+    // Notifications, alone or in a batch, get an empty 204
+    for notification in [
+        json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        json!([{"jsonrpc": "2.0", "method": "notifications/initialized"}]),
+    ] {
+        let resp = Client::new().post(&url).header("Authorization", "Bearer secret").json(&notification).send().await?;
+        assert_eq!(resp.status(), 204);
+        assert!(resp.bytes().await?.is_empty());
+    }
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[test]
+fn integration_mcp_stdio_access_log() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let (dir, file) = temp_project("app.py", "x = 1\n")?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gnawtreewriter"))
+        .args(["mcp", "stdio"])
+        .current_dir(dir.path())
+        .env("GNAW_MCP_LOG", "debug")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        let call = json!({"jsonrpc": "2.0", "id": 7, "method": "tools/call",
+            "params": {"name": "analyze", "arguments": {"file_path": file.to_str().unwrap()}}});
+        writeln!(stdin, "{}", call)?;
+        writeln!(stdin, "not json")?;
+    }
+    let output = child.wait_with_output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+
+    let entry: serde_json::Value = stderr
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .find(|v| v["id"] == 7)
+        .unwrap_or_else(|| panic!("no access log line in stderr:\n{}", stderr));
+    assert_eq!(entry["transport"], "stdio");
+    assert_eq!(entry["method"], "tools/call");
+    assert_eq!(entry["tool"], "analyze");
+    assert_eq!(entry["status"], "ok");
+    assert!(entry["duration_ms"].is_u64());
+    assert!(entry["params"].as_str().unwrap().contains("analyze"));
+    assert!(stderr.contains("\"status\":\"parse_error\""), "{}", stderr);
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_max_concurrency_rejects_extra_requests() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir()?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let url = format!("http://{}/", addr);
+    let (tx, rx) = oneshot::channel::<()>();
+    let options = gnawtreewriter::mcp::mcp_server::ServerOptions {
+        max_concurrency: Some(1),
+        ..Default::default()
+    };
+    let server_handle = tokio::spawn(async move {
+        gnawtreewriter::mcp::mcp_server::serve_with_options(listener, None, root, options, async move {
+            let _ = rx.await;
+        })
+        .await
+        .unwrap();
+    });
+
+    // A request whose body is only half sent holds the single permit
+    let body = json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}).to_string();
+    let (head, rest) = body.split_at(10);
+    let mut slow = loop {
+        match tokio::net::TcpStream::connect(addr).await {
+            Ok(stream) => break stream,
+            Err(_) => sleep(Duration::from_millis(20)).await,
+        }
+    };
+    let headers = format!(
+        "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        addr,
+        body.len()
+    );
+    slow.write_all(headers.as_bytes()).await?;
+    slow.write_all(head.as_bytes()).await?;
+
+    let client = Client::new();
+    let probe = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"});
+    let mut rejected = None;
+    for _ in 0..100 {
+        let resp = client.post(&url).json(&probe).send().await?;
+        if resp.status() == 429 {
+            rejected = Some(resp.json::<serde_json::Value>().await?);
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    let rejected = rejected.expect("the second concurrent request should be rejected");
+    assert_eq!(rejected["error"]["code"], -32005);
+
+    // Finishing the first request releases the permit
+    slow.write_all(rest.as_bytes()).await?;
+    let mut response = String::new();
+    slow.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let after = client.post(&url).json(&probe).send().await?;
+    assert_eq!(after.status(), 200);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_capabilities_follow_features() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let init = rpc(&url, "initialize", json!({})).await?;
+    let caps = &init["result"]["capabilities"]["experimental"]["gnawtreewriter"];
+    assert_eq!(caps["semantic"], json!(cfg!(feature = "modernbert")), "{}", init);
+    assert_eq!(caps["editing"], json!(true));
+
+    let list = rpc(&url, "tools/list", json!({})).await?;
+    let names: Vec<&str> = list["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|t| t["name"].as_str())
+        .collect();
+    for tool in ["complete_code", "suggest_refactor", "suggest_batch"] {
+        assert_eq!(names.contains(&tool), cfg!(feature = "modernbert"), "{} in {:?}", tool, names);
+    }
+    // These fall back to heuristics, so they are always offered
+    assert!(names.contains(&"sense"));
+    assert!(names.contains(&"get_semantic_report"));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_diff_nodes_compares_a_function_across_files() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, a) = temp_project("a.py", "def greet(name):\n    prefix = 'Hi'\n    return prefix + name\n")?;
+    let b = dir.path().join("b.py");
+    std::fs::write(&b, "x = 1\n\ndef greet(name):\n    prefix = 'Hello'\n    return prefix + name\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let diff = |path_b: &'static str| {
+        let url = url.clone();
+        let (a, b) = (a.to_str().unwrap().to_string(), b.to_str().unwrap().to_string());
+        async move {
+            rpc(
+                &url,
+                "tools/call",
+                json!({"name": "diff_nodes", "arguments": {"file_a": a, "path_a": "0", "file_b": b, "path_b": path_b}}),
+            )
+            .await
+        }
+    };
+
+    let resp = diff("1").await?;
+    assert_eq!(resp["result"]["identical"], json!(false));
+    let text = resp["result"]["diff"].as_str().expect("diff");
+    assert!(text.contains("-    prefix = 'Hi'\n"), "{}", text);
+    assert!(text.contains("+    prefix = 'Hello'\n"), "{}", text);
+    assert!(text.contains("     return prefix + name"), "{}", text);
+    assert_eq!(text.lines().filter(|l| l.starts_with('+') || l.starts_with('-')).count(), 2, "{}", text);
+
+    let resp = diff("9").await?;
+    assert_eq!(resp["result"]["isError"], json!(true), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_analyze_stream_sends_one_node_per_line() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def greet():\n    return 1\n\nx = 2\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let client = Client::new();
+    let body = json!({"file_path": file.to_str().unwrap(), "fields": ["path", "node_type"]});
+
+    let resp = client.post(format!("{}analyze_stream", url)).json(&body).send().await?;
+    assert_eq!(resp.status(), 401);
+
+    let mut resp = client
+        .post(format!("{}analyze_stream", url))
+        .header("Authorization", "Bearer secret")
+        .json(&body)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+
+    // Read chunk by chunk, as a streaming client would
+    let mut buffered = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        buffered.extend_from_slice(&chunk);
+    }
+    let nodes: Vec<serde_json::Value> = String::from_utf8(buffered)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(nodes[0]["node_type"], "module");
+    assert_eq!(nodes[0]["depth"], 0);
+    assert_eq!(nodes[1]["node_type"], "function_definition");
+    assert_eq!(nodes[1]["depth"], 1);
+    assert!(nodes.iter().all(|n| n.get("children").is_none() && n.get("content").is_none()));
+    assert!(nodes.iter().any(|n| n["node_type"] == "expression_statement"));
+
+    let resp = client
+        .post(format!("{}analyze_stream", url))
+        .header("Authorization", "Bearer secret")
+        .json(&json!({"file_path": file.to_str().unwrap(), "max_depth": 1}))
+        .send()
+        .await?;
+    let text = resp.text().await?;
+    assert_eq!(text.lines().count(), 3, "{}", text);
+
+    // A file that does not parse is reported as a parse error, not an I/O one
+    let broken = root.join("broken.py");
+    std::fs::write(&broken, "def (:\n")?;
+    let resp = client
+        .post(format!("{}analyze_stream", url))
+        .header("Authorization", "Bearer secret")
+        .json(&json!({"file_path": broken.to_str().unwrap()}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 422);
+    let failure: serde_json::Value = resp.json().await?;
+    assert_eq!(failure["error"]["kind"], "parse", "{}", failure);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_get_relations_exports_a_call_between_functions() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def helper():\n    return 1\n\ndef main():\n    return helper()\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(&url, "tools/call", json!({"name": "get_relations", "arguments": {"path": fp}})).await?;
+    let result = &resp["result"];
+    let ids: Vec<&str> = result["nodes"].as_array().expect("nodes").iter().filter_map(|n| n["id"].as_str()).collect();
+    assert_eq!(ids, [format!("{}#helper", fp), format!("{}#main", fp)], "{}", resp);
+    let edges = result["edges"].as_array().expect("edges");
+    assert_eq!(edges.len(), 1, "{}", resp);
+    assert_eq!(edges[0]["from"], json!(format!("{}#main", fp)));
+    assert_eq!(edges[0]["to"], json!(format!("{}#helper", fp)));
+    assert_eq!(edges[0]["relation_type"], json!("Call"));
+
+    let missing = dir.path().join("missing.py");
+    let resp = rpc(&url, "tools/call", json!({"name": "get_relations", "arguments": {"path": missing}})).await?;
+    assert_eq!(resp["result"]["isError"], json!(true), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_find_references_reports_each_relation_type() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project(
+        "shapes.py",
+        "class Base:\n    pass\n\nclass User(Base):\n    pass\n\ndef make():\n    return Base()\n",
+    )?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "find_references", "arguments": {"name": "Base", "path": file.to_str().unwrap()}}),
+    )
+    .await?;
+    let relations: Vec<&str> = resp["result"]["references"]
+        .as_array()
+        .expect("references")
+        .iter()
+        .filter_map(|r| r["relation"].as_str())
+        .collect();
+    assert_eq!(relations, ["Inherit", "Call"], "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_include_pulse_false_skips_indexing() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("app.py", "def greet():\n    return 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+    let graph_dir = root.join(".gnawtreewriter_ai").join("graph");
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {
+            "file_path": fp, "node_path": "0", "content": "def greet():\n    return 2", "include_pulse": false
+        }}),
+    )
+    .await?;
+    assert!(resp["result"]["diff"].is_string(), "{}", resp);
+    assert!(resp["result"].get("pulse").is_none(), "{}", resp);
+    assert!(!graph_dir.exists(), "the pulse indexed {}", graph_dir.display());
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "0", "content": "def greet():\n    return 3"}}),
+    )
+    .await?;
+    assert!(resp["result"]["pulse"]["related_nodes"].is_array(), "{}", resp);
+    assert!(graph_dir.exists());
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_pulse_finds_typescript_test_files() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, file) = temp_project("foo.ts", "function foo() {\n    return 1;\n}\n")?;
+    std::fs::write(dir.path().join("foo.test.ts"), "test('foo', () => {});\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let edit = |content: &'static str| {
+        let url = url.clone();
+        let fp = fp.to_string();
+        async move {
+            rpc(&url, "tools/call", json!({"name": "edit_node", "arguments": {"file_path": fp, "node_path": "0", "content": content}}))
+                .await
+        }
+    };
+
+    let resp = edit("function foo() {\n    return 2;\n}").await?;
+    let tests = resp["result"]["pulse"]["test_files"].as_array().expect("test_files");
+    assert_eq!(tests.len(), 1, "{}", resp);
+    assert!(tests[0].as_str().unwrap().ends_with("foo.test.ts"), "{}", resp);
+
+    // Configured patterns replace the defaults
+    std::fs::write(dir.path().join(".gnawtreewriter.toml"), "[mcp]\ntest_patterns = [\"checks/{stem}.ts\"]\n")?;
+    std::fs::create_dir(dir.path().join("checks"))?;
+    std::fs::write(dir.path().join("checks").join("foo.ts"), "// checks\n")?;
+    let resp = edit("function foo() {\n    return 3;\n}").await?;
+    let tests = resp["result"]["pulse"]["test_files"].as_array().expect("test_files");
+    assert_eq!(tests.len(), 1, "{}", resp);
+    assert!(tests[0].as_str().unwrap().ends_with("checks/foo.ts"), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_call_cli_runs_analyze_with_key_value_args() -> Result<(), Box<dyn std::error::Error>> {
+    use gnawtreewriter::mcp::mcp_server::build_call_args;
+
+    let (dir, file) = temp_project("app.py", "def greet():\n    return 1\n")?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap().to_string();
+
+    let schema = json!({"properties": {"max_depth": {"type": "integer"}, "name": {"type": "string"}, "content": {"type": "string"}}});
+    let args = build_call_args(&["max_depth=2".into(), "name=a=b".into()], Some(r#"{"max_depth": 1, "all": true}"#), &schema)?;
+    assert_eq!(args, json!({"max_depth": 2, "name": "a=b", "all": true}));
+    // String fields and keys the schema does not know stay text, unless := asks for JSON
+    let args = build_call_args(
+        &["content=42".into(), "name=true".into(), "other=1".into(), "flag:=true".into(), "max_depth=deep".into()],
+        None,
+        &schema,
+    )?;
+    assert_eq!(args, json!({"content": "42", "name": "true", "other": "1", "flag": true, "max_depth": "deep"}));
+    assert!(build_call_args(&["flag:=nope".into()], None, &schema).is_err());
+    assert!(build_call_args(&["oops".into()], None, &schema).is_err());
+    assert!(build_call_args(&[], Some("[1]"), &serde_json::Value::Null).is_err());
+
+    let call = |args: Vec<String>| {
+        let url = url.clone();
+        tokio::task::spawn_blocking(move || {
+            std::process::Command::new(env!("CARGO_BIN_EXE_gnawtreewriter"))
+                .args(["mcp", "call", "--url", &url, "--token", "secret"])
+                .args(args)
+                .output()
+                .unwrap()
+        })
+    };
+
+    let out = call(vec!["analyze".into(), format!("file_path={}", fp)]).await?;
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let resp: serde_json::Value = serde_json::from_slice(&out.stdout)?;
+    assert!(resp["result"]["content"][0]["text"].as_str().unwrap().starts_with("Analyzed"), "{}", resp);
+    assert_eq!(resp["result"]["data"]["children"][0]["node_type"], json!("function_definition"), "{}", resp);
+
+    // `content` is a string in edit_node's schema, so 42 is sent as text
+    let out = call(vec!["edit_node".into(), format!("file_path={}", fp), "node_path=0".into(), "content=42".into()]).await?;
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(std::fs::read_to_string(&file)?.trim_end(), "42");
+
+    let missing = json!({"file_path": root.join("missing.py")}).to_string();
+    let out = call(vec!["analyze".into(), "--json-args".into(), missing]).await?;
+    assert!(!out.status.success());
+    let resp: serde_json::Value = serde_json::from_slice(&out.stdout)?;
+    assert_eq!(resp["result"]["isError"], json!(true), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_read_node_include_children_returns_every_method() -> Result<(), Box<dyn std::error::Error>> {
+    let src = "class Greeter:\n    def hello(self):\n        return 'hi'\n\n    def bye(self):\n        return 'bye'\n";
+    let (dir, file) = temp_project("app.py", src)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "read_node", "arguments": {"file_path": fp, "node_path": "0", "include_children": true}}),
+    )
+    .await?;
+    let children = resp["result"]["children"].as_array().expect("children");
+    let methods: Vec<_> = children.iter().filter(|c| c["type"] == json!("function_definition")).collect();
+    assert_eq!(methods.len(), 2, "{}", resp);
+    assert_eq!(methods[0]["name"], json!("hello"));
+    assert_eq!(methods[0]["content"], json!("def hello(self):\n        return 'hi'"));
+    assert_eq!(methods[1]["name"], json!("bye"));
+    assert_eq!(methods[1]["path"], json!("0.3.1"));
+    // The class itself comes first, then its name and methods
+    assert_eq!(resp["result"]["node"]["name"], json!("Greeter"));
+    assert_eq!(children[0]["type"], json!("identifier"));
+    let text = resp["result"]["content"][0]["text"].as_str().unwrap();
+    assert!(text.starts_with("--- 0 class_definition Greeter (lines 1-6)\nclass Greeter:"), "{}", text);
+    assert!(text.contains("--- 0.3.1 function_definition bye (lines 5-6)\ndef bye(self):"), "{}", text);
+
+    // Without the flag the node is returned whole, as before
+    let resp = rpc(&url, "tools/call", json!({"name": "read_node", "arguments": {"file_path": fp, "node_path": "0"}})).await?;
+    assert_eq!(resp["result"]["content"][0]["text"], json!(src.trim_end()));
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_mcp_search_nodes_include_parents_reports_class_and_method() -> Result<(), Box<dyn std::error::Error>> {
+    let src = "class Greeter:\n    def hello(self):\n        return 'needle'\n";
+    let (dir, file) = temp_project("app.py", src)?;
+    let root = std::fs::canonicalize(dir.path())?;
+    let (url, tx, server_handle) = spawn_server_at(&root).await?;
+    let fp = file.to_str().unwrap();
+
+    let resp = rpc(
+        &url,
+        "tools/call",
+        json!({"name": "search_nodes", "arguments": {"file_path": fp, "pattern": "'needle'", "include_parents": true}}),
+    )
+    .await?;
+    let matches = resp["result"]["matches"].as_array().expect("matches");
+    let string = matches.iter().find(|m| m["type"] == json!("string")).expect("string match");
+    let parents = string["parents"].as_array().unwrap();
+    assert_eq!(parents[0]["type"], json!("module"));
+    let named: Vec<(&str, &str)> = parents
+        .iter()
+        .filter_map(|p| Some((p["type"].as_str()?, p["name"].as_str()?)))
+        .collect();
+    assert_eq!(named, [("class_definition", "Greeter"), ("function_definition", "hello")], "{}", string);
+    // Parents run from the root down to the match's own parent
+    let last = parents.last().unwrap()["path"].as_str().unwrap();
+    assert!(string["path"].as_str().unwrap().starts_with(&format!("{}.", last)), "{}", string);
+
+    let resp = rpc(&url, "tools/call", json!({"name": "search_nodes", "arguments": {"file_path": fp, "pattern": "'needle'"}})).await?;
+    assert!(resp["result"]["matches"][0].get("parents").is_none(), "{}", resp);
+
+    let _ = tx.send(());
+    server_handle.await?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Tests for replaying an exported transaction log onto a reset checkout.
+
+use gnawtreewriter::core::replay::{load_transactions, replay, ReplayOptions};
+use gnawtreewriter::core::transaction_log::ExportFormat;
+use gnawtreewriter::core::{EditOperation, TransactionLog};
+use gnawtreewriter::GnawTreeWriter;
+
+const ORIGINAL: &str = "def greet():\n    return 'hi'\n\ndef unused():\n    return 0\n";
+
+fn make_project() -> (tempfile::TempDir, std::path::PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join(".git")).unwrap();
+    let root = std::fs::canonicalize(dir.path()).unwrap();
+    let file = root.join("app.py");
+    std::fs::write(&file, ORIGINAL).unwrap();
+    (dir, file)
+}
+
+#[test]
+fn replaying_exported_session_reaches_same_state() {
+    let (dir, file) = make_project();
+    let root = file.parent().unwrap().to_path_buf();
+    let fp = file.to_str().unwrap();
+
+    let mut writer = GnawTreeWriter::new(fp).unwrap();
+    writer
+        .edit(EditOperation::Edit { node_path: "0".into(), content: "def greet():\n    return 'hello'".into() }, false)
+        .unwrap();
+    writer.edit(EditOperation::Delete { node_path: "1".into() }, true).unwrap();
+    writer
+        .edit(EditOperation::Insert { parent_path: "".into(), position: 2, content: "def extra():\n    return 1".into() }, false)
+        .unwrap();
+    let end_state = std::fs::read_to_string(&file).unwrap();
+    assert_ne!(end_state, ORIGINAL);
+
+    let export = dir.path().join("history.json");
+    let exported = TransactionLog::load(&root).unwrap().export_history(ExportFormat::Json).unwrap();
+    std::fs::write(&export, exported).unwrap();
+
+    std::fs::write(&file, ORIGINAL).unwrap();
+    let transactions = load_transactions(&export).unwrap();
+    let options = ReplayOptions { project_root: root.clone(), strip_prefix: None, preview: false };
+    let report = replay(&transactions, &options).unwrap();
+
+    assert!(report.conflicts.is_empty(), "{:?}", report.conflicts);
+    assert_eq!(report.applied, 3);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), end_state);
+}
+
+#[test]
+fn replay_reports_conflict_when_file_diverged() {
+    let (dir, file) = make_project();
+    let root = file.parent().unwrap().to_path_buf();
+
+    let mut writer = GnawTreeWriter::new(file.to_str().unwrap()).unwrap();
+    writer
+        .edit(EditOperation::Edit { node_path: "0".into(), content: "def greet():\n    return 'hello'".into() }, false)
+        .unwrap();
+
+    let export = dir.path().join("history.json");
+    let exported = TransactionLog::load(&root).unwrap().export_history(ExportFormat::Json).unwrap();
+    std::fs::write(&export, exported).unwrap();
+
+    let diverged = "def greet():\n    return 'something else'\n";
+    std::fs::write(&file, diverged).unwrap();
+    let options = ReplayOptions { project_root: root, strip_prefix: None, preview: false };
+    let report = replay(&load_transactions(&export).unwrap(), &options).unwrap();
+
+    assert_eq!(report.applied, 0);
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), diverged);
+}